@@ -4,8 +4,8 @@ use std::slice::from_raw_parts;
 use libc::{c_ulong, c_void};
 use num_traits::cast::{FromPrimitive, ToPrimitive};
 use sspi::{
-    AuthIdentityBuffers, DataRepresentation, DecryptionFlags, EncryptionFlags, ErrorKind, SecurityBuffer,
-    SecurityBufferType, ServerRequestFlags, Sspi,
+    AuthIdentityBuffers, CredentialsBuffers, DataRepresentation, DecryptionFlags, EncryptionFlags, ErrorKind,
+    SecurityBuffer, SecurityBufferType, ServerRequestFlags, Sspi,
 };
 
 use crate::sec_buffer::{
@@ -41,7 +41,7 @@ pub unsafe extern "system" fn AcceptSecurityContext(
     let mut auth_data = if auth_data == null::<AuthIdentityBuffers>() as *mut _ {
         None
     } else {
-        Some(auth_data.as_mut().unwrap().clone())
+        Some(CredentialsBuffers::AuthIdentity(auth_data.as_mut().unwrap().clone()))
     };
 
     let kerberos = p_ctxt_handle_to_kerberos(ph_context).as_mut().unwrap();
@@ -121,27 +121,61 @@ pub extern "system" fn RevertSecurityContext(_ph_context: PCtxtHandle) -> Securi
 }
 pub type RevertSecurityContextFn = extern "system" fn(PCtxtHandle) -> SecurityStatus;
 
+#[allow(clippy::useless_conversion)]
 #[no_mangle]
-pub extern "system" fn MakeSignature(
-    _ph_context: PCtxtHandle,
-    _f_qop: c_ulong,
-    _p_message: PSecBufferDesc,
-    _message_seq_no: c_ulong,
+pub unsafe extern "system" fn MakeSignature(
+    ph_context: PCtxtHandle,
+    f_qop: c_ulong,
+    p_message: PSecBufferDesc,
+    message_seq_no: c_ulong,
 ) -> SecurityStatus {
-    ErrorKind::UnsupportedFunction.to_u32().unwrap()
+    let kerberos = p_ctxt_handle_to_kerberos(ph_context).as_mut().unwrap();
+
+    let len = (*p_message).c_buffers as usize;
+    let raw_buffers = from_raw_parts((*p_message).p_buffers, len);
+    let mut message = p_sec_buffers_to_security_buffers(raw_buffers);
+
+    match kerberos
+        .make_signature(
+            f_qop.try_into().unwrap(),
+            &mut message,
+            message_seq_no.try_into().unwrap(),
+        )
+        .and_then(|_| copy_to_c_sec_buffer(&message, (*p_message).p_buffers))
+    {
+        Ok(()) => 0,
+        Err(error) => error.error_type.to_u32().unwrap(),
+    }
 }
-pub type MakeSignatureFn = extern "system" fn(PCtxtHandle, c_ulong, PSecBufferDesc, c_ulong) -> SecurityStatus;
+pub type MakeSignatureFn = unsafe extern "system" fn(PCtxtHandle, c_ulong, PSecBufferDesc, c_ulong) -> SecurityStatus;
 
+#[allow(clippy::useless_conversion)]
 #[no_mangle]
-pub extern "system" fn VerifySignature(
-    _ph_context: PCtxtHandle,
-    _message: PSecBufferDesc,
-    _message_seq_no: c_ulong,
-    _pf_qop: *mut c_ulong,
+pub unsafe extern "system" fn VerifySignature(
+    ph_context: PCtxtHandle,
+    p_message: PSecBufferDesc,
+    message_seq_no: c_ulong,
+    pf_qop: *mut c_ulong,
 ) -> SecurityStatus {
-    ErrorKind::UnsupportedFunction.to_u32().unwrap()
+    let kerberos = p_ctxt_handle_to_kerberos(ph_context).as_mut().unwrap();
+
+    let len = (*p_message).c_buffers as usize;
+    let raw_buffers = from_raw_parts((*p_message).p_buffers, len);
+    let mut message = p_sec_buffers_to_security_buffers(raw_buffers);
+
+    let (qop, status) = match kerberos.verify_signature(&mut message, message_seq_no.try_into().unwrap()) {
+        Ok(qop) => (qop, 0),
+        Err(error) => (0, error.error_type.to_u32().unwrap()),
+    };
+
+    if !pf_qop.is_null() {
+        *pf_qop = qop.try_into().unwrap();
+    }
+
+    status
 }
-pub type VerifySignatureFn = extern "system" fn(PCtxtHandle, PSecBufferDesc, c_ulong, *mut c_ulong) -> SecurityStatus;
+pub type VerifySignatureFn =
+    unsafe extern "system" fn(PCtxtHandle, PSecBufferDesc, c_ulong, *mut c_ulong) -> SecurityStatus;
 
 #[no_mangle]
 pub unsafe extern "system" fn FreeContextBuffer(pv_context_buffer: *mut c_void) -> SecurityStatus {
@@ -191,7 +225,9 @@ pub unsafe extern "system" fn EncryptMessage(
         Err(error) => error.error_type.to_u32().unwrap(),
     };
 
-    copy_to_c_sec_buffer(&message, (*p_message).p_buffers);
+    if let Err(error) = copy_to_c_sec_buffer(&message, (*p_message).p_buffers) {
+        return error.error_type.to_u32().unwrap();
+    }
 
     result_status
 }
@@ -216,7 +252,9 @@ pub unsafe extern "system" fn DecryptMessage(
         Err(error) => (DecryptionFlags::empty(), error.error_type.to_u32().unwrap()),
     };
 
-    copy_to_c_sec_buffer(&message, (*p_message).p_buffers);
+    if let Err(error) = copy_to_c_sec_buffer(&message, (*p_message).p_buffers) {
+        return error.error_type.to_u32().unwrap();
+    }
     *pf_qop = decryption_flags.bits().try_into().unwrap();
 
     status
@@ -6,7 +6,7 @@ use libc::c_uint;
 #[cfg(target_os = "windows")]
 use libc::c_ulong;
 use num_traits::{FromPrimitive, ToPrimitive};
-use sspi::{SecurityBuffer, SecurityBufferType};
+use sspi::{ErrorKind, SecurityBuffer, SecurityBufferType};
 
 use crate::utils::vec_into_raw_ptr;
 
@@ -73,8 +73,24 @@ pub(crate) unsafe fn security_buffers_to_raw(buffers: Vec<SecurityBuffer>) -> PS
     )
 }
 
-pub(crate) unsafe fn copy_to_c_sec_buffer(from_buffers: &[SecurityBuffer], to_buffers: PSecBuffer) {
+// the buffers of the caller can not grow, so nothing is copied if any of them is too small
+pub(crate) unsafe fn copy_to_c_sec_buffer(from_buffers: &[SecurityBuffer], to_buffers: PSecBuffer) -> sspi::Result<()> {
     let to_buffers = from_raw_parts_mut(to_buffers as *mut SecBuffer, from_buffers.len());
+    for (from_buffer, to_buffer) in from_buffers.iter().zip(to_buffers.iter()) {
+        let to_buffer_len: usize = to_buffer.cb_buffer.try_into().unwrap();
+        if from_buffer.buffer.len() > to_buffer_len {
+            return Err(sspi::Error::new(
+                ErrorKind::BufferTooSmall,
+                format!(
+                    "The {:?} buffer is too small: {} < {}",
+                    from_buffer.buffer_type,
+                    to_buffer_len,
+                    from_buffer.buffer.len()
+                ),
+            ));
+        }
+    }
+
     for i in 0..from_buffers.len() {
         let buffer = &from_buffers[i];
         let len = buffer.buffer.len();
@@ -83,4 +99,6 @@ pub(crate) unsafe fn copy_to_c_sec_buffer(from_buffers: &[SecurityBuffer], to_bu
         let to_buffer = from_raw_parts_mut(to_buffers[i].pv_buffer, len);
         to_buffer.copy_from_slice(from_raw_parts(buffer.buffer.as_ptr() as *const i8, len));
     }
+
+    Ok(())
 }
@@ -5,7 +5,11 @@ use std::slice::from_raw_parts;
 use libc::{c_ulong, c_ulonglong, c_void};
 use num_traits::{FromPrimitive, ToPrimitive};
 use sspi::kerberos::config::KerberosConfig;
-use sspi::{AuthIdentityBuffers, ClientRequestFlags, DataRepresentation, ErrorKind, Kerberos, Sspi};
+use sspi::kerberos::KpasswdResult;
+use sspi::{
+    AuthIdentity, AuthIdentityBuffers, ClientRequestFlags, CredentialsBuffers, DataRepresentation, ErrorKind, Kerberos,
+    SecurityBuffer, SecurityBufferType, Sspi,
+};
 
 use crate::sec_buffer::{
     p_sec_buffers_to_security_buffers, security_buffers_to_raw, PSecBuffer, PSecBufferDesc, SecBufferDesc,
@@ -14,7 +18,7 @@ use crate::sec_winnt_auth_identity::{SecWinntAuthIdentityA, SecWinntAuthIdentity
 use crate::sspi_data_types::{
     LpStr, LpcWStr, PSecurityString, PTimeStamp, SecChar, SecGetKeyFn, SecPkgContextSizes, SecWChar, SecurityStatus,
 };
-use crate::utils::{c_w_str_to_string, into_raw_ptr, raw_str_into_bytes, raw_w_str_to_bytes};
+use crate::utils::{c_str_into_string, c_w_str_to_string, into_raw_ptr, raw_str_into_bytes, raw_w_str_to_bytes};
 
 #[repr(C)]
 pub struct SecHandle {
@@ -33,8 +37,11 @@ pub(crate) unsafe fn p_ctxt_handle_to_kerberos(mut context: PCtxtHandle) -> *mut
         });
     }
     if (*context).dw_lower == 0 {
-        (*context).dw_lower =
-            into_raw_ptr(Kerberos::new_client_from_config(KerberosConfig::from_env()).unwrap()) as c_ulonglong;
+        (*context).dw_lower = into_raw_ptr(
+            KerberosConfig::from_env()
+                .and_then(Kerberos::new_client_from_config)
+                .unwrap(),
+        ) as c_ulonglong;
     }
     (*context).dw_lower as *mut Kerberos
 }
@@ -154,7 +161,7 @@ pub unsafe extern "system" fn InitializeSecurityContextA(
     let mut auth_data = if auth_data == null::<AuthIdentityBuffers>() as *mut _ {
         None
     } else {
-        Some(auth_data.as_mut().unwrap().clone())
+        Some(CredentialsBuffers::AuthIdentity(auth_data.as_mut().unwrap().clone()))
     };
 
     let kerberos_ptr = p_ctxt_handle_to_kerberos(ph_context);
@@ -228,7 +235,7 @@ pub unsafe extern "system" fn InitializeSecurityContextW(
     let mut auth_data = if auth_data == null::<AuthIdentityBuffers>() as *mut _ {
         None
     } else {
-        Some(auth_data.as_mut().unwrap().clone())
+        Some(CredentialsBuffers::AuthIdentity(auth_data.as_mut().unwrap().clone()))
     };
 
     let kerberos_ptr = p_ctxt_handle_to_kerberos(ph_context);
@@ -444,20 +451,85 @@ pub extern "system" fn SetCredentialsAttributesW(
 }
 pub type SetCredentialsAttributesFnW = extern "system" fn(PCtxtHandle, c_ulong, *mut c_void, c_ulong) -> SecurityStatus;
 
+unsafe fn change_account_password(
+    package: &str,
+    domain: String,
+    account: String,
+    old_password: String,
+    new_password: String,
+    p_output: PSecBufferDesc,
+) -> SecurityStatus {
+    if !package.eq_ignore_ascii_case(sspi::kerberos::PKG_NAME) && !package.eq_ignore_ascii_case("Negotiate") {
+        return ErrorKind::UnsupportedFunction.to_u32().unwrap();
+    }
+
+    let identity = AuthIdentity {
+        username: account,
+        password: old_password,
+        domain: Some(domain).filter(|domain| !domain.is_empty()),
+    };
+
+    let result = KerberosConfig::from_env()
+        .and_then(Kerberos::new_client_from_config)
+        .and_then(|mut kerberos| kerberos.request_password_change(&identity, &new_password));
+
+    match result {
+        Ok(result) => {
+            if !p_output.is_null() {
+                write_change_password_response(&result, p_output);
+            }
+
+            result
+                .check()
+                .map_or_else(|err| err.error_type.to_u32().unwrap(), |_| 0)
+        }
+        Err(err) => err.error_type.to_u32().unwrap(),
+    }
+}
+
+// the result code of the kpasswd server (big-endian, as in the kpasswd reply) followed by its text
+unsafe fn write_change_password_response(result: &KpasswdResult, p_output: PSecBufferDesc) {
+    if (*p_output).p_buffers.is_null() {
+        return;
+    }
+
+    let raw_buffers = from_raw_parts((*p_output).p_buffers, (*p_output).c_buffers as usize);
+    let mut output_buffers = p_sec_buffers_to_security_buffers(raw_buffers);
+
+    if let Ok(buffer) = SecurityBuffer::find_buffer_mut(&mut output_buffers, SecurityBufferType::ChangePasswordResponse)
+    {
+        buffer.buffer = result.code.to_be_bytes().to_vec();
+        buffer.buffer.extend_from_slice(result.message().as_bytes());
+
+        (*p_output).p_buffers = security_buffers_to_raw(output_buffers);
+    }
+}
+
 #[no_mangle]
-pub extern "system" fn ChangeAccountPasswordA(
-    _psz_package_name: *mut SecChar,
-    _psz_domain_name: *mut SecChar,
-    _psz_account_name: *mut SecChar,
-    _psz_old_password: *mut SecChar,
-    _psz_new_password: *mut SecChar,
+pub unsafe extern "system" fn ChangeAccountPasswordA(
+    psz_package_name: *mut SecChar,
+    psz_domain_name: *mut SecChar,
+    psz_account_name: *mut SecChar,
+    psz_old_password: *mut SecChar,
+    psz_new_password: *mut SecChar,
     _b_impersonating: bool,
     _dw_reserved: c_ulong,
-    _p_output: PSecBufferDesc,
+    p_output: PSecBufferDesc,
 ) -> SecurityStatus {
-    ErrorKind::UnsupportedFunction.to_u32().unwrap()
+    match (
+        c_str_into_string(psz_package_name),
+        c_str_into_string(psz_domain_name),
+        c_str_into_string(psz_account_name),
+        c_str_into_string(psz_old_password),
+        c_str_into_string(psz_new_password),
+    ) {
+        (Some(package), Some(domain), Some(account), Some(old_password), Some(new_password)) => {
+            change_account_password(&package, domain, account, old_password, new_password, p_output)
+        }
+        _ => ErrorKind::InvalidParameter.to_u32().unwrap(),
+    }
 }
-pub type ChangeAccountPasswordFnA = extern "system" fn(
+pub type ChangeAccountPasswordFnA = unsafe extern "system" fn(
     *mut SecChar,
     *mut SecChar,
     *mut SecChar,
@@ -469,19 +541,37 @@ pub type ChangeAccountPasswordFnA = extern "system" fn(
 ) -> SecurityStatus;
 
 #[no_mangle]
-pub extern "system" fn ChangeAccountPasswordW(
-    _psz_package_name: *mut SecWChar,
-    _psz_domain_name: *mut SecWChar,
-    _psz_account_name: *mut SecWChar,
-    _psz_old_password: *mut SecWChar,
-    _psz_new_password: *mut SecWChar,
+pub unsafe extern "system" fn ChangeAccountPasswordW(
+    psz_package_name: *mut SecWChar,
+    psz_domain_name: *mut SecWChar,
+    psz_account_name: *mut SecWChar,
+    psz_old_password: *mut SecWChar,
+    psz_new_password: *mut SecWChar,
     _b_impersonating: bool,
     _dw_reserved: c_ulong,
-    _p_output: PSecBufferDesc,
+    p_output: PSecBufferDesc,
 ) -> SecurityStatus {
-    ErrorKind::UnsupportedFunction.to_u32().unwrap()
+    let strings = [
+        psz_package_name,
+        psz_domain_name,
+        psz_account_name,
+        psz_old_password,
+        psz_new_password,
+    ];
+    if strings.iter().any(|s| s.is_null()) {
+        return ErrorKind::InvalidParameter.to_u32().unwrap();
+    }
+
+    change_account_password(
+        &c_w_str_to_string(psz_package_name),
+        c_w_str_to_string(psz_domain_name),
+        c_w_str_to_string(psz_account_name),
+        c_w_str_to_string(psz_old_password),
+        c_w_str_to_string(psz_new_password),
+        p_output,
+    )
 }
-pub type ChangeAccountPasswordFnW = extern "system" fn(
+pub type ChangeAccountPasswordFnW = unsafe extern "system" fn(
     *mut SecWChar,
     *mut SecWChar,
     *mut SecWChar,
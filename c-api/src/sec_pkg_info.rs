@@ -1,5 +1,6 @@
 use libc::{c_uint, c_ulong, c_ushort};
-use sspi::{enumerate_security_packages, PackageInfo, KERBEROS_VERSION};
+use num_traits::ToPrimitive;
+use sspi::{enumerate_security_packages, ErrorKind, PackageInfo, KERBEROS_VERSION};
 
 use crate::sspi_data_types::{SecChar, SecWChar, SecurityStatus};
 use crate::utils::{c_str_into_string, c_w_str_to_string, into_raw_ptr, vec_into_raw_ptr};
@@ -112,7 +113,10 @@ pub unsafe extern "system" fn QuerySecurityPackageInfoA(
     p_package_name: *const SecChar,
     pp_package_info: *mut PSecPkgInfoA,
 ) -> SecurityStatus {
-    let pkg_name = c_str_into_string(p_package_name);
+    let pkg_name = match c_str_into_string(p_package_name) {
+        Some(pkg_name) => pkg_name,
+        None => return ErrorKind::InvalidParameter.to_u32().unwrap(),
+    };
 
     *pp_package_info = enumerate_security_packages()
         .unwrap()
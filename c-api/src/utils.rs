@@ -33,12 +33,17 @@ pub unsafe fn raw_str_into_bytes(raw_buffer: *const c_char, len: usize) -> Vec<u
     from_raw_parts(raw_buffer, len).iter().map(|c| *c as u8).collect()
 }
 
-pub unsafe fn c_str_into_string(s: *const SecChar) -> String {
+// None if the pointer is null or the string is not valid UTF-8
+pub unsafe fn c_str_into_string(s: *const SecChar) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+
     let mut len = 0;
 
     while *(s.add(len)) != 0 {
         len += 1;
     }
 
-    String::from_utf8(from_raw_parts(s as *const u8, len).to_vec()).unwrap()
+    String::from_utf8(from_raw_parts(s as *const u8, len).to_vec()).ok()
 }
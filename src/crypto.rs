@@ -2,7 +2,7 @@ mod rc4;
 
 use std::io;
 
-use crypto_mac::Mac;
+use hmac::{Hmac, Mac};
 use md4::{Digest, Md4};
 use md5::Md5;
 pub use rc4::Rc4;
@@ -40,8 +40,7 @@ pub fn compute_sha256(data: &[u8]) -> [u8; SHA256_SIZE] {
 }
 
 pub fn compute_hmac_md5(key: &[u8], input: &[u8]) -> io::Result<[u8; HASH_SIZE]> {
-    use hmac::NewMac;
-    let mut mac = hmac::Hmac::<Md5>::new_from_slice(key)
+    let mut mac = Hmac::<Md5>::new_from_slice(key)
         .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to compute hmac md5: {}", e)))?;
     let mut result = [0x00; HASH_SIZE];
     mac.update(input);
@@ -81,7 +81,10 @@ cfg_if::cfg_if! {
 }
 
 pub use crate::sspi::kerberos::config::KerberosConfig;
-pub use crate::sspi::kerberos::{Kerberos, KERBEROS_VERSION, PACKAGE_INFO as KERBEROS_PACKAGE_INFO};
+pub use crate::sspi::kerberos::network_client::NetworkClient;
+pub use crate::sspi::kerberos::{
+    Credentials, CredentialsBuffers, Kerberos, KerberosError, KERBEROS_VERSION, PACKAGE_INFO as KERBEROS_PACKAGE_INFO,
+};
 #[cfg(windows)]
 pub use crate::sspi::winapi;
 pub use crate::sspi::{
@@ -89,6 +92,7 @@ pub use crate::sspi::{
     AcceptSecurityContextResult, AcquireCredentialsHandleResult, AuthIdentity, AuthIdentityBuffers,
     CertTrustErrorStatus, CertTrustInfoStatus, CertTrustStatus, ClientRequestFlags, ClientResponseFlags, ContextNames,
     ContextSizes, CredentialUse, DataRepresentation, DecryptionFlags, EncryptionFlags, Error, ErrorKind,
-    InitializeSecurityContextResult, Ntlm, PackageCapabilities, PackageInfo, Result, SecurityBuffer,
-    SecurityBufferType, SecurityPackageType, SecurityStatus, ServerRequestFlags, ServerResponseFlags, Sspi, SspiEx,
+    InitializeSecurityContextResult, Negotiate, NegotiateConfig, Ntlm, PackageCapabilities, PackageInfo, Result,
+    SecurityBuffer, SecurityBufferType, SecurityPackageType, SecurityStatus, ServerRequestFlags, ServerResponseFlags,
+    Sspi, SspiEx,
 };
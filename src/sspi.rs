@@ -5,6 +5,7 @@ pub mod kerberos;
 #[cfg(windows)]
 pub mod winapi;
 
+mod negotiate;
 mod ntlm;
 
 use std::{error, fmt, io, result, str, string};
@@ -25,6 +26,9 @@ pub use self::builders::{
     AcceptSecurityContextResult, AcquireCredentialsHandleResult, InitializeSecurityContextResult,
 };
 use self::internal::SspiImpl;
+use self::kerberos::pac::Pac;
+use self::kerberos::KerberosError;
+pub use self::negotiate::{Negotiate, NegotiateConfig};
 pub use self::ntlm::{AuthIdentity, AuthIdentityBuffers, Ntlm};
 
 /// Representation of SSPI-related result operation. Makes it easier to return a `Result` with SSPI-related `Error`.
@@ -57,6 +61,7 @@ pub fn query_security_package_info(package_type: SecurityPackageType) -> Result<
     match package_type {
         SecurityPackageType::Ntlm => Ok(ntlm::PACKAGE_INFO.clone()),
         SecurityPackageType::Kerberos => Ok(kerberos::PACKAGE_INFO.clone()),
+        SecurityPackageType::Other(s) if s == negotiate::PKG_NAME => Ok(negotiate::PACKAGE_INFO.clone()),
         SecurityPackageType::Other(s) => Err(Error::new(
             ErrorKind::Unknown,
             format!("Queried info about unknown package: {:?}", s),
@@ -86,10 +91,7 @@ pub fn query_security_package_info(package_type: SecurityPackageType) -> Result<
 ///
 /// * [EnumerateSecurityPackagesW function](https://docs.microsoft.com/en-us/windows/win32/api/sspi/nf-sspi-enumeratesecuritypackagesw)
 pub fn enumerate_security_packages() -> Result<Vec<PackageInfo>> {
-    Ok(vec![
-        kerberos::PACKAGE_INFO.clone(),
-        kerberos::NEGO_PACKAGE_INFO.clone(),
-    ])
+    Ok(vec![kerberos::PACKAGE_INFO.clone(), negotiate::PACKAGE_INFO.clone()])
 }
 
 /// This trait provides interface for all available SSPI functions. The `acquire_credentials_handle`,
@@ -587,6 +589,45 @@ where
     /// * [DecryptMessage function](https://docs.microsoft.com/en-us/windows/win32/api/sspi/nf-sspi-decryptmessage)
     fn decrypt_message(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> Result<DecryptionFlags>;
 
+    /// Generates a cryptographic checksum of the message, and also includes sequencing information to prevent message loss or insertion.
+    /// The message itself is not encrypted.
+    ///
+    /// # Parameters
+    ///
+    /// * `flags`: package-specific flags that indicate the quality of protection
+    /// * `message`: on input, the structure references one or more `SecurityBuffer` structures.
+    ///   One of these must be of type `SecurityBufferType::Data` and contains the message to be signed.
+    ///   The signature is written to the buffer of type `SecurityBufferType::Token`
+    /// * `sequence_number`: the sequence number that the transport application assigned to the message. If the transport application does not maintain sequence numbers, this parameter must be zero
+    ///
+    /// # Returns
+    ///
+    /// * `SspiOk` on success
+    /// * `Error` on error
+    ///
+    /// # MSDN
+    ///
+    /// * [MakeSignature function](https://docs.microsoft.com/en-us/windows/win32/api/sspi/nf-sspi-makesignature)
+    fn make_signature(&mut self, flags: u32, message: &mut [SecurityBuffer], sequence_number: u32) -> Result<()>;
+
+    /// Verifies that a message signed by using the `make_signature` function was received in the correct sequence and has not been modified.
+    ///
+    /// # Parameters
+    ///
+    /// * `message`: on input, the structure references one or more `SecurityBuffer` structures.
+    ///   The buffer of type `SecurityBufferType::Data` contains the message and the buffer of type `SecurityBufferType::Token` contains the signature
+    /// * `sequence_number`: the sequence number expected by the transport application, if any. If the transport application does not maintain sequence numbers, this parameter must be zero
+    ///
+    /// # Returns
+    ///
+    /// * package-specific flags that indicate the quality of protection upon success
+    /// * `Error` on error
+    ///
+    /// # MSDN
+    ///
+    /// * [VerifySignature function](https://docs.microsoft.com/en-us/windows/win32/api/sspi/nf-sspi-verifysignature)
+    fn verify_signature(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> Result<u32>;
+
     /// Retrieves information about the bounds of sizes of authentication information of the current security principal.
     ///
     /// # Returns
@@ -684,6 +725,29 @@ where
     ///
     /// * [QueryContextAttributes (CredSSP) function (`ulAttribute` parameter)](https://docs.microsoft.com/en-us/windows/win32/secauthn/querycontextattributes--credssp)
     fn query_context_cert_trust_status(&mut self) -> Result<CertTrustStatus>;
+
+    /// Retrieves the PAC of the ticket accepted from the client: the groups and the claims of the client.
+    /// Only the Kerberos acceptor supports it
+    ///
+    /// # Returns
+    ///
+    /// * `Pac` on success
+    /// * `Error` on error
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sspi::Sspi;
+    /// # let mut ntlm = sspi::Ntlm::new();
+    /// if let Ok(pac) = ntlm.query_context_pac() {
+    ///     println!("Groups: {:?}", pac.logon_info.map(|logon_info| logon_info.group_sids()));
+    /// }
+    /// ```
+    ///
+    /// # MSDN
+    ///
+    /// * [PAC_LOGON_INFO](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/ecf37f5b-1e1e-4d3b-8a69-a47b46e1d3c3)
+    fn query_context_pac(&mut self) -> Result<Pac>;
 }
 
 pub trait SspiEx
@@ -1375,6 +1439,7 @@ pub enum ErrorKind {
 pub struct Error {
     pub error_type: ErrorKind,
     pub description: String,
+    kerberos_error: Option<Box<KerberosError>>,
 }
 
 /// The success status of SSPI-related operation.
@@ -1397,8 +1462,22 @@ impl Error {
         Self {
             error_type,
             description: error,
+            kerberos_error: None,
         }
     }
+
+    /// Attaches the KRB-ERROR of the KDC which caused the error.
+    pub fn with_kerberos_error(self, kerberos_error: KerberosError) -> Self {
+        Self {
+            kerberos_error: Some(Box::new(kerberos_error)),
+            ..self
+        }
+    }
+
+    /// The KRB-ERROR of the KDC which caused the error, if any.
+    pub fn kerberos_error(&self) -> Option<&KerberosError> {
+        self.kerberos_error.as_deref()
+    }
 }
 
 impl error::Error for Error {}
@@ -1475,19 +1554,13 @@ pub fn get_krb_status_from_code(error_code: &[u8]) -> &'static str {
         [0x42] => "KRB_AP_ERR_USER_TO_USER_REQUIRED",
         [0x43] => "KRB_AP_ERR_NO_TGT",
         [0x44] => "Unrecognised Domain - KDC_ERR_WRONG_REALM",
-        _ =>  "MISSING_ERROR",
-    }
+        _ => "MISSING_ERROR",
+    };
 }
 
 impl From<KrbError> for Error {
     fn from(err: KrbError) -> Self {
-        let error_code = err.0.error_code.as_unsigned_bytes_be();
-        let error = get_krb_status_from_code(error_code);
-
-        Self::new(
-            ErrorKind::InternalError,
-            format!("Got the krb error: {} ({})", error, err.0.to_string()),
-        )
+        Self::from(KerberosError::from(&err))
     }
 }
 
@@ -1496,32 +1569,19 @@ impl From<kerberos_crypto::Error> for Error {
         use kerberos_crypto::Error;
 
         match err {
-            Error::DecryptionError(description) => Self {
-                error_type: ErrorKind::DecryptFailure,
-                description,
-            },
-            Error::UnsupportedAlgorithm(alg) => Self {
-                error_type: ErrorKind::InternalError,
-                description: format!("unsupported algorithm: {}", alg),
-            },
-            Error::InvalidKeyCharset => Self {
-                error_type: ErrorKind::InternalError,
-                description: "invalid key charset".to_owned(),
-            },
-            Error::InvalidKeyLength(len) => Self {
-                error_type: ErrorKind::InternalError,
-                description: format!("invalid key len: {}", len),
-            },
+            Error::DecryptionError(description) => Self::new(ErrorKind::DecryptFailure, description),
+            Error::UnsupportedAlgorithm(alg) => {
+                Self::new(ErrorKind::InternalError, format!("unsupported algorithm: {}", alg))
+            }
+            Error::InvalidKeyCharset => Self::new(ErrorKind::InternalError, "invalid key charset".to_owned()),
+            Error::InvalidKeyLength(len) => Self::new(ErrorKind::InternalError, format!("invalid key len: {}", len)),
         }
     }
 }
 
 impl From<CharSetError> for Error {
     fn from(err: CharSetError) -> Self {
-        Self {
-            error_type: ErrorKind::InternalError,
-            description: err.to_string(),
-        }
+        Self::new(ErrorKind::InternalError, err.to_string())
     }
 }
 
@@ -1529,22 +1589,10 @@ impl From<GssApiMessageError> for Error {
     fn from(err: GssApiMessageError) -> Self {
         match err {
             GssApiMessageError::IoError(err) => Self::from(err),
-            GssApiMessageError::InvalidId(_, _) => Self {
-                error_type: ErrorKind::InvalidToken,
-                description: err.to_string(),
-            },
-            GssApiMessageError::InvalidMicFiller(_) => Self {
-                error_type: ErrorKind::InvalidToken,
-                description: err.to_string(),
-            },
-            GssApiMessageError::InvalidWrapFiller(_) => Self {
-                error_type: ErrorKind::InvalidToken,
-                description: err.to_string(),
-            },
-            GssApiMessageError::Asn1Error(_) => Self {
-                error_type: ErrorKind::InvalidToken,
-                description: err.to_string(),
-            },
+            GssApiMessageError::InvalidId(_, _) => Self::new(ErrorKind::InvalidToken, err.to_string()),
+            GssApiMessageError::InvalidMicFiller(_) => Self::new(ErrorKind::InvalidToken, err.to_string()),
+            GssApiMessageError::InvalidWrapFiller(_) => Self::new(ErrorKind::InvalidToken, err.to_string()),
+            GssApiMessageError::Asn1Error(_) => Self::new(ErrorKind::InvalidToken, err.to_string()),
         }
     }
 }
@@ -267,6 +267,30 @@ impl<'a, Inner: SspiImpl<CredentialsHandle = CredsHandle>, CredsHandle>
             context_requirements: self.context_requirements,
             target_data_representation: self.target_data_representation,
 
+            output: self.output,
+            input: self.input,
+        }
+    }
+    /// Passes the builder to the security package with another type of the credentials handle
+    pub(crate) fn transform_with_credentials_handle<Inner2>(
+        self,
+        inner: &'a mut Inner2,
+        credentials_handle: Option<&'a mut Inner2::CredentialsHandle>,
+    ) -> FilledAcceptSecurityContext<'a, Inner2, Inner2::CredentialsHandle>
+    where
+        Inner2: SspiImpl,
+    {
+        AcceptSecurityContext {
+            inner: Some(inner),
+            phantom_creds_use_set: PhantomData,
+            phantom_context_req_set: PhantomData,
+            phantom_data_repr_set: PhantomData,
+            phantom_output_set: PhantomData,
+
+            credentials_handle,
+            context_requirements: self.context_requirements,
+            target_data_representation: self.target_data_representation,
+
             output: self.output,
             input: self.input,
         }
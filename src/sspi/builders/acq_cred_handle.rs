@@ -131,6 +131,27 @@ where
             auth_data: self.auth_data,
         }
     }
+
+    /// Passes the builder to the security package with another type of the authentication data
+    pub(crate) fn transform_with_auth_data<Inner2>(
+        self,
+        inner: &'a mut Inner2,
+        auth_data: Option<&'a Inner2::AuthenticationData>,
+    ) -> FilledAcquireCredentialsHandle<'a, Inner2, Inner2::CredentialsHandle, Inner2::AuthenticationData>
+    where
+        Inner2: SspiImpl,
+    {
+        AcquireCredentialsHandle {
+            inner: Some(inner),
+            phantom_cred_handle: PhantomData,
+            phantom_cred_use_set: PhantomData,
+
+            principal_name: self.principal_name,
+            credential_use: self.credential_use,
+            logon_id: self.logon_id,
+            auth_data,
+        }
+    }
 }
 
 /// Simulates the presence of the `credential_use` value of the
@@ -282,6 +282,31 @@ impl<'a, Inner: SspiImpl<CredentialsHandle = CredsHandle>, CredsHandle>
             target_data_representation: self.target_data_representation,
             output: self.output,
 
+            target_name: self.target_name,
+            input: self.input,
+        }
+    }
+    /// Passes the builder to the security package with another type of the credentials handle
+    pub(crate) fn transform_with_credentials_handle<Inner2>(
+        self,
+        inner: &'a mut Inner2,
+        credentials_handle: Option<&'a mut Inner2::CredentialsHandle>,
+    ) -> FilledInitializeSecurityContext<'a, Inner2, Inner2::CredentialsHandle>
+    where
+        Inner2: SspiImpl,
+    {
+        InitializeSecurityContext {
+            inner: Some(inner),
+            phantom_creds_use_set: PhantomData,
+            phantom_context_req_set: PhantomData,
+            phantom_data_repr_set: PhantomData,
+            phantom_output_set: PhantomData,
+
+            credentials_handle,
+            context_requirements: self.context_requirements,
+            target_data_representation: self.target_data_representation,
+            output: self.output,
+
             target_name: self.target_name,
             input: self.input,
         }
@@ -19,8 +19,10 @@ use ts_request::{NONCE_SIZE, TS_REQUEST_VERSION};
 use crate::crypto::compute_sha256;
 use crate::sspi::internal::SspiImpl;
 use crate::sspi::kerberos::config::KerberosConfig;
-use crate::sspi::kerberos::Kerberos;
-use crate::sspi::ntlm::{AuthIdentity, AuthIdentityBuffers, Ntlm, SIGNATURE_SIZE};
+use crate::sspi::kerberos::pac::Pac;
+use crate::sspi::kerberos::{Credentials, CredentialsBuffers, Kerberos};
+use crate::sspi::negotiate::{Negotiate, NegotiateConfig};
+use crate::sspi::ntlm::{self, AuthIdentity, AuthIdentityBuffers, Ntlm, SIGNATURE_SIZE};
 use crate::sspi::{
     self, CertTrustStatus, ClientRequestFlags, ContextNames, ContextSizes, CredentialUse, DataRepresentation,
     DecryptionFlags, EncryptionFlags, FilledAcceptSecurityContext, FilledAcquireCredentialsHandle,
@@ -35,6 +37,13 @@ const HASH_MAGIC_LEN: usize = 38;
 const SERVER_CLIENT_HASH_MAGIC: &[u8; HASH_MAGIC_LEN] = b"CredSSP Server-To-Client Binding Hash\0";
 const CLIENT_SERVER_HASH_MAGIC: &[u8; HASH_MAGIC_LEN] = b"CredSSP Client-To-Server Binding Hash\0";
 
+// the server is authenticated, and the public key and the credentials are encrypted
+const CLIENT_REQUEST_FLAGS: ClientRequestFlags = ClientRequestFlags::MUTUAL_AUTH
+    .union(ClientRequestFlags::REPLAY_DETECT)
+    .union(ClientRequestFlags::SEQUENCE_DETECT)
+    .union(ClientRequestFlags::CONFIDENTIALITY)
+    .union(ClientRequestFlags::INTEGRITY);
+
 /// Provides an interface for implementing proxy credentials structures.
 pub trait CredentialsProxy {
     type AuthenticationData;
@@ -149,9 +158,12 @@ enum EndpointType {
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum ClientMode {
     Kerberos(KerberosConfig),
     Ntlm,
+    /// Kerberos or NTLM negotiated by SPNEGO
+    Negotiate(NegotiateConfig),
 }
 
 /// Implements the CredSSP *client*. The client's credentials are to
@@ -228,6 +240,9 @@ impl CredSspClient {
                     Kerberos::new_client_from_config(kerberos_config.clone())?,
                 ))),
                 ClientMode::Ntlm => Some(CredSspContext::new(SspiContext::Ntlm(Ntlm::new()))),
+                ClientMode::Negotiate(negotiate_config) => Some(CredSspContext::new(SspiContext::Negotiate(
+                    Negotiate::new(negotiate_config.clone()),
+                ))),
             };
             let AcquireCredentialsHandleResult { credentials_handle, .. } = self
                 .context
@@ -259,7 +274,7 @@ impl CredSspClient {
                     .sspi_context
                     .initialize_security_context()
                     .with_credentials_handle(&mut credentials_handle)
-                    .with_context_requirements(ClientRequestFlags::empty())
+                    .with_context_requirements(CLIENT_REQUEST_FLAGS)
                     .with_target_data_representation(DataRepresentation::Native)
                     .with_target_name(&self.service_principal_name)
                     .with_input(&mut [input_token])
@@ -286,7 +301,22 @@ impl CredSspClient {
                 Ok(ClientState::ReplyNeeded(ts_request))
             }
             CredSspState::AuthInfo => {
-                ts_request.nego_tokens = None;
+                if let Some(nego_token) = ts_request.nego_tokens.take().filter(|token| !token.is_empty()) {
+                    let mut credentials_handle = self.credentials_handle.take();
+                    self.context
+                        .as_mut()
+                        .unwrap()
+                        .sspi_context
+                        .initialize_security_context()
+                        .with_credentials_handle(&mut credentials_handle)
+                        .with_context_requirements(CLIENT_REQUEST_FLAGS)
+                        .with_target_data_representation(DataRepresentation::Native)
+                        .with_target_name(&self.service_principal_name)
+                        .with_input(&mut [SecurityBuffer::new(nego_token, SecurityBufferType::Token)])
+                        .with_output(&mut [SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)])
+                        .execute()?;
+                    self.credentials_handle = credentials_handle;
+                }
 
                 let pub_key_auth = ts_request.pub_key_auth.take().ok_or_else(|| {
                     sspi::Error::new(
@@ -380,6 +410,9 @@ impl<C: CredentialsProxy<AuthenticationData = AuthIdentity>> CredSspServer<C> {
                     try_cred_ssp_server!(Kerberos::new_server_from_config(kerberos_config.clone()), ts_request),
                 ))),
                 ClientMode::Ntlm => Some(CredSspContext::new(SspiContext::Ntlm(Ntlm::new()))),
+                ClientMode::Negotiate(negotiate_config) => Some(CredSspContext::new(SspiContext::Negotiate(
+                    Negotiate::new(negotiate_config.clone()),
+                ))),
             };
             let AcquireCredentialsHandleResult { credentials_handle, .. } = try_cred_ssp_server!(
                 self.context
@@ -451,7 +484,9 @@ impl<C: CredentialsProxy<AuthenticationData = AuthIdentity>> CredSspServer<C> {
                     AcceptSecurityContextResult { status, .. } if status == SecurityStatus::ContinueNeeded => {
                         ts_request.nego_tokens = Some(output_token.remove(0).buffer);
                     }
-                    AcceptSecurityContextResult { status, .. } if status == SecurityStatus::CompleteNeeded => {
+                    AcceptSecurityContextResult { status, .. }
+                        if status == SecurityStatus::CompleteNeeded || status == SecurityStatus::Ok =>
+                    {
                         let ContextNames { username, domain } = try_cred_ssp_server!(
                             self.context.as_mut().unwrap().sspi_context.query_context_names(),
                             ts_request
@@ -468,11 +503,18 @@ impl<C: CredentialsProxy<AuthenticationData = AuthIdentity>> CredSspServer<C> {
                             .sspi_context
                             .custom_set_auth_identity(auth_data);
 
+                        let mut final_token = [SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
                         try_cred_ssp_server!(
-                            self.context.as_mut().unwrap().sspi_context.complete_auth_token(&mut []),
+                            self.context
+                                .as_mut()
+                                .unwrap()
+                                .sspi_context
+                                .complete_auth_token(&mut final_token),
                             ts_request
                         );
-                        ts_request.nego_tokens = None;
+                        // the final SPNEGO token carries the mechListMIC of the server
+                        let [final_token] = final_token;
+                        ts_request.nego_tokens = Some(final_token.buffer).filter(|token| !token.is_empty());
 
                         let pub_key_auth = try_cred_ssp_server!(
                             ts_request.pub_key_auth.take().ok_or_else(|| {
@@ -527,6 +569,7 @@ impl<C: CredentialsProxy<AuthenticationData = AuthIdentity>> CredSspServer<C> {
 enum SspiContext {
     Ntlm(Ntlm),
     Kerberos(Kerberos),
+    Negotiate(Negotiate),
 }
 
 impl SspiImpl for SspiContext {
@@ -539,7 +582,28 @@ impl SspiImpl for SspiContext {
     ) -> sspi::Result<AcquireCredentialsHandleResult<Self::CredentialsHandle>> {
         match self {
             SspiContext::Ntlm(ntlm) => builder.transform(ntlm).execute(),
-            SspiContext::Kerberos(kerberos) => builder.transform(kerberos).execute(),
+            SspiContext::Kerberos(kerberos) => {
+                let auth_data = builder.auth_data.cloned().map(Credentials::from);
+                let result = builder
+                    .transform_with_auth_data(kerberos, auth_data.as_ref())
+                    .execute()?;
+
+                Ok(AcquireCredentialsHandleResult {
+                    credentials_handle: result.credentials_handle.and_then(CredentialsBuffers::auth_identity),
+                    expiry: result.expiry,
+                })
+            }
+            SspiContext::Negotiate(negotiate) => {
+                let auth_data = builder.auth_data.cloned().map(Credentials::from);
+                let result = builder
+                    .transform_with_auth_data(negotiate, auth_data.as_ref())
+                    .execute()?;
+
+                Ok(AcquireCredentialsHandleResult {
+                    credentials_handle: result.credentials_handle.and_then(CredentialsBuffers::auth_identity),
+                    expiry: result.expiry,
+                })
+            }
         }
     }
 
@@ -549,7 +613,24 @@ impl SspiImpl for SspiContext {
     ) -> sspi::Result<InitializeSecurityContextResult> {
         match self {
             SspiContext::Ntlm(ntlm) => builder.transform(ntlm).execute(),
-            SspiContext::Kerberos(kerberos) => builder.transform(kerberos).execute(),
+            SspiContext::Kerberos(kerberos) => {
+                let mut credentials_handle = builder
+                    .credentials_handle
+                    .as_ref()
+                    .map(|handle| handle.as_ref().cloned().map(CredentialsBuffers::from));
+                builder
+                    .transform_with_credentials_handle(kerberos, credentials_handle.as_mut())
+                    .execute()
+            }
+            SspiContext::Negotiate(negotiate) => {
+                let mut credentials_handle = builder
+                    .credentials_handle
+                    .as_ref()
+                    .map(|handle| handle.as_ref().cloned().map(CredentialsBuffers::from));
+                builder
+                    .transform_with_credentials_handle(negotiate, credentials_handle.as_mut())
+                    .execute()
+            }
         }
     }
 
@@ -559,7 +640,24 @@ impl SspiImpl for SspiContext {
     ) -> sspi::Result<AcceptSecurityContextResult> {
         match self {
             SspiContext::Ntlm(ntlm) => builder.transform(ntlm).execute(),
-            SspiContext::Kerberos(kerberos) => builder.transform(kerberos).execute(),
+            SspiContext::Kerberos(kerberos) => {
+                let mut credentials_handle = builder
+                    .credentials_handle
+                    .as_ref()
+                    .map(|handle| handle.as_ref().cloned().map(CredentialsBuffers::from));
+                builder
+                    .transform_with_credentials_handle(kerberos, credentials_handle.as_mut())
+                    .execute()
+            }
+            SspiContext::Negotiate(negotiate) => {
+                let mut credentials_handle = builder
+                    .credentials_handle
+                    .as_ref()
+                    .map(|handle| handle.as_ref().cloned().map(CredentialsBuffers::from));
+                builder
+                    .transform_with_credentials_handle(negotiate, credentials_handle.as_mut())
+                    .execute()
+            }
         }
     }
 }
@@ -569,6 +667,7 @@ impl Sspi for SspiContext {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.complete_auth_token(token),
             SspiContext::Kerberos(kerberos) => kerberos.complete_auth_token(token),
+            SspiContext::Negotiate(negotiate) => negotiate.complete_auth_token(token),
         }
     }
 
@@ -581,6 +680,7 @@ impl Sspi for SspiContext {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.encrypt_message(flags, message, sequence_number),
             SspiContext::Kerberos(kerberos) => kerberos.encrypt_message(flags, message, sequence_number),
+            SspiContext::Negotiate(negotiate) => negotiate.encrypt_message(flags, message, sequence_number),
         }
     }
 
@@ -592,6 +692,23 @@ impl Sspi for SspiContext {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.decrypt_message(message, sequence_number),
             SspiContext::Kerberos(kerberos) => kerberos.decrypt_message(message, sequence_number),
+            SspiContext::Negotiate(negotiate) => negotiate.decrypt_message(message, sequence_number),
+        }
+    }
+
+    fn make_signature(&mut self, flags: u32, message: &mut [SecurityBuffer], sequence_number: u32) -> sspi::Result<()> {
+        match self {
+            SspiContext::Ntlm(ntlm) => ntlm.make_signature(flags, message, sequence_number),
+            SspiContext::Kerberos(kerberos) => kerberos.make_signature(flags, message, sequence_number),
+            SspiContext::Negotiate(negotiate) => negotiate.make_signature(flags, message, sequence_number),
+        }
+    }
+
+    fn verify_signature(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> sspi::Result<u32> {
+        match self {
+            SspiContext::Ntlm(ntlm) => ntlm.verify_signature(message, sequence_number),
+            SspiContext::Kerberos(kerberos) => kerberos.verify_signature(message, sequence_number),
+            SspiContext::Negotiate(negotiate) => negotiate.verify_signature(message, sequence_number),
         }
     }
 
@@ -599,24 +716,35 @@ impl Sspi for SspiContext {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.query_context_sizes(),
             SspiContext::Kerberos(kerberos) => kerberos.query_context_sizes(),
+            SspiContext::Negotiate(negotiate) => negotiate.query_context_sizes(),
         }
     }
     fn query_context_names(&mut self) -> sspi::Result<ContextNames> {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.query_context_names(),
             SspiContext::Kerberos(kerberos) => kerberos.query_context_names(),
+            SspiContext::Negotiate(negotiate) => negotiate.query_context_names(),
         }
     }
     fn query_context_package_info(&mut self) -> sspi::Result<PackageInfo> {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.query_context_package_info(),
             SspiContext::Kerberos(kerberos) => kerberos.query_context_package_info(),
+            SspiContext::Negotiate(negotiate) => negotiate.query_context_package_info(),
         }
     }
     fn query_context_cert_trust_status(&mut self) -> sspi::Result<CertTrustStatus> {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.query_context_cert_trust_status(),
             SspiContext::Kerberos(kerberos) => kerberos.query_context_cert_trust_status(),
+            SspiContext::Negotiate(negotiate) => negotiate.query_context_cert_trust_status(),
+        }
+    }
+    fn query_context_pac(&mut self) -> sspi::Result<Pac> {
+        match self {
+            SspiContext::Ntlm(ntlm) => ntlm.query_context_pac(),
+            SspiContext::Kerberos(kerberos) => kerberos.query_context_pac(),
+            SspiContext::Negotiate(negotiate) => negotiate.query_context_pac(),
         }
     }
 }
@@ -625,7 +753,8 @@ impl SspiEx for SspiContext {
     fn custom_set_auth_identity(&mut self, identity: Self::AuthenticationData) {
         match self {
             SspiContext::Ntlm(ntlm) => ntlm.custom_set_auth_identity(identity),
-            SspiContext::Kerberos(kerberos) => kerberos.custom_set_auth_identity(identity),
+            SspiContext::Kerberos(kerberos) => kerberos.custom_set_auth_identity(identity.into()),
+            SspiContext::Negotiate(negotiate) => negotiate.custom_set_auth_identity(identity.into()),
         }
     }
 }
@@ -732,6 +861,11 @@ impl CredSspContext {
                 }
             }
             SspiContext::Kerberos(_) => {}
+            SspiContext::Negotiate(ref negotiate) => {
+                if endpoint == EndpointType::Server && negotiate.negotiated_package() == Some(ntlm::PKG_NAME) {
+                    integer_increment_le(&mut public_key);
+                }
+            }
         };
 
         self.encrypt_message(&public_key)
@@ -1,144 +1,1674 @@
+pub mod ccache;
 mod client;
 pub mod config;
+mod credentials;
+mod crypto;
+mod data_types;
 mod encryption_params;
+mod error;
+mod fast;
+pub mod kdc_locator;
+pub mod keytab;
+mod kpasswd;
 pub mod network_client;
+pub mod pac;
+pub mod pkinit;
+mod rc4_tokens;
+mod s4u;
 mod server;
+#[cfg(test)]
+pub(crate) mod test;
 mod utils;
 
 use std::fmt::Debug;
 use std::io::Write;
 
-use kerberos_crypto::new_kerberos_cipher;
+use chrono::{DateTime, Duration, Utc};
+use kerberos_constants::error_codes::{KDC_ERR_PREAUTH_REQUIRED, KDC_ERR_WRONG_REALM, KRB_AP_ERR_SKEW};
+use kerberos_constants::key_usages::{
+    KEY_USAGE_TGS_REP_ENC_PART_AUTHEN_SUBKEY, KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY,
+};
 use lazy_static::lazy_static;
-use picky_krb::constants::key_usages::ACCEPTOR_SIGN;
-use picky_krb::data_types::{KrbResult, ResultExt};
-use picky_krb::gss_api::{NegTokenTarg1, WrapToken};
-use picky_krb::messages::{ApReq, AsRep, TgsRep};
+use oid::ObjectIdentifier;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag3, ExplicitContextTag4, ExplicitContextTag6,
+    IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_asn1_x509::oids::{KRB5, KRB5_USER_TO_USER};
+use picky_krb::constants::gss_api::TGT_REQ_TOKEN_ID;
+use picky_krb::constants::key_usages::{ACCEPTOR_SIGN, INITIATOR_SIGN};
+use picky_krb::constants::types::{NT_SRV_INST, PA_ENC_TIMESTAMP};
+use picky_krb::data_types::{EncryptionKey, KrbResult, PaData, PrincipalName, ResultExt, Ticket, TicketInner};
+use picky_krb::gss_api::{MechTypeList, NegTokenTarg1, WrapToken};
+use picky_krb::messages::{ApRep, ApReq, AsRep, AsReq, KdcReqBody, TgsRep, TgtReq};
 use rand::rngs::OsRng;
 use rand::Rng;
+use subtle::ConstantTimeEq;
+use url::Url;
 
+use self::ccache::CachedCredentials;
 use self::client::extractors::{
-    extract_encryption_params_from_as_rep, extract_session_key_from_as_rep, extract_session_key_from_tgs_rep,
+    extract_enc_as_rep_part, extract_enc_tgs_rep_part, extract_encryption_params_from_as_rep, extract_etype_info,
 };
 use self::client::generators::{
-    generate_ap_req, generate_as_req, generate_authenticator_for_ap_req, generate_authenticator_for_tgs_ap_req,
-    generate_neg_ap_req, generate_neg_token_init, generate_tgs_req,
+    generate_ap_req, generate_as_req, generate_as_req_body, generate_as_req_with_pa_datas,
+    generate_authenticator_for_ap_req, generate_authenticator_for_tgs_ap_req, generate_krb_cred, generate_neg_ap_req,
+    generate_neg_token_init, generate_pa_pac_request, generate_tgs_req, DEFAULT_AP_REQ_OPTIONS,
+    DEFAULT_TGS_REQ_OPTIONS, FORWARDED_TGT_REQ_OPTIONS, GSS_C_CONF_FLAG, GSS_C_DCE_STYLE, GSS_C_DELEG_FLAG,
+    GSS_C_INTEG_FLAG, GSS_C_MUTUAL_FLAG, GSS_C_REPLAY_FLAG, GSS_C_SEQUENCE_FLAG, MUTUAL_REQUIRED_AP_OPTION,
+    S4U2PROXY_TGS_REQ_OPTIONS, S4U2SELF_TGS_REQ_OPTIONS, USER_TO_USER_AP_REQ_OPTIONS, USER_TO_USER_TGS_REQ_OPTIONS,
+};
+pub use self::client::{
+    AES128_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA256_128, AES256_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA384_192, RC4_HMAC,
+    SUPPORTED_ENCRYPTION_TYPES,
 };
-use self::client::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96};
 use self::config::{KdcType, KerberosConfig};
+pub use self::credentials::{Credentials, CredentialsBuffers, Principal};
+use self::crypto::new_cipher;
 use self::encryption_params::EncryptionParams;
-use self::server::extractors::extract_tgt_ticket;
-use self::utils::{serialize_message, utf16_bytes_to_utf8_string};
-use crate::sspi::kerberos::client::extractors::extract_salt_from_krb_error;
+pub use self::error::KerberosError;
+use self::fast::FastArmor;
+use self::keytab::Keytab;
+pub use self::kpasswd::{KpasswdResult, PasswordPolicy};
+use self::pac::{extract_pac, Pac};
+use self::pkinit::DhKeyPair;
+pub use self::pkinit::{PkInitCredentials, PkInitSigner, PrivateKeySigner};
+use self::server::extractors::{
+    extract_authenticator, extract_context_flags, extract_delegated_credentials, extract_enc_ticket_part,
+    extract_initiator_message, extract_tgt_ticket,
+};
+use self::server::generators::{
+    generate_acceptor_sub_key, generate_ap_rep, generate_final_neg_token_resp, generate_krb_ap_rep_token,
+    generate_krb_tgt_rep_token, generate_neg_ap_rep, generate_neg_tgt_rep,
+};
+pub use self::server::{ReplayCache, ServerProperties, ServiceKey};
+use self::utils::{integer_to_u32, serialize_message, unframe_kdc_reply, utf16_bytes_to_utf8_string};
+use crate::sspi::kerberos::client::extractors::{extract_method_data, EtypeInfo};
 use crate::sspi::kerberos::client::generators::{
-    generate_as_req_without_pre_auth, generate_final_neg_token_targ, get_mech_list,
+    generate_as_req_without_pre_auth, generate_final_neg_token_targ, get_client_principal_realm, get_mech_list,
 };
 use crate::sspi::kerberos::server::extractors::{
-    extract_ap_rep_from_neg_token_targ, extract_sub_session_key_from_ap_rep,
+    extract_ap_rep_from_neg_token_targ, extract_enc_ap_rep_part, extract_sub_session_key_from_ap_rep,
 };
-use crate::sspi::kerberos::utils::{generate_initiator_raw, validate_mic_token};
-use crate::sspi::ntlm::AuthIdentityBuffers;
+use crate::sspi::kerberos::utils::{generate_acceptor_raw, generate_initiator_raw, validate_mic_token_with_payload};
+pub use crate::sspi::negotiate::PACKAGE_INFO as NEGO_PACKAGE_INFO;
 use crate::sspi::{self, Error, ErrorKind, Result, Sspi, SspiEx, SspiImpl, PACKAGE_ID_NONE};
 use crate::{
-    AcceptSecurityContextResult, AcquireCredentialsHandleResult, AuthIdentity, ClientResponseFlags, ContextNames,
-    ContextSizes, CredentialUse, DecryptionFlags, InitializeSecurityContextResult, PackageCapabilities, PackageInfo,
-    SecurityBuffer, SecurityBufferType, SecurityPackageType, SecurityStatus, ServerResponseFlags,
+    AcceptSecurityContextResult, AcquireCredentialsHandleResult, AuthIdentity, AuthIdentityBuffers, ClientRequestFlags,
+    ClientResponseFlags, ContextNames, ContextSizes, CredentialUse, DecryptionFlags, EncryptionFlags,
+    InitializeSecurityContextResult, PackageCapabilities, PackageInfo, SecurityBuffer, SecurityBufferType,
+    SecurityPackageType, SecurityStatus, ServerResponseFlags,
 };
 
-pub const PKG_NAME: &str = "Kerberos";
-pub const KERBEROS_VERSION: u8 = 0x05;
-pub const TGT_SERVICE_NAME: &str = "krbtgt";
-pub const SERVICE_NAME: &str = "TERMSRV";
+pub const PKG_NAME: &str = "Kerberos";
+pub const KERBEROS_VERSION: u8 = 0x05;
+pub const TGT_SERVICE_NAME: &str = "krbtgt";
+pub const KPASSWD_SERVICE_NAME: &str = "kadmin/changepw";
+
+const SSPI_KDC_URL_ENV: &str = "SSPI_KDC_URL";
+
+const DEFAULT_ENCRYPTION_TYPE: i32 = AES256_CTS_HMAC_SHA1_96;
+
+// MIC token header len
+const MIC_TOKEN_HEADER_LEN: usize = 16;
+// AES confounder len
+const CONFOUNDER_SIZE: usize = 16;
+// [RFC 4121 4.2.2](https://datatracker.ietf.org/doc/html/rfc4121#section-4.2.2)
+const SENT_BY_ACCEPTOR_FLAG: u8 = 0x01;
+const SEALED_FLAG: u8 = 0x02;
+const ACCEPTOR_SUBKEY_FLAG: u8 = 0x04;
+// [RFC 4120 5.3](https://www.rfc-editor.org/rfc/rfc4120#section-5.3): the forwardable flag of the ticket
+const FORWARDABLE_TICKET_FLAG: u32 = 0x4000_0000;
+// [RFC 4120 5.5.1](https://www.rfc-editor.org/rfc/rfc4120#section-5.5.1): the use-session-key AP option
+const USE_SESSION_KEY_AP_OPTION: u8 = 0x40;
+const KPASSWD_AP_REQ_OPTIONS: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
+// [RFC 4121 4.1.1.1](https://www.rfc-editor.org/rfc/rfc4121#section-4.1.1.1): the context flags of the authenticator
+// checksum, the SSPI flags which request them and the SSPI flags which report them
+const CONTEXT_FLAGS: [(u32, ClientRequestFlags, ClientResponseFlags, ServerResponseFlags); 7] = [
+    (
+        GSS_C_DELEG_FLAG,
+        ClientRequestFlags::DELEGATE,
+        ClientResponseFlags::DELEGATE,
+        ServerResponseFlags::DELEGATE,
+    ),
+    (
+        GSS_C_MUTUAL_FLAG,
+        ClientRequestFlags::MUTUAL_AUTH,
+        ClientResponseFlags::MUTUAL_AUTH,
+        ServerResponseFlags::MUTUAL_AUTH,
+    ),
+    (
+        GSS_C_REPLAY_FLAG,
+        ClientRequestFlags::REPLAY_DETECT,
+        ClientResponseFlags::REPLAY_DETECT,
+        ServerResponseFlags::REPLAY_DETECT,
+    ),
+    (
+        GSS_C_SEQUENCE_FLAG,
+        ClientRequestFlags::SEQUENCE_DETECT,
+        ClientResponseFlags::SEQUENCE_DETECT,
+        ServerResponseFlags::SEQUENCE_DETECT,
+    ),
+    (
+        GSS_C_CONF_FLAG,
+        ClientRequestFlags::CONFIDENTIALITY,
+        ClientResponseFlags::CONFIDENTIALITY,
+        ServerResponseFlags::CONFIDENTIALITY,
+    ),
+    (
+        GSS_C_INTEG_FLAG,
+        ClientRequestFlags::INTEGRITY,
+        ClientResponseFlags::INTEGRITY,
+        ServerResponseFlags::INTEGRITY,
+    ),
+    (
+        GSS_C_DCE_STYLE,
+        ClientRequestFlags::USE_DCE_STYLE,
+        ClientResponseFlags::USED_DCE_STYLE,
+        ServerResponseFlags::USED_DCE_STYLE,
+    ),
+];
+
+lazy_static! {
+    pub static ref PACKAGE_INFO: PackageInfo = PackageInfo {
+        capabilities: PackageCapabilities::empty(),
+        rpc_id: PACKAGE_ID_NONE,
+        max_token_len: 0xbb80, // 48 000 bytes: default maximum token len in Windows
+        name: SecurityPackageType::Kerberos,
+        comment: String::from("Kerberos Security Package"),
+    };
+}
+
+#[derive(Debug, Clone)]
+pub enum KerberosState {
+    Negotiate,
+    Preauthentication,
+    ApExchange,
+    Established,
+}
+
+#[derive(Debug, Clone)]
+pub struct Kerberos {
+    state: KerberosState,
+    config: KerberosConfig,
+    credentials: Option<CredentialsBuffers>,
+    encryption_params: EncryptionParams,
+    seq_number: u32,
+    // the next expected sequence number of the peer
+    peer_seq_number: Option<u32>,
+    server: Option<ServerProperties>,
+    // the difference between the clock of the KDC and the local clock
+    time_offset: Duration,
+    // the context flags requested by the initiator and granted by the acceptor
+    context_flags: u32,
+    // the mechanisms proposed by the initiator in the NegTokenInit and protected by the mechListMIC
+    mech_types: MechTypeList,
+    // the TGT of the client is requested once per context
+    tgt: Option<CachedCredentials>,
+    // the wrap tokens are protected with the subkey of the acceptor, otherwise with the subkey of the initiator
+    has_acceptor_subkey: bool,
+}
+
+impl Kerberos {
+    pub fn new_client_from_config(config: KerberosConfig) -> Result<Self> {
+        let encryption_params = EncryptionParams {
+            permitted_encryption_types: permitted_encryption_types(&config)?,
+            ..EncryptionParams::default_for_client()
+        };
+
+        Ok(Self {
+            state: KerberosState::Negotiate,
+            config,
+            credentials: None,
+            encryption_params,
+            seq_number: OsRng::new()?.gen::<u32>(),
+            peer_seq_number: None,
+            server: None,
+            time_offset: Duration::zero(),
+            context_flags: 0,
+            mech_types: get_mech_list(),
+            tgt: None,
+            has_acceptor_subkey: false,
+        })
+    }
+
+    pub fn new_server_from_config(config: KerberosConfig) -> Result<Self> {
+        Self::new_server_from_config_with_properties(config, ServerProperties::default())
+    }
+
+    pub fn new_server_from_config_with_properties(
+        config: KerberosConfig,
+        server_properties: ServerProperties,
+    ) -> Result<Self> {
+        let encryption_params = EncryptionParams {
+            permitted_encryption_types: permitted_encryption_types(&config)?,
+            ..EncryptionParams::default_for_server()
+        };
+
+        Ok(Self {
+            state: KerberosState::Negotiate,
+            config,
+            credentials: None,
+            encryption_params,
+            seq_number: OsRng::new()?.gen::<u32>(),
+            peer_seq_number: None,
+            server: Some(server_properties),
+            time_offset: Duration::zero(),
+            context_flags: 0,
+            mech_types: get_mech_list(),
+            tgt: None,
+            has_acceptor_subkey: false,
+        })
+    }
+
+    /// Credentials the client delegated to the service, usually its forwarded TGT.
+    /// They can be stored in the credentials cache of the client context to authenticate to other services
+    /// on behalf of the client
+    pub fn delegated_credentials(&self) -> &[CachedCredentials] {
+        self.server
+            .as_ref()
+            .map(|server| server.delegated_credentials.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Sets the mechanisms proposed by the initiator, e.g. with NTLM after Kerberos when Kerberos
+    /// is negotiated by the Negotiate package
+    pub(crate) fn set_mech_types(&mut self, mech_types: MechTypeList) {
+        self.mech_types = mech_types;
+    }
+
+    pub fn next_seq_number(&mut self) -> u32 {
+        self.seq_number = self.seq_number.wrapping_add(1);
+        self.seq_number
+    }
+
+    // the AP-REQ and the AP-REP are sent without GSS-API framing and the initiator answers the AP-REP
+    fn is_dce_style(&self) -> bool {
+        self.context_flags & GSS_C_DCE_STYLE != 0
+    }
+
+    // the local time adjusted to the clock of the KDC
+    fn current_time(&self) -> DateTime<Utc> {
+        Utc::now() + self.time_offset
+    }
+
+    // the request rejected because of the clock skew is retried once with the time of the KDC
+    fn with_skew_retry<T>(&mut self, mut request: impl FnMut(&mut Self) -> Result<T>) -> Result<T> {
+        match request(self) {
+            Err(Error {
+                kerberos_error: Some(error),
+                ..
+            }) if error.error_code == KRB_AP_ERR_SKEW => {
+                self.time_offset = error.server_time - Utc::now();
+
+                request(self)
+            }
+            result => result,
+        }
+    }
+
+    fn check_established(&self) -> Result<()> {
+        match self.state {
+            KerberosState::Established => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::OutOfSequence,
+                "Kerberos context is not established".into(),
+            )),
+        }
+    }
+
+    // [RFC 4121 4.2.6](https://datatracker.ietf.org/doc/html/rfc4121#section-4.2.6): tokens with skipped
+    // sequence numbers are accepted, but replayed and out-of-order tokens are rejected
+    fn check_peer_seq_number(&mut self, seq_number: u64) -> Result<()> {
+        let seq_number = seq_number as u32;
+
+        if let Some(expected) = self.peer_seq_number {
+            if seq_number.wrapping_sub(expected) > u32::MAX / 2 {
+                return Err(Error::new(
+                    ErrorKind::OutOfSequence,
+                    format!(
+                        "The message is replayed or out of order: got sequence number {}, expected {}",
+                        seq_number, expected
+                    ),
+                ));
+            }
+        }
+
+        self.peer_seq_number = Some(seq_number.wrapping_add(1));
+
+        Ok(())
+    }
+
+    /// Obtains the TGT of the client before the first token, e.g. to fall back to NTLM if the KDC does not reply.
+    /// The TGT is reused by the following exchanges of the context
+    pub(crate) fn request_initial_tgt(&mut self, credentials: &CredentialsBuffers) -> Result<()> {
+        let (username, domain) = client_principal(credentials)?;
+        self.tgt(credentials, &username, &domain)?;
+
+        Ok(())
+    }
+
+    // tries the KDCs of the realm one by one until one of them replies
+    fn send(&self, realm: &str, data: &[u8]) -> Result<Vec<u8>> {
+        self.send_to(&self.config.kdc_locator.locate(realm)?, realm, data)
+    }
+
+    fn send_to(&self, kdc_urls: &[Url], realm: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let mut error = None;
+        for kdc_url in kdc_urls {
+            let result = match KdcType::from_url(kdc_url) {
+                KdcType::Kdc => self.config.network_client.send(kdc_url, data),
+                KdcType::KdcProxy => self
+                    .config
+                    .network_client
+                    .send_http(kdc_url, data, Some(realm.to_owned())),
+            };
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(err) => error = Some(err),
+            }
+        }
+
+        // any reply of the KDC is returned, so the errors mean that none of the KDCs is reachable
+        Err(match error {
+            Some(error) => Error::new(
+                ErrorKind::NoAuthenticatingAuthority,
+                format!("No KDC of the {} realm replied: {}", realm, error.description),
+            ),
+            None => Error::new(ErrorKind::NoAuthenticatingAuthority, "No KDC is available".into()),
+        })
+    }
+
+    // credentials with session keys of not permitted encryption types are not reused
+    fn cached_credentials(&self, client: &Principal, server: &Principal) -> Result<Option<CachedCredentials>> {
+        match self.config.credentials_cache {
+            Some(ref credentials_cache) => Ok(credentials_cache.get(client, server)?.filter(|credentials| {
+                self.encryption_params
+                    .check_permitted(credentials.encryption_type)
+                    .is_ok()
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn cache_credentials(&self, credentials: &CachedCredentials) -> Result<()> {
+        match self.config.credentials_cache {
+            Some(ref credentials_cache) => credentials_cache.store(credentials.clone()),
+            None => Ok(()),
+        }
+    }
+
+    // the cached TGT of the client or the new one
+    fn tgt(&mut self, credentials: &CredentialsBuffers, username: &str, domain: &str) -> Result<CachedCredentials> {
+        if let Some(ref tgt) = self.tgt {
+            return Ok(tgt.clone());
+        }
+
+        let client = Principal::new(username, domain);
+        let tgt = match self.cached_credentials(&client, &tgt_principal(domain))? {
+            Some(tgt) => tgt,
+            None => {
+                let tgt = self.with_skew_retry(|kerberos| kerberos.request_tgt(credentials, username, domain))?;
+                self.cache_credentials(&tgt)?;
+
+                tgt
+            }
+        };
+        self.tgt = Some(tgt.clone());
+
+        Ok(tgt)
+    }
+
+    fn request_tgt(
+        &mut self,
+        credentials: &CredentialsBuffers,
+        username: &str,
+        domain: &str,
+    ) -> Result<CachedCredentials> {
+        self.request_initial_ticket(credentials, username, domain, None)
+    }
+
+    // AS exchange: [RFC 4120 3.1](https://www.rfc-editor.org/rfc/rfc4120#section-3.1)
+    //
+    // the first request is sent without the pre-authentication: the KDC either issues the ticket or replies with
+    // KDC_ERR_PREAUTH_REQUIRED listing the accepted pre-authentication methods and the salts of the client keys.
+    // The initial ticket is issued for the TGS of the client realm unless the other service is specified
+    fn request_initial_ticket(
+        &mut self,
+        credentials: &CredentialsBuffers,
+        username: &str,
+        domain: &str,
+        service: Option<&Principal>,
+    ) -> Result<CachedCredentials> {
+        if let CredentialsBuffers::PkInit(credentials) = credentials {
+            return self.request_tgt_with_certificate(credentials, username, domain, service);
+        }
+
+        if let CredentialsBuffers::Keytab(keytab) = credentials {
+            self.encryption_params.encryption_type = Some(keytab_encryption_type(
+                keytab,
+                username,
+                domain,
+                &self.encryption_params.permitted_encryption_types,
+            )?);
+        }
+
+        // the armor is made once for the exchange: the KDC binds the PA-FX-COOKIE to it
+        let armor = match &self.config.armor_ticket {
+            Some(armor_ticket) => Some(FastArmor::from_ticket(armor_ticket, self.current_time())?),
+            None => None,
+        };
+
+        let mut domain = domain.to_owned();
+        let mut default_salt = format!("{}{}", domain, username);
+        let mut pre_auth: Option<PreAuth> = None;
+        let mut referrals = 0;
+
+        let (mut as_rep, nonce) = loop {
+            let mut as_req = match &pre_auth {
+                Some(pre_auth) => self.generate_pre_auth_as_req(username, &domain, pre_auth, armor.as_ref())?,
+                None => generate_as_req_without_pre_auth(username, &domain, &self.encryption_params)?,
+            };
+            set_as_req_service(&mut as_req.0.req_body.0, service)?;
+            let nonce = integer_to_u32(&as_req.0.req_body.0.nonce.0);
+            if let Some(armor) = &armor {
+                armor.armor_request(&mut as_req.0)?;
+            }
+
+            let response = self.send(
+                &get_client_principal_realm(username, &domain),
+                &serialize_message(&as_req)?,
+            )?;
+
+            let mut d = picky_asn1_der::Deserializer::new_from_bytes(unframe_kdc_reply(&response)?);
+            let as_rep: KrbResult<AsRep> = KrbResult::deserialize(&mut d)?;
+            let error = match as_rep {
+                Ok(as_rep) => break (as_rep, nonce),
+                Err(error) => error,
+            };
+
+            let (error, pa_datas) = match &armor {
+                Some(armor) => armor.unwrap_error(&error, nonce)?,
+                None => {
+                    let pa_datas = extract_method_data(&error);
+
+                    (error, pa_datas)
+                }
+            };
+
+            let error = KerberosError::from(&error);
+
+            // the pre-authentication is required only once, the KDC rejects the invalid pre-authentication
+            // with the KDC_ERR_PREAUTH_FAILED error
+            match (error.error_code, &error.client_realm) {
+                (KDC_ERR_PREAUTH_REQUIRED, _) if pre_auth.is_none() => {
+                    pre_auth = Some(self.pre_auth(credentials, &pa_datas, armor.is_some(), &default_salt)?);
+                }
+                // the client belongs to the other realm: [RFC 6806 7](https://www.rfc-editor.org/rfc/rfc6806#section-7)
+                (KDC_ERR_WRONG_REALM, Some(client_realm)) if !client_realm.eq_ignore_ascii_case(&domain) => {
+                    referrals += 1;
+                    if referrals > self.config.max_referrals {
+                        return Err(max_referrals_exceeded(self.config.max_referrals));
+                    }
+
+                    domain = client_realm.clone();
+                    default_salt = format!("{}{}", domain, username);
+                    pre_auth = None;
+                }
+                _ => return Err(Error::from(error)),
+            }
+        };
+
+        let fast_response = match &armor {
+            Some(armor) => {
+                let padata = as_rep.0.padata.0.as_ref().map(|padata| padata.0 .0.as_slice());
+                let fast_response = armor.unwrap_response(padata.unwrap_or_default(), nonce)?;
+
+                if let Some(pre_auth) = &pre_auth {
+                    armor.check_kdc_challenge(&fast_response.padata.0 .0, &pre_auth.key, pre_auth.encryption_type)?;
+                }
+
+                // the client name of the armored reply is authenticated by the KrbFastFinished
+                let finished = armor.check_finished(&fast_response, &as_rep.0.ticket.0)?;
+                as_rep.0.crealm = ExplicitContextTag3::from(finished.crealm.0.clone());
+                as_rep.0.cname = ExplicitContextTag4::from(finished.cname.0.clone());
+
+                Some(fast_response)
+            }
+            None => None,
+        };
+
+        let (encryption_type, etype_info) = extract_encryption_params_from_as_rep(&as_rep)?;
+        self.encryption_params.check_permitted(encryption_type)?;
+        self.encryption_params.encryption_type = Some(encryption_type);
+
+        // the padata of the armored reply is encrypted in the KrbFastResponse
+        let etype_info = match &fast_response {
+            Some(fast_response) => extract_etype_info(&fast_response.padata.0 .0)?
+                .into_iter()
+                .find(|etype_info| etype_info.encryption_type == encryption_type),
+            None => etype_info,
+        };
+
+        // the KDC can omit the PA-ETYPE-INFO2 when the pre-authentication key is used for the reply
+        let key = match pre_auth {
+            Some(pre_auth)
+                if encryption_type == pre_auth.encryption_type
+                    && (etype_info.is_none() || etype_info == pre_auth.etype_info) =>
+            {
+                pre_auth.key
+            }
+            _ => client_key(credentials, encryption_type, etype_info.as_ref(), &default_salt)?,
+        };
+        let key = match &fast_response {
+            Some(fast_response) => fast::strengthen_reply_key(fast_response, key, encryption_type)?,
+            None => key,
+        };
+        let enc_as_rep_part = extract_enc_as_rep_part(&as_rep, &key, &self.encryption_params)?;
+
+        let tgt = CachedCredentials::from_kdc_rep(&as_rep.0, &enc_as_rep_part.0)?;
+        self.encryption_params.check_permitted(tgt.encryption_type)?;
+
+        Ok(tgt)
+    }
+
+    // chooses the client key for the pre-authentication methods of the KDC_ERR_PREAUTH_REQUIRED error
+    fn pre_auth(
+        &mut self,
+        credentials: &CredentialsBuffers,
+        pa_datas: &[PaData],
+        is_armored: bool,
+        default_salt: &str,
+    ) -> Result<PreAuth> {
+        // the armored request proves the knowledge of the key with the encrypted challenge instead of the timestamp
+        let (method, method_name): (&[u8], _) = if is_armored {
+            (&fast::PA_ENCRYPTED_CHALLENGE, "encrypted challenge")
+        } else {
+            (&PA_ENC_TIMESTAMP, "encrypted timestamp")
+        };
+        if !pa_datas.is_empty() && !pa_datas.iter().any(|pa_data| pa_data.padata_type.0 .0 == method) {
+            return Err(Error::new(
+                ErrorKind::UnsupportedPreAuth,
+                format!("The KDC does not accept the {} pre-authentication", method_name),
+            ));
+        }
+
+        let etype_infos = extract_etype_info(pa_datas)?;
+
+        // the keytab key is already chosen, the password key can be derived for the type preferred by the KDC
+        if let CredentialsBuffers::AuthIdentity(_) = credentials {
+            if let Some(etype_info) = etype_infos.iter().find(|etype_info| {
+                self.encryption_params
+                    .check_permitted(etype_info.encryption_type)
+                    .is_ok()
+            }) {
+                self.encryption_params.encryption_type = Some(etype_info.encryption_type);
+            }
+        }
+
+        let encryption_type = self.encryption_params.encryption_type();
+        let etype_info = etype_infos
+            .into_iter()
+            .find(|etype_info| etype_info.encryption_type == encryption_type);
+
+        Ok(PreAuth {
+            key: client_key(credentials, encryption_type, etype_info.as_ref(), default_salt)?,
+            encryption_type,
+            etype_info,
+            fx_cookie: fast::fx_cookie(pa_datas),
+        })
+    }
+
+    fn generate_pre_auth_as_req(
+        &self,
+        username: &str,
+        domain: &str,
+        pre_auth: &PreAuth,
+        armor: Option<&FastArmor>,
+    ) -> Result<AsReq> {
+        match armor {
+            Some(armor) => {
+                let mut pa_datas = vec![
+                    armor.client_challenge(&pre_auth.key, pre_auth.encryption_type, self.current_time())?,
+                    generate_pa_pac_request()?,
+                ];
+                pa_datas.extend(pre_auth.fx_cookie.clone());
+
+                Ok(generate_as_req_with_pa_datas(
+                    generate_as_req_body(username, domain, &self.encryption_params)?,
+                    pa_datas,
+                ))
+            }
+            None => generate_as_req(
+                username,
+                &pre_auth.key,
+                domain,
+                &self.encryption_params,
+                self.current_time(),
+            ),
+        }
+    }
+
+    // AS exchange with the PKINIT pre-authentication: [RFC 4556 3.2](https://www.rfc-editor.org/rfc/rfc4556#section-3.2)
+    fn request_tgt_with_certificate(
+        &mut self,
+        credentials: &PkInitCredentials,
+        username: &str,
+        domain: &str,
+        service: Option<&Principal>,
+    ) -> Result<CachedCredentials> {
+        let mut req_body = generate_as_req_body(username, domain, &self.encryption_params)?;
+        set_as_req_service(&mut req_body, service)?;
+        let nonce = integer_to_u32(&req_body.nonce.0);
+
+        let dh_key_pair = DhKeyPair::generate()?;
+        let pa_pk_as_req = pkinit::generate_pa_pk_as_req(
+            credentials.signer.as_ref(),
+            &req_body,
+            &dh_key_pair,
+            self.current_time(),
+        )?;
+
+        let as_req = generate_as_req_with_pa_datas(req_body, vec![pa_pk_as_req, generate_pa_pac_request()?]);
+
+        let realm = get_client_principal_realm(username, domain);
+        let response = self.send(&realm, &serialize_message(&as_req)?)?;
+
+        let mut d = picky_asn1_der::Deserializer::new_from_bytes(unframe_kdc_reply(&response)?);
+        let as_rep: KrbResult<AsRep> = KrbResult::deserialize(&mut d)?;
+        let as_rep = as_rep.map_err(pkinit::pk_init_error)?;
+
+        let encryption_type = integer_to_u32(&as_rep.0.enc_part.0.etype.0) as i32;
+        self.encryption_params.check_permitted(encryption_type)?;
+        self.encryption_params.encryption_type = Some(encryption_type);
+
+        let key = pkinit::extract_reply_key(
+            &as_rep,
+            &realm,
+            nonce,
+            &dh_key_pair,
+            &self.config.kdc_trust_anchors,
+            encryption_type,
+        )?;
+        let enc_as_rep_part = extract_enc_as_rep_part(&as_rep, &key, &self.encryption_params)?;
+
+        let tgt = CachedCredentials::from_kdc_rep(&as_rep.0, &enc_as_rep_part.0)?;
+        self.encryption_params.check_permitted(tgt.encryption_type)?;
+
+        Ok(tgt)
+    }
+
+    // TGS exchange: [RFC 4120 3.3](https://www.rfc-editor.org/rfc/rfc4120#section-3.3)
+    //
+    // the ticket of the service of the other realm is requested with the cross-realm TGT of that realm. The KDC
+    // which does not know the service refers the client to the other realm with the cross-realm TGT:
+    // [RFC 6806 8](https://www.rfc-editor.org/rfc/rfc6806#section-8)
+    fn request_service_ticket(
+        &mut self,
+        tgt: &CachedCredentials,
+        service: &Principal,
+        additional_ticket: Option<Ticket>,
+    ) -> Result<CachedCredentials> {
+        let mut tgt = tgt.clone();
+        let mut service_realm = service.realm.clone();
+
+        for _ in 0..=self.config.max_referrals {
+            let kdc_realm = tgt_realm(&tgt);
+            let is_service_request = kdc_realm.eq_ignore_ascii_case(&service_realm);
+            let server = if is_service_request {
+                service.name()
+            } else {
+                format!("{}/{}", TGT_SERVICE_NAME, service_realm)
+            };
+
+            // the user-to-user ticket is encrypted with the session key of the additional TGT of the service
+            let second_ticket = additional_ticket.clone().filter(|_| is_service_request);
+            let kdc_options = if second_ticket.is_some() {
+                USER_TO_USER_TGS_REQ_OPTIONS
+            } else {
+                DEFAULT_TGS_REQ_OPTIONS
+            };
+
+            let ticket = self.send_tgs_req(&tgt, &kdc_realm, &server, second_ticket, kdc_options, None)?;
+
+            if !is_service_request {
+                // the cross-realm TGT or the referral to the realm on the path to the service realm
+                if krbtgt_realm(&ticket.server).is_none() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidToken,
+                        format!("Expected the TGT, got the ticket of {}", ticket.server.name()),
+                    ));
+                }
+                tgt = ticket;
+                continue;
+            }
+
+            match referral_realm(&ticket, &server) {
+                Some(referral_realm) => {
+                    // the referral for the service tells its realm, the path to the realm is followed as is
+                    service_realm = referral_realm;
+                    tgt = ticket;
+                }
+                None => return Ok(ticket),
+            }
+        }
+
+        Err(max_referrals_exceeded(self.config.max_referrals))
+    }
+
+    fn send_tgs_req(
+        &mut self,
+        tgt: &CachedCredentials,
+        kdc_realm: &str,
+        service_principal: &str,
+        additional_ticket: Option<Ticket>,
+        kdc_options: [u8; 4],
+        s4u_user: Option<&Principal>,
+    ) -> Result<CachedCredentials> {
+        self.encryption_params.encryption_type = Some(tgt.encryption_type);
+
+        let mut authenticator = generate_authenticator_for_tgs_ap_req(
+            &tgt.client.principal_name()?,
+            &tgt.client.kerberos_realm()?,
+            self.current_time(),
+        )?;
+
+        // the TGS exchange is armored implicitly with the authenticator subkey, the reply is encrypted with it
+        let armor = match self.config.armor_ticket {
+            Some(_) => {
+                let subkey = crypto::generate_random_key(tgt.encryption_type)?;
+                authenticator.0.subkey = Optional::from(Some(ExplicitContextTag6::from(EncryptionKey {
+                    key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![tgt.encryption_type as u8])),
+                    key_value: ExplicitContextTag1::from(OctetStringAsn1::from(subkey.clone())),
+                })));
+
+                Some((
+                    FastArmor::from_tgs_subkey(tgt.encryption_type, &subkey, &tgt.key)?,
+                    subkey,
+                ))
+            }
+            None => None,
+        };
+
+        let mut tgs_req = generate_tgs_req(
+            kdc_realm,
+            service_principal,
+            &tgt.key,
+            tgt.decode_ticket()?,
+            &mut authenticator,
+            additional_ticket.map(|ticket| vec![ticket]),
+            kdc_options,
+            &self.encryption_params,
+        )?;
+        let nonce = integer_to_u32(&tgs_req.0.req_body.0.nonce.0);
+        if let (Some(user), Some(padata)) = (s4u_user, tgs_req.0.padata.0.as_mut()) {
+            padata.0 .0.push(s4u::generate_pa_for_user(user, &tgt.key)?);
+            padata.0 .0.push(s4u::generate_pa_s4u_x509_user(
+                user,
+                nonce,
+                &tgt.key,
+                tgt.encryption_type,
+            )?);
+        }
+        if let Some((armor, _)) = &armor {
+            armor.armor_request(&mut tgs_req.0)?;
+        }
+
+        let response = self.send(kdc_realm, &serialize_message(&tgs_req)?)?;
+
+        let mut d = picky_asn1_der::Deserializer::new_from_bytes(unframe_kdc_reply(&response)?);
+        let tgs_rep: KrbResult<TgsRep> = KrbResult::deserialize(&mut d)?;
+
+        let (tgs_rep, key, key_usage) = match (tgs_rep, armor) {
+            (Err(error), Some((armor, _))) => return Err(Error::from(armor.unwrap_error(&error, nonce)?.0)),
+            (tgs_rep, Some((armor, subkey))) => {
+                let tgs_rep = tgs_rep?;
+
+                let padata = tgs_rep.0.padata.0.as_ref().map(|padata| padata.0 .0.as_slice());
+                let fast_response = armor.unwrap_response(padata.unwrap_or_default(), nonce)?;
+                armor.check_finished(&fast_response, &tgs_rep.0.ticket.0)?;
+
+                let key = fast::strengthen_reply_key(&fast_response, subkey, tgt.encryption_type)?;
+
+                (tgs_rep, key, KEY_USAGE_TGS_REP_ENC_PART_AUTHEN_SUBKEY)
+            }
+            (tgs_rep, None) => (tgs_rep?, tgt.key.clone(), KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY),
+        };
+
+        let enc_tgs_rep_part = extract_enc_tgs_rep_part(&tgs_rep, &key, key_usage, &self.encryption_params)?;
+
+        let service_ticket = CachedCredentials::from_kdc_rep(&tgs_rep.0, &enc_tgs_rep_part.0)?;
+        self.encryption_params.check_permitted(service_ticket.encryption_type)?;
+
+        Ok(service_ticket)
+    }
+
+    // the TGT delegated to the service is requested with the forwardable TGT of the client:
+    // [RFC 4120 2.6](https://www.rfc-editor.org/rfc/rfc4120#section-2.6)
+    fn request_forwarded_tgt(&mut self, tgt: &CachedCredentials) -> Result<CachedCredentials> {
+        let realm = tgt_realm(tgt);
+
+        self.send_tgs_req(
+            tgt,
+            &realm,
+            &tgt_principal(&realm).name(),
+            None,
+            FORWARDED_TGT_REQ_OPTIONS,
+            None,
+        )
+    }
+
+    /// S4U2Self: requests the ticket to the service itself on behalf of the user.
+    /// The service authenticates with its own credentials, the password of the user is not needed.
+    /// The ticket is the evidence of the user authentication for [`Kerberos::request_s4u2proxy_ticket`]
+    pub fn request_s4u2self_ticket(&mut self, user: &Principal) -> Result<CachedCredentials> {
+        let credentials = self
+            .credentials
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "No credentials provided".to_owned()))?;
+        let (username, domain) = client_principal(&credentials)?;
+
+        let tgt = self.tgt(&credentials, &username, &domain)?;
+        let kdc_realm = tgt_realm(&tgt);
+
+        self.with_skew_retry(|kerberos| {
+            kerberos.send_tgs_req(
+                &tgt,
+                &kdc_realm,
+                &tgt.client.name(),
+                None,
+                S4U2SELF_TGS_REQ_OPTIONS,
+                Some(user),
+            )
+        })
+        .map_err(s4u::s4u_error)
+    }
+
+    /// S4U2Proxy: requests the ticket to the other service on behalf of the client of the evidence ticket,
+    /// e.g. of the S4U2Self ticket or of the ticket the client authenticated to this service with.
+    /// The KDC checks that the service is allowed to delegate to the other one
+    pub fn request_s4u2proxy_ticket(
+        &mut self,
+        evidence_ticket: &CachedCredentials,
+        service: &Principal,
+    ) -> Result<CachedCredentials> {
+        let credentials = self
+            .credentials
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "No credentials provided".to_owned()))?;
+        let (username, domain) = client_principal(&credentials)?;
+
+        let tgt = self.tgt(&credentials, &username, &domain)?;
+        let kdc_realm = tgt_realm(&tgt);
+        let evidence_ticket = evidence_ticket.decode_ticket()?;
+
+        self.with_skew_retry(|kerberos| {
+            kerberos.send_tgs_req(
+                &tgt,
+                &kdc_realm,
+                &service.name(),
+                Some(evidence_ticket.clone()),
+                S4U2PROXY_TGS_REQ_OPTIONS,
+                None,
+            )
+        })
+        .map_err(s4u::s4u_error)
+    }
+
+    /// Changes the password of the account authenticated with its current password:
+    /// [RFC 3244](https://www.rfc-editor.org/rfc/rfc3244). The expired password can be changed too,
+    /// the KDC issues the ticket of the password change service for it
+    pub fn change_password(&mut self, identity: &AuthIdentity, new_password: &str) -> Result<()> {
+        self.request_password_change(identity, new_password)?.check()
+    }
+
+    /// Changes the password like [Kerberos::change_password], but returns the result of the kpasswd server
+    /// even if the password is not changed, e.g. to show its text or the password policy to the user
+    pub fn request_password_change(&mut self, identity: &AuthIdentity, new_password: &str) -> Result<KpasswdResult> {
+        let credentials = CredentialsBuffers::AuthIdentity(AuthIdentityBuffers::from(identity.clone()));
+
+        self.kpasswd(&credentials, None, new_password)
+    }
+
+    /// Sets the password of the other principal, e.g. when the administrator resets it.
+    /// The request is authenticated with the credentials of the context
+    pub fn set_password(&mut self, target: &Principal, new_password: &str) -> Result<()> {
+        let credentials = self
+            .credentials
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "No credentials provided".to_owned()))?;
+
+        self.kpasswd(&credentials, Some(target), new_password)?.check()
+    }
+
+    // the kpasswd server accepts only the initial tickets, so the ticket is requested with the AS exchange
+    // and is not cached
+    fn kpasswd(
+        &mut self,
+        credentials: &CredentialsBuffers,
+        target: Option<&Principal>,
+        new_password: &str,
+    ) -> Result<KpasswdResult> {
+        let (username, domain) = client_principal(credentials)?;
+        let service = kpasswd_principal(&get_client_principal_realm(&username, &domain));
+
+        let ticket = self.with_skew_retry(|kerberos| {
+            kerberos.request_initial_ticket(credentials, &username, &domain, Some(&service))
+        })?;
+
+        let enc_params = EncryptionParams {
+            encryption_type: Some(ticket.encryption_type),
+            ..self.encryption_params.clone()
+        };
+
+        // the AP-REQ is not the GSS-API token, so the authenticator has no GSS checksum
+        let seq_number = OsRng::new()?.gen::<u32>();
+        let mut authenticator = generate_authenticator_for_ap_req(
+            &ticket.client.principal_name()?,
+            &ticket.client.kerberos_realm()?,
+            seq_number,
+            ticket.encryption_type,
+            self.current_time(),
+            None,
+            0,
+        )?;
+        authenticator.0.cksum = Optional::from(None);
+        let subkey = authenticator
+            .0
+            .subkey
+            .0
+            .as_ref()
+            .map(|subkey| subkey.0.key_value.0 .0.clone())
+            .unwrap_or_default();
+
+        let ap_req = generate_ap_req(
+            ticket.decode_ticket()?,
+            &ticket.key,
+            &authenticator,
+            KPASSWD_AP_REQ_OPTIONS,
+            &enc_params,
+        )?;
+        let request =
+            kpasswd::generate_kpasswd_request(&ap_req, new_password, target, &subkey, seq_number, &enc_params)?;
+
+        let realm = ticket.server.realm.clone();
+        let response = self.send_to(&self.config.kdc_locator.locate_kpasswd(&realm)?, &realm, &request)?;
+
+        kpasswd::extract_kpasswd_result(&response, &ticket.key, &subkey, &enc_params)
+    }
+
+    // answers the TGT-REQ of the initiator with the TGT of the service:
+    // [draft-swift-win2k-krb-user2user 2](https://datatracker.ietf.org/doc/html/draft-swift-win2k-krb-user2user-03#section-2)
+    //
+    // the service which has its long-term keys does not need the user-to-user authentication
+    fn accept_tgt_req(&mut self) -> Result<Option<CachedCredentials>> {
+        let server = self.server.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::OutOfSequence,
+                "Kerberos context is not configured as the acceptor".into(),
+            )
+        })?;
+        if !server.service_keys.is_empty() {
+            return Ok(None);
+        }
+
+        let credentials = self.credentials.clone().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NoCredentials,
+                "Neither service keys nor service credentials are provided".into(),
+            )
+        })?;
+        let (username, domain) = client_principal(&credentials)?;
+
+        let tgt = self.tgt(&credentials, &username, &domain)?;
+        if let Some(server) = self.server.as_mut() {
+            server.user_to_user_key = Some(ServiceKey {
+                encryption_type: tgt.encryption_type,
+                kvno: None,
+                key: tgt.key.clone(),
+            });
+        }
+
+        Ok(Some(tgt))
+    }
+
+    // takes the service keys from the inbound credentials when no service keys are provided.
+    //
+    // The AP-REQ does not carry the salt and the s2kparams of the service account, so the password is tried
+    // with the default iteration count and the salts which the KDC usually uses: the account name of the user,
+    // the AD salt of the computer account and the principal name of the ticket. The keytab is recommended
+    // for the accounts with other salts or iteration counts
+    fn service_keys_from_credentials(&self, ticket: &TicketInner) -> Result<Vec<ServiceKey>> {
+        let credentials = self.credentials.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NoCredentials,
+                "Neither service keys nor service credentials are provided".into(),
+            )
+        })?;
+
+        match credentials {
+            CredentialsBuffers::AuthIdentity(identity) => {
+                let username = utf16_bytes_to_utf8_string(&identity.user);
+                let domain = utf16_bytes_to_utf8_string(&identity.domain);
+                let password = utf16_bytes_to_utf8_string(&identity.password);
+                let encryption_type = integer_to_u32(&ticket.enc_part.0.etype.0) as i32;
+
+                let realm = domain.to_ascii_uppercase();
+                let mut salts = vec![format!("{}{}", realm, username)];
+                if let Some(host) = username.strip_suffix('$') {
+                    salts.push(format!(
+                        "{}host{}.{}",
+                        realm,
+                        host.to_ascii_lowercase(),
+                        domain.to_ascii_lowercase()
+                    ));
+                }
+                let mut principal_salt = ticket.realm.0.to_string();
+                principal_salt.extend(ticket.sname.0.name_string.0 .0.iter().map(|name| name.to_string()));
+                if !salts.contains(&principal_salt) {
+                    salts.push(principal_salt);
+                }
+
+                salts
+                    .iter()
+                    .map(|salt| {
+                        Ok(ServiceKey {
+                            encryption_type,
+                            kvno: None,
+                            key: crypto::string_to_key(encryption_type, &password, salt, None)?,
+                        })
+                    })
+                    .collect()
+            }
+            CredentialsBuffers::Keytab(keytab) => {
+                Ok(keytab.service_keys(&principal_name_to_string(&ticket.sname.0), &ticket.realm.0.to_string()))
+            }
+            CredentialsBuffers::PkInit(_) => Err(Error::new(
+                ErrorKind::NoCredentials,
+                "Certificate credentials do not contain service keys".into(),
+            )),
+        }
+    }
+
+    // AP-REQ with the service ticket of the target. The ticket is encrypted with the session key of the TGT
+    // of the service if the service sent it
+    fn initiator_ap_req(
+        &mut self,
+        credentials: &CredentialsBuffers,
+        target_name: Option<&str>,
+        context_requirements: ClientRequestFlags,
+        tgt_ticket: Option<Ticket>,
+    ) -> Result<ApReq> {
+        let is_user_to_user = tgt_ticket.is_some();
+
+        let (username, domain) = client_principal(credentials)?;
+
+        let client = Principal::new(&username, &domain);
+        let service = service_principal(target_name, &domain)?;
+
+        // user-to-user tickets are bound to the TGT of the service, so they are not cached
+        let cached_service_ticket = if tgt_ticket.is_none() {
+            self.cached_credentials(&client, &service)?
+        } else {
+            None
+        };
+
+        let service_ticket = match cached_service_ticket {
+            Some(service_ticket) => service_ticket,
+            None => {
+                let tgt = self.tgt(credentials, &username, &domain)?;
+
+                let service_ticket = self
+                    .with_skew_retry(|kerberos| kerberos.request_service_ticket(&tgt, &service, tgt_ticket.clone()))?;
+                if !is_user_to_user {
+                    self.cache_credentials(&service_ticket)?;
+                }
+
+                service_ticket
+            }
+        };
+
+        // the TGT is not delegated if the KDC policy does not allow to forward it
+        let tgt = if context_requirements.contains(ClientRequestFlags::DELEGATE) {
+            Some(self.tgt(credentials, &username, &domain)?)
+                .filter(|tgt| tgt.ticket_flags & FORWARDABLE_TICKET_FLAG != 0)
+        } else {
+            None
+        };
+        let krb_cred = match tgt {
+            Some(tgt) => {
+                let forwarded_tgt = self.with_skew_retry(|kerberos| kerberos.request_forwarded_tgt(&tgt))?;
+
+                Some(generate_krb_cred(
+                    &forwarded_tgt,
+                    &service_ticket.key,
+                    service_ticket.encryption_type,
+                    self.current_time(),
+                )?)
+            }
+            None => None,
+        };
+
+        self.encryption_params.encryption_type = Some(service_ticket.encryption_type);
+        self.encryption_params.session_key = Some(service_ticket.key.clone());
+
+        self.context_flags = requested_context_flags(context_requirements);
+        if krb_cred.is_some() {
+            self.context_flags |= GSS_C_DELEG_FLAG;
+        }
+
+        let authenticator = generate_authenticator_for_ap_req(
+            &service_ticket.client.principal_name()?,
+            &service_ticket.client.kerberos_realm()?,
+            self.next_seq_number(),
+            service_ticket.encryption_type,
+            self.current_time(),
+            krb_cred.as_ref(),
+            self.context_flags,
+        )?;
+
+        let mut ap_options = if is_user_to_user {
+            USER_TO_USER_AP_REQ_OPTIONS
+        } else {
+            DEFAULT_AP_REQ_OPTIONS
+        };
+        if self.context_flags & GSS_C_MUTUAL_FLAG == 0 {
+            ap_options[0] &= !MUTUAL_REQUIRED_AP_OPTION;
+        }
+        let ap_req = generate_ap_req(
+            service_ticket.decode_ticket()?,
+            &service_ticket.key,
+            &authenticator,
+            ap_options,
+            &self.encryption_params,
+        )?;
+
+        // the subkey of the authenticator protects the messages until the AP-REP brings the subkey of the acceptor
+        self.encryption_params.sub_session_key = authenticator
+            .0
+            .subkey
+            .0
+            .as_ref()
+            .map(|sub_key| sub_key.0.key_value.0 .0.clone());
+
+        Ok(ap_req)
+    }
+
+    // [RFC 4120 3.2.3](https://www.rfc-editor.org/rfc/rfc4120#section-3.2.3).
+    // The AP-REP is generated only if the initiator requires the mutual authentication
+    fn accept_ap_req(&mut self, ap_req: &ApReq) -> Result<Option<ApRep>> {
+        let server = self.server.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::OutOfSequence,
+                "Kerberos context is not configured as the acceptor".into(),
+            )
+        })?;
+
+        let service_keys = if is_user_to_user(ap_req) {
+            // user-to-user: the ticket is encrypted with the session key of the TGT sent to the initiator
+            let user_to_user_key = server.user_to_user_key.clone().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidToken,
+                    "The ticket is encrypted with the session key but the TGT was not sent to the initiator".into(),
+                )
+            })?;
+
+            vec![user_to_user_key]
+        } else if server.service_keys.is_empty() {
+            self.service_keys_from_credentials(&ap_req.0.ticket.0 .0)?
+        } else {
+            server.service_keys.clone()
+        };
+
+        let (enc_ticket_part, ticket_key) = extract_enc_ticket_part(&ap_req.0.ticket.0, &service_keys)?;
+        let enc_ticket_part = enc_ticket_part.0;
+
+        // the PAC which can not be verified does not fail the authentication. The error is reported by query_context_pac
+        let pac = enc_ticket_part
+            .authorization_data
+            .0
+            .as_ref()
+            .and_then(|authorization_data| extract_pac(&authorization_data.0).transpose())
+            .map(|pac| {
+                let kdc_keys = if server.kdc_keys.is_empty() {
+                    None
+                } else {
+                    Some(server.kdc_keys.as_slice())
+                };
+
+                pac.and_then(|pac| Pac::decode(&pac, ticket_key, kdc_keys))
+                    .map(Box::new)
+            });
+
+        let session_key = enc_ticket_part.key.0;
+
+        let authenticator = extract_authenticator(ap_req, &session_key)?.0;
+
+        if authenticator.cname.0 != enc_ticket_part.cname.0 || authenticator.crealm.0 != enc_ticket_part.crealm.0 {
+            return Err(Error::new(
+                ErrorKind::WrongPrincipalName,
+                "The client principal of the authenticator does not match the ticket".into(),
+            ));
+        }
+
+        let now = Utc::now();
+        let max_time_skew = server.max_time_skew;
+
+        let ctime = DateTime::<Utc>::from(authenticator.ctime.0 .0.clone());
+        if ctime > now + max_time_skew || ctime < now - max_time_skew {
+            return Err(Error::new(
+                ErrorKind::TimeSkew,
+                format!("Clock skew is too great: authenticator time is {}", ctime),
+            ));
+        }
+
+        let start_time = enc_ticket_part
+            .starttime
+            .0
+            .as_ref()
+            .map(|start_time| start_time.0.clone())
+            .unwrap_or_else(|| enc_ticket_part.authtime.0.clone());
+        if DateTime::<Utc>::from(start_time.0) > now + max_time_skew {
+            return Err(Error::new(
+                ErrorKind::ContextExpired,
+                "The ticket is not yet valid".into(),
+            ));
+        }
+        if DateTime::<Utc>::from(enc_ticket_part.endtime.0 .0.clone()) < now - max_time_skew {
+            return Err(Error::new(ErrorKind::ContextExpired, "The ticket has expired".into()));
+        }
+
+        let client_name = principal_name_to_string(&authenticator.cname.0);
+        let client_realm = authenticator.crealm.0.to_string();
+
+        self.peer_seq_number = authenticator
+            .seq_number
+            .0
+            .as_ref()
+            .map(|seq_number| integer_to_u32(&seq_number.0));
+
+        let authenticator_id = format!(
+            "{}@{}:{}.{}",
+            client_name,
+            client_realm,
+            ctime.timestamp(),
+            integer_to_u32(&authenticator.cusec.0)
+        );
+        if !server.replay_cache.insert(authenticator_id, ctime + max_time_skew) {
+            return Err(Error::new(
+                ErrorKind::OutOfSequence,
+                "The authenticator has already been used".into(),
+            ));
+        }
+
+        let encryption_type = authenticator
+            .subkey
+            .0
+            .as_ref()
+            .map(|sub_key| &sub_key.0.key_type.0)
+            .unwrap_or(&session_key.key_type.0);
+        let encryption_type = integer_to_u32(encryption_type) as i32;
+        self.encryption_params.check_permitted(encryption_type)?;
+
+        self.encryption_params.encryption_type = Some(encryption_type);
+        self.encryption_params.session_key = Some(session_key.key_value.0 .0.clone());
+
+        let delegated_credentials = extract_delegated_credentials(&authenticator, &session_key)?;
+
+        let mut context_flags = extract_context_flags(&authenticator);
+        // the DCE-style context is always mutual
+        let is_mutual = is_mutual_required(ap_req) || context_flags & GSS_C_DCE_STYLE != 0;
+        if is_mutual {
+            context_flags |= GSS_C_MUTUAL_FLAG;
+        } else {
+            context_flags &= !GSS_C_MUTUAL_FLAG;
+        }
+        if delegated_credentials.is_empty() {
+            context_flags &= !GSS_C_DELEG_FLAG;
+        }
+        self.context_flags = context_flags;
+
+        if let Some(server) = self.server.as_mut() {
+            server.client = Some(ContextNames {
+                username: client_name,
+                domain: Some(client_realm),
+            });
+            server.delegated_credentials = delegated_credentials;
+            server.pac = pac;
+            server.authenticator_time = Some((authenticator.ctime.0.clone(), authenticator.cusec.0.clone()));
+        }
+
+        if !is_mutual {
+            // without the AP-REP both peers use the subkey and continue the sequence numbers of the initiator
+            // as MIT Kerberos does
+            self.encryption_params.sub_session_key = Some(
+                authenticator
+                    .subkey
+                    .0
+                    .map(|sub_key| sub_key.0.key_value.0 .0)
+                    .unwrap_or_else(|| session_key.key_value.0 .0.clone()),
+            );
+            self.seq_number = self.peer_seq_number.unwrap_or_default();
+
+            return Ok(None);
+        }
+
+        let acceptor_sub_key = generate_acceptor_sub_key(encryption_type)?;
+        self.encryption_params.sub_session_key = Some(acceptor_sub_key.key_value.0 .0.clone());
+        self.has_acceptor_subkey = true;
+
+        generate_ap_rep(
+            &session_key,
+            authenticator.ctime.0,
+            authenticator.cusec.0,
+            Some(acceptor_sub_key),
+            self.seq_number,
+        )
+        .map(Some)
+    }
+
+    // the DCE-style AP-REP of the initiator is encrypted with the session key of the ticket
+    fn accept_dce_style_ap_rep(&self, ap_rep: &ApRep) -> Result<()> {
+        let session_key = self.encryption_params.session_key.clone().unwrap_or_default();
+        let ap_rep_enc_part = extract_enc_ap_rep_part(ap_rep, &session_key, &self.encryption_params)?.0;
+
+        let authenticator_time = self
+            .server
+            .as_ref()
+            .and_then(|server| server.authenticator_time.as_ref());
+        if authenticator_time != Some(&(ap_rep_enc_part.ctime.0, ap_rep_enc_part.cusec.0)) {
+            return Err(Error::new(
+                ErrorKind::InvalidToken,
+                "The AP-REP of the initiator does not match the authenticator".into(),
+            ));
+        }
+
+        let seq_number = ap_rep_enc_part
+            .seq_number
+            .0
+            .as_ref()
+            .map(|seq_number| integer_to_u32(&seq_number.0));
+        if seq_number != Some(self.seq_number) {
+            return Err(Error::new(
+                ErrorKind::OutOfSequence,
+                "The AP-REP of the initiator does not echo the sequence number of the acceptor".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// [MS-KILE 3.4.5.4.1](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-kile/e94b3acd-8415-4d0d-9786-749d0c39d550):
+// the RRC rotates the checksum, and the encrypted header when sealing, into the token header
+// (12 and 28 bytes for AES-SHA1)
+fn wrap_token_rrc(encryption_type: i32, seal: bool) -> Result<u16> {
+    let checksum_len = crypto::checksum_len(encryption_type)?;
+
+    Ok(if seal {
+        (WrapToken::header_len() + checksum_len) as u16
+    } else {
+        checksum_len as u16
+    })
+}
+
+// len of the MIC token: [RFC 4121 4.2.6.1](https://datatracker.ietf.org/doc/html/rfc4121#section-4.2.6.1)
+fn mic_token_len(encryption_type: i32) -> Result<usize> {
+    if encryption_type == RC4_HMAC {
+        return Ok(rc4_tokens::MIC_TOKEN_FRAMED_LEN);
+    }
+
+    Ok(MIC_TOKEN_HEADER_LEN + crypto::checksum_len(encryption_type)?)
+}
+
+// len of the wrap token part that does not fit into the data buffer
+fn security_trailer(encryption_type: i32) -> Result<usize> {
+    if encryption_type == RC4_HMAC {
+        return Ok(rc4_tokens::MAX_WRAP_HEADER_LEN);
+    }
+
+    Ok(WrapToken::header_len() + usize::from(wrap_token_rrc(encryption_type, true)?) + CONFOUNDER_SIZE)
+}
+
+// the permitted encryption types must be implemented by the client
+fn permitted_encryption_types(config: &KerberosConfig) -> Result<Vec<i32>> {
+    if config.permitted_encryption_types.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidParameter,
+            "At least one encryption type must be permitted".into(),
+        ));
+    }
+
+    if let Some(encryption_type) = config
+        .permitted_encryption_types
+        .iter()
+        .find(|encryption_type| !SUPPORTED_ENCRYPTION_TYPES.contains(encryption_type))
+    {
+        return Err(Error::new(
+            ErrorKind::KdcUnknownEType,
+            format!("The {} encryption type is not supported", encryption_type),
+        ));
+    }
+
+    Ok(config.permitted_encryption_types.clone())
+}
+
+// returns the name and the realm of the client principal
+fn client_principal(credentials: &CredentialsBuffers) -> Result<(String, String)> {
+    match credentials {
+        CredentialsBuffers::AuthIdentity(identity) => Ok((
+            utf16_bytes_to_utf8_string(&identity.user),
+            utf16_bytes_to_utf8_string(&identity.domain),
+        )),
+        CredentialsBuffers::Keytab(keytab) => keytab
+            .entries
+            .first()
+            .map(|entry| (entry.principal.name(), entry.principal.realm.clone()))
+            .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "The keytab does not contain any keys".into())),
+        CredentialsBuffers::PkInit(credentials) => Ok((credentials.username.clone(), credentials.domain.clone())),
+    }
+}
+
+// the client key chosen for the pre-authentication methods of the KDC
+struct PreAuth {
+    key: Vec<u8>,
+    encryption_type: i32,
+    etype_info: Option<EtypeInfo>,
+    fx_cookie: Option<PaData>,
+}
+
+// derives the long-term key of the client principal with the salt and the s2kparams of the PA-ETYPE-INFO2 entry
+fn client_key(
+    credentials: &CredentialsBuffers,
+    encryption_type: i32,
+    etype_info: Option<&EtypeInfo>,
+    default_salt: &str,
+) -> Result<Vec<u8>> {
+    match credentials {
+        CredentialsBuffers::AuthIdentity(identity) => {
+            let password = utf16_bytes_to_utf8_string(&identity.password);
+            let salt = etype_info
+                .and_then(|etype_info| etype_info.salt.as_deref())
+                .unwrap_or(default_salt);
+            let s2kparams = etype_info.and_then(|etype_info| etype_info.s2kparams.as_deref());
+
+            crypto::string_to_key(encryption_type, &password, salt, s2kparams)
+        }
+        CredentialsBuffers::Keytab(keytab) => {
+            let (username, domain) = client_principal(credentials)?;
+
+            keytab
+                .find_key(&username, &domain, None, encryption_type)
+                .map(|entry| entry.key.clone())
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NoCredentials,
+                        format!(
+                            "The keytab does not contain the {} key for {}@{}",
+                            encryption_type, username, domain
+                        ),
+                    )
+                })
+        }
+        // the reply key of the PKINIT is agreed with the KDC
+        CredentialsBuffers::PkInit(_) => Err(Error::new(
+            ErrorKind::NoCredentials,
+            "Certificate credentials do not contain the long-term key".into(),
+        )),
+    }
+}
+
+// selects the most preferred encryption type of the client keys stored in the keytab
+fn keytab_encryption_type(keytab: &Keytab, username: &str, domain: &str, permitted: &[i32]) -> Result<i32> {
+    permitted
+        .iter()
+        .copied()
+        .find(|encryption_type| keytab.find_key(username, domain, None, *encryption_type).is_some())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::KdcUnknownEType,
+                format!(
+                    "The keytab does not contain keys of permitted encryption types for {}@{}",
+                    username, domain
+                ),
+            )
+        })
+}
 
-const SSPI_KDC_URL_ENV: &str = "SSPI_KDC_URL";
+// the service principal of the target name. The service is in the realm of the client unless the SPN names its realm
+fn service_principal(target_name: Option<&str>, client_realm: &str) -> Result<Principal> {
+    let target_name = target_name.ok_or_else(|| {
+        Error::new(
+            ErrorKind::NoCredentials,
+            "Service target name (service principal name) is not provided".into(),
+        )
+    })?;
 
-const DEFAULT_ENCRYPTION_TYPE: i32 = AES256_CTS_HMAC_SHA1_96;
+    Principal::from_service_principal_name(target_name, client_realm)
+}
 
-/// [MS-KILE](https://winprotocoldoc.blob.core.windows.net/productionwindowsarchives/MS-KILE/%5bMS-KILE%5d.pdf)
-/// The RRC field is 12 if no encryption is requested or 28 if encryption is requested
-const RRC: u16 = 28;
-// wrap token header len
-const MAX_SIGNATURE: usize = 16;
-// minimal len to fit encrypted public key in wrap token
-const SECURITY_TRAILER: usize = 60;
+// the ticket of the user-to-user AP-REQ is encrypted with the session key of the TGT of the service
+fn is_user_to_user(ap_req: &ApReq) -> bool {
+    let ap_options = ap_req.0.ap_options.0 .0.payload_view();
 
-lazy_static! {
-    pub static ref PACKAGE_INFO: PackageInfo = PackageInfo {
-        capabilities: PackageCapabilities::empty(),
-        rpc_id: PACKAGE_ID_NONE,
-        max_token_len: 0xbb80, // 48 000 bytes: default maximum token len in Windows
-        name: SecurityPackageType::Kerberos,
-        comment: String::from("Kerberos Security Package"),
-    };
+    ap_options
+        .first()
+        .is_some_and(|options| options & USE_SESSION_KEY_AP_OPTION != 0)
+}
 
-    pub static ref NEGO_PACKAGE_INFO: PackageInfo = PackageInfo {
-        capabilities: PackageCapabilities::empty(),
-        rpc_id: PACKAGE_ID_NONE,
-        max_token_len: 0xbb80, // 48 000 bytes: default maximum token len in Windows
-        name: SecurityPackageType::Other("Negotiate".into()),
-        comment: String::from("Negotiate Security Package"),
-    };
+// the initiator expects the AP-REP of the acceptor
+fn is_mutual_required(ap_req: &ApReq) -> bool {
+    let ap_options = ap_req.0.ap_options.0 .0.payload_view();
+
+    ap_options
+        .first()
+        .is_some_and(|options| options & MUTUAL_REQUIRED_AP_OPTION != 0)
 }
 
-#[derive(Debug, Clone)]
-pub enum KerberosState {
-    Negotiate,
-    Preauthentication,
-    ApExchange,
-    PubKeyAuth,
-    Credentials,
-    Final,
+// the delegation flag is set only if the TGT is delegated, and the DCE-style context requires the AP-REP
+fn requested_context_flags(context_requirements: ClientRequestFlags) -> u32 {
+    let mut context_flags = CONTEXT_FLAGS
+        .iter()
+        .filter(|(flag, request, _, _)| *flag != GSS_C_DELEG_FLAG && context_requirements.contains(*request))
+        .fold(0, |context_flags, (flag, _, _, _)| context_flags | flag);
+
+    if context_requirements.contains(ClientRequestFlags::NO_INTEGRITY) {
+        context_flags &= !GSS_C_INTEG_FLAG;
+    }
+    if context_flags & GSS_C_DCE_STYLE != 0 {
+        context_flags |= GSS_C_MUTUAL_FLAG;
+    }
+
+    context_flags
 }
 
-#[derive(Debug, Clone)]
-pub struct Kerberos {
-    state: KerberosState,
-    config: KerberosConfig,
-    auth_identity: Option<AuthIdentityBuffers>,
-    encryption_params: EncryptionParams,
-    seq_number: u32,
-    realm: Option<String>,
+fn client_response_flags(context_flags: u32) -> ClientResponseFlags {
+    CONTEXT_FLAGS
+        .iter()
+        .filter(|(flag, _, _, _)| context_flags & flag != 0)
+        .fold(ClientResponseFlags::empty(), |flags, (_, _, response, _)| {
+            flags | *response
+        })
 }
 
-impl Kerberos {
-    pub fn new_client_from_config(config: KerberosConfig) -> Result<Self> {
-        Ok(Self {
-            state: KerberosState::Negotiate,
-            config,
-            auth_identity: None,
-            encryption_params: EncryptionParams::default_for_client(),
-            seq_number: OsRng::new()?.gen::<u32>(),
-            realm: None,
+fn server_response_flags(context_flags: u32) -> ServerResponseFlags {
+    CONTEXT_FLAGS
+        .iter()
+        .filter(|(flag, _, _, _)| context_flags & flag != 0)
+        .fold(ServerResponseFlags::empty(), |flags, (_, _, _, response)| {
+            flags | *response
         })
+}
+
+// realm of the KDC which accepts the TGT: the realm of the krbtgt/REALM service
+fn tgt_realm(tgt: &CachedCredentials) -> String {
+    krbtgt_realm(&tgt.server).unwrap_or_else(|| tgt.server.realm.clone())
+}
+
+// returns the realm of the cross-realm TGT issued instead of the requested ticket
+fn referral_realm(ticket: &CachedCredentials, requested_server: &str) -> Option<String> {
+    if ticket.server.name().eq_ignore_ascii_case(requested_server) {
+        return None;
     }
 
-    pub fn new_server_from_config(config: KerberosConfig) -> Result<Self> {
-        Ok(Self {
-            state: KerberosState::Negotiate,
-            config,
-            auth_identity: None,
-            encryption_params: EncryptionParams::default_for_server(),
-            seq_number: OsRng::new()?.gen::<u32>(),
-            realm: None,
-        })
+    krbtgt_realm(&ticket.server)
+}
+
+// realm of krbtgt/REALM
+fn krbtgt_realm(principal: &Principal) -> Option<String> {
+    match principal.components.as_slice() {
+        [service, realm] if service.eq_ignore_ascii_case(TGT_SERVICE_NAME) => Some(realm.clone()),
+        _ => None,
     }
+}
 
-    pub fn next_seq_number(&mut self) -> u32 {
-        self.seq_number += 1;
-        self.seq_number
+fn max_referrals_exceeded(max_referrals: u32) -> Error {
+    Error::new(
+        ErrorKind::MaxReferralsExceeded,
+        format!("The KDC referrals exceeded the limit of {}", max_referrals),
+    )
+}
+
+// principal of the ticket-granting service: krbtgt/REALM@REALM
+fn tgt_principal(realm: &str) -> Principal {
+    let realm = realm.to_ascii_uppercase();
+
+    Principal {
+        components: vec![TGT_SERVICE_NAME.to_owned(), realm.clone()],
+        realm,
+        name_type: u32::from(NT_SRV_INST),
     }
+}
 
-    fn send(&self, data: &[u8]) -> Result<Vec<u8>> {
-        match self.config.kdc_type {
-            KdcType::Kdc => self.config.network_client.send(&self.config.url, data),
-            KdcType::KdcProxy => self
-                .config
-                .network_client
-                .send_http(&self.config.url, data, self.realm.clone()),
-        }
+fn kpasswd_principal(realm: &str) -> Principal {
+    Principal {
+        components: KPASSWD_SERVICE_NAME.split('/').map(ToOwned::to_owned).collect(),
+        realm: realm.to_ascii_uppercase(),
+        name_type: u32::from(NT_SRV_INST),
     }
 }
 
+// the AS-REQ is made for the TGS, the sname of the other service is set before the request is armored or signed
+fn set_as_req_service(req_body: &mut KdcReqBody, service: Option<&Principal>) -> Result<()> {
+    if let Some(service) = service {
+        req_body.sname = Optional::from(Some(ExplicitContextTag3::from(service.principal_name()?)));
+    }
+
+    Ok(())
+}
+
+fn principal_name_to_string(principal_name: &PrincipalName) -> String {
+    principal_name
+        .name_string
+        .0
+         .0
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
 impl Sspi for Kerberos {
     fn complete_auth_token(&mut self, _token: &mut [SecurityBuffer]) -> Result<SecurityStatus> {
         Ok(SecurityStatus::Ok)
@@ -146,19 +1676,15 @@ impl Sspi for Kerberos {
 
     fn encrypt_message(
         &mut self,
-        _flags: crate::EncryptionFlags,
+        flags: crate::EncryptionFlags,
         message: &mut [SecurityBuffer],
         _sequence_number: u32,
     ) -> Result<SecurityStatus> {
+        self.check_established()?;
+
         SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?;
         let data = SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Data)?;
 
-        let cipher = new_kerberos_cipher(
-            self.encryption_params
-                .encryption_type
-                .unwrap_or(DEFAULT_ENCRYPTION_TYPE),
-        )?;
-
         let seq_number = self.next_seq_number();
 
         // the sub-session key is always preferred over the session key
@@ -173,35 +1699,81 @@ impl Sspi for Kerberos {
             ));
         };
         let key_usage = self.encryption_params.sspi_encrypt_key_usage;
+        let encryption_type = self.encryption_params.encryption_type();
+        let seal = !flags.contains(EncryptionFlags::WRAP_NO_ENCRYPT);
+
+        if encryption_type == RC4_HMAC {
+            let (header, protected_data) = rc4_tokens::wrap(
+                key,
+                seq_number,
+                self.server.is_some(),
+                seal,
+                self.is_dce_style(),
+                &data.buffer,
+            )?;
+
+            *data.buffer.as_mut() = protected_data;
+            let token = SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?;
+            *token.buffer.as_mut() = header;
+
+            return Ok(SecurityStatus::Ok);
+        }
 
         let mut wrap_token = WrapToken::with_seq_number(seq_number as u64);
+        if self.server.is_some() {
+            wrap_token.flags |= SENT_BY_ACCEPTOR_FLAG;
+        }
+        if !self.has_acceptor_subkey {
+            wrap_token.flags &= !ACCEPTOR_SUBKEY_FLAG;
+        }
 
-        let mut payload = data.buffer.to_vec();
-        payload.extend_from_slice(&wrap_token.header());
+        let rrc = wrap_token_rrc(encryption_type, seal)?;
 
-        let mut checksum = cipher.encrypt(key, key_usage, &payload);
-        checksum.rotate_right(RRC.into());
+        let (raw_wrap_token, header_len) = if seal {
+            let cipher = new_cipher(encryption_type)?;
 
-        wrap_token.set_rrc(RRC);
-        wrap_token.set_checksum(checksum);
+            let mut payload = data.buffer.to_vec();
+            payload.extend_from_slice(&wrap_token.header());
 
-        let mut raw_wrap_token = Vec::with_capacity(92);
-        wrap_token.encode(&mut raw_wrap_token)?;
+            let mut checksum = cipher.encrypt(key, key_usage, &payload);
+            checksum.rotate_right(rrc.into());
 
-        match self.state {
-            KerberosState::PubKeyAuth | KerberosState::Credentials => {
-                *data.buffer.as_mut() = raw_wrap_token[SECURITY_TRAILER..].to_vec();
-                let header = SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?;
-                *header.buffer.as_mut() = raw_wrap_token[0..SECURITY_TRAILER].to_vec();
-            }
-            _ => {
-                return Err(Error {
-                    error_type: ErrorKind::OutOfSequence,
-                    description: "Kerberos context is not established or finished".to_owned(),
-                })
-            }
+            wrap_token.set_rrc(rrc);
+            wrap_token.set_checksum(checksum);
+
+            let mut raw_wrap_token = Vec::with_capacity(data.buffer.len() + security_trailer(encryption_type)?);
+            wrap_token.encode(&mut raw_wrap_token)?;
+
+            (
+                raw_wrap_token,
+                WrapToken::header_len() + usize::from(rrc) + CONFOUNDER_SIZE,
+            )
+        } else {
+            wrap_token.flags &= !SEALED_FLAG;
+
+            // the checksum is calculated over the token header with the zero EC and RRC:
+            // [RFC 4121 4.2.4](https://datatracker.ietf.org/doc/html/rfc4121#section-4.2.4)
+            let mut payload = data.buffer.to_vec();
+            payload.extend_from_slice(&wrap_token.header());
+
+            let mut checksum = data.buffer.to_vec();
+            checksum.extend_from_slice(&crypto::checksum(encryption_type, key, key_usage, &payload)?);
+            checksum.rotate_right(rrc.into());
+
+            wrap_token.ec = crypto::checksum_len(encryption_type)? as u16;
+            wrap_token.set_rrc(rrc);
+            wrap_token.set_checksum(checksum);
+
+            let mut raw_wrap_token = Vec::with_capacity(data.buffer.len() + WrapToken::header_len() + usize::from(rrc));
+            wrap_token.encode(&mut raw_wrap_token)?;
+
+            (raw_wrap_token, WrapToken::header_len() + usize::from(rrc))
         };
 
+        *data.buffer.as_mut() = raw_wrap_token[header_len..].to_vec();
+        let header = SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?;
+        *header.buffer.as_mut() = raw_wrap_token[0..header_len].to_vec();
+
         Ok(SecurityStatus::Ok)
     }
 
@@ -210,6 +1782,8 @@ impl Sspi for Kerberos {
         message: &mut [SecurityBuffer],
         _sequence_number: u32,
     ) -> Result<crate::DecryptionFlags> {
+        self.check_established()?;
+
         let mut encrypted = SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?
             .buffer
             .clone();
@@ -217,12 +1791,6 @@ impl Sspi for Kerberos {
 
         encrypted.extend_from_slice(&data.buffer);
 
-        let cipher = new_kerberos_cipher(
-            self.encryption_params
-                .encryption_type
-                .unwrap_or(DEFAULT_ENCRYPTION_TYPE),
-        )?;
-
         // the sub-session key is always preferred over the session key
         let key = if let Some(key) = self.encryption_params.sub_session_key.as_ref() {
             key
@@ -235,50 +1803,181 @@ impl Sspi for Kerberos {
             ));
         };
         let key_usage = self.encryption_params.sspi_decrypt_key_usage;
+        let encryption_type = self.encryption_params.encryption_type();
 
-        let mut wrap_token = WrapToken::decode(encrypted.as_slice())?;
+        if encryption_type == RC4_HMAC {
+            let unwrapped = rc4_tokens::unwrap(key, &encrypted, self.server.is_none(), self.is_dce_style())?;
 
-        wrap_token.checksum.rotate_left(RRC.into());
+            self.check_peer_seq_number(unwrapped.seq_number.into())?;
 
-        let mut decrypted = cipher.decrypt(key, key_usage, &wrap_token.checksum)?;
-        // remove wrap token header
-        decrypted.truncate(decrypted.len() - WrapToken::header_len());
+            *data.buffer.as_mut() = unwrapped.data;
 
-        match self.state {
-            KerberosState::PubKeyAuth => {
-                self.state = KerberosState::Credentials;
+            return Ok(if unwrapped.sealed {
+                DecryptionFlags::empty()
+            } else {
+                DecryptionFlags::WRAP_NO_ENCRYPT
+            });
+        }
+
+        let mut wrap_token = WrapToken::decode(encrypted.as_slice())?;
+
+        if wrap_token.checksum.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidToken, "Wrap token is too short".into()));
+        }
+        // the RRC may exceed the len of the rotated data
+        let rrc = usize::from(wrap_token.rrc) % wrap_token.checksum.len();
+        wrap_token.checksum.rotate_left(rrc);
+
+        let (decrypted, flags) = if wrap_token.flags & SEALED_FLAG == 0 {
+            let checksum_len = usize::from(wrap_token.ec);
+            if wrap_token.checksum.len() < checksum_len {
+                return Err(Error::new(ErrorKind::MessageAltered, "Wrap token is too short".into()));
+            }
+            let checksum = wrap_token.checksum.split_off(wrap_token.checksum.len() - checksum_len);
+
+            // the checksum is calculated over the token header with the zero EC and RRC
+            wrap_token.ec = 0;
+            wrap_token.set_rrc(0);
+            let mut payload = wrap_token.checksum.clone();
+            payload.extend_from_slice(&wrap_token.header());
 
-                *data.buffer.as_mut() = decrypted;
-                Ok(DecryptionFlags::empty())
+            if !bool::from(crypto::checksum(encryption_type, key, key_usage, &payload)?.ct_eq(&checksum)) {
+                return Err(Error::new(
+                    ErrorKind::MessageAltered,
+                    "Wrap token checksum verification failed".into(),
+                ));
             }
-            KerberosState::Credentials => {
-                self.state = KerberosState::Final;
 
-                *data.buffer.as_mut() = decrypted;
-                Ok(DecryptionFlags::empty())
+            (wrap_token.checksum, DecryptionFlags::WRAP_NO_ENCRYPT)
+        } else {
+            let cipher = new_cipher(encryption_type)?;
+
+            let mut decrypted = cipher.decrypt(key, key_usage, &wrap_token.checksum)?;
+
+            // the plaintext is followed by EC filler bytes and the copy of the token header
+            let filler_len = usize::from(wrap_token.ec);
+            if decrypted.len() < filler_len + WrapToken::header_len() {
+                return Err(Error::new(ErrorKind::InvalidToken, "Wrap token is too short".into()));
             }
-            _ => {
-                *data.buffer.as_mut() = decrypted;
-                Ok(DecryptionFlags::empty())
+            let mut inner_header = decrypted.split_off(decrypted.len() - WrapToken::header_len());
+            decrypted.truncate(decrypted.len() - filler_len);
+
+            // the encrypted header must match the token header except for EC and RRC:
+            // [RFC 4121 4.2.4](https://datatracker.ietf.org/doc/html/rfc4121#section-4.2.4)
+            let mut outer_header = wrap_token.header();
+            inner_header[4..8].fill(0);
+            outer_header[4..8].fill(0);
+            if inner_header != outer_header {
+                return Err(Error::new(
+                    ErrorKind::MessageAltered,
+                    "The encrypted header of the wrap token does not match the token header".into(),
+                ));
             }
+
+            (decrypted, DecryptionFlags::empty())
+        };
+
+        self.check_peer_seq_number(wrap_token.seq_num)?;
+
+        *data.buffer.as_mut() = decrypted;
+
+        Ok(flags)
+    }
+
+    fn make_signature(&mut self, flags: u32, message: &mut [SecurityBuffer], _sequence_number: u32) -> Result<()> {
+        self.check_established()?;
+
+        // the MIC token has no quality of protection options
+        if flags != 0 {
+            return Err(Error::new(
+                ErrorKind::OperationNotSupported,
+                format!("Unsupported quality of protection: {:#x}", flags),
+            ));
+        }
+
+        SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?;
+        let payload = SecurityBuffer::find_buffer(message, SecurityBufferType::Data)?
+            .buffer
+            .clone();
+
+        let seq_number = self.next_seq_number() as u64;
+
+        // the sub-session key is always preferred over the session key
+        let key = if let Some(key) = self.encryption_params.sub_session_key.as_ref() {
+            key
+        } else if let Some(key) = self.encryption_params.session_key.as_ref() {
+            key
+        } else {
+            return Err(Error::new(ErrorKind::OutOfSequence, "No signing key provided".into()));
+        };
+
+        let encryption_type = self.encryption_params.encryption_type();
+        let mic_token = if self.server.is_some() {
+            generate_acceptor_raw(payload, seq_number, key, encryption_type)?
+        } else {
+            generate_initiator_raw(payload, seq_number, key, encryption_type)?
+        };
+
+        let signature = SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?;
+        // the buffer sized by the caller must fit the token. An empty one is allocated
+        if !signature.buffer.is_empty() && signature.buffer.len() < mic_token.len() {
+            return Err(Error::new(
+                ErrorKind::BufferTooSmall,
+                format!(
+                    "The signature buffer is too small: {} < {}",
+                    signature.buffer.len(),
+                    mic_token.len()
+                ),
+            ));
         }
+        *signature.buffer.as_mut() = mic_token;
+
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, message: &mut [SecurityBuffer], _sequence_number: u32) -> Result<u32> {
+        self.check_established()?;
+
+        let mic_token = SecurityBuffer::find_buffer(message, SecurityBufferType::Token)?;
+        let payload = SecurityBuffer::find_buffer(message, SecurityBufferType::Data)?;
+
+        // the signature of the peer is generated with the opposite key usage
+        let key_usage = if self.server.is_some() {
+            INITIATOR_SIGN
+        } else {
+            ACCEPTOR_SIGN
+        };
+
+        let seq_number = validate_mic_token_with_payload(
+            &mic_token.buffer,
+            key_usage,
+            &self.encryption_params,
+            payload.buffer.clone(),
+        )?;
+        self.check_peer_seq_number(seq_number)?;
+
+        Ok(0)
     }
 
     fn query_context_sizes(&mut self) -> Result<ContextSizes> {
         Ok(ContextSizes {
             max_token: PACKAGE_INFO.max_token_len,
-            max_signature: MAX_SIGNATURE as u32,
+            max_signature: mic_token_len(self.encryption_params.encryption_type())? as u32,
             block: 0,
-            security_trailer: SECURITY_TRAILER as u32,
+            security_trailer: security_trailer(self.encryption_params.encryption_type())? as u32,
         })
     }
 
     fn query_context_names(&mut self) -> Result<ContextNames> {
-        if let Some(ref identity_buffers) = self.auth_identity {
-            let identity: AuthIdentity = identity_buffers.clone().into();
+        if let Some(client) = self.server.as_ref().and_then(|server| server.client.as_ref()) {
+            return Ok(client.clone());
+        }
+
+        if let Some(ref credentials) = self.credentials {
+            let (username, domain) = client_principal(credentials)?;
             Ok(ContextNames {
-                username: identity.username,
-                domain: identity.domain,
+                username,
+                domain: Some(domain).filter(|domain| !domain.is_empty()),
             })
         } else {
             Err(sspi::Error::new(
@@ -298,12 +1997,36 @@ impl Sspi for Kerberos {
             "Certificate trust status is not supported".to_owned(),
         ))
     }
+
+    fn query_context_pac(&mut self) -> Result<Pac> {
+        let server = self.server.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::OutOfSequence,
+                "Kerberos context is not configured as the acceptor".into(),
+            )
+        })?;
+        if server.client.is_none() {
+            return Err(Error::new(
+                ErrorKind::OutOfSequence,
+                "The ticket of the client is not accepted yet".into(),
+            ));
+        }
+
+        match &server.pac {
+            Some(Ok(pac)) => Ok(pac.as_ref().clone()),
+            Some(Err(error)) => Err(error.clone()),
+            None => Err(Error::new(
+                ErrorKind::InvalidToken,
+                "The ticket of the client does not contain the PAC".into(),
+            )),
+        }
+    }
 }
 
 impl SspiImpl for Kerberos {
-    type CredentialsHandle = Option<AuthIdentityBuffers>;
+    type CredentialsHandle = Option<CredentialsBuffers>;
 
-    type AuthenticationData = AuthIdentity;
+    type AuthenticationData = Credentials;
 
     fn acquire_credentials_handle_impl(
         &mut self,
@@ -321,10 +2044,31 @@ impl SspiImpl for Kerberos {
             ));
         }
 
-        self.auth_identity = builder.auth_data.cloned().map(AuthIdentityBuffers::from);
+        let mut credentials = builder.auth_data.cloned().map(CredentialsBuffers::from);
+
+        // the principal name selects the client keys if the keytab contains keys of several principals
+        if let (Some(CredentialsBuffers::Keytab(keytab)), Some(principal_name), CredentialUse::Outbound) =
+            (credentials.as_mut(), builder.principal_name, builder.credential_use)
+        {
+            let (username, domain) = principal_name.rsplit_once('@').unwrap_or((principal_name, ""));
+
+            keytab.entries.retain(|entry| {
+                entry.principal.name() == username
+                    && (domain.is_empty() || entry.principal.realm.eq_ignore_ascii_case(domain))
+            });
+
+            if keytab.entries.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::NoCredentials,
+                    format!("The keytab does not contain keys for {}", principal_name),
+                ));
+            }
+        }
+
+        self.credentials = credentials;
 
         Ok(AcquireCredentialsHandleResult {
-            credentials_handle: self.auth_identity.clone(),
+            credentials_handle: self.credentials.clone(),
             expiry: None,
         })
     }
@@ -334,23 +2078,41 @@ impl SspiImpl for Kerberos {
         builder: crate::builders::FilledInitializeSecurityContext<'_, Self, Self::CredentialsHandle>,
     ) -> Result<crate::InitializeSecurityContextResult> {
         let status = match self.state {
+            KerberosState::Negotiate if builder.context_requirements.contains(ClientRequestFlags::USE_DCE_STYLE) => {
+                // DCE style: the raw AP-REQ is sent without SPNEGO and the user-to-user negotiation
+                let credentials = builder
+                    .credentials_handle
+                    .unwrap()
+                    .as_ref()
+                    .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "No credentials provided".to_owned()))?;
+
+                let ap_req =
+                    self.initiator_ap_req(credentials, builder.target_name, builder.context_requirements, None)?;
+
+                let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
+                output_token.buffer.write_all(&picky_asn1_der::to_vec(&ap_req)?)?;
+
+                self.state = KerberosState::ApExchange;
+
+                SecurityStatus::ContinueNeeded
+            }
             KerberosState::Negotiate => {
-                let credentials = builder.credentials_handle.unwrap().as_ref().ok_or_else(|| Error {
-                    error_type: ErrorKind::NoCredentials,
-                    description: "No credentials provided".to_owned(),
-                })?;
+                let credentials = builder
+                    .credentials_handle
+                    .unwrap()
+                    .as_ref()
+                    .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "No credentials provided".to_owned()))?;
 
-                let username = utf16_bytes_to_utf8_string(&credentials.user);
-                let domain = utf16_bytes_to_utf8_string(&credentials.domain);
+                let (_, domain) = client_principal(credentials)?;
+                let service = service_principal(builder.target_name, &domain)?;
 
                 let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
                 output_token
                     .buffer
-                    .write_all(&picky_asn1_der::to_vec(&generate_neg_token_init(&format!(
-                        "{}.{}",
-                        username,
-                        domain.to_ascii_lowercase()
-                    ))?)?)?;
+                    .write_all(&picky_asn1_der::to_vec(&generate_neg_token_init(
+                        &service,
+                        self.mech_types.clone(),
+                    )?)?)?;
 
                 self.state = KerberosState::Preauthentication;
 
@@ -363,131 +2125,126 @@ impl SspiImpl for Kerberos {
                 let input_token = SecurityBuffer::find_buffer(input, SecurityBufferType::Token)?;
 
                 let tgt_ticket = extract_tgt_ticket(&input_token.buffer)?;
-
-                let credentials = builder.credentials_handle.unwrap().as_ref().ok_or_else(|| Error {
-                    error_type: ErrorKind::NoCredentials,
-                    description: "No credentials provided".to_owned(),
-                })?;
-
-                let username = utf16_bytes_to_utf8_string(&credentials.user);
-                let domain = utf16_bytes_to_utf8_string(&credentials.domain);
-                let password = utf16_bytes_to_utf8_string(&credentials.password);
-                let mut salt = format!("{}{}", domain, username);
-
-                self.realm = Some(domain.clone());
-
-                let as_req = generate_as_req_without_pre_auth(&username, &domain)?;
-
-                let response = self.send(&serialize_message(&as_req)?)?;
-
-                // first 4 bytes is message len. skipping them
-                let mut d = picky_asn1_der::Deserializer::new_from_bytes(&response[4..]);
-                let as_rep: KrbResult<AsRep> = KrbResult::deserialize(&mut d)?;
-
-                if as_rep.is_ok() {
-                    return Err(Error {
-                        error_type: ErrorKind::InternalError,
-                        description: "KDC server should not proccess AS_REQ without the pa-pac data".to_owned(),
-                    });
-                }
-
-                if let Some(correct_salt) = extract_salt_from_krb_error(&as_rep.unwrap_err())? {
-                    salt = correct_salt;
-                }
-
-                let as_req = generate_as_req(&username, salt.as_bytes(), &password, &domain, &self.encryption_params)?;
-
-                let response = self.send(&serialize_message(&as_req)?)?;
-
-                // first 4 bytes is message len. skipping them
-                let mut d = picky_asn1_der::Deserializer::new_from_bytes(&response[4..]);
-                let as_rep: KrbResult<AsRep> = KrbResult::deserialize(&mut d)?;
-                let as_rep = as_rep?;
-
-                self.realm = Some(as_rep.0.crealm.0.to_string());
-
-                let (encryption_type, salt) = extract_encryption_params_from_as_rep(&as_rep)?;
-                self.encryption_params.encryption_type = Some(encryption_type as i32);
-
-                let mut authenticator = generate_authenticator_for_tgs_ap_req(&as_rep.0)?;
-
-                let session_key_1 =
-                    extract_session_key_from_as_rep(&as_rep, &salt, &password, &self.encryption_params)?;
-
-                let service_principal = builder.target_name.ok_or_else(|| Error {
-                    error_type: ErrorKind::NoCredentials,
-                    description: "Service target name (service principal name) is not provided".into(),
-                })?;
-
-                let tgs_req = generate_tgs_req(
-                    &as_rep.0.crealm.0.to_string(),
-                    service_principal,
-                    &session_key_1,
-                    as_rep.0.ticket.0,
-                    &mut authenticator,
-                    tgt_ticket.map(|ticket| vec![ticket]),
-                    &self.encryption_params,
-                )?;
-
-                let response = self.send(&serialize_message(&tgs_req)?)?;
-
-                // first 4 bytes is message len. skipping them
-                let mut d = picky_asn1_der::Deserializer::new_from_bytes(&response[4..]);
-                let tgs_rep: KrbResult<TgsRep> = KrbResult::deserialize(&mut d)?;
-                let tgs_rep = tgs_rep?;
-
-                self.encryption_params.session_key = Some(extract_session_key_from_tgs_rep(
-                    &tgs_rep,
-                    &session_key_1,
-                    &self.encryption_params,
-                )?);
-
-                let authenticator = generate_authenticator_for_ap_req(&tgs_rep.0, self.next_seq_number())?;
-
-                let ap_req = generate_ap_req(
-                    tgs_rep.0.ticket.0,
-                    self.encryption_params.session_key.as_ref().unwrap(),
-                    &authenticator,
-                    &self.encryption_params,
+                let krb5_oid = if tgt_ticket.is_some() { KRB5_USER_TO_USER } else { KRB5 };
+
+                let credentials = builder
+                    .credentials_handle
+                    .unwrap()
+                    .as_ref()
+                    .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "No credentials provided".to_owned()))?;
+
+                let ap_req = self.initiator_ap_req(
+                    credentials,
+                    builder.target_name,
+                    builder.context_requirements,
+                    tgt_ticket,
                 )?;
 
                 let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
                 output_token
                     .buffer
-                    .write_all(&picky_asn1_der::to_vec(&generate_neg_ap_req(ap_req)?)?)?;
+                    .write_all(&picky_asn1_der::to_vec(&generate_neg_ap_req(
+                        ap_req,
+                        ObjectIdentifier::try_from(krb5_oid).unwrap(),
+                    )?)?)?;
 
                 self.state = KerberosState::ApExchange;
 
                 SecurityStatus::ContinueNeeded
             }
-            KerberosState::ApExchange => {
+            KerberosState::ApExchange if self.is_dce_style() => {
                 let input = builder
                     .input
                     .ok_or_else(|| Error::new(ErrorKind::InvalidToken, "Input buffers must be specified".into()))?;
                 let input_token = SecurityBuffer::find_buffer(input, SecurityBufferType::Token)?;
 
-                let neg_token_targ: NegTokenTarg1 =
-                    picky_asn1_der::from_bytes(&input_token.buffer).map_err(|err| Error {
-                        error_type: ErrorKind::InvalidToken,
-                        description: format!("{:?}", err),
-                    })?;
+                let ap_rep: ApRep = picky_asn1_der::from_bytes(&input_token.buffer)
+                    .map_err(|err| Error::new(ErrorKind::InvalidToken, format!("{:?}", err)))?;
+
+                let session_key = self.encryption_params.session_key.clone().unwrap_or_default();
+                let ap_rep_enc_part = extract_enc_ap_rep_part(&ap_rep, &session_key, &self.encryption_params)?;
+
+                self.encryption_params.sub_session_key = Some(extract_sub_session_key_from_ap_rep(&ap_rep_enc_part)?);
+                self.has_acceptor_subkey = true;
+                let peer_seq_number = ap_rep_enc_part
+                    .0
+                    .seq_number
+                    .0
+                    .as_ref()
+                    .map(|seq_number| integer_to_u32(&seq_number.0));
+                self.peer_seq_number = peer_seq_number;
+
+                // [MS-KILE 3.2.5.2](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-kile/b1a9b91c-a9c4-4a2f-b8b0-9d6d0b9c7cd5):
+                // the AP-REP of the initiator echoes the time of the authenticator and the sequence number
+                // of the acceptor
+                let ap_rep = generate_ap_rep(
+                    &EncryptionKey {
+                        key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![self
+                            .encryption_params
+                            .encryption_type()
+                            as u8])),
+                        key_value: ExplicitContextTag1::from(OctetStringAsn1::from(session_key)),
+                    },
+                    ap_rep_enc_part.0.ctime.0,
+                    ap_rep_enc_part.0.cusec.0,
+                    None,
+                    peer_seq_number.unwrap_or_default(),
+                )?;
+
+                let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
+                output_token.buffer.write_all(&picky_asn1_der::to_vec(&ap_rep)?)?;
 
-                let ap_rep = extract_ap_rep_from_neg_token_targ(&neg_token_targ)?;
+                self.state = KerberosState::Established;
 
-                self.encryption_params.sub_session_key = Some(extract_sub_session_key_from_ap_rep(
-                    &ap_rep,
-                    self.encryption_params.session_key.as_ref().unwrap(),
-                    &self.encryption_params,
-                )?);
+                SecurityStatus::Ok
+            }
+            KerberosState::ApExchange => {
+                let input = builder
+                    .input
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidToken, "Input buffers must be specified".into()))?;
+                let input_token = SecurityBuffer::find_buffer(input, SecurityBufferType::Token)?;
+
+                let neg_token_targ: NegTokenTarg1 = picky_asn1_der::from_bytes(&input_token.buffer)
+                    .map_err(|err| Error::new(ErrorKind::InvalidToken, format!("{:?}", err)))?;
+
+                // the acceptor sends the AP-REP only if the mutual authentication is required
+                if self.context_flags & GSS_C_MUTUAL_FLAG != 0 || neg_token_targ.0.response_token.0.is_some() {
+                    let ap_rep = extract_ap_rep_from_neg_token_targ(&neg_token_targ)?;
+
+                    let ap_rep_enc_part = extract_enc_ap_rep_part(
+                        &ap_rep,
+                        self.encryption_params.session_key.as_ref().unwrap(),
+                        &self.encryption_params,
+                    )?;
+
+                    self.encryption_params.sub_session_key =
+                        Some(extract_sub_session_key_from_ap_rep(&ap_rep_enc_part)?);
+                    self.has_acceptor_subkey = true;
+                    self.peer_seq_number = ap_rep_enc_part
+                        .0
+                        .seq_number
+                        .0
+                        .as_ref()
+                        .map(|seq_number| integer_to_u32(&seq_number.0));
+                } else {
+                    // the acceptor continues the sequence numbers of the initiator
+                    self.peer_seq_number = Some(self.seq_number);
+                }
 
                 if let Some(ref token) = neg_token_targ.0.mech_list_mic.0 {
-                    validate_mic_token(&token.0 .0, ACCEPTOR_SIGN, &self.encryption_params)?;
+                    validate_mic_token_with_payload(
+                        &token.0 .0,
+                        ACCEPTOR_SIGN,
+                        &self.encryption_params,
+                        picky_asn1_der::to_vec(&self.mech_types)?,
+                    )?;
                 }
 
                 let neg_token_targ = generate_final_neg_token_targ(Some(generate_initiator_raw(
-                    picky_asn1_der::to_vec(&get_mech_list())?,
+                    picky_asn1_der::to_vec(&self.mech_types)?,
                     self.seq_number as u64,
                     self.encryption_params.sub_session_key.as_ref().unwrap(),
+                    self.encryption_params.encryption_type(),
                 )?));
 
                 let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
@@ -495,7 +2252,7 @@ impl SspiImpl for Kerberos {
                     .buffer
                     .write_all(&picky_asn1_der::to_vec(&neg_token_targ)?)?;
 
-                self.state = KerberosState::PubKeyAuth;
+                self.state = KerberosState::Established;
 
                 SecurityStatus::Ok
             }
@@ -509,7 +2266,7 @@ impl SspiImpl for Kerberos {
 
         Ok(InitializeSecurityContextResult {
             status,
-            flags: ClientResponseFlags::empty(),
+            flags: client_response_flags(self.context_flags),
             expiry: None,
         })
     }
@@ -522,18 +2279,152 @@ impl SspiImpl for Kerberos {
             .input
             .ok_or_else(|| sspi::Error::new(ErrorKind::InvalidToken, "Input buffers must be specified".into()))?;
 
-        let status = match &self.state {
+        let input_token = SecurityBuffer::find_buffer(input, SecurityBufferType::Token)?;
+        let message = extract_initiator_message(&input_token.buffer)?;
+
+        let status = match self.state {
+            KerberosState::Negotiate if message.token_id == Some(TGT_REQ_TOKEN_ID) => {
+                let _: TgtReq = picky_asn1_der::from_bytes(&message.krb_message)
+                    .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+
+                let tgt = self.accept_tgt_req()?;
+
+                let mech_types = message.mech_types.unwrap_or_else(get_mech_list);
+                let neg_token_targ = generate_neg_tgt_rep(
+                    tgt.map(|tgt| tgt.decode_ticket().and_then(generate_krb_tgt_rep_token))
+                        .transpose()?,
+                    mech_types.0.first().cloned(),
+                );
+
+                let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
+                output_token
+                    .buffer
+                    .write_all(&picky_asn1_der::to_vec(&neg_token_targ)?)?;
+
+                if let Some(server) = self.server.as_mut() {
+                    server.mech_types = Some(picky_asn1_der::to_vec(&mech_types)?);
+                }
+
+                // the AP-REQ is expected next
+                SecurityStatus::ContinueNeeded
+            }
+            KerberosState::Negotiate => {
+                let ap_req: ApReq = picky_asn1_der::from_bytes(&message.krb_message)
+                    .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+
+                let ap_rep = self.accept_ap_req(&ap_req)?;
+
+                let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
+
+                if message.is_spnego {
+                    let mech_types = match message.mech_types {
+                        Some(mech_types) => mech_types,
+                        // the mechanisms were proposed together with the TGT-REQ
+                        None => match self.server.as_ref().and_then(|server| server.mech_types.as_ref()) {
+                            Some(raw_mech_types) => picky_asn1_der::from_bytes(raw_mech_types)?,
+                            None => get_mech_list(),
+                        },
+                    };
+                    let raw_mech_types = picky_asn1_der::to_vec(&mech_types)?;
+
+                    let mech_list_mic = generate_acceptor_raw(
+                        raw_mech_types.clone(),
+                        self.seq_number as u64,
+                        self.encryption_params.sub_session_key.as_ref().unwrap(),
+                        self.encryption_params.encryption_type(),
+                    )?;
+
+                    let neg_token_targ = generate_neg_ap_rep(
+                        ap_rep
+                            .map(|ap_rep| generate_krb_ap_rep_token(ap_rep, message.krb5_oid))
+                            .transpose()?,
+                        mech_types.0.first().cloned(),
+                        Some(mech_list_mic),
+                    );
+                    output_token
+                        .buffer
+                        .write_all(&picky_asn1_der::to_vec(&neg_token_targ)?)?;
+
+                    if let Some(server) = self.server.as_mut() {
+                        server.mech_types = Some(raw_mech_types);
+                    }
+
+                    // the initiator must answer with its own mechListMIC
+                    self.state = KerberosState::ApExchange;
+
+                    SecurityStatus::ContinueNeeded
+                } else {
+                    match ap_rep {
+                        Some(ap_rep) if self.is_dce_style() => {
+                            output_token.buffer.write_all(&picky_asn1_der::to_vec(&ap_rep)?)?;
+
+                            // the initiator must answer with its own AP-REP
+                            self.state = KerberosState::ApExchange;
+
+                            SecurityStatus::ContinueNeeded
+                        }
+                        Some(ap_rep) => {
+                            if message.token_id.is_some() {
+                                output_token
+                                    .buffer
+                                    .write_all(&generate_krb_ap_rep_token(ap_rep, message.krb5_oid)?)?;
+                            } else {
+                                output_token.buffer.write_all(&picky_asn1_der::to_vec(&ap_rep)?)?;
+                            }
+
+                            self.state = KerberosState::Established;
+
+                            SecurityStatus::Ok
+                        }
+                        // the context is established without the output token
+                        None => {
+                            self.state = KerberosState::Established;
+
+                            SecurityStatus::Ok
+                        }
+                    }
+                }
+            }
+            KerberosState::ApExchange if self.is_dce_style() => {
+                let ap_rep: ApRep = picky_asn1_der::from_bytes(&message.krb_message)
+                    .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+
+                self.accept_dce_style_ap_rep(&ap_rep)?;
+
+                self.state = KerberosState::Established;
+
+                SecurityStatus::Ok
+            }
             KerberosState::ApExchange => {
-                let input_token = SecurityBuffer::find_buffer(input, SecurityBufferType::Token)?;
+                let mech_list_mic = message.mech_list_mic.ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidToken,
+                        "The initiator did not send the mechListMIC".into(),
+                    )
+                })?;
+                let raw_mech_types = self
+                    .server
+                    .as_ref()
+                    .and_then(|server| server.mech_types.clone())
+                    .unwrap_or_default();
+
+                validate_mic_token_with_payload(
+                    &mech_list_mic,
+                    INITIATOR_SIGN,
+                    &self.encryption_params,
+                    raw_mech_types,
+                )?;
 
-                let _ap_req: ApReq = picky_asn1_der::from_bytes(&input_token.buffer)
-                    .map_err(|e| Error::new(ErrorKind::DecryptFailure, format!("{:?}", e)))?;
+                let output_token = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
+                output_token
+                    .buffer
+                    .write_all(&picky_asn1_der::to_vec(&generate_final_neg_token_resp())?)?;
 
-                self.state = KerberosState::Final;
+                self.state = KerberosState::Established;
 
                 SecurityStatus::Ok
             }
-            state => {
+            ref state => {
                 return Err(Error::new(
                     ErrorKind::OutOfSequence,
                     format!("Got wrong Kerberos state: {:?}", state),
@@ -543,7 +2434,7 @@ impl SspiImpl for Kerberos {
 
         Ok(AcceptSecurityContextResult {
             status,
-            flags: ServerResponseFlags::empty(),
+            flags: server_response_flags(self.context_flags),
             expiry: None,
         })
     }
@@ -551,6 +2442,6 @@ impl SspiImpl for Kerberos {
 
 impl SspiEx for Kerberos {
     fn custom_set_auth_identity(&mut self, identity: Self::AuthenticationData) {
-        self.auth_identity = Some(identity.into());
+        self.credentials = Some(identity.into());
     }
 }
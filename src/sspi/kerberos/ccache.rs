@@ -0,0 +1,563 @@
+#[cfg(test)]
+mod test;
+
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use picky_asn1::bit_string::BitString;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3, ExplicitContextTag4,
+    ExplicitContextTag5, ExplicitContextTag6, ExplicitContextTag7, ExplicitContextTag8, ExplicitContextTag9,
+    IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::data_types::{EncryptionKey, KerberosFlags, KerberosTime, Ticket};
+use picky_krb::messages::{EncKdcRepPart, KdcRep};
+
+use super::credentials::Principal;
+use super::data_types::KrbCredInfo;
+use super::utils::integer_to_u32;
+use crate::sspi::{Error, ErrorKind, Result};
+
+const CCACHE_FIRST_BYTE: u8 = 0x05;
+const CCACHE_VERSION: u8 = 0x04;
+const FILE_CCACHE_PREFIX: &str = "FILE:";
+// tickets which expire sooner are not reused
+const EXPIRATION_MARGIN_SECONDS: i64 = 60;
+
+/// Address or authorization data element of the cached credentials
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TaggedData {
+    pub data_type: u16,
+    pub data: Vec<u8>,
+}
+
+/// Ticket with its session key. Times are in seconds since the Unix epoch
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CachedCredentials {
+    pub client: Principal,
+    pub server: Principal,
+    pub encryption_type: i32,
+    pub key: Vec<u8>,
+    pub auth_time: u32,
+    pub start_time: u32,
+    pub end_time: u32,
+    pub renew_till: u32,
+    pub is_skey: bool,
+    pub ticket_flags: u32,
+    pub addresses: Vec<TaggedData>,
+    pub auth_data: Vec<TaggedData>,
+    /// DER-encoded ticket
+    pub ticket: Vec<u8>,
+    pub second_ticket: Vec<u8>,
+}
+
+impl CachedCredentials {
+    pub(crate) fn from_kdc_rep(kdc_rep: &KdcRep, enc_part: &EncKdcRepPart) -> Result<Self> {
+        let auth_time = kerberos_time_to_timestamp(&enc_part.auth_time.0);
+        let flags = enc_part.flags.0.payload_view();
+
+        Ok(Self {
+            client: Principal::from_principal_name(&kdc_rep.cname.0, &kdc_rep.crealm.0),
+            server: Principal::from_principal_name(&enc_part.sname.0, &enc_part.srealm.0),
+            encryption_type: integer_to_u32(&enc_part.key.0.key_type.0) as i32,
+            key: enc_part.key.0.key_value.0 .0.clone(),
+            auth_time,
+            start_time: enc_part
+                .start_time
+                .0
+                .as_ref()
+                .map(|start_time| kerberos_time_to_timestamp(&start_time.0))
+                .unwrap_or(auth_time),
+            end_time: kerberos_time_to_timestamp(&enc_part.end_time.0),
+            renew_till: enc_part
+                .renew_till
+                .0
+                .as_ref()
+                .map(|renew_till| kerberos_time_to_timestamp(&renew_till.0))
+                .unwrap_or_default(),
+            is_skey: false,
+            ticket_flags: flags
+                .iter()
+                .chain([0; 4].iter())
+                .take(4)
+                .fold(0, |acc, byte| (acc << 8) | u32::from(*byte)),
+            addresses: enc_part
+                .caadr
+                .0
+                .as_ref()
+                .map(|address| TaggedData {
+                    data_type: integer_to_u32(&address.0.addr_type.0) as u16,
+                    data: address.0.address.0 .0.clone(),
+                })
+                .into_iter()
+                .collect(),
+            auth_data: Vec::new(),
+            ticket: picky_asn1_der::to_vec(&kdc_rep.ticket.0)?,
+            second_ticket: Vec::new(),
+        })
+    }
+
+    /// Credentials delegated by the client in the KRB-CRED message
+    pub(crate) fn from_krb_cred_info(ticket: &Ticket, info: &KrbCredInfo) -> Result<Self> {
+        let missing = |field: &str| {
+            Error::new(
+                ErrorKind::InvalidToken,
+                format!("The KRB-CRED ticket info does not contain the {}", field),
+            )
+        };
+        let timestamp = |time: Option<&KerberosTime>| time.map(kerberos_time_to_timestamp).unwrap_or_default();
+
+        let client_realm = info.prealm.0.as_ref().ok_or_else(|| missing("client realm"))?;
+        let client_name = info.pname.0.as_ref().ok_or_else(|| missing("client name"))?;
+        let auth_time = timestamp(info.authtime.0.as_ref().map(|time| &time.0));
+
+        Ok(Self {
+            client: Principal::from_principal_name(&client_name.0, &client_realm.0),
+            server: Principal::from_principal_name(
+                info.sname.0.as_ref().map(|sname| &sname.0).unwrap_or(&ticket.0.sname.0),
+                info.srealm
+                    .0
+                    .as_ref()
+                    .map(|srealm| &srealm.0)
+                    .unwrap_or(&ticket.0.realm.0),
+            ),
+            encryption_type: integer_to_u32(&info.key.0.key_type.0) as i32,
+            key: info.key.0.key_value.0 .0.clone(),
+            auth_time,
+            start_time: info
+                .starttime
+                .0
+                .as_ref()
+                .map(|start_time| kerberos_time_to_timestamp(&start_time.0))
+                .unwrap_or(auth_time),
+            end_time: timestamp(info.endtime.0.as_ref().map(|time| &time.0)),
+            renew_till: timestamp(info.renew_till.0.as_ref().map(|time| &time.0)),
+            is_skey: false,
+            ticket_flags: info
+                .flags
+                .0
+                .as_ref()
+                .map(|flags| {
+                    flags
+                        .0
+                        .payload_view()
+                        .iter()
+                        .chain([0; 4].iter())
+                        .take(4)
+                        .fold(0, |acc, byte| (acc << 8) | u32::from(*byte))
+                })
+                .unwrap_or_default(),
+            addresses: Vec::new(),
+            auth_data: Vec::new(),
+            ticket: picky_asn1_der::to_vec(ticket)?,
+            second_ticket: Vec::new(),
+        })
+    }
+
+    /// Ticket info of the KRB-CRED message the credentials are delegated with
+    pub(crate) fn krb_cred_info(&self) -> Result<KrbCredInfo> {
+        let time = |timestamp: u32| KerberosTime::from(GeneralizedTime::from(timestamp_to_date_time(timestamp)));
+
+        Ok(KrbCredInfo {
+            key: ExplicitContextTag0::from(EncryptionKey {
+                key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![self.encryption_type as u8])),
+                key_value: ExplicitContextTag1::from(OctetStringAsn1::from(self.key.clone())),
+            }),
+            prealm: Optional::from(Some(ExplicitContextTag1::from(self.client.kerberos_realm()?))),
+            pname: Optional::from(Some(ExplicitContextTag2::from(self.client.principal_name()?))),
+            flags: Optional::from(Some(ExplicitContextTag3::from(KerberosFlags::from(
+                BitString::with_bytes(self.ticket_flags.to_be_bytes().to_vec()),
+            )))),
+            authtime: Optional::from(Some(ExplicitContextTag4::from(time(self.auth_time)))),
+            starttime: Optional::from(Some(ExplicitContextTag5::from(time(self.start_time)))),
+            endtime: Optional::from(Some(ExplicitContextTag6::from(time(self.end_time)))),
+            renew_till: Optional::from(
+                Some(self.renew_till)
+                    .filter(|renew_till| *renew_till != 0)
+                    .map(|renew_till| ExplicitContextTag7::from(time(renew_till))),
+            ),
+            srealm: Optional::from(Some(ExplicitContextTag8::from(self.server.kerberos_realm()?))),
+            sname: Optional::from(Some(ExplicitContextTag9::from(self.server.principal_name()?))),
+            caddr: Optional::from(None),
+        })
+    }
+
+    pub(crate) fn decode_ticket(&self) -> Result<Ticket> {
+        Ok(picky_asn1_der::from_bytes(&self.ticket)?)
+    }
+
+    /// Returns `true` if the ticket can be used now and does not expire soon
+    pub fn is_valid(&self) -> bool {
+        let now = Utc::now();
+
+        timestamp_to_date_time(self.start_time) <= now + Duration::seconds(EXPIRATION_MARGIN_SECONDS)
+            && timestamp_to_date_time(self.end_time) > now + Duration::seconds(EXPIRATION_MARGIN_SECONDS)
+    }
+
+    fn is_for(&self, client: &Principal, server: &Principal) -> bool {
+        self.client.matches(&client.name(), &client.realm) && self.server.matches(&server.name(), &server.realm)
+    }
+}
+
+/// MIT credential cache in the file format version 4:
+/// [ccache.txt](https://web.mit.edu/kerberos/krb5-devel/doc/formats/ccache_file_format.html)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Ccache {
+    pub header: Vec<TaggedData>,
+    pub default_principal: Principal,
+    pub credentials: Vec<CachedCredentials>,
+}
+
+impl Ccache {
+    pub fn new(default_principal: Principal) -> Self {
+        Self {
+            header: Vec::new(),
+            default_principal,
+            credentials: Vec::new(),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_buffer(BufReader::new(File::open(path)?))
+    }
+
+    pub fn from_buffer(mut stream: impl io::Read) -> Result<Self> {
+        let first_byte = stream.read_u8()?;
+        let version = stream.read_u8()?;
+        if first_byte != CCACHE_FIRST_BYTE || version != CCACHE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                format!("Unsupported ccache version: {:#04x}{:02x}", first_byte, version),
+            ));
+        }
+
+        let header_len = stream.read_u16::<BigEndian>()?;
+        let header_data = read_exact_len(&mut stream, header_len.into())?;
+
+        let mut header = Vec::new();
+        let mut header_stream = header_data.as_slice();
+        while !header_stream.is_empty() {
+            let data_type = header_stream.read_u16::<BigEndian>()?;
+            let len = header_stream.read_u16::<BigEndian>()?;
+            let data = read_exact_len(&mut header_stream, len.into())?;
+
+            header.push(TaggedData { data_type, data });
+        }
+
+        let default_principal = read_principal(&mut stream)?;
+
+        let mut credentials = Vec::new();
+        loop {
+            let client = match read_principal(&mut stream) {
+                Ok(client) => client,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            credentials.push(read_credentials(&mut stream, client)?);
+        }
+
+        Ok(Self {
+            header,
+            default_principal,
+            credentials,
+        })
+    }
+
+    /// Replaces the file atomically. The cache contains session keys, so the file is readable only by its owner
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        // a temp file left by a crashed process is not reused, so its mode is always set
+        let _ = std::fs::remove_file(&temp_path);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let result = options.open(&temp_path).map_err(Error::from).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            self.encode(&mut writer)?;
+            writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+
+            Ok(std::fs::rename(&temp_path, path)?)
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+
+    pub fn encode(&self, mut stream: impl io::Write) -> Result<()> {
+        stream.write_u8(CCACHE_FIRST_BYTE)?;
+        stream.write_u8(CCACHE_VERSION)?;
+
+        let mut header = Vec::new();
+        for field in &self.header {
+            header.write_u16::<BigEndian>(field.data_type)?;
+            header.write_u16::<BigEndian>(field.data.len() as u16)?;
+            header.write_all(&field.data)?;
+        }
+        stream.write_u16::<BigEndian>(header.len() as u16)?;
+        stream.write_all(&header)?;
+
+        write_principal(&mut stream, &self.default_principal)?;
+
+        for credentials in &self.credentials {
+            write_credentials(&mut stream, credentials)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns valid credentials of the client for the server
+    pub fn find(&self, client: &Principal, server: &Principal) -> Option<&CachedCredentials> {
+        self.credentials
+            .iter()
+            .filter(|credentials| credentials.is_for(client, server) && credentials.is_valid())
+            .max_by_key(|credentials| credentials.end_time)
+    }
+
+    /// Replaces the credentials of the same client and server
+    pub fn store(&mut self, credentials: CachedCredentials) {
+        self.credentials
+            .retain(|cached| !cached.is_for(&credentials.client, &credentials.server));
+        self.credentials.push(credentials);
+    }
+}
+
+/// Storage of the tickets shared between security contexts
+pub trait CredentialsCache: Debug + Send + Sync {
+    /// Returns valid credentials of the client for the server
+    fn get(&self, client: &Principal, server: &Principal) -> Result<Option<CachedCredentials>>;
+
+    fn store(&self, credentials: CachedCredentials) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryCredentialsCache {
+    credentials: Mutex<Vec<CachedCredentials>>,
+}
+
+impl MemoryCredentialsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CredentialsCache for MemoryCredentialsCache {
+    fn get(&self, client: &Principal, server: &Principal) -> Result<Option<CachedCredentials>> {
+        let credentials = self.credentials.lock().expect("credentials cache lock is poisoned");
+
+        Ok(credentials
+            .iter()
+            .filter(|credentials| credentials.is_for(client, server) && credentials.is_valid())
+            .max_by_key(|credentials| credentials.end_time)
+            .cloned())
+    }
+
+    fn store(&self, new_credentials: CachedCredentials) -> Result<()> {
+        let mut credentials = self.credentials.lock().expect("credentials cache lock is poisoned");
+
+        credentials
+            .retain(|cached| cached.is_valid() && !cached.is_for(&new_credentials.client, &new_credentials.server));
+        credentials.push(new_credentials);
+
+        Ok(())
+    }
+}
+
+/// Credentials cache backed by the MIT `FILE:` ccache
+#[derive(Debug)]
+pub struct FileCredentialsCache {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileCredentialsCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Creates the cache from the name in the `KRB5CCNAME` form: `FILE:/path/to/ccache` or `/path/to/ccache`
+    pub fn from_name(name: &str) -> Result<Self> {
+        if let Some(path) = name.strip_prefix(FILE_CCACHE_PREFIX) {
+            return Ok(Self::new(path));
+        }
+
+        match name.split_once(':') {
+            // one-letter prefixes are Windows drive letters
+            Some((cache_type, _)) if cache_type.len() > 1 => Err(Error::new(
+                ErrorKind::UnsupportedFunction,
+                format!("Unsupported credentials cache type: {}", cache_type),
+            )),
+            _ => Ok(Self::new(name)),
+        }
+    }
+
+    fn read(&self) -> Result<Option<Ccache>> {
+        match File::open(&self.path) {
+            Ok(file) => Ccache::from_buffer(BufReader::new(file)).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl CredentialsCache for FileCredentialsCache {
+    fn get(&self, client: &Principal, server: &Principal) -> Result<Option<CachedCredentials>> {
+        let _guard = self.lock.lock().expect("credentials cache lock is poisoned");
+
+        Ok(self.read()?.and_then(|ccache| ccache.find(client, server).cloned()))
+    }
+
+    fn store(&self, credentials: CachedCredentials) -> Result<()> {
+        let _guard = self.lock.lock().expect("credentials cache lock is poisoned");
+
+        let mut ccache = self.read()?.unwrap_or_else(|| Ccache::new(credentials.client.clone()));
+        ccache.store(credentials);
+
+        ccache.to_file(&self.path)
+    }
+}
+
+fn kerberos_time_to_timestamp(time: &KerberosTime) -> u32 {
+    DateTime::<Utc>::from(time.0.clone()).timestamp() as u32
+}
+
+fn timestamp_to_date_time(timestamp: u32) -> DateTime<Utc> {
+    Utc.timestamp_opt(i64::from(timestamp), 0).unwrap()
+}
+
+fn read_data(mut stream: impl io::Read) -> io::Result<Vec<u8>> {
+    let len = stream.read_u32::<BigEndian>()?;
+
+    read_exact_len(stream, len.into())
+}
+
+// the length is not trusted to allocate the data, so a corrupted ccache can not exhaust the memory
+fn read_exact_len(stream: impl io::Read, len: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    stream.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("ccache data is shorter than its length: {} < {}", data.len(), len),
+        ));
+    }
+
+    Ok(data)
+}
+
+fn read_string(stream: impl io::Read) -> io::Result<String> {
+    String::from_utf8(read_data(stream)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn read_principal(mut stream: impl io::Read) -> io::Result<Principal> {
+    let name_type = stream.read_u32::<BigEndian>()?;
+    let components_count = stream.read_u32::<BigEndian>()?;
+    let realm = read_string(&mut stream)?;
+    let components = (0..components_count)
+        .map(|_| read_string(&mut stream))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(Principal {
+        realm,
+        components,
+        name_type,
+    })
+}
+
+fn read_tagged_data_list(mut stream: impl io::Read) -> io::Result<Vec<TaggedData>> {
+    let count = stream.read_u32::<BigEndian>()?;
+
+    (0..count)
+        .map(|_| {
+            Ok(TaggedData {
+                data_type: stream.read_u16::<BigEndian>()?,
+                data: read_data(&mut stream)?,
+            })
+        })
+        .collect()
+}
+
+fn read_credentials(mut stream: impl io::Read, client: Principal) -> Result<CachedCredentials> {
+    let server = read_principal(&mut stream)?;
+    let encryption_type = i32::from(stream.read_u16::<BigEndian>()?);
+    let key = read_data(&mut stream)?;
+
+    Ok(CachedCredentials {
+        client,
+        server,
+        encryption_type,
+        key,
+        auth_time: stream.read_u32::<BigEndian>()?,
+        start_time: stream.read_u32::<BigEndian>()?,
+        end_time: stream.read_u32::<BigEndian>()?,
+        renew_till: stream.read_u32::<BigEndian>()?,
+        is_skey: stream.read_u8()? != 0,
+        ticket_flags: stream.read_u32::<BigEndian>()?,
+        addresses: read_tagged_data_list(&mut stream)?,
+        auth_data: read_tagged_data_list(&mut stream)?,
+        ticket: read_data(&mut stream)?,
+        second_ticket: read_data(&mut stream)?,
+    })
+}
+
+fn write_data(mut stream: impl io::Write, data: &[u8]) -> io::Result<()> {
+    stream.write_u32::<BigEndian>(data.len() as u32)?;
+    stream.write_all(data)
+}
+
+fn write_principal(mut stream: impl io::Write, principal: &Principal) -> io::Result<()> {
+    stream.write_u32::<BigEndian>(principal.name_type)?;
+    stream.write_u32::<BigEndian>(principal.components.len() as u32)?;
+    write_data(&mut stream, principal.realm.as_bytes())?;
+    for component in &principal.components {
+        write_data(&mut stream, component.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn write_tagged_data_list(mut stream: impl io::Write, list: &[TaggedData]) -> io::Result<()> {
+    stream.write_u32::<BigEndian>(list.len() as u32)?;
+    for element in list {
+        stream.write_u16::<BigEndian>(element.data_type)?;
+        write_data(&mut stream, &element.data)?;
+    }
+
+    Ok(())
+}
+
+fn write_credentials(mut stream: impl io::Write, credentials: &CachedCredentials) -> io::Result<()> {
+    write_principal(&mut stream, &credentials.client)?;
+    write_principal(&mut stream, &credentials.server)?;
+    stream.write_u16::<BigEndian>(credentials.encryption_type as u16)?;
+    write_data(&mut stream, &credentials.key)?;
+    stream.write_u32::<BigEndian>(credentials.auth_time)?;
+    stream.write_u32::<BigEndian>(credentials.start_time)?;
+    stream.write_u32::<BigEndian>(credentials.end_time)?;
+    stream.write_u32::<BigEndian>(credentials.renew_till)?;
+    stream.write_u8(u8::from(credentials.is_skey))?;
+    stream.write_u32::<BigEndian>(credentials.ticket_flags)?;
+    write_tagged_data_list(&mut stream, &credentials.addresses)?;
+    write_tagged_data_list(&mut stream, &credentials.auth_data)?;
+    write_data(&mut stream, &credentials.ticket)?;
+    write_data(&mut stream, &credentials.second_ticket)
+}
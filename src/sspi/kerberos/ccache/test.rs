@@ -0,0 +1,138 @@
+use super::*;
+use crate::sspi::kerberos::client::AES256_CTS_HMAC_SHA1_96;
+
+fn credentials(server: &str, end_time: DateTime<Utc>) -> CachedCredentials {
+    let now = Utc::now().timestamp() as u32;
+
+    CachedCredentials {
+        client: Principal::new("user", "EXAMPLE.COM"),
+        server: Principal::new(server, "EXAMPLE.COM"),
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        key: vec![0x42; 32],
+        auth_time: now,
+        start_time: now,
+        end_time: end_time.timestamp() as u32,
+        renew_till: 0,
+        is_skey: false,
+        ticket_flags: 0x40e1_0000,
+        addresses: vec![TaggedData {
+            data_type: 20,
+            data: b"CLIENT".to_vec(),
+        }],
+        auth_data: Vec::new(),
+        ticket: vec![0x61, 0x03, 0x02, 0x01, 0x05],
+        second_ticket: Vec::new(),
+    }
+}
+
+#[test]
+fn ccache_round_trip() {
+    let mut ccache = Ccache::new(Principal::new("user", "EXAMPLE.COM"));
+    ccache.header.push(TaggedData {
+        data_type: 1,
+        data: vec![0; 8],
+    });
+    ccache.store(credentials("krbtgt/EXAMPLE.COM", Utc::now() + Duration::hours(10)));
+    ccache.store(credentials(
+        "TERMSRV/server.example.com",
+        Utc::now() + Duration::hours(10),
+    ));
+
+    let mut buffer = Vec::new();
+    ccache.encode(&mut buffer).unwrap();
+
+    assert_eq!(&buffer[..6], &[0x05, 0x04, 0x00, 0x0c, 0x00, 0x01]);
+    assert_eq!(Ccache::from_buffer(buffer.as_slice()).unwrap(), ccache);
+}
+
+#[test]
+fn ccache_from_buffer_fails_on_unsupported_version() {
+    let error = Ccache::from_buffer([0x05, 0x03, 0x00, 0x00].as_ref()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+}
+
+#[test]
+fn ccache_from_buffer_fails_on_data_longer_than_ccache() {
+    let mut buffer = vec![0x05, 0x04, 0x00, 0x00];
+    // the name type, the components count and the realm of the default principal
+    buffer.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01]);
+    buffer.extend_from_slice(&u32::MAX.to_be_bytes());
+    buffer.extend_from_slice(b"EXAMPLE.COM");
+
+    let error = Ccache::from_buffer(buffer.as_slice()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InternalError);
+}
+
+#[test]
+fn ccache_find_skips_expired_credentials() {
+    let mut ccache = Ccache::new(Principal::new("user", "EXAMPLE.COM"));
+    ccache
+        .credentials
+        .push(credentials("krbtgt/EXAMPLE.COM", Utc::now() - Duration::minutes(1)));
+
+    let client = Principal::new("user", "example.com");
+    let server = Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM");
+
+    assert!(ccache.find(&client, &server).is_none());
+
+    ccache.store(credentials("krbtgt/EXAMPLE.COM", Utc::now() + Duration::hours(1)));
+
+    assert!(ccache.find(&client, &server).is_some());
+    assert_eq!(ccache.credentials.len(), 1);
+}
+
+#[test]
+fn memory_credentials_cache_replaces_credentials_of_the_same_server() {
+    let cache = MemoryCredentialsCache::new();
+    let client = Principal::new("user", "EXAMPLE.COM");
+    let server = Principal::new("TERMSRV/server.example.com", "EXAMPLE.COM");
+
+    assert_eq!(cache.get(&client, &server).unwrap(), None);
+
+    cache
+        .store(credentials(
+            "TERMSRV/server.example.com",
+            Utc::now() + Duration::hours(1),
+        ))
+        .unwrap();
+    let mut new_credentials = credentials("TERMSRV/server.example.com", Utc::now() + Duration::hours(2));
+    new_credentials.key = vec![0x24; 32];
+    cache.store(new_credentials.clone()).unwrap();
+
+    assert_eq!(cache.get(&client, &server).unwrap(), Some(new_credentials));
+    assert_eq!(
+        cache
+            .get(&client, &Principal::new("HTTP/server.example.com", "EXAMPLE.COM"))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn file_credentials_cache_stores_credentials() {
+    let path = std::env::temp_dir().join(format!("sspi-ccache-test-{}", std::process::id()));
+    let cache = FileCredentialsCache::from_name(&format!("FILE:{}", path.display())).unwrap();
+    let tgt = credentials("krbtgt/EXAMPLE.COM", Utc::now() + Duration::hours(1));
+
+    cache.store(tgt.clone()).unwrap();
+
+    let ccache = Ccache::from_file(&path).unwrap();
+    #[cfg(unix)]
+    let mode = std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions());
+    std::fs::remove_file(&path).unwrap();
+
+    #[cfg(unix)]
+    assert_eq!(mode & 0o777, 0o600);
+
+    assert_eq!(ccache.default_principal, tgt.client);
+    assert_eq!(ccache.credentials, vec![tgt]);
+}
+
+#[test]
+fn file_credentials_cache_rejects_other_cache_types() {
+    let error = FileCredentialsCache::from_name("KEYRING:persistent:1000").unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::UnsupportedFunction);
+}
@@ -1,114 +1,99 @@
-use kerberos_constants::key_usages::{KEY_USAGE_AS_REP_ENC_PART, KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY};
-use kerberos_crypto::new_kerberos_cipher;
+use kerberos_constants::key_usages::KEY_USAGE_AS_REP_ENC_PART;
 use picky_asn1::wrapper::Asn1SequenceOf;
 use picky_krb::constants::types::PA_ETYPE_INFO2_TYPE;
 use picky_krb::data_types::{EtypeInfo2, PaData};
 use picky_krb::messages::{AsRep, EncAsRepPart, EncTgsRepPart, KrbError, TgsRep};
 
-use crate::sspi::kerberos::{EncryptionParams, DEFAULT_ENCRYPTION_TYPE};
+use crate::sspi::kerberos::crypto::new_cipher;
+use crate::sspi::kerberos::utils::integer_to_u32;
+use crate::sspi::kerberos::EncryptionParams;
 use crate::sspi::{Error, ErrorKind, Result};
 
-pub fn extract_salt_from_krb_error(error: &KrbError) -> Result<Option<String>> {
-    if let Some(e_data) = error.0.e_data.0.as_ref() {
-        let pa_datas: Asn1SequenceOf<PaData> = picky_asn1_der::from_bytes(&e_data.0 .0)?;
+/// Entry of the PA-ETYPE-INFO2: [RFC 4120 5.2.7.5](https://www.rfc-editor.org/rfc/rfc4120#section-5.2.7.5)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EtypeInfo {
+    pub encryption_type: i32,
+    pub salt: Option<String>,
+    pub s2kparams: Option<Vec<u8>>,
+}
 
-        if let Some(pa_etype_info_2) = pa_datas
-            .0
-            .into_iter()
-            .find(|pa_data| pa_data.padata_type.0 .0 == PA_ETYPE_INFO2_TYPE)
-        {
-            let etype_info_2: EtypeInfo2 = picky_asn1_der::from_bytes(&pa_etype_info_2.padata_data.0 .0)?;
-            if let Some(params) = etype_info_2.0.get(0) {
-                return Ok(params.salt.0.as_ref().map(|salt| salt.0.to_string()));
-            }
-        }
-    }
+// all PA-ETYPE-INFO2 entries in the order of the KDC preference
+pub fn extract_etype_info(pa_datas: &[PaData]) -> Result<Vec<EtypeInfo>> {
+    let pa_etype_info_2 = match pa_datas
+        .iter()
+        .find(|pa_data| pa_data.padata_type.0 .0 == PA_ETYPE_INFO2_TYPE)
+    {
+        Some(pa_etype_info_2) => pa_etype_info_2,
+        None => return Ok(Vec::new()),
+    };
 
-    Ok(None)
+    let etype_info_2: EtypeInfo2 = picky_asn1_der::from_bytes(&pa_etype_info_2.padata_data.0 .0)?;
+
+    Ok(etype_info_2
+        .0
+        .into_iter()
+        .map(|entry| EtypeInfo {
+            encryption_type: integer_to_u32(&entry.etype.0) as i32,
+            salt: entry.salt.0.map(|salt| salt.0.to_string()),
+            s2kparams: entry.s2kparams.0.map(|s2kparams| s2kparams.0 .0),
+        })
+        .collect())
 }
 
-pub fn extract_session_key_from_as_rep(
-    as_rep: &AsRep,
-    salt: &str,
-    password: &str,
-    enc_params: &EncryptionParams,
-) -> Result<Vec<u8>> {
-    let cipher = new_kerberos_cipher(enc_params.encryption_type.unwrap_or(DEFAULT_ENCRYPTION_TYPE))?;
+/// Returns the METHOD-DATA of the KDC error: the e-data of the errors other than KDC_ERR_PREAUTH_REQUIRED is ignored
+pub fn extract_method_data(error: &KrbError) -> Vec<PaData> {
+    error
+        .0
+        .e_data
+        .0
+        .as_ref()
+        .and_then(|e_data| picky_asn1_der::from_bytes::<Asn1SequenceOf<PaData>>(&e_data.0 .0).ok())
+        .map(|method_data| method_data.0)
+        .unwrap_or_default()
+}
 
-    let key = cipher.generate_key_from_string(password, salt.as_bytes());
+pub fn extract_enc_as_rep_part(as_rep: &AsRep, key: &[u8], enc_params: &EncryptionParams) -> Result<EncAsRepPart> {
+    let cipher = new_cipher(enc_params.encryption_type())?;
 
     let enc_data = cipher
-        .decrypt(&key, KEY_USAGE_AS_REP_ENC_PART, &as_rep.0.enc_part.0.cipher.0 .0)
-        .map_err(|e| Error {
-            error_type: ErrorKind::DecryptFailure,
-            description: format!("Cannot decrypt as_rep.enc_part: {:?}", e),
+        .decrypt(key, KEY_USAGE_AS_REP_ENC_PART, &as_rep.0.enc_part.0.cipher.0 .0)
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::DecryptFailure,
+                format!("Cannot decrypt as_rep.enc_part: {:?}", e),
+            )
         })?;
 
-    let enc_as_rep_part: EncAsRepPart = picky_asn1_der::from_bytes(&enc_data)?;
-
-    Ok(enc_as_rep_part.0.key.0.key_value.0.to_vec())
+    Ok(picky_asn1_der::from_bytes(&enc_data)?)
 }
 
-pub fn extract_session_key_from_tgs_rep(
+/// The reply is encrypted with the TGT session key or, if the authenticator contains it, with the subkey
+pub fn extract_enc_tgs_rep_part(
     tgs_rep: &TgsRep,
-    session_key: &[u8],
+    key: &[u8],
+    key_usage: i32,
     enc_params: &EncryptionParams,
-) -> Result<Vec<u8>> {
-    let cipher = new_kerberos_cipher(enc_params.encryption_type.unwrap_or(DEFAULT_ENCRYPTION_TYPE))?;
+) -> Result<EncTgsRepPart> {
+    let cipher = new_cipher(enc_params.encryption_type())?;
 
     let enc_data = cipher
-        .decrypt(
-            session_key,
-            KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY,
-            &tgs_rep.0.enc_part.0.cipher.0 .0,
-        )
-        .map_err(|e| Error {
-            error_type: ErrorKind::InternalError,
-            description: format!("{:?}", e),
-        })?;
-
-    let enc_as_rep_part: EncTgsRepPart = picky_asn1_der::from_bytes(&enc_data)?;
+        .decrypt(key, key_usage, &tgs_rep.0.enc_part.0.cipher.0 .0)
+        .map_err(|e| Error::new(ErrorKind::InternalError, format!("{:?}", e)))?;
 
-    Ok(enc_as_rep_part.0.key.0.key_value.0.to_vec())
+    Ok(picky_asn1_der::from_bytes(&enc_data)?)
 }
 
-pub fn extract_encryption_params_from_as_rep(as_rep: &AsRep) -> Result<(u8, String)> {
-    match as_rep
-        .0
-        .padata
-        .0
-        .as_ref()
-        .map(|v| {
-            v.0 .0
-                .iter()
-                .find(|e| e.padata_type.0 .0 == PA_ETYPE_INFO2_TYPE)
-                .map(|pa_data| pa_data.padata_data.0 .0.clone())
-        })
-        .unwrap_or_default()
-    {
-        Some(data) => {
-            let pa_etype_into2: EtypeInfo2 = picky_asn1_der::from_bytes(&data)?;
-            let pa_etype_into2 = pa_etype_into2.0.get(0).ok_or(Error {
-                error_type: ErrorKind::InternalError,
-                description: "Missing EtypeInto2Entry in EtypeInfo2".into(),
-            })?;
+// returns the encryption type of the AS-REP enc-part and the PA-ETYPE-INFO2 entry of this type.
+// The entry can be missing, e.g. RC4-HMAC keys are not salted
+pub fn extract_encryption_params_from_as_rep(as_rep: &AsRep) -> Result<(i32, Option<EtypeInfo>)> {
+    let encryption_type = integer_to_u32(&as_rep.0.enc_part.0.etype.0) as i32;
+
+    let etype_info = match as_rep.0.padata.0.as_ref() {
+        Some(padata) => extract_etype_info(&padata.0 .0)?
+            .into_iter()
+            .find(|etype_info| etype_info.encryption_type == encryption_type),
+        None => None,
+    };
 
-            Ok((
-                pa_etype_into2.etype.0 .0.get(0).copied().unwrap(),
-                pa_etype_into2
-                    .salt
-                    .0
-                    .as_ref()
-                    .map(|salt| salt.0.to_string())
-                    .ok_or(Error {
-                        error_type: ErrorKind::InternalError,
-                        description: "Missing salt in EtypeInto2Entry".into(),
-                    })?,
-            ))
-        }
-        None => Err(Error {
-            error_type: ErrorKind::NoPaData,
-            description: format!("Missing PaData: PA_ETYPE_INFO2 ({:0x?})", PA_ETYPE_INFO2_TYPE),
-        }),
-    }
+    Ok((encryption_type, etype_info))
 }
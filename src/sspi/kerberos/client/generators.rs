@@ -1,9 +1,9 @@
 use std::convert::TryFrom;
 use std::str::FromStr;
 
-use chrono::{Duration, Utc};
-use kerberos_constants::key_usages::{KEY_USAGE_AP_REQ_AUTHEN, KEY_USAGE_TGS_REQ_AUTHEN};
-use kerberos_crypto::new_kerberos_cipher;
+use byteorder::{LittleEndian, WriteBytesExt};
+use chrono::{DateTime, Duration, Utc};
+use kerberos_constants::key_usages::{KEY_USAGE_AP_REQ_AUTHEN, KEY_USAGE_KRB_CRED_ENC_PART, KEY_USAGE_TGS_REQ_AUTHEN};
 use md5::{Digest, Md5};
 use oid::ObjectIdentifier;
 use picky_asn1::bit_string::BitString;
@@ -32,12 +32,14 @@ use picky_krb::data_types::{
 use picky_krb::gss_api::{
     ApplicationTag0, GssApiNegInit, KrbMessage, MechType, MechTypeList, NegTokenInit, NegTokenTarg, NegTokenTarg1,
 };
-use picky_krb::messages::{ApReq, ApReqInner, AsReq, KdcRep, KdcReq, KdcReqBody, TgsReq, TgtReq};
+use picky_krb::messages::{ApReq, ApReqInner, AsReq, KdcReq, KdcReqBody, TgsReq, TgtReq};
 use rand::rngs::OsRng;
 use rand::Rng;
 
-use super::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96};
-use crate::sspi::kerberos::{EncryptionParams, KERBEROS_VERSION, SERVICE_NAME, TGT_SERVICE_NAME};
+use crate::sspi::kerberos::ccache::CachedCredentials;
+use crate::sspi::kerberos::crypto::{self, new_cipher};
+use crate::sspi::kerberos::data_types::{EncKrbCredPart, EncKrbCredPartInner, KrbCred, KrbCredInner, KRB_CRED_TYPE};
+use crate::sspi::kerberos::{EncryptionParams, Principal, KERBEROS_VERSION, TGT_SERVICE_NAME};
 use crate::sspi::Result;
 use crate::{Error, ErrorKind};
 
@@ -46,15 +48,47 @@ const NONCE_LEN: usize = 4;
 const MAX_MICROSECONDS_IN_SECOND: u32 = 999_999;
 const MD5_CHECSUM_TYPE: [u8; 1] = [0x07];
 
+// [RFC 4121 4.1.1](https://www.rfc-editor.org/rfc/rfc4121#section-4.1.1)
+pub const GSS_CHECKSUM_TYPE: [u8; 3] = [0x00, 0x80, 0x03];
+pub const CHANNEL_BINDING_LEN: usize = 16;
+// length of the channel binding, the channel binding and the flags
+pub const GSS_CHECKSUM_LEN: usize = 4 + CHANNEL_BINDING_LEN + 4;
+// context flags: [RFC 2744 5.19](https://www.rfc-editor.org/rfc/rfc2744#section-5.19)
+pub const GSS_C_DELEG_FLAG: u32 = 0x01;
+pub const GSS_C_MUTUAL_FLAG: u32 = 0x02;
+pub const GSS_C_REPLAY_FLAG: u32 = 0x04;
+pub const GSS_C_SEQUENCE_FLAG: u32 = 0x08;
+pub const GSS_C_CONF_FLAG: u32 = 0x10;
+pub const GSS_C_INTEG_FLAG: u32 = 0x20;
+// [MS-KILE 3.2.5.2](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-kile/b1a9b91c-a9c4-4a2f-b8b0-9d6d0b9c7cd5):
+// the initiator answers the AP-REP of the acceptor with its own AP-REP
+pub const GSS_C_DCE_STYLE: u32 = 0x1000;
+pub const KRB_CRED_DELEGATION_OPTION: u16 = 1;
+
 const DEFAULT_AS_REQ_OPTIONS: [u8; 4] = [0x40, 0x81, 0x00, 0x10];
-const DEFAULT_TGS_REQ_OPTIONS: [u8; 4] = [0x40, 0x81, 0x00, 0x08];
+pub const DEFAULT_TGS_REQ_OPTIONS: [u8; 4] = [0x40, 0x81, 0x00, 0x00];
+// forwardable, renewable, canonicalize, enc-tkt-in-skey: the ticket is encrypted with the session key of the
+// additional TGT of the service: [RFC 4120 2.9.2](https://www.rfc-editor.org/rfc/rfc4120#section-2.9.2)
+pub const USER_TO_USER_TGS_REQ_OPTIONS: [u8; 4] = [0x40, 0x81, 0x00, 0x08];
+// forwardable, forwarded, renewable, canonicalize: the TGT delegated to the service
+pub const FORWARDED_TGT_REQ_OPTIONS: [u8; 4] = [0x60, 0x81, 0x00, 0x00];
+// forwardable, renewable, canonicalize: the S4U2Self ticket must be forwardable to be used in S4U2Proxy
+pub const S4U2SELF_TGS_REQ_OPTIONS: [u8; 4] = [0x40, 0x81, 0x00, 0x00];
+// forwardable, renewable, cname-in-addl-tkt, canonicalize: the client of the additional ticket is impersonated
+pub const S4U2PROXY_TGS_REQ_OPTIONS: [u8; 4] = [0x40, 0x83, 0x00, 0x00];
+const CNAME_IN_ADDL_TKT_OPTION: u8 = 0x02;
 const DEFAULT_PA_PAC_OPTIONS: [u8; 4] = [0x40, 0x00, 0x00, 0x00];
+// claims and resource-based constrained delegation:
+// [MS-KILE 2.2.10](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-kile/99721865-8eeb-4caf-9c04-6a8b0a02fd1a)
+const S4U2PROXY_PA_PAC_OPTIONS: [u8; 4] = [0x50, 0x00, 0x00, 0x00];
 
 // AP-REQ toggled options:
 // * mutual required
-// * use session key
 // other options are disabled
-const DEFAULT_AP_REQ_OPTIONS: [u8; 4] = [0x60, 0x00, 0x00, 0x00];
+pub const DEFAULT_AP_REQ_OPTIONS: [u8; 4] = [0x20, 0x00, 0x00, 0x00];
+// mutual required and use session key: the ticket is encrypted with the session key of the TGT of the service
+pub const USER_TO_USER_AP_REQ_OPTIONS: [u8; 4] = [0x60, 0x00, 0x00, 0x00];
+pub const MUTUAL_REQUIRED_AP_OPTION: u8 = 0x20;
 
 // [MS-KILE] 3.3.5.6.1 Client Principal Lookup
 // https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-kile/6435d3fb-8cf6-4df5-a156-1277690ed59c
@@ -66,7 +100,8 @@ fn get_client_principal_name_type(username: &str, _domain: &str) -> u8 {
     }
 }
 
-fn get_client_principal_realm(username: &str, domain: &str) -> String {
+/// Realm of the client: the realm of the enterprise name is taken from the name when the domain is not specified
+pub fn get_client_principal_realm(username: &str, domain: &str) -> String {
     if domain.is_empty() {
         if let Some((_left, right)) = username.split_once('@') {
             return right.to_string();
@@ -75,17 +110,30 @@ fn get_client_principal_realm(username: &str, domain: &str) -> String {
     domain.to_string()
 }
 
-pub fn generate_as_req_without_pre_auth(username: &str, domain: &str) -> Result<AsReq> {
-    let expiration_date = Utc::now()
-        .checked_add_signed(Duration::days(TGT_TICKET_LIFETIME_DAYS))
-        .unwrap();
+// etype field of the KDC requests
+fn encryption_types(enc_params: &EncryptionParams) -> Asn1SequenceOf<IntegerAsn1> {
+    Asn1SequenceOf::from(
+        enc_params
+            .permitted_encryption_types
+            .iter()
+            .map(|encryption_type| IntegerAsn1::from(vec![*encryption_type as u8]))
+            .collect::<Vec<_>>(),
+    )
+}
 
-    let pa_pac_request = PaData {
+pub fn generate_pa_pac_request() -> Result<PaData> {
+    Ok(PaData {
         padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_PAC_REQUEST_TYPE.to_vec())),
         padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&KerbPaPacRequest {
             include_pac: ExplicitContextTag0::from(true),
         })?)),
-    };
+    })
+}
+
+pub fn generate_as_req_body(username: &str, domain: &str, enc_params: &EncryptionParams) -> Result<KdcReqBody> {
+    let expiration_date = Utc::now()
+        .checked_add_signed(Duration::days(TGT_TICKET_LIFETIME_DAYS))
+        .unwrap();
 
     let address = sys_info::hostname().ok().map(|hostname| {
         ExplicitContextTag9::from(Asn1SequenceOf::from(vec![HostAddress {
@@ -97,59 +145,56 @@ pub fn generate_as_req_without_pre_auth(username: &str, domain: &str) -> Result<
     let name_type = get_client_principal_name_type(username, domain);
     let realm = &get_client_principal_realm(username, domain);
 
-    Ok(AsReq::from(KdcReq {
+    Ok(KdcReqBody {
+        kdc_options: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(
+            DEFAULT_AS_REQ_OPTIONS.to_vec(),
+        ))),
+        cname: Optional::from(Some(ExplicitContextTag1::from(PrincipalName {
+            name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![name_type])),
+            name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![KerberosStringAsn1::from(
+                IA5String::from_string(username.into())?,
+            )])),
+        }))),
+        realm: ExplicitContextTag2::from(Realm::from(IA5String::from_string(realm.into())?)),
+        sname: Optional::from(Some(ExplicitContextTag3::from(PrincipalName {
+            name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![NT_SRV_INST])),
+            name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![
+                KerberosStringAsn1::from(IA5String::from_string(TGT_SERVICE_NAME.into())?),
+                KerberosStringAsn1::from(IA5String::from_string(realm.into())?),
+            ])),
+        }))),
+        from: Optional::from(None),
+        till: ExplicitContextTag5::from(GeneralizedTimeAsn1::from(GeneralizedTime::from(expiration_date))),
+        rtime: Optional::from(Some(ExplicitContextTag6::from(GeneralizedTimeAsn1::from(
+            GeneralizedTime::from(expiration_date),
+        )))),
+        nonce: ExplicitContextTag7::from(IntegerAsn1::from(OsRng::new()?.gen::<[u8; NONCE_LEN]>().to_vec())),
+        etype: ExplicitContextTag8::from(encryption_types(enc_params)),
+        addresses: Optional::from(address),
+        enc_authorization_data: Optional::from(None),
+        additional_tickets: Optional::from(None),
+    })
+}
+
+/// AS-REQ with the given pre-authentication data
+pub fn generate_as_req_with_pa_datas(req_body: KdcReqBody, pa_datas: Vec<PaData>) -> AsReq {
+    AsReq::from(KdcReq {
         pvno: ExplicitContextTag1::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
         msg_type: ExplicitContextTag2::from(IntegerAsn1::from(vec![AS_REQ_MSG_TYPE])),
-        padata: Optional::from(Some(ExplicitContextTag3::from(Asn1SequenceOf::from(vec![
-            pa_pac_request,
-        ])))),
-        req_body: ExplicitContextTag4::from(KdcReqBody {
-            kdc_options: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(
-                DEFAULT_AS_REQ_OPTIONS.to_vec(),
-            ))),
-            cname: Optional::from(Some(ExplicitContextTag1::from(PrincipalName {
-                name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![name_type])),
-                name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![KerberosStringAsn1::from(
-                    IA5String::from_string(username.into())?,
-                )])),
-            }))),
-            realm: ExplicitContextTag2::from(Realm::from(IA5String::from_string(realm.into())?)),
-            sname: Optional::from(Some(ExplicitContextTag3::from(PrincipalName {
-                name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![NT_SRV_INST])),
-                name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![
-                    KerberosStringAsn1::from(IA5String::from_string(TGT_SERVICE_NAME.into())?),
-                    KerberosStringAsn1::from(IA5String::from_string(realm.into())?),
-                ])),
-            }))),
-            from: Optional::from(None),
-            till: ExplicitContextTag5::from(GeneralizedTimeAsn1::from(GeneralizedTime::from(expiration_date))),
-            rtime: Optional::from(Some(ExplicitContextTag6::from(GeneralizedTimeAsn1::from(
-                GeneralizedTime::from(expiration_date),
-            )))),
-            nonce: ExplicitContextTag7::from(IntegerAsn1::from(OsRng::new()?.gen::<[u8; NONCE_LEN]>().to_vec())),
-            etype: ExplicitContextTag8::from(Asn1SequenceOf::from(vec![
-                IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8]),
-                IntegerAsn1::from(vec![AES128_CTS_HMAC_SHA1_96 as u8]),
-            ])),
-            addresses: Optional::from(address),
-            enc_authorization_data: Optional::from(None),
-            additional_tickets: Optional::from(None),
-        }),
-    }))
+        padata: Optional::from(Some(ExplicitContextTag3::from(Asn1SequenceOf::from(pa_datas)))),
+        req_body: ExplicitContextTag4::from(req_body),
+    })
 }
 
-pub fn generate_as_req(
-    username: &str,
-    salt: &[u8],
-    password: &str,
-    domain: &str,
-    enc_params: &EncryptionParams,
-) -> Result<AsReq> {
-    let expiration_date = Utc::now()
-        .checked_add_signed(Duration::days(TGT_TICKET_LIFETIME_DAYS))
-        .unwrap();
+pub fn generate_as_req_without_pre_auth(username: &str, domain: &str, enc_params: &EncryptionParams) -> Result<AsReq> {
+    Ok(generate_as_req_with_pa_datas(
+        generate_as_req_body(username, domain, enc_params)?,
+        vec![generate_pa_pac_request()?],
+    ))
+}
 
-    let current_date = Utc::now();
+/// DER-encoded PA-ENC-TS-ENC of the current time
+pub fn generate_pa_enc_ts_enc(current_date: DateTime<Utc>) -> Result<Vec<u8>> {
     let mut microseconds = current_date.timestamp_subsec_micros();
     if microseconds > MAX_MICROSECONDS_IN_SECOND {
         microseconds = MAX_MICROSECONDS_IN_SECOND;
@@ -161,81 +206,40 @@ pub fn generate_as_req(
             microseconds.to_be_bytes().to_vec(),
         )))),
     };
-    let timestamp_bytes = picky_asn1_der::to_vec(&timestamp)?;
 
-    let cipher = new_kerberos_cipher(enc_params.encryption_type.unwrap_or(AES256_CTS_HMAC_SHA1_96))?;
-    let key = cipher.generate_key_from_string(password, salt);
+    Ok(picky_asn1_der::to_vec(&timestamp)?)
+}
+
+pub fn generate_as_req(
+    username: &str,
+    key: &[u8],
+    domain: &str,
+    enc_params: &EncryptionParams,
+    current_date: DateTime<Utc>,
+) -> Result<AsReq> {
+    let timestamp_bytes = generate_pa_enc_ts_enc(current_date)?;
+
+    let encryption_type = enc_params.encryption_type();
+    let cipher = new_cipher(encryption_type)?;
 
-    let encrypted_timestamp = cipher.encrypt(&key, PA_ENC_TIMESTAMP_KEY_USAGE, &timestamp_bytes);
+    let encrypted_timestamp = cipher.encrypt(key, PA_ENC_TIMESTAMP_KEY_USAGE, &timestamp_bytes);
 
     let pa_enc_timestamp = PaData {
         padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_ENC_TIMESTAMP.to_vec())),
         padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&EncryptedData {
-            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
             kvno: Optional::from(None),
             cipher: ExplicitContextTag2::from(OctetStringAsn1::from(encrypted_timestamp)),
         })?)),
     };
 
-    let pa_pac_request = PaData {
-        padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_PAC_REQUEST_TYPE.to_vec())),
-        padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&KerbPaPacRequest {
-            include_pac: ExplicitContextTag0::from(true),
-        })?)),
-    };
-
-    let address = sys_info::hostname().ok().map(|hostname| {
-        ExplicitContextTag9::from(Asn1SequenceOf::from(vec![HostAddress {
-            addr_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![NET_BIOS_ADDR_TYPE])),
-            address: ExplicitContextTag1::from(OctetStringAsn1::from(hostname.as_bytes().to_vec())),
-        }]))
-    });
-
-    let name_type = get_client_principal_name_type(username, domain);
-    let realm = &get_client_principal_realm(username, domain);
-
-    Ok(AsReq::from(KdcReq {
-        pvno: ExplicitContextTag1::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
-        msg_type: ExplicitContextTag2::from(IntegerAsn1::from(vec![AS_REQ_MSG_TYPE])),
-        padata: Optional::from(Some(ExplicitContextTag3::from(Asn1SequenceOf::from(vec![
-            pa_enc_timestamp,
-            pa_pac_request,
-        ])))),
-        req_body: ExplicitContextTag4::from(KdcReqBody {
-            kdc_options: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(
-                DEFAULT_AS_REQ_OPTIONS.to_vec(),
-            ))),
-            cname: Optional::from(Some(ExplicitContextTag1::from(PrincipalName {
-                name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![name_type])),
-                name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![KerberosStringAsn1::from(
-                    IA5String::from_string(username.into())?,
-                )])),
-            }))),
-            realm: ExplicitContextTag2::from(Realm::from(IA5String::from_string(realm.into())?)),
-            sname: Optional::from(Some(ExplicitContextTag3::from(PrincipalName {
-                name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![NT_SRV_INST])),
-                name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![
-                    KerberosStringAsn1::from(IA5String::from_string(TGT_SERVICE_NAME.into())?),
-                    KerberosStringAsn1::from(IA5String::from_string(realm.into())?),
-                ])),
-            }))),
-            from: Optional::from(None),
-            till: ExplicitContextTag5::from(GeneralizedTimeAsn1::from(GeneralizedTime::from(expiration_date))),
-            rtime: Optional::from(Some(ExplicitContextTag6::from(GeneralizedTimeAsn1::from(
-                GeneralizedTime::from(expiration_date),
-            )))),
-            nonce: ExplicitContextTag7::from(IntegerAsn1::from(OsRng::new()?.gen::<[u8; NONCE_LEN]>().to_vec())),
-            etype: ExplicitContextTag8::from(Asn1SequenceOf::from(vec![
-                IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8]),
-                IntegerAsn1::from(vec![AES128_CTS_HMAC_SHA1_96 as u8]),
-            ])),
-            addresses: Optional::from(address),
-            enc_authorization_data: Optional::from(None),
-            additional_tickets: Optional::from(None),
-        }),
-    }))
+    Ok(generate_as_req_with_pa_datas(
+        generate_as_req_body(username, domain, enc_params)?,
+        vec![pa_enc_timestamp, generate_pa_pac_request()?],
+    ))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn generate_tgs_req(
     realm: &str,
     service_principal: &str,
@@ -243,49 +247,45 @@ pub fn generate_tgs_req(
     ticket: Ticket,
     mut authenticator: &mut Authenticator,
     additional_tickets: Option<Vec<Ticket>>,
+    kdc_options: [u8; 4],
     enc_params: &EncryptionParams,
 ) -> Result<TgsReq> {
-    let divider = service_principal.find('/').ok_or_else(|| Error {
-        error_type: ErrorKind::InvalidParameter,
-        description: "Invalid service principal name: missing '/'".into(),
-    })?;
-
-    if divider == 0 || divider == service_principal.len() - 1 {
-        return Err(Error {
-            error_type: ErrorKind::InvalidParameter,
-            description: "Invalid service principal name".into(),
-        });
+    // `service/host[/service name]` or the name of the service account itself, e.g. in the S4U2Self request
+    let components = service_principal.split('/').collect::<Vec<_>>();
+    if components.iter().any(|component| component.is_empty()) {
+        return Err(Error::new(
+            ErrorKind::InvalidParameter,
+            "Invalid service principal name".into(),
+        ));
     }
-
-    let service_name = &service_principal[0..divider];
-    // `divider + 1` - do not include '/' char
-    let service_principal_name = &service_principal[(divider + 1)..];
+    let name_type = if components.len() > 1 {
+        NT_SRV_INST
+    } else {
+        NT_PRINCIPAL
+    };
 
     let expiration_date = Utc::now()
         .checked_add_signed(Duration::days(TGT_TICKET_LIFETIME_DAYS))
         .unwrap();
 
     let req_body = KdcReqBody {
-        kdc_options: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(
-            DEFAULT_TGS_REQ_OPTIONS.to_vec(),
-        ))),
+        kdc_options: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(kdc_options.to_vec()))),
         cname: Optional::from(None),
         realm: ExplicitContextTag2::from(Realm::from(IA5String::from_str(realm)?)),
         sname: Optional::from(Some(ExplicitContextTag3::from(PrincipalName {
-            name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![NT_SRV_INST])),
-            name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![
-                KerberosStringAsn1::from(IA5String::from_string(service_name.into())?),
-                KerberosStringAsn1::from(IA5String::from_string(service_principal_name.into())?),
-            ])),
+            name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![name_type])),
+            name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(
+                components
+                    .into_iter()
+                    .map(|component| Ok(KerberosStringAsn1::from(IA5String::from_string(component.into())?)))
+                    .collect::<Result<Vec<_>>>()?,
+            )),
         }))),
         from: Optional::from(None),
         till: ExplicitContextTag5::from(GeneralizedTimeAsn1::from(GeneralizedTime::from(expiration_date))),
         rtime: Optional::from(None),
         nonce: ExplicitContextTag7::from(IntegerAsn1::from(OsRng::new()?.gen::<[u8; NONCE_LEN]>().to_vec())),
-        etype: ExplicitContextTag8::from(Asn1SequenceOf::from(vec![
-            IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8]),
-            IntegerAsn1::from(vec![AES128_CTS_HMAC_SHA1_96 as u8]),
-        ])),
+        etype: ExplicitContextTag8::from(encryption_types(enc_params)),
         addresses: Optional::from(None),
         enc_authorization_data: Optional::from(None),
         additional_tickets: Optional::from(
@@ -314,7 +314,11 @@ pub fn generate_tgs_req(
         padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_PAC_OPTIONS_TYPE.to_vec())),
         padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&PaPacOptions {
             flags: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(
-                DEFAULT_PA_PAC_OPTIONS.to_vec(),
+                if kdc_options[1] & CNAME_IN_ADDL_TKT_OPTION != 0 {
+                    S4U2PROXY_PA_PAC_OPTIONS.to_vec()
+                } else {
+                    DEFAULT_PA_PAC_OPTIONS.to_vec()
+                },
             ))),
         })?)),
     };
@@ -330,8 +334,11 @@ pub fn generate_tgs_req(
     }))
 }
 
-pub fn generate_authenticator_for_tgs_ap_req(kdc_rep: &KdcRep) -> Result<Authenticator> {
-    let current_date = Utc::now();
+pub fn generate_authenticator_for_tgs_ap_req(
+    cname: &PrincipalName,
+    crealm: &Realm,
+    current_date: DateTime<Utc>,
+) -> Result<Authenticator> {
     let mut microseconds = current_date.timestamp_subsec_micros();
     if microseconds > MAX_MICROSECONDS_IN_SECOND {
         microseconds = MAX_MICROSECONDS_IN_SECOND;
@@ -339,8 +346,8 @@ pub fn generate_authenticator_for_tgs_ap_req(kdc_rep: &KdcRep) -> Result<Authent
 
     Ok(Authenticator::from(AuthenticatorInner {
         authenticator_bno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
-        crealm: ExplicitContextTag1::from(kdc_rep.crealm.0.clone()),
-        cname: ExplicitContextTag2::from(kdc_rep.cname.0.clone()),
+        crealm: ExplicitContextTag1::from(crealm.clone()),
+        cname: ExplicitContextTag2::from(cname.clone()),
         cksum: Optional::from(None),
         cusec: ExplicitContextTag4::from(IntegerAsn1::from(microseconds.to_be_bytes().to_vec())),
         ctime: ExplicitContextTag5::from(KerberosTime::from(GeneralizedTime::from(current_date))),
@@ -352,8 +359,18 @@ pub fn generate_authenticator_for_tgs_ap_req(kdc_rep: &KdcRep) -> Result<Authent
     }))
 }
 
-pub fn generate_authenticator_for_ap_req(kdc_rep: &KdcRep, seq_num: u32) -> Result<Authenticator> {
-    let current_date = Utc::now();
+pub fn generate_authenticator_for_ap_req(
+    cname: &PrincipalName,
+    crealm: &Realm,
+    seq_num: u32,
+    encryption_type: i32,
+    current_date: DateTime<Utc>,
+    krb_cred: Option<&KrbCred>,
+    context_flags: u32,
+) -> Result<Authenticator> {
+    let mut sub_key = vec![0; crypto::key_len(encryption_type)?];
+    OsRng::new()?.fill(sub_key.as_mut_slice());
+
     let mut microseconds = current_date.timestamp_subsec_micros();
     if microseconds > MAX_MICROSECONDS_IN_SECOND {
         microseconds = MAX_MICROSECONDS_IN_SECOND;
@@ -361,20 +378,17 @@ pub fn generate_authenticator_for_ap_req(kdc_rep: &KdcRep, seq_num: u32) -> Resu
 
     Ok(Authenticator::from(AuthenticatorInner {
         authenticator_bno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
-        crealm: ExplicitContextTag1::from(kdc_rep.crealm.0.clone()),
-        cname: ExplicitContextTag2::from(kdc_rep.cname.0.clone()),
+        crealm: ExplicitContextTag1::from(crealm.clone()),
+        cname: ExplicitContextTag2::from(cname.clone()),
         cksum: Optional::from(Some(ExplicitContextTag3::from(Checksum {
-            cksumtype: ExplicitContextTag0::from(IntegerAsn1::from(vec![0x00, 0x80, 0x03])),
-            checksum: ExplicitContextTag1::from(OctetStringAsn1::from(vec![
-                0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x00, 0x3E, 0x00, 0x00, 0x00,
-            ])),
+            cksumtype: ExplicitContextTag0::from(IntegerAsn1::from(GSS_CHECKSUM_TYPE.to_vec())),
+            checksum: ExplicitContextTag1::from(OctetStringAsn1::from(generate_gss_checksum(krb_cred, context_flags)?)),
         }))),
         cusec: ExplicitContextTag4::from(IntegerAsn1::from(microseconds.to_be_bytes().to_vec())),
         ctime: ExplicitContextTag5::from(KerberosTime::from(GeneralizedTime::from(current_date))),
         subkey: Optional::from(Some(ExplicitContextTag6::from(EncryptionKey {
-            key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
-            key_value: ExplicitContextTag1::from(OctetStringAsn1::from(OsRng::new()?.gen::<[u8; 32]>().to_vec())),
+            key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
+            key_value: ExplicitContextTag1::from(OctetStringAsn1::from(sub_key)),
         }))),
         seq_number: Optional::from(Some(ExplicitContextTag7::from(IntegerAsn1::from_bytes_be_unsigned(
             seq_num.to_be_bytes().to_vec(),
@@ -383,14 +397,80 @@ pub fn generate_authenticator_for_ap_req(kdc_rep: &KdcRep, seq_num: u32) -> Resu
     }))
 }
 
+// [RFC 4121 4.1.1](https://www.rfc-editor.org/rfc/rfc4121#section-4.1.1): the channel binding is empty,
+// the delegated credentials follow the flags
+fn generate_gss_checksum(krb_cred: Option<&KrbCred>, flags: u32) -> Result<Vec<u8>> {
+    let mut checksum = Vec::with_capacity(GSS_CHECKSUM_LEN);
+    checksum.write_u32::<LittleEndian>(CHANNEL_BINDING_LEN as u32)?;
+    checksum.extend_from_slice(&[0; CHANNEL_BINDING_LEN]);
+
+    match krb_cred {
+        Some(krb_cred) => {
+            let krb_cred = picky_asn1_der::to_vec(krb_cred)?;
+
+            checksum.write_u32::<LittleEndian>(flags | GSS_C_DELEG_FLAG)?;
+            checksum.write_u16::<LittleEndian>(KRB_CRED_DELEGATION_OPTION)?;
+            checksum.write_u16::<LittleEndian>(krb_cred.len() as u16)?;
+            checksum.extend_from_slice(&krb_cred);
+        }
+        None => checksum.write_u32::<LittleEndian>(flags)?,
+    }
+
+    Ok(checksum)
+}
+
+/// KRB-CRED message with the credentials delegated to the service, encrypted with the session key of the service
+/// ticket: [RFC 4120 3.6](https://www.rfc-editor.org/rfc/rfc4120#section-3.6)
+pub fn generate_krb_cred(
+    credentials: &CachedCredentials,
+    session_key: &[u8],
+    encryption_type: i32,
+    current_date: DateTime<Utc>,
+) -> Result<KrbCred> {
+    let enc_krb_cred_part = EncKrbCredPart::from(EncKrbCredPartInner {
+        ticket_info: ExplicitContextTag0::from(Asn1SequenceOf::from(vec![credentials.krb_cred_info()?])),
+        nonce: Optional::from(None),
+        timestamp: Optional::from(Some(ExplicitContextTag2::from(KerberosTime::from(
+            GeneralizedTime::from(current_date),
+        )))),
+        usec: Optional::from(Some(ExplicitContextTag3::from(IntegerAsn1::from_bytes_be_unsigned(
+            current_date
+                .timestamp_subsec_micros()
+                .min(MAX_MICROSECONDS_IN_SECOND)
+                .to_be_bytes()
+                .to_vec(),
+        )))),
+        s_address: Optional::from(None),
+        r_address: Optional::from(None),
+    });
+
+    let cipher = new_cipher(encryption_type)?;
+    let enc_part = cipher.encrypt(
+        session_key,
+        KEY_USAGE_KRB_CRED_ENC_PART,
+        &picky_asn1_der::to_vec(&enc_krb_cred_part)?,
+    );
+
+    Ok(KrbCred::from(KrbCredInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![KRB_CRED_TYPE])),
+        tickets: ExplicitContextTag2::from(Asn1SequenceOf::from(vec![credentials.decode_ticket()?])),
+        enc_part: ExplicitContextTag3::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(enc_part)),
+        }),
+    }))
+}
+
 pub fn generate_tgs_ap_req(
     ticket: Ticket,
     session_key: &[u8],
     authenticator: &Authenticator,
     enc_params: &EncryptionParams,
 ) -> Result<ApReq> {
-    let encryption_type = enc_params.encryption_type.unwrap_or(AES256_CTS_HMAC_SHA1_96);
-    let cipher = new_kerberos_cipher(encryption_type)?;
+    let encryption_type = enc_params.encryption_type();
+    let cipher = new_cipher(encryption_type)?;
 
     let encrypted_authenticator = cipher.encrypt(
         session_key,
@@ -418,10 +498,11 @@ pub fn generate_ap_req(
     ticket: Ticket,
     session_key: &[u8],
     authenticator: &Authenticator,
+    ap_options: [u8; 4],
     enc_params: &EncryptionParams,
 ) -> Result<ApReq> {
-    let encryption_type = enc_params.encryption_type.unwrap_or(AES256_CTS_HMAC_SHA1_96);
-    let cipher = new_kerberos_cipher(encryption_type)?;
+    let encryption_type = enc_params.encryption_type();
+    let cipher = new_cipher(encryption_type)?;
 
     let encrypted_authenticator = cipher.encrypt(
         session_key,
@@ -432,7 +513,7 @@ pub fn generate_ap_req(
     Ok(ApReq::from(ApReqInner {
         pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
         msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![AP_REQ_MSG_TYPE])),
-        ap_options: ExplicitContextTag2::from(ApOptions::from(BitString::with_bytes(DEFAULT_AP_REQ_OPTIONS.to_vec()))),
+        ap_options: ExplicitContextTag2::from(ApOptions::from(BitString::with_bytes(ap_options.to_vec()))),
         ticket: ExplicitContextTag3::from(ticket),
         authenticator: ExplicitContextTag4::from(EncryptedData {
             etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
@@ -450,27 +531,25 @@ pub fn get_mech_list() -> MechTypeList {
     ])
 }
 
-pub fn generate_neg_token_init(username: &str) -> Result<ApplicationTag0<GssApiNegInit>> {
+// the TGT-REQ asks the target service for its TGT for the user-to-user authentication
+pub fn generate_neg_token_init(
+    service: &Principal,
+    mech_types: MechTypeList,
+) -> Result<ApplicationTag0<GssApiNegInit>> {
     let krb5_neg_token_init: ApplicationTag<_, 0> = ApplicationTag::from(KrbMessage {
         krb5_oid: ObjectIdentifierAsn1::from(ObjectIdentifier::try_from(KRB5_USER_TO_USER).unwrap()),
         krb5_token_id: TGT_REQ_TOKEN_ID,
         krb_msg: TgtReq {
             pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
             msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![TGT_REQ_MSG_TYPE])),
-            server_name: ExplicitContextTag2::from(PrincipalName {
-                name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![NT_SRV_INST])),
-                name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![
-                    KerberosStringAsn1::from(IA5String::from_string(SERVICE_NAME.into())?),
-                    KerberosStringAsn1::from(IA5String::from_string(username.into())?),
-                ])),
-            }),
+            server_name: ExplicitContextTag2::from(service.principal_name()?),
         },
     });
 
     Ok(ApplicationTag0(GssApiNegInit {
         oid: ObjectIdentifierAsn1::from(ObjectIdentifier::try_from(SPNEGO).unwrap()),
         neg_token_init: ExplicitContextTag0::from(NegTokenInit {
-            mech_types: Optional::from(Some(ExplicitContextTag0::from(get_mech_list()))),
+            mech_types: Optional::from(Some(ExplicitContextTag0::from(mech_types))),
             req_flags: Optional::from(None),
             mech_token: Optional::from(Some(ExplicitContextTag2::from(OctetStringAsn1::from(
                 picky_asn1_der::to_vec(&krb5_neg_token_init)?,
@@ -480,9 +559,9 @@ pub fn generate_neg_token_init(username: &str) -> Result<ApplicationTag0<GssApiN
     }))
 }
 
-pub fn generate_neg_ap_req(ap_req: ApReq) -> Result<ExplicitContextTag1<NegTokenTarg>> {
+pub fn generate_neg_ap_req(ap_req: ApReq, krb5_oid: ObjectIdentifier) -> Result<ExplicitContextTag1<NegTokenTarg>> {
     let krb_blob: ApplicationTag<_, 0> = ApplicationTag(KrbMessage {
-        krb5_oid: ObjectIdentifierAsn1::from(ObjectIdentifier::try_from(KRB5_USER_TO_USER).unwrap()),
+        krb5_oid: ObjectIdentifierAsn1::from(krb5_oid),
         krb5_token_id: AP_REQ_TOKEN_ID,
         krb_msg: ap_req,
     });
@@ -1,6 +1,20 @@
 pub mod extractors;
 pub mod generators;
+#[cfg(test)]
+mod test;
 
 // supported encryption types
 pub const AES128_CTS_HMAC_SHA1_96: i32 = kerberos_constants::etypes::AES128_CTS_HMAC_SHA1_96;
 pub const AES256_CTS_HMAC_SHA1_96: i32 = kerberos_constants::etypes::AES256_CTS_HMAC_SHA1_96;
+pub const AES128_CTS_HMAC_SHA256_128: i32 = 19;
+pub const AES256_CTS_HMAC_SHA384_192: i32 = 20;
+pub const RC4_HMAC: i32 = kerberos_constants::etypes::RC4_HMAC;
+
+// encryption types implemented by the client, in the default order of preference
+pub const SUPPORTED_ENCRYPTION_TYPES: [i32; 5] = [
+    AES256_CTS_HMAC_SHA1_96,
+    AES128_CTS_HMAC_SHA1_96,
+    AES256_CTS_HMAC_SHA384_192,
+    AES128_CTS_HMAC_SHA256_128,
+    RC4_HMAC,
+];
@@ -0,0 +1,60 @@
+use picky_asn1::restricted_string::IA5String;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::constants::types::PA_ETYPE_INFO2_TYPE;
+use picky_krb::data_types::{EtypeInfo2, EtypeInfo2Entry, KerberosStringAsn1, PaData};
+
+use super::extractors::{extract_etype_info, EtypeInfo};
+use super::{AES256_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA384_192, RC4_HMAC};
+
+fn etype_info_2_entry(encryption_type: i32, salt: Option<&str>, s2kparams: Option<&[u8]>) -> EtypeInfo2Entry {
+    EtypeInfo2Entry {
+        etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
+        salt: Optional::from(salt.map(|salt| {
+            ExplicitContextTag1::from(KerberosStringAsn1::from(IA5String::from_string(salt.into()).unwrap()))
+        })),
+        s2kparams: Optional::from(
+            s2kparams.map(|s2kparams| ExplicitContextTag2::from(OctetStringAsn1::from(s2kparams.to_vec()))),
+        ),
+    }
+}
+
+#[test]
+fn extract_etype_info_returns_all_entries() {
+    let etype_info_2 = EtypeInfo2::from(vec![
+        etype_info_2_entry(AES256_CTS_HMAC_SHA384_192, Some("EXAMPLE.COMuser"), Some(&[0, 1, 0, 0])),
+        etype_info_2_entry(AES256_CTS_HMAC_SHA1_96, Some("EXAMPLE.COMalias"), None),
+        etype_info_2_entry(RC4_HMAC, None, None),
+    ]);
+    let pa_datas = [PaData {
+        padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_ETYPE_INFO2_TYPE.to_vec())),
+        padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&etype_info_2).unwrap())),
+    }];
+
+    assert_eq!(
+        extract_etype_info(&pa_datas).unwrap(),
+        [
+            EtypeInfo {
+                encryption_type: AES256_CTS_HMAC_SHA384_192,
+                salt: Some("EXAMPLE.COMuser".into()),
+                s2kparams: Some(vec![0, 1, 0, 0]),
+            },
+            EtypeInfo {
+                encryption_type: AES256_CTS_HMAC_SHA1_96,
+                salt: Some("EXAMPLE.COMalias".into()),
+                s2kparams: None,
+            },
+            EtypeInfo {
+                encryption_type: RC4_HMAC,
+                salt: None,
+                s2kparams: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn extract_etype_info_without_pa_etype_info_2_is_empty() {
+    assert!(extract_etype_info(&[]).unwrap().is_empty());
+}
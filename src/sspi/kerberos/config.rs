@@ -1,12 +1,18 @@
-use std::env;
 use std::fmt::Debug;
-use std::str::FromStr;
+use std::sync::Arc;
 
 use url::Url;
 
+use super::ccache::{CachedCredentials, CredentialsCache};
+use super::client::SUPPORTED_ENCRYPTION_TYPES;
+use super::kdc_locator::{KdcLocator, SrvResolver};
 #[cfg(feature = "network_client")]
 use super::network_client::reqwest_network_client::ReqwestNetworkClient;
-use super::SSPI_KDC_URL_ENV;
+use super::network_client::NetworkClient;
+use crate::sspi::Result;
+
+// the same as the referral hops limit of MIT Kerberos
+const DEFAULT_MAX_REFERRALS: u32 = 10;
 
 #[derive(Debug, Clone)]
 pub enum KdcType {
@@ -14,50 +20,107 @@ pub enum KdcType {
     KdcProxy,
 }
 
+impl KdcType {
+    pub fn from_url(url: &Url) -> Self {
+        match url.scheme() {
+            "tcp" => KdcType::Kdc,
+            "udp" => KdcType::Kdc,
+            "http" => KdcType::KdcProxy,
+            "https" => KdcType::KdcProxy,
+            _ => KdcType::Kdc,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct KerberosConfig {
-    pub url: Url,
-    pub kdc_type: KdcType,
-    pub network_client: Box<ReqwestNetworkClient>,
+    pub kdc_locator: KdcLocator,
+    pub network_client: Box<dyn NetworkClient>,
+    /// Tickets obtained by the client are stored in the cache and reused by the following security contexts
+    pub credentials_cache: Option<Arc<dyn CredentialsCache>>,
+    /// Encryption types the client requests and accepts, in the order of preference.
+    /// It is the analog of the `permitted_enctypes` of the krb5.conf file
+    pub permitted_encryption_types: Vec<i32>,
+    /// DER-encoded certificates the PKINIT replies are verified with.
+    /// The KDC certificate must be one of them or must be issued by one of them
+    pub kdc_trust_anchors: Vec<Vec<u8>>,
+    /// Ticket the FAST armor is made of, e.g. the machine TGT or the TGT of the anonymous PKINIT.
+    /// When it is set, the password and keytab AS exchanges and all TGS exchanges are armored
+    pub armor_ticket: Option<CachedCredentials>,
+    /// The maximum number of the KDC referrals followed to get the ticket of the service of the other realm
+    pub max_referrals: u32,
 }
 
 impl KerberosConfig {
-    pub fn get_kdc_env() -> Option<(Url, KdcType)> {
-        let mut kdc_url_env = env::var(SSPI_KDC_URL_ENV).expect("SSPI_KDC_URL environment variable must be set!");
-        if !kdc_url_env.contains("://") {
-            kdc_url_env = format!("tcp://{}", kdc_url_env);
+    /// Creates the config for the KDC or KDC Proxy located at `url`. The KDC type is deduced from the url scheme
+    pub fn new(url: Url, network_client: Box<dyn NetworkClient>) -> Self {
+        Self::new_with_kdc_locator(KdcLocator::new(url), network_client)
+    }
+
+    pub fn new_with_kdc_locator(kdc_locator: KdcLocator, network_client: Box<dyn NetworkClient>) -> Self {
+        Self {
+            kdc_locator,
+            network_client,
+            credentials_cache: None,
+            permitted_encryption_types: SUPPORTED_ENCRYPTION_TYPES.to_vec(),
+            kdc_trust_anchors: Vec::new(),
+            armor_ticket: None,
+            max_referrals: DEFAULT_MAX_REFERRALS,
         }
-        let kdc_url = Url::from_str(&kdc_url_env).unwrap();
-        let kdc_type = match kdc_url.scheme() {
-            "tcp" => KdcType::Kdc,
-            "udp" => KdcType::Kdc,
-            "http" => KdcType::KdcProxy,
-            "https" => KdcType::KdcProxy,
-            _ => KdcType::Kdc,
-        };
-        Some((kdc_url, kdc_type))
     }
 
-    pub fn new_with_network_client(network_client: Box<ReqwestNetworkClient>) -> Self {
-        if let Some((kdc_url, kdc_type)) = Self::get_kdc_env() {
-            Self {
-                url: kdc_url,
-                kdc_type,
-                network_client,
-            }
-        } else {
-            panic!("{} environment variable is not set properly!", SSPI_KDC_URL_ENV);
+    pub fn new_with_network_client(network_client: Box<dyn NetworkClient>) -> Result<Self> {
+        Ok(Self::new_with_kdc_locator(KdcLocator::from_env()?, network_client))
+    }
+
+    pub fn with_credentials_cache(self, credentials_cache: Arc<dyn CredentialsCache>) -> Self {
+        Self {
+            credentials_cache: Some(credentials_cache),
+            ..self
+        }
+    }
+
+    /// Restricts the encryption types, e.g. to forbid RC4-HMAC or to require AES only
+    pub fn with_permitted_encryption_types(self, permitted_encryption_types: Vec<i32>) -> Self {
+        Self {
+            permitted_encryption_types,
+            ..self
+        }
+    }
+
+    pub fn with_kdc_trust_anchors(self, kdc_trust_anchors: Vec<Vec<u8>>) -> Self {
+        Self {
+            kdc_trust_anchors,
+            ..self
+        }
+    }
+
+    /// Enables the Kerberos armoring (FAST) with the armor ticket
+    pub fn with_armor_ticket(self, armor_ticket: CachedCredentials) -> Self {
+        Self {
+            armor_ticket: Some(armor_ticket),
+            ..self
         }
     }
 
+    pub fn with_max_referrals(self, max_referrals: u32) -> Self {
+        Self { max_referrals, ..self }
+    }
+
+    /// Enables the KDC lookup by the DNS SRV records
+    pub fn with_srv_resolver(mut self, srv_resolver: Arc<dyn SrvResolver>) -> Self {
+        self.kdc_locator.srv_resolver = Some(srv_resolver);
+        self
+    }
+
     #[cfg(feature = "network_client")]
-    pub fn from_env() -> Self {
+    pub fn from_env() -> Result<Self> {
         let network_client = Box::new(ReqwestNetworkClient::new());
         Self::new_with_network_client(network_client)
     }
 
     #[cfg(not(feature = "network_client"))]
-    pub fn from_env(network_client: Box<ReqwestNetworkClient>) -> Self {
+    pub fn from_env(network_client: Box<dyn NetworkClient>) -> Result<Self> {
         Self::new_with_network_client(network_client)
     }
 }
@@ -65,9 +128,13 @@ impl KerberosConfig {
 impl Clone for KerberosConfig {
     fn clone(&self) -> Self {
         Self {
-            url: self.url.clone(),
-            kdc_type: self.kdc_type.clone(),
+            kdc_locator: self.kdc_locator.clone(),
             network_client: self.network_client.clone(),
+            credentials_cache: self.credentials_cache.clone(),
+            permitted_encryption_types: self.permitted_encryption_types.clone(),
+            kdc_trust_anchors: self.kdc_trust_anchors.clone(),
+            armor_ticket: self.armor_ticket.clone(),
+            max_referrals: self.max_referrals,
         }
     }
 }
@@ -0,0 +1,173 @@
+use picky_asn1::restricted_string::IA5String;
+use picky_asn1::wrapper::{Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, IntegerAsn1};
+use picky_krb::data_types::{KerberosStringAsn1, PrincipalName, Realm};
+
+use super::keytab::Keytab;
+use super::pkinit::PkInitCredentials;
+use super::utils::integer_to_u32;
+use crate::sspi::ntlm::AuthIdentityBuffers;
+use crate::sspi::{Error, ErrorKind, Result};
+use crate::AuthIdentity;
+
+pub(crate) const NT_PRINCIPAL: u32 = 1;
+pub(crate) const NT_SRV_INST: u32 = 2;
+
+/// Kerberos principal stored in keytabs and credential caches
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Principal {
+    pub realm: String,
+    pub components: Vec<String>,
+    pub name_type: u32,
+}
+
+impl Principal {
+    /// Creates the principal from the name in the `component/component` form
+    pub fn new(name: &str, realm: &str) -> Self {
+        Self {
+            realm: realm.to_owned(),
+            components: name.split('/').map(ToOwned::to_owned).collect(),
+            name_type: NT_PRINCIPAL,
+        }
+    }
+
+    /// Parses the service principal name of the target: `service/host[:port][/service name][@REALM]`, or the
+    /// `service@host` host-based service name of [RFC 2743 4.1](https://www.rfc-editor.org/rfc/rfc2743#section-4.1).
+    /// The host name is lowercased without the trailing dot, the realm defaults to the realm of the client
+    pub fn from_service_principal_name(spn: &str, default_realm: &str) -> Result<Self> {
+        let (name, realm) = if spn.contains('/') {
+            match spn.rsplit_once('@') {
+                Some((name, realm)) => (name.to_owned(), realm.to_ascii_uppercase()),
+                None => (spn.to_owned(), default_realm.to_owned()),
+            }
+        } else {
+            match spn.split_once('@') {
+                Some((service, host)) => (format!("{}/{}", service, host), default_realm.to_owned()),
+                None => (spn.to_owned(), default_realm.to_owned()),
+            }
+        };
+
+        let mut components = name.split('/').map(ToOwned::to_owned).collect::<Vec<_>>();
+        if components.len() < 2 || components.iter().any(String::is_empty) || realm.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                format!("Invalid service principal name: {}", spn),
+            ));
+        }
+        components[1] = canonical_host(&components[1]);
+
+        Ok(Self {
+            realm,
+            components,
+            name_type: NT_SRV_INST,
+        })
+    }
+
+    /// Principal name without the realm. Components are separated by `/`
+    pub fn name(&self) -> String {
+        self.components.join("/")
+    }
+
+    pub fn matches(&self, name: &str, realm: &str) -> bool {
+        self.name() == name && self.realm.eq_ignore_ascii_case(realm)
+    }
+
+    pub(crate) fn from_principal_name(principal_name: &PrincipalName, realm: &Realm) -> Self {
+        Self {
+            realm: realm.to_string(),
+            components: principal_name
+                .name_string
+                .0
+                 .0
+                .iter()
+                .map(|component| component.to_string())
+                .collect(),
+            name_type: integer_to_u32(&principal_name.name_type.0),
+        }
+    }
+
+    pub(crate) fn principal_name(&self) -> Result<PrincipalName> {
+        Ok(PrincipalName {
+            name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![self.name_type as u8])),
+            name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(
+                self.components
+                    .iter()
+                    .map(|component| Ok(KerberosStringAsn1::from(IA5String::from_string(component.clone())?)))
+                    .collect::<Result<Vec<_>>>()?,
+            )),
+        })
+    }
+
+    pub(crate) fn kerberos_realm(&self) -> Result<Realm> {
+        Ok(Realm::from(IA5String::from_string(self.realm.clone())?))
+    }
+}
+
+// DNS names are case-insensitive, the port or the instance name follows the colon
+fn canonical_host(instance: &str) -> String {
+    match instance.split_once(':') {
+        Some((host, port)) => format!("{}:{}", host.trim_end_matches('.').to_ascii_lowercase(), port),
+        None => instance.trim_end_matches('.').to_ascii_lowercase(),
+    }
+}
+
+/// Authentication data of the Kerberos security package
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Credentials {
+    /// username and password of the principal
+    AuthIdentity(AuthIdentity),
+    /// long-term keys of the principal. The client authenticates as the principal of the first keytab entry
+    Keytab(Keytab),
+    /// client certificate with the signer of its private key, e.g. a smart card
+    PkInit(PkInitCredentials),
+}
+
+impl From<AuthIdentity> for Credentials {
+    fn from(identity: AuthIdentity) -> Self {
+        Self::AuthIdentity(identity)
+    }
+}
+
+impl From<Keytab> for Credentials {
+    fn from(keytab: Keytab) -> Self {
+        Self::Keytab(keytab)
+    }
+}
+
+impl From<PkInitCredentials> for Credentials {
+    fn from(credentials: PkInitCredentials) -> Self {
+        Self::PkInit(credentials)
+    }
+}
+
+/// Credentials handle of the Kerberos security package
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CredentialsBuffers {
+    AuthIdentity(AuthIdentityBuffers),
+    Keytab(Keytab),
+    PkInit(PkInitCredentials),
+}
+
+impl CredentialsBuffers {
+    pub fn auth_identity(self) -> Option<AuthIdentityBuffers> {
+        match self {
+            Self::AuthIdentity(identity) => Some(identity),
+            Self::Keytab(_) | Self::PkInit(_) => None,
+        }
+    }
+}
+
+impl From<Credentials> for CredentialsBuffers {
+    fn from(credentials: Credentials) -> Self {
+        match credentials {
+            Credentials::AuthIdentity(identity) => Self::AuthIdentity(identity.into()),
+            Credentials::Keytab(keytab) => Self::Keytab(keytab),
+            Credentials::PkInit(credentials) => Self::PkInit(credentials),
+        }
+    }
+}
+
+impl From<AuthIdentityBuffers> for CredentialsBuffers {
+    fn from(identity: AuthIdentityBuffers) -> Self {
+        Self::AuthIdentity(identity)
+    }
+}
@@ -0,0 +1,209 @@
+mod aes_sha1;
+mod aes_sha2;
+#[cfg(test)]
+mod test;
+
+use hmac::{Hmac, Mac};
+use kerberos_constants::checksum_types::{HMAC_MD5, HMAC_SHA1_96_AES128, HMAC_SHA1_96_AES256};
+use kerberos_crypto::{checksum_hmac_md5, checksum_sha_aes, new_kerberos_cipher, AesSizes, KerberosCipher};
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha1::Sha1;
+
+use self::aes_sha2::AesSha2Cipher;
+use crate::sspi::kerberos::client::{
+    AES128_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA256_128, AES256_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA384_192, RC4_HMAC,
+};
+use crate::sspi::{Error, ErrorKind, Result};
+
+// s2kparams of the AES encryption types: the iteration count as the big-endian u32
+const S2K_PARAMS_LEN: usize = 4;
+
+// checksum types of [RFC 8009](https://www.rfc-editor.org/rfc/rfc8009#section-8)
+const HMAC_SHA256_128_AES128: i32 = 19;
+const HMAC_SHA384_192_AES256: i32 = 20;
+
+/// Returns the cipher of the encryption type: RFC 3962 (AES-SHA1), RFC 4757 (RC4-HMAC) or RFC 8009 (AES-SHA2)
+pub fn new_cipher(encryption_type: i32) -> Result<Box<dyn KerberosCipher>> {
+    match encryption_type {
+        AES128_CTS_HMAC_SHA256_128 => Ok(Box::new(AesSha2Cipher::aes128())),
+        AES256_CTS_HMAC_SHA384_192 => Ok(Box::new(AesSha2Cipher::aes256())),
+        _ => Ok(new_kerberos_cipher(encryption_type)?),
+    }
+}
+
+/// Derives the key from the password. The AES `s2kparams` contain the PBKDF2 iteration count:
+/// [RFC 3962 4](https://www.rfc-editor.org/rfc/rfc3962#section-4), [RFC 8009 4](https://www.rfc-editor.org/rfc/rfc8009#section-4)
+pub fn string_to_key(encryption_type: i32, password: &str, salt: &str, s2kparams: Option<&[u8]>) -> Result<Vec<u8>> {
+    let s2kparams = match s2kparams {
+        Some(s2kparams) => s2kparams,
+        None => return Ok(new_cipher(encryption_type)?.generate_key_from_string(password, salt.as_bytes())),
+    };
+
+    match encryption_type {
+        AES128_CTS_HMAC_SHA1_96 | AES256_CTS_HMAC_SHA1_96 => Ok(aes_sha1::string_to_key(
+            password.as_bytes(),
+            salt.as_bytes(),
+            iteration_count(s2kparams)?,
+            key_len(encryption_type)?,
+        )),
+        AES128_CTS_HMAC_SHA256_128 => Ok(AesSha2Cipher::aes128().string_to_key(
+            password.as_bytes(),
+            salt.as_bytes(),
+            iteration_count(s2kparams)?,
+        )),
+        AES256_CTS_HMAC_SHA384_192 => Ok(AesSha2Cipher::aes256().string_to_key(
+            password.as_bytes(),
+            salt.as_bytes(),
+            iteration_count(s2kparams)?,
+        )),
+        // RC4-HMAC has no string-to-key parameters
+        _ => Ok(new_cipher(encryption_type)?.generate_key_from_string(password, salt.as_bytes())),
+    }
+}
+
+fn iteration_count(s2kparams: &[u8]) -> Result<u32> {
+    let iteration_count = <[u8; S2K_PARAMS_LEN]>::try_from(s2kparams)
+        .map(u32::from_be_bytes)
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidParameter,
+                format!("Invalid s2kparams length: {}", s2kparams.len()),
+            )
+        })?;
+
+    if iteration_count == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidParameter,
+            "The s2kparams iteration count must not be zero".into(),
+        ));
+    }
+
+    Ok(iteration_count)
+}
+
+/// Computes the keyed checksum associated with the encryption type
+pub fn checksum(encryption_type: i32, key: &[u8], key_usage: i32, data: &[u8]) -> Result<Vec<u8>> {
+    match encryption_type {
+        AES128_CTS_HMAC_SHA1_96 => Ok(checksum_sha_aes(key, key_usage, data, &AesSizes::Aes128)),
+        AES256_CTS_HMAC_SHA1_96 => Ok(checksum_sha_aes(key, key_usage, data, &AesSizes::Aes256)),
+        AES128_CTS_HMAC_SHA256_128 => Ok(AesSha2Cipher::aes128().checksum(key, key_usage, data)),
+        AES256_CTS_HMAC_SHA384_192 => Ok(AesSha2Cipher::aes256().checksum(key, key_usage, data)),
+        RC4_HMAC => Ok(checksum_hmac_md5(key, key_usage, data)),
+        _ => Err(unsupported(encryption_type)),
+    }
+}
+
+/// Type of the keyed checksum associated with the encryption type
+pub fn checksum_type(encryption_type: i32) -> Result<i32> {
+    match encryption_type {
+        AES128_CTS_HMAC_SHA1_96 => Ok(HMAC_SHA1_96_AES128),
+        AES256_CTS_HMAC_SHA1_96 => Ok(HMAC_SHA1_96_AES256),
+        AES128_CTS_HMAC_SHA256_128 => Ok(HMAC_SHA256_128_AES128),
+        AES256_CTS_HMAC_SHA384_192 => Ok(HMAC_SHA384_192_AES256),
+        RC4_HMAC => Ok(HMAC_MD5),
+        _ => Err(unsupported(encryption_type)),
+    }
+}
+
+/// Encryption type of the keys of the keyed checksum type
+pub fn checksum_encryption_type(checksum_type: i32) -> Result<i32> {
+    match checksum_type {
+        HMAC_SHA1_96_AES128 => Ok(AES128_CTS_HMAC_SHA1_96),
+        HMAC_SHA1_96_AES256 => Ok(AES256_CTS_HMAC_SHA1_96),
+        HMAC_SHA256_128_AES128 => Ok(AES128_CTS_HMAC_SHA256_128),
+        HMAC_SHA384_192_AES256 => Ok(AES256_CTS_HMAC_SHA384_192),
+        HMAC_MD5 => Ok(RC4_HMAC),
+        _ => Err(Error::new(
+            ErrorKind::InternalError,
+            format!("unsupported checksum type: {}", checksum_type),
+        )),
+    }
+}
+
+/// Pseudo-random function of the encryption type: [RFC 3961 3](https://www.rfc-editor.org/rfc/rfc3961#section-3).
+/// RC4-HMAC uses HMAC-SHA1 of the key as MIT Kerberos and Windows do
+pub fn prf(encryption_type: i32, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    match encryption_type {
+        AES128_CTS_HMAC_SHA1_96 | AES256_CTS_HMAC_SHA1_96 => Ok(aes_sha1::prf(key, data)),
+        AES128_CTS_HMAC_SHA256_128 => Ok(AesSha2Cipher::aes128().prf(key, data)),
+        AES256_CTS_HMAC_SHA384_192 => Ok(AesSha2Cipher::aes256().prf(key, data)),
+        RC4_HMAC => {
+            let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(data);
+
+            Ok(mac.finalize().into_bytes().to_vec())
+        }
+        _ => Err(unsupported(encryption_type)),
+    }
+}
+
+/// KRB-FX-CF2 of [RFC 6113 5.1](https://www.rfc-editor.org/rfc/rfc6113#section-5.1): combines two keys
+/// into the key of the encryption type of the first one. The random-to-key of AES and RC4-HMAC is the identity
+pub fn cf2(
+    encryption_type: i32,
+    key1: &[u8],
+    pepper1: &[u8],
+    key2_encryption_type: i32,
+    key2: &[u8],
+    pepper2: &[u8],
+) -> Result<Vec<u8>> {
+    let key_len = key_len(encryption_type)?;
+
+    let mut key = prf_plus(encryption_type, key1, pepper1, key_len)?;
+    key.iter_mut()
+        .zip(prf_plus(key2_encryption_type, key2, pepper2, key_len)?)
+        .for_each(|(byte, other)| *byte ^= other);
+
+    Ok(key)
+}
+
+// PRF+: the PRF outputs of the incrementing counter byte and the pepper are concatenated
+fn prf_plus(encryption_type: i32, key: &[u8], pepper: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(len);
+    let mut counter = 1_u8;
+    while output.len() < len {
+        let mut data = vec![counter];
+        data.extend_from_slice(pepper);
+
+        output.extend_from_slice(&prf(encryption_type, key, &data)?);
+        counter += 1;
+    }
+    output.truncate(len);
+
+    Ok(output)
+}
+
+/// Generates a random key of the encryption type, e.g. the authenticator subkey
+pub fn generate_random_key(encryption_type: i32) -> Result<Vec<u8>> {
+    let mut key = vec![0; key_len(encryption_type)?];
+    OsRng::new()?.fill(key.as_mut_slice());
+
+    Ok(key)
+}
+
+/// Length of the keyed checksum of the encryption type
+pub fn checksum_len(encryption_type: i32) -> Result<usize> {
+    match encryption_type {
+        AES128_CTS_HMAC_SHA1_96 | AES256_CTS_HMAC_SHA1_96 => Ok(12),
+        AES128_CTS_HMAC_SHA256_128 | RC4_HMAC => Ok(16),
+        AES256_CTS_HMAC_SHA384_192 => Ok(24),
+        _ => Err(unsupported(encryption_type)),
+    }
+}
+
+/// Length of the protocol key of the encryption type
+pub fn key_len(encryption_type: i32) -> Result<usize> {
+    match encryption_type {
+        AES128_CTS_HMAC_SHA1_96 | AES128_CTS_HMAC_SHA256_128 | RC4_HMAC => Ok(16),
+        AES256_CTS_HMAC_SHA1_96 | AES256_CTS_HMAC_SHA384_192 => Ok(32),
+        _ => Err(unsupported(encryption_type)),
+    }
+}
+
+fn unsupported(encryption_type: i32) -> Error {
+    Error::new(
+        ErrorKind::InternalError,
+        format!("unsupported algorithm: {}", encryption_type),
+    )
+}
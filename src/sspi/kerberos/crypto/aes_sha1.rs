@@ -0,0 +1,89 @@
+//! [RFC 3962](https://www.rfc-editor.org/rfc/rfc3962) string-to-key with an explicit iteration count and the PRF.
+//! Encryption and checksums of aes128-cts-hmac-sha1-96 and aes256-cts-hmac-sha1-96 are provided by `kerberos_crypto`
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
+use pbkdf2::pbkdf2_hmac;
+use sha1::{Digest, Sha1};
+
+const AES_BLOCK_SIZE: usize = 16;
+
+/// The key len is 16 for aes128 and 32 for aes256
+pub fn string_to_key(password: &[u8], salt: &[u8], iteration_count: u32, key_len: usize) -> Vec<u8> {
+    let mut tkey = vec![0; key_len];
+    pbkdf2_hmac::<Sha1>(password, salt, iteration_count, &mut tkey);
+
+    dk(&tkey, b"kerberos")
+}
+
+/// PRF of [RFC 3962 6](https://www.rfc-editor.org/rfc/rfc3962#section-6)
+pub fn prf(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut block = GenericArray::clone_from_slice(&Sha1::digest(data)[..AES_BLOCK_SIZE]);
+    encrypt_block(&dk(key, b"prf"), &mut block);
+
+    block.to_vec()
+}
+
+// DK(key, constant): the n-folded constant is encrypted repeatedly until the key is filled
+fn dk(key: &[u8], constant: &[u8]) -> Vec<u8> {
+    let mut block = GenericArray::clone_from_slice(&n_fold(constant, AES_BLOCK_SIZE));
+    let mut derived_key = Vec::with_capacity(key.len());
+    while derived_key.len() < key.len() {
+        encrypt_block(key, &mut block);
+        derived_key.extend_from_slice(&block);
+    }
+
+    derived_key
+}
+
+fn encrypt_block(key: &[u8], block: &mut GenericArray<u8, aes::cipher::consts::U16>) {
+    if key.len() == 32 {
+        Aes256::new(GenericArray::from_slice(key)).encrypt_block(block);
+    } else {
+        Aes128::new(GenericArray::from_slice(key)).encrypt_block(block);
+    }
+}
+
+/// n-fold of [RFC 3961 5.1](https://www.rfc-editor.org/rfc/rfc3961#section-5.1): the input is repeated
+/// with the 13-bit rotation up to the least common multiple of the lengths and added with the end-around carry
+pub fn n_fold(input: &[u8], output_len: usize) -> Vec<u8> {
+    let input_bits = input.len() * 8;
+    let lcm = input.len() * output_len / gcd(input.len(), output_len);
+
+    let mut output = vec![0_u8; output_len];
+    let mut carry = 0_u32;
+    for i in (0..lcm).rev() {
+        // the most significant bit of the byte `i` of the rotated and repeated input
+        let msbit =
+            (input_bits - 1 + (input_bits + 13) * (i / input.len()) + (input.len() - i % input.len()) * 8) % input_bits;
+
+        let byte = ((u32::from(input[(input.len() - 1 - (msbit >> 3)) % input.len()]) << 8)
+            | u32::from(input[(input.len() - (msbit >> 3)) % input.len()]))
+            >> ((msbit & 7) + 1);
+
+        carry += (byte & 0xff) + u32::from(output[i % output_len]);
+        output[i % output_len] = carry as u8;
+        carry >>= 8;
+    }
+
+    // the end-around carry
+    for byte in output.iter_mut().rev() {
+        if carry == 0 {
+            break;
+        }
+        carry += u32::from(*byte);
+        *byte = carry as u8;
+        carry >>= 8;
+    }
+
+    output
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
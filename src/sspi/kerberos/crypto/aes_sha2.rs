@@ -0,0 +1,305 @@
+//! [RFC 8009](https://www.rfc-editor.org/rfc/rfc8009): aes128-cts-hmac-sha256-128 and aes256-cts-hmac-sha384-192
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
+use hmac::{Hmac, Mac};
+use kerberos_crypto::KerberosCipher;
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha2::{Sha256, Sha384};
+
+use crate::sspi::kerberos::client::{AES128_CTS_HMAC_SHA256_128, AES256_CTS_HMAC_SHA384_192};
+
+const AES_BLOCK_SIZE: usize = 16;
+const DEFAULT_ITERATION_COUNT: u32 = 32768;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AesSha2Cipher {
+    encryption_type: i32,
+}
+
+impl AesSha2Cipher {
+    pub fn aes128() -> Self {
+        Self {
+            encryption_type: AES128_CTS_HMAC_SHA256_128,
+        }
+    }
+
+    pub fn aes256() -> Self {
+        Self {
+            encryption_type: AES256_CTS_HMAC_SHA384_192,
+        }
+    }
+
+    fn is_aes256(&self) -> bool {
+        self.encryption_type == AES256_CTS_HMAC_SHA384_192
+    }
+
+    fn key_len(&self) -> usize {
+        if self.is_aes256() {
+            32
+        } else {
+            16
+        }
+    }
+
+    fn mac_len(&self) -> usize {
+        if self.is_aes256() {
+            24
+        } else {
+            16
+        }
+    }
+
+    fn name(&self) -> &'static [u8] {
+        if self.is_aes256() {
+            b"aes256-cts-hmac-sha384-192"
+        } else {
+            b"aes128-cts-hmac-sha256-128"
+        }
+    }
+
+    fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        if self.is_aes256() {
+            let mut mac = <Hmac<Sha384> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        } else {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+
+    // KDF-HMAC-SHA2 (RFC 8009 3): the output never exceeds one HMAC block
+    fn kdf(&self, key: &[u8], label: &[u8], context: &[u8], bits: usize) -> Vec<u8> {
+        let mut data = 1_u32.to_be_bytes().to_vec();
+        data.extend_from_slice(label);
+        data.push(0);
+        data.extend_from_slice(context);
+        data.extend_from_slice(&(bits as u32).to_be_bytes());
+
+        let mut output = self.hmac(key, &data);
+        output.truncate(bits / 8);
+
+        output
+    }
+
+    fn derive_key(&self, key: &[u8], key_usage: i32, key_type: u8, bits: usize) -> Vec<u8> {
+        let mut label = key_usage.to_be_bytes().to_vec();
+        label.push(key_type);
+
+        self.kdf(key, &label, &[], bits)
+    }
+
+    fn pbkdf2(&self, password: &[u8], salt: &[u8], iteration_count: u32) -> Vec<u8> {
+        let mut data = salt.to_vec();
+        data.extend_from_slice(&1_u32.to_be_bytes());
+
+        let mut u = self.hmac(password, &data);
+        let mut key = u.clone();
+        for _ in 1..iteration_count {
+            u = self.hmac(password, &u);
+            key.iter_mut().zip(u.iter()).for_each(|(key, u)| *key ^= u);
+        }
+        key.truncate(self.key_len());
+
+        key
+    }
+
+    pub fn string_to_key(&self, password: &[u8], salt: &[u8], iteration_count: u32) -> Vec<u8> {
+        let mut salt_p = self.name().to_vec();
+        salt_p.push(0);
+        salt_p.extend_from_slice(salt);
+
+        let tkey = self.pbkdf2(password, &salt_p, iteration_count);
+
+        self.kdf(&tkey, b"kerberos", &[], self.key_len() * 8)
+    }
+
+    /// PRF of [RFC 8009 5](https://www.rfc-editor.org/rfc/rfc8009#section-5): 256 bits for aes128, 384 for aes256
+    pub fn prf(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let bits = if self.is_aes256() { 384 } else { 256 };
+
+        self.kdf(key, b"prf", data, bits)
+    }
+
+    pub fn checksum(&self, key: &[u8], key_usage: i32, data: &[u8]) -> Vec<u8> {
+        let kc = self.derive_key(key, key_usage, 0x99, self.mac_len() * 8);
+
+        let mut checksum = self.hmac(&kc, data);
+        checksum.truncate(self.mac_len());
+
+        checksum
+    }
+
+    pub fn encrypt_with_confounder(&self, key: &[u8], key_usage: i32, confounder: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let ke = self.derive_key(key, key_usage, 0xaa, self.key_len() * 8);
+        let ki = self.derive_key(key, key_usage, 0x55, self.mac_len() * 8);
+
+        let mut data = confounder.to_vec();
+        data.extend_from_slice(plaintext);
+
+        let mut ciphertext = encrypt_cts(&ke, &data);
+
+        let mut mac_data = vec![0; AES_BLOCK_SIZE];
+        mac_data.extend_from_slice(&ciphertext);
+        ciphertext.extend_from_slice(&self.hmac(&ki, &mac_data)[..self.mac_len()]);
+
+        ciphertext
+    }
+}
+
+impl KerberosCipher for AesSha2Cipher {
+    fn etype(&self) -> i32 {
+        self.encryption_type
+    }
+
+    fn generate_salt(&self, realm: &str, client_name: &str) -> Vec<u8> {
+        format!("{}{}", realm, client_name).into_bytes()
+    }
+
+    fn generate_key(&self, raw_key: &[u8], salt: &[u8]) -> Vec<u8> {
+        self.string_to_key(raw_key, salt, DEFAULT_ITERATION_COUNT)
+    }
+
+    fn generate_key_from_string(&self, password: &str, salt: &[u8]) -> Vec<u8> {
+        self.generate_key(password.as_bytes(), salt)
+    }
+
+    fn decrypt(&self, key: &[u8], key_usage: i32, ciphertext: &[u8]) -> kerberos_crypto::Result<Vec<u8>> {
+        if ciphertext.len() < AES_BLOCK_SIZE + self.mac_len() {
+            return Err(kerberos_crypto::Error::DecryptionError(
+                "Ciphertext is too short".into(),
+            ));
+        }
+
+        let ke = self.derive_key(key, key_usage, 0xaa, self.key_len() * 8);
+        let ki = self.derive_key(key, key_usage, 0x55, self.mac_len() * 8);
+
+        let (ciphertext, mac) = ciphertext.split_at(ciphertext.len() - self.mac_len());
+
+        let mut mac_data = vec![0; AES_BLOCK_SIZE];
+        mac_data.extend_from_slice(ciphertext);
+        if self.hmac(&ki, &mac_data)[..self.mac_len()] != *mac {
+            return Err(kerberos_crypto::Error::DecryptionError("Hmac integrity failure".into()));
+        }
+
+        let mut plaintext = decrypt_cts(&ke, ciphertext);
+        plaintext.drain(..AES_BLOCK_SIZE);
+
+        Ok(plaintext)
+    }
+
+    fn encrypt(&self, key: &[u8], key_usage: i32, plaintext: &[u8]) -> Vec<u8> {
+        let confounder = OsRng::new()
+            .expect("OS random number generator is available")
+            .gen::<[u8; AES_BLOCK_SIZE]>();
+
+        self.encrypt_with_confounder(key, key_usage, &confounder, plaintext)
+    }
+}
+
+// AES block cipher of the key length
+trait BlockCipher {
+    fn encrypt(&self, block: &mut [u8]);
+
+    fn decrypt(&self, block: &mut [u8]);
+}
+
+impl<C: BlockEncrypt + BlockDecrypt> BlockCipher for C {
+    fn encrypt(&self, block: &mut [u8]) {
+        self.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    fn decrypt(&self, block: &mut [u8]) {
+        self.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+}
+
+fn new_aes(key: &[u8]) -> Box<dyn BlockCipher> {
+    if key.len() == 32 {
+        Box::new(Aes256::new(GenericArray::from_slice(key)))
+    } else {
+        Box::new(Aes128::new(GenericArray::from_slice(key)))
+    }
+}
+
+fn xor(block: &mut [u8], other: &[u8]) {
+    block
+        .iter_mut()
+        .zip(other.iter())
+        .for_each(|(byte, other)| *byte ^= other);
+}
+
+// CBC with ciphertext stealing, the last two blocks are always swapped (RFC 3962 5)
+fn encrypt_cts(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let aes = new_aes(key);
+
+    let blocks_count = data.len().div_ceil(AES_BLOCK_SIZE);
+    let mut blocks = data.to_vec();
+    blocks.resize(blocks_count * AES_BLOCK_SIZE, 0);
+
+    let mut previous = [0; AES_BLOCK_SIZE];
+    for block in blocks.chunks_mut(AES_BLOCK_SIZE) {
+        xor(block, &previous);
+        aes.encrypt(block);
+        previous.copy_from_slice(block);
+    }
+
+    if blocks_count > 1 {
+        let last = blocks.split_off((blocks_count - 1) * AES_BLOCK_SIZE);
+        let penultimate = blocks.split_off((blocks_count - 2) * AES_BLOCK_SIZE);
+
+        blocks.extend_from_slice(&last);
+        blocks.extend_from_slice(&penultimate);
+    }
+    blocks.truncate(data.len());
+
+    blocks
+}
+
+fn decrypt_cts(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let aes = new_aes(key);
+
+    if data.len() == AES_BLOCK_SIZE {
+        let mut block = data.to_vec();
+        aes.decrypt(&mut block);
+
+        return block;
+    }
+
+    let blocks_count = data.len().div_ceil(AES_BLOCK_SIZE);
+    let tail_len = data.len() - (blocks_count - 1) * AES_BLOCK_SIZE;
+    let (head, tail) = data.split_at((blocks_count - 2) * AES_BLOCK_SIZE);
+
+    let mut plaintext = Vec::with_capacity(data.len());
+    let mut previous = [0; AES_BLOCK_SIZE];
+    for block in head.chunks(AES_BLOCK_SIZE) {
+        let mut decrypted = block.to_vec();
+        aes.decrypt(&mut decrypted);
+        xor(&mut decrypted, &previous);
+
+        plaintext.extend_from_slice(&decrypted);
+        previous.copy_from_slice(block);
+    }
+
+    // the swapped last block restores the stolen bytes of the penultimate block
+    let mut last = tail[..AES_BLOCK_SIZE].to_vec();
+    aes.decrypt(&mut last);
+
+    let mut penultimate = tail[AES_BLOCK_SIZE..].to_vec();
+    penultimate.extend_from_slice(&last[tail_len..]);
+
+    xor(&mut last, &penultimate);
+    last.truncate(tail_len);
+
+    aes.decrypt(&mut penultimate);
+    xor(&mut penultimate, &previous);
+
+    plaintext.extend_from_slice(&penultimate);
+    plaintext.extend_from_slice(&last);
+
+    plaintext
+}
@@ -0,0 +1,261 @@
+use kerberos_crypto::KerberosCipher;
+
+use super::aes_sha2::AesSha2Cipher;
+use super::*;
+use crate::sspi::kerberos::client::SUPPORTED_ENCRYPTION_TYPES;
+
+// test vectors: [RFC 8009 Appendix A](https://www.rfc-editor.org/rfc/rfc8009#appendix-A)
+const AES128_BASE_KEY: [u8; 16] = [
+    0x37, 0x05, 0xd9, 0x60, 0x80, 0xc1, 0x77, 0x28, 0xa0, 0xe8, 0x00, 0xea, 0xb6, 0xe0, 0xd2, 0x3c,
+];
+const AES256_BASE_KEY: [u8; 32] = [
+    0x6d, 0x40, 0x4d, 0x37, 0xfa, 0xf7, 0x9f, 0x9d, 0xf0, 0xd3, 0x35, 0x68, 0xd3, 0x20, 0x66, 0x98, 0x00, 0xeb, 0x48,
+    0x36, 0x47, 0x2e, 0xa8, 0xa0, 0x26, 0xd1, 0x6b, 0x71, 0x82, 0x46, 0x0c, 0x52,
+];
+const KEY_USAGE: i32 = 2;
+
+fn string_to_key_salt() -> Vec<u8> {
+    let mut salt = vec![
+        0x10, 0xdf, 0x9d, 0xd7, 0x83, 0xe5, 0xbc, 0x8a, 0xce, 0xa1, 0x73, 0x0e, 0x74, 0x35, 0x5f, 0x61,
+    ];
+    salt.extend_from_slice(b"ATHENA.MIT.EDUraeburn");
+
+    salt
+}
+
+#[test]
+fn aes128_sha256_string_to_key() {
+    assert_eq!(
+        AesSha2Cipher::aes128().generate_key_from_string("password", &string_to_key_salt()),
+        vec![0x08, 0x9b, 0xca, 0x48, 0xb1, 0x05, 0xea, 0x6e, 0xa7, 0x7c, 0xa5, 0xd2, 0xf3, 0x9d, 0xc5, 0xe7]
+    );
+}
+
+#[test]
+fn aes256_sha384_string_to_key() {
+    assert_eq!(
+        AesSha2Cipher::aes256().generate_key_from_string("password", &string_to_key_salt()),
+        vec![
+            0x45, 0xbd, 0x80, 0x6d, 0xbf, 0x6a, 0x83, 0x3a, 0x9c, 0xff, 0xc1, 0xc9, 0x45, 0x89, 0xa2, 0x22, 0x36, 0x7a,
+            0x79, 0xbc, 0x21, 0xc4, 0x13, 0x71, 0x89, 0x06, 0xe9, 0xf5, 0x78, 0xa7, 0x84, 0x67
+        ]
+    );
+}
+
+#[test]
+fn aes128_sha256_checksum() {
+    let data = (0..21).collect::<Vec<u8>>();
+
+    assert_eq!(
+        checksum(AES128_CTS_HMAC_SHA256_128, &AES128_BASE_KEY, KEY_USAGE, &data).unwrap(),
+        vec![0xd7, 0x83, 0x67, 0x18, 0x66, 0x43, 0xd6, 0x7b, 0x41, 0x1c, 0xba, 0x91, 0x39, 0xfc, 0x1d, 0xee]
+    );
+}
+
+#[test]
+fn aes256_sha384_checksum() {
+    let data = (0..21).collect::<Vec<u8>>();
+
+    assert_eq!(
+        checksum(AES256_CTS_HMAC_SHA384_192, &AES256_BASE_KEY, KEY_USAGE, &data).unwrap(),
+        vec![
+            0x45, 0xee, 0x79, 0x15, 0x67, 0xee, 0xfc, 0xa3, 0x7f, 0x4a, 0xc1, 0xe0, 0x22, 0x2d, 0xe8, 0x0d, 0x43, 0xc3,
+            0xbf, 0xa0, 0x66, 0x99, 0x67, 0x2a
+        ]
+    );
+}
+
+#[test]
+fn aes128_sha256_encryption_with_partial_block() {
+    let confounder = [
+        0x7b, 0xca, 0x28, 0x5e, 0x2f, 0xd4, 0x13, 0x0f, 0xb5, 0x5b, 0x1a, 0x5c, 0x83, 0xbc, 0x5b, 0x24,
+    ];
+    let plaintext = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05];
+
+    let ciphertext =
+        AesSha2Cipher::aes128().encrypt_with_confounder(&AES128_BASE_KEY, KEY_USAGE, &confounder, &plaintext);
+
+    assert_eq!(
+        ciphertext,
+        vec![
+            0x84, 0xd7, 0xf3, 0x07, 0x54, 0xed, 0x98, 0x7b, 0xab, 0x0b, 0xf3, 0x50, 0x6b, 0xeb, 0x09, 0xcf, 0xb5, 0x54,
+            0x02, 0xce, 0xf7, 0xe6, 0x87, 0x7c, 0xe9, 0x9e, 0x24, 0x7e, 0x52, 0xd1, 0x6e, 0xd4, 0x42, 0x1d, 0xfd, 0xf8,
+            0x97, 0x6c
+        ]
+    );
+    assert_eq!(
+        AesSha2Cipher::aes128()
+            .decrypt(&AES128_BASE_KEY, KEY_USAGE, &ciphertext)
+            .unwrap(),
+        plaintext.to_vec()
+    );
+}
+
+#[test]
+fn aes256_sha384_encryption_of_empty_plaintext() {
+    let confounder = [
+        0xf7, 0x64, 0xe9, 0xfa, 0x15, 0xc2, 0x76, 0x47, 0x8b, 0x2c, 0x7d, 0x0c, 0x4e, 0x5f, 0x58, 0xe4,
+    ];
+
+    let ciphertext = AesSha2Cipher::aes256().encrypt_with_confounder(&AES256_BASE_KEY, KEY_USAGE, &confounder, &[]);
+
+    assert_eq!(
+        ciphertext,
+        vec![
+            0x41, 0xf5, 0x3f, 0xa5, 0xbf, 0xe7, 0x02, 0x6d, 0x91, 0xfa, 0xf9, 0xbe, 0x95, 0x91, 0x95, 0xa0, 0x58, 0x70,
+            0x72, 0x73, 0xa9, 0x6a, 0x40, 0xf0, 0xa0, 0x19, 0x60, 0x62, 0x1a, 0xc6, 0x12, 0x74, 0x8b, 0x9b, 0xbf, 0xbe,
+            0x7e, 0xb4, 0xce, 0x3c
+        ]
+    );
+    assert!(AesSha2Cipher::aes256()
+        .decrypt(&AES256_BASE_KEY, KEY_USAGE, &ciphertext)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn aes_sha2_decryption_fails_on_altered_ciphertext() {
+    let cipher = new_cipher(AES256_CTS_HMAC_SHA384_192).unwrap();
+
+    let mut ciphertext = cipher.encrypt(&AES256_BASE_KEY, KEY_USAGE, b"ldap request");
+    ciphertext[20] ^= 0xff;
+
+    assert!(cipher.decrypt(&AES256_BASE_KEY, KEY_USAGE, &ciphertext).is_err());
+}
+
+#[test]
+fn all_supported_encryption_types_round_trip() {
+    for encryption_type in SUPPORTED_ENCRYPTION_TYPES {
+        let cipher = new_cipher(encryption_type).unwrap();
+        let key = vec![0x42; key_len(encryption_type).unwrap()];
+
+        for len in [0, 1, 15, 16, 17, 32, 100] {
+            let plaintext = vec![0x24; len];
+            let ciphertext = cipher.encrypt(&key, KEY_USAGE, &plaintext);
+
+            assert_eq!(cipher.decrypt(&key, KEY_USAGE, &ciphertext).unwrap(), plaintext);
+        }
+        assert_eq!(
+            checksum(encryption_type, &key, KEY_USAGE, b"data").unwrap().len(),
+            checksum_len(encryption_type).unwrap()
+        );
+    }
+}
+
+// [RFC 3962 Appendix B](https://www.rfc-editor.org/rfc/rfc3962#appendix-B)
+#[test]
+fn aes_sha1_string_to_key_honors_iteration_count() {
+    let salt = "ATHENA.MIT.EDUraeburn";
+
+    assert_eq!(
+        string_to_key(AES128_CTS_HMAC_SHA1_96, "password", salt, Some(&[0, 0, 0, 2])).unwrap(),
+        vec![0xc6, 0x51, 0xbf, 0x29, 0xe2, 0x30, 0x0a, 0xc2, 0x7f, 0xa4, 0x69, 0xd6, 0x93, 0xbd, 0xda, 0x13]
+    );
+    assert_eq!(
+        string_to_key(AES256_CTS_HMAC_SHA1_96, "password", salt, Some(&[0, 0, 0x04, 0xb0])).unwrap(),
+        vec![
+            0x55, 0xa6, 0xac, 0x74, 0x0a, 0xd1, 0x7b, 0x48, 0x46, 0x94, 0x10, 0x51, 0xe1, 0xe8, 0xb0, 0xa7, 0x54, 0x8d,
+            0x93, 0xb0, 0xab, 0x30, 0xa8, 0xbc, 0x3f, 0xf1, 0x62, 0x80, 0x38, 0x2b, 0x8c, 0x2a
+        ]
+    );
+}
+
+#[test]
+fn string_to_key_with_default_iteration_count_matches_key_without_s2kparams() {
+    let salt = "EXAMPLE.COMuser";
+
+    for (encryption_type, s2kparams) in [
+        (AES128_CTS_HMAC_SHA1_96, 4096_u32),
+        (AES256_CTS_HMAC_SHA1_96, 4096),
+        (AES128_CTS_HMAC_SHA256_128, 32768),
+        (AES256_CTS_HMAC_SHA384_192, 32768),
+    ] {
+        assert_eq!(
+            string_to_key(encryption_type, "password", salt, Some(&s2kparams.to_be_bytes())).unwrap(),
+            string_to_key(encryption_type, "password", salt, None).unwrap()
+        );
+    }
+}
+
+#[test]
+fn string_to_key_fails_on_invalid_s2kparams() {
+    for s2kparams in [&[0x10, 0x00][..], &[0, 0, 0, 0]] {
+        assert_eq!(
+            string_to_key(AES256_CTS_HMAC_SHA1_96, "password", "EXAMPLE.COMuser", Some(s2kparams))
+                .unwrap_err()
+                .error_type,
+            ErrorKind::InvalidParameter
+        );
+    }
+}
+
+// [RFC 3961 A.1](https://www.rfc-editor.org/rfc/rfc3961#appendix-A.1)
+#[test]
+fn n_fold_test_vectors() {
+    assert_eq!(
+        super::aes_sha1::n_fold(b"012345", 8),
+        vec![0xbe, 0x07, 0x26, 0x31, 0x27, 0x6b, 0x19, 0x55]
+    );
+    assert_eq!(
+        super::aes_sha1::n_fold(b"password", 7),
+        vec![0x78, 0xa0, 0x7b, 0x6c, 0xaf, 0x85, 0xfa]
+    );
+    assert_eq!(
+        super::aes_sha1::n_fold(b"Rough Consensus, and Running Code", 8),
+        vec![0xbb, 0x6e, 0xd3, 0x08, 0x70, 0xb7, 0xf0, 0xe0]
+    );
+    assert_eq!(
+        super::aes_sha1::n_fold(b"password", 21),
+        vec![
+            0x59, 0xe4, 0xa8, 0xca, 0x7c, 0x03, 0x85, 0xc3, 0xc3, 0x7b, 0x3f, 0x6d, 0x20, 0x00, 0x24, 0x7c, 0xb6, 0xe6,
+            0xbd, 0x5b, 0x3e
+        ]
+    );
+    assert_eq!(
+        super::aes_sha1::n_fold(b"kerberos", 16),
+        vec![0x6b, 0x65, 0x72, 0x62, 0x65, 0x72, 0x6f, 0x73, 0x7b, 0x9b, 0x5b, 0x2b, 0x93, 0x13, 0x2b, 0x93]
+    );
+}
+
+#[test]
+fn aes128_sha256_prf() {
+    assert_eq!(
+        prf(AES128_CTS_HMAC_SHA256_128, &AES128_BASE_KEY, b"test").unwrap(),
+        vec![
+            0x9d, 0x18, 0x86, 0x16, 0xf6, 0x38, 0x52, 0xfe, 0x86, 0x91, 0x5b, 0xb8, 0x40, 0xb4, 0xa8, 0x86, 0xff, 0x3e,
+            0x6b, 0xb0, 0xf8, 0x19, 0xb4, 0x9b, 0x89, 0x33, 0x93, 0xd3, 0x93, 0x85, 0x42, 0x95
+        ]
+    );
+}
+
+#[test]
+fn aes256_sha384_prf() {
+    assert_eq!(
+        prf(AES256_CTS_HMAC_SHA384_192, &AES256_BASE_KEY, b"test").unwrap(),
+        vec![
+            0x98, 0x01, 0xf6, 0x9a, 0x36, 0x8c, 0x2b, 0xf6, 0x75, 0xe5, 0x95, 0x21, 0xe1, 0x77, 0xd9, 0xa0, 0x7f, 0x67,
+            0xef, 0xe1, 0xcf, 0xde, 0x8d, 0x3c, 0x8d, 0x6f, 0x6a, 0x02, 0x56, 0xe3, 0xb1, 0x7d, 0xb3, 0xc1, 0xb6, 0x2a,
+            0xd1, 0xb8, 0x55, 0x33, 0x60, 0xd1, 0x73, 0x67, 0xeb, 0x15, 0x14, 0xd2
+        ]
+    );
+}
+
+#[test]
+fn cf2_combines_keys_of_all_supported_encryption_types() {
+    for encryption_type in SUPPORTED_ENCRYPTION_TYPES {
+        let key1 = generate_random_key(encryption_type).unwrap();
+        let key2 = generate_random_key(encryption_type).unwrap();
+
+        let key = cf2(encryption_type, &key1, b"a", encryption_type, &key2, b"b").unwrap();
+
+        assert_eq!(key.len(), key_len(encryption_type).unwrap());
+        assert_eq!(
+            key,
+            cf2(encryption_type, &key1, b"a", encryption_type, &key2, b"b").unwrap()
+        );
+        assert_ne!(
+            key,
+            cf2(encryption_type, &key1, b"a", encryption_type, &key2, b"c").unwrap()
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag10, ExplicitContextTag2,
+    ExplicitContextTag3, ExplicitContextTag4, ExplicitContextTag5, ExplicitContextTag6, ExplicitContextTag7,
+    ExplicitContextTag8, ExplicitContextTag9, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_asn1_der::application_tag::ApplicationTag;
+use picky_krb::data_types::{
+    AuthorizationData, EncryptedData, EncryptionKey, HostAddress, KerberosFlags, KerberosTime, Microseconds,
+    PrincipalName, Realm, Ticket,
+};
+use serde::{Deserialize, Serialize};
+
+pub const ENC_TICKET_PART_TYPE: u8 = 3;
+pub const KRB_CRED_TYPE: u8 = 22;
+pub const ENC_KRB_CRED_PART_TYPE: u8 = 29;
+pub const KRB_PRIV_TYPE: u8 = 21;
+pub const ENC_KRB_PRIV_PART_TYPE: u8 = 28;
+
+/// [RFC 4120 5.3](https://www.rfc-editor.org/rfc/rfc4120.txt)
+///
+/// ```not_rust
+/// TransitedEncoding       ::= SEQUENCE {
+///         tr-type         [0] Int32 -- must be registered --,
+///         contents        [1] OCTET STRING
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TransitedEncoding {
+    pub tr_type: ExplicitContextTag0<IntegerAsn1>,
+    pub contents: ExplicitContextTag1<OctetStringAsn1>,
+}
+
+/// [RFC 4120 5.3](https://www.rfc-editor.org/rfc/rfc4120.txt)
+///
+/// ```not_rust
+/// EncTicketPart   ::= [APPLICATION 3] SEQUENCE {
+///         flags                   [0] TicketFlags,
+///         key                     [1] EncryptionKey,
+///         crealm                  [2] Realm,
+///         cname                   [3] PrincipalName,
+///         transited               [4] TransitedEncoding,
+///         authtime                [5] KerberosTime,
+///         starttime               [6] KerberosTime OPTIONAL,
+///         endtime                 [7] KerberosTime,
+///         renew-till              [8] KerberosTime OPTIONAL,
+///         caddr                   [9] HostAddresses OPTIONAL,
+///         authorization-data      [10] AuthorizationData OPTIONAL
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct EncTicketPartInner {
+    pub flags: ExplicitContextTag0<KerberosFlags>,
+    pub key: ExplicitContextTag1<EncryptionKey>,
+    pub crealm: ExplicitContextTag2<Realm>,
+    pub cname: ExplicitContextTag3<PrincipalName>,
+    pub transited: ExplicitContextTag4<TransitedEncoding>,
+    pub authtime: ExplicitContextTag5<KerberosTime>,
+    pub starttime: Optional<Option<ExplicitContextTag6<KerberosTime>>>,
+    pub endtime: ExplicitContextTag7<KerberosTime>,
+    #[serde(default)]
+    pub renew_till: Optional<Option<ExplicitContextTag8<KerberosTime>>>,
+    #[serde(default)]
+    pub caddr: Optional<Option<ExplicitContextTag9<Asn1SequenceOf<HostAddress>>>>,
+    #[serde(default)]
+    pub authorization_data: Optional<Option<ExplicitContextTag10<AuthorizationData>>>,
+}
+
+pub type EncTicketPart = ApplicationTag<EncTicketPartInner, ENC_TICKET_PART_TYPE>;
+
+/// [RFC 4120 5.8.1](https://www.rfc-editor.org/rfc/rfc4120#section-5.8.1)
+///
+/// ```not_rust
+/// KRB-CRED        ::= [APPLICATION 22] SEQUENCE {
+///         pvno            [0] INTEGER (5),
+///         msg-type        [1] INTEGER (22),
+///         tickets         [2] SEQUENCE OF Ticket,
+///         enc-part        [3] EncryptedData -- EncKrbCredPart
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbCredInner {
+    pub pvno: ExplicitContextTag0<IntegerAsn1>,
+    pub msg_type: ExplicitContextTag1<IntegerAsn1>,
+    pub tickets: ExplicitContextTag2<Asn1SequenceOf<Ticket>>,
+    pub enc_part: ExplicitContextTag3<EncryptedData>,
+}
+
+pub type KrbCred = ApplicationTag<KrbCredInner, KRB_CRED_TYPE>;
+
+/// [RFC 4120 5.8.1](https://www.rfc-editor.org/rfc/rfc4120#section-5.8.1)
+///
+/// ```not_rust
+/// EncKrbCredPart  ::= [APPLICATION 29] SEQUENCE {
+///         ticket-info     [0] SEQUENCE OF KrbCredInfo,
+///         nonce           [1] UInt32 OPTIONAL,
+///         timestamp       [2] KerberosTime OPTIONAL,
+///         usec            [3] Microseconds OPTIONAL,
+///         s-address       [4] HostAddress OPTIONAL,
+///         r-address       [5] HostAddress OPTIONAL
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct EncKrbCredPartInner {
+    pub ticket_info: ExplicitContextTag0<Asn1SequenceOf<KrbCredInfo>>,
+    #[serde(default)]
+    pub nonce: Optional<Option<ExplicitContextTag1<IntegerAsn1>>>,
+    #[serde(default)]
+    pub timestamp: Optional<Option<ExplicitContextTag2<KerberosTime>>>,
+    #[serde(default)]
+    pub usec: Optional<Option<ExplicitContextTag3<Microseconds>>>,
+    #[serde(default)]
+    pub s_address: Optional<Option<ExplicitContextTag4<HostAddress>>>,
+    #[serde(default)]
+    pub r_address: Optional<Option<ExplicitContextTag5<HostAddress>>>,
+}
+
+pub type EncKrbCredPart = ApplicationTag<EncKrbCredPartInner, ENC_KRB_CRED_PART_TYPE>;
+
+/// [RFC 4120 5.8.1](https://www.rfc-editor.org/rfc/rfc4120#section-5.8.1)
+///
+/// ```not_rust
+/// KrbCredInfo     ::= SEQUENCE {
+///         key             [0] EncryptionKey,
+///         prealm          [1] Realm OPTIONAL,
+///         pname           [2] PrincipalName OPTIONAL,
+///         flags           [3] TicketFlags OPTIONAL,
+///         authtime        [4] KerberosTime OPTIONAL,
+///         starttime       [5] KerberosTime OPTIONAL,
+///         endtime         [6] KerberosTime OPTIONAL,
+///         renew-till      [7] KerberosTime OPTIONAL,
+///         srealm          [8] Realm OPTIONAL,
+///         sname           [9] PrincipalName OPTIONAL,
+///         caddr           [10] HostAddresses OPTIONAL
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbCredInfo {
+    pub key: ExplicitContextTag0<EncryptionKey>,
+    #[serde(default)]
+    pub prealm: Optional<Option<ExplicitContextTag1<Realm>>>,
+    #[serde(default)]
+    pub pname: Optional<Option<ExplicitContextTag2<PrincipalName>>>,
+    #[serde(default)]
+    pub flags: Optional<Option<ExplicitContextTag3<KerberosFlags>>>,
+    #[serde(default)]
+    pub authtime: Optional<Option<ExplicitContextTag4<KerberosTime>>>,
+    #[serde(default)]
+    pub starttime: Optional<Option<ExplicitContextTag5<KerberosTime>>>,
+    #[serde(default)]
+    pub endtime: Optional<Option<ExplicitContextTag6<KerberosTime>>>,
+    #[serde(default)]
+    pub renew_till: Optional<Option<ExplicitContextTag7<KerberosTime>>>,
+    #[serde(default)]
+    pub srealm: Optional<Option<ExplicitContextTag8<Realm>>>,
+    #[serde(default)]
+    pub sname: Optional<Option<ExplicitContextTag9<PrincipalName>>>,
+    #[serde(default)]
+    pub caddr: Optional<Option<ExplicitContextTag10<Asn1SequenceOf<HostAddress>>>>,
+}
+
+/// [RFC 4120 5.7.1](https://www.rfc-editor.org/rfc/rfc4120#section-5.7.1)
+///
+/// ```not_rust
+/// KRB-PRIV        ::= [APPLICATION 21] SEQUENCE {
+///         pvno            [0] INTEGER (5),
+///         msg-type        [1] INTEGER (21),
+///                         -- NOTE: there is no [2] tag
+///         enc-part        [3] EncryptedData -- EncKrbPrivPart
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbPrivInner {
+    pub pvno: ExplicitContextTag0<IntegerAsn1>,
+    pub msg_type: ExplicitContextTag1<IntegerAsn1>,
+    pub enc_part: ExplicitContextTag3<EncryptedData>,
+}
+
+pub type KrbPriv = ApplicationTag<KrbPrivInner, KRB_PRIV_TYPE>;
+
+/// [RFC 4120 5.7.1](https://www.rfc-editor.org/rfc/rfc4120#section-5.7.1)
+///
+/// ```not_rust
+/// EncKrbPrivPart  ::= [APPLICATION 28] SEQUENCE {
+///         user-data       [0] OCTET STRING,
+///         timestamp       [1] KerberosTime OPTIONAL,
+///         usec            [2] Microseconds OPTIONAL,
+///         seq-number      [3] UInt32 OPTIONAL,
+///         s-address       [4] HostAddress -- sender's addr --,
+///         r-address       [5] HostAddress OPTIONAL -- recip's addr
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct EncKrbPrivPartInner {
+    pub user_data: ExplicitContextTag0<OctetStringAsn1>,
+    #[serde(default)]
+    pub timestamp: Optional<Option<ExplicitContextTag1<KerberosTime>>>,
+    #[serde(default)]
+    pub usec: Optional<Option<ExplicitContextTag2<Microseconds>>>,
+    #[serde(default)]
+    pub seq_number: Optional<Option<ExplicitContextTag3<IntegerAsn1>>>,
+    pub s_address: ExplicitContextTag4<HostAddress>,
+    #[serde(default)]
+    pub r_address: Optional<Option<ExplicitContextTag5<HostAddress>>>,
+}
+
+pub type EncKrbPrivPart = ApplicationTag<EncKrbPrivPartInner, ENC_KRB_PRIV_PART_TYPE>;
@@ -1,11 +1,14 @@
-use kerberos_crypto::AesSizes;
 use picky_krb::constants::key_usages::{ACCEPTOR_SEAL, INITIATOR_SEAL};
 
-use crate::sspi::kerberos::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96};
+use crate::sspi::kerberos::client::SUPPORTED_ENCRYPTION_TYPES;
+use crate::sspi::kerberos::DEFAULT_ENCRYPTION_TYPE;
+use crate::sspi::{Error, ErrorKind, Result};
 
 #[derive(Debug, Clone)]
 pub struct EncryptionParams {
     pub encryption_type: Option<i32>,
+    // in the order of preference
+    pub permitted_encryption_types: Vec<i32>,
     pub session_key: Option<Vec<u8>>,
     pub sub_session_key: Option<Vec<u8>>,
     pub sspi_encrypt_key_usage: i32,
@@ -16,6 +19,7 @@ impl EncryptionParams {
     pub fn default_for_client() -> Self {
         Self {
             encryption_type: None,
+            permitted_encryption_types: SUPPORTED_ENCRYPTION_TYPES.to_vec(),
             session_key: None,
             sub_session_key: None,
             sspi_encrypt_key_usage: INITIATOR_SEAL,
@@ -26,6 +30,7 @@ impl EncryptionParams {
     pub fn default_for_server() -> Self {
         Self {
             encryption_type: None,
+            permitted_encryption_types: SUPPORTED_ENCRYPTION_TYPES.to_vec(),
             session_key: None,
             sub_session_key: None,
             sspi_encrypt_key_usage: ACCEPTOR_SEAL,
@@ -33,11 +38,21 @@ impl EncryptionParams {
         }
     }
 
-    pub fn aes_sizes(&self) -> Option<AesSizes> {
-        self.encryption_type.map(|e_type| match e_type {
-            AES256_CTS_HMAC_SHA1_96 => AesSizes::Aes256,
-            AES128_CTS_HMAC_SHA1_96 => AesSizes::Aes128,
-            _ => AesSizes::Aes256,
-        })
+    // the most preferred permitted encryption type is used until the KDC chooses one
+    pub fn encryption_type(&self) -> i32 {
+        self.encryption_type
+            .or_else(|| self.permitted_encryption_types.first().copied())
+            .unwrap_or(DEFAULT_ENCRYPTION_TYPE)
+    }
+
+    pub fn check_permitted(&self, encryption_type: i32) -> Result<()> {
+        if self.permitted_encryption_types.contains(&encryption_type) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::KdcUnknownEType,
+                format!("The {} encryption type is not permitted", encryption_type),
+            ))
+        }
     }
 }
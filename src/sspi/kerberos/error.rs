@@ -0,0 +1,62 @@
+//! KRB-ERROR messages: [RFC 4120 5.9.1](https://www.rfc-editor.org/rfc/rfc4120#section-5.9.1)
+
+use chrono::{DateTime, Duration, Utc};
+use kerberos_constants::error_codes::{KDC_ERR_PREAUTH_FAILED, KRB_AP_ERR_SKEW};
+use picky_asn1::date::GeneralizedTime;
+use picky_krb::messages::KrbError;
+
+use super::utils::integer_to_u32;
+use crate::sspi::{get_krb_status_from_code, Error, ErrorKind};
+
+/// The KRB-ERROR returned by the KDC, so callers can react to the error code without parsing the description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KerberosError {
+    pub error_code: i32,
+    pub e_text: Option<String>,
+    pub e_data: Option<Vec<u8>>,
+    pub server_time: DateTime<Utc>,
+    /// The realm of the client, e.g. the realm the client is referred to by KDC_ERR_WRONG_REALM
+    pub client_realm: Option<String>,
+}
+
+impl KerberosError {
+    /// The name of the error code, e.g. KDC_ERR_PREAUTH_REQUIRED
+    pub fn name(&self) -> &'static str {
+        match u8::try_from(self.error_code) {
+            Ok(error_code) => get_krb_status_from_code(&[error_code]),
+            Err(_) => get_krb_status_from_code(&[]),
+        }
+    }
+}
+
+impl From<&KrbError> for KerberosError {
+    fn from(error: &KrbError) -> Self {
+        let server_time = DateTime::<Utc>::from(GeneralizedTime::from(error.0.stime.0.clone()))
+            + Duration::microseconds(i64::from(integer_to_u32(&error.0.susec.0)));
+
+        Self {
+            error_code: integer_to_u32(&error.0.error_code.0) as i32,
+            e_text: error.0.e_text.0.as_ref().map(|e_text| e_text.0.to_string()),
+            e_data: error.0.e_data.0.as_ref().map(|e_data| e_data.0 .0.clone()),
+            server_time,
+            client_realm: error.0.crealm.0.as_ref().map(|crealm| crealm.0.to_string()),
+        }
+    }
+}
+
+impl From<KerberosError> for Error {
+    fn from(error: KerberosError) -> Self {
+        let error_type = match error.error_code {
+            KRB_AP_ERR_SKEW => ErrorKind::TimeSkew,
+            KDC_ERR_PREAUTH_FAILED => ErrorKind::LogonDenied,
+            _ => ErrorKind::InternalError,
+        };
+
+        let description = match &error.e_text {
+            Some(e_text) => format!("Got the krb error: {} ({}): {}", error.name(), error.error_code, e_text),
+            None => format!("Got the krb error: {} ({})", error.name(), error.error_code),
+        };
+
+        Self::new(error_type, description).with_kerberos_error(error)
+    }
+}
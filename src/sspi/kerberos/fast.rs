@@ -0,0 +1,372 @@
+//! Flexible authentication secure tunneling: [RFC 6113](https://www.rfc-editor.org/rfc/rfc6113)
+//!
+//! The padata and the body of the KDC requests are encrypted with the armor key. The AS exchange is armored
+//! explicitly with the AP-REQ of the armor ticket, the TGS exchange implicitly with the subkey of the PA-TGS-REQ
+
+mod data_types;
+#[cfg(test)]
+mod test;
+
+use chrono::{DateTime, Utc};
+use kerberos_constants::error_codes::KRB_AP_ERR_SKEW;
+use kerberos_constants::key_usages::KEY_USAGE_AP_REQ_AUTHEN;
+use picky_asn1::bit_string::BitString;
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3,
+    ExplicitContextTag4, ExplicitContextTag6, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::constants::types::{AP_REQ_MSG_TYPE, PA_TGS_REQ_TYPE};
+use picky_krb::data_types::{
+    ApOptions, Checksum, EncryptedData, EncryptionKey, KerberosFlags, PaData, PaEncTsEnc, Ticket,
+};
+use picky_krb::messages::{ApReq, ApReqInner, KdcReq, KrbError};
+use subtle::ConstantTimeEq;
+
+pub(crate) use self::data_types::PA_ENCRYPTED_CHALLENGE;
+use self::data_types::{
+    KrbFastArmor, KrbFastArmoredReq, KrbFastFinished, KrbFastReq, KrbFastResponse, PaFxFastReply, PaFxFastRequest,
+    FX_FAST_ARMOR_AP_REQUEST, PA_FX_COOKIE, PA_FX_ERROR, PA_FX_FAST,
+};
+use super::ccache::CachedCredentials;
+use super::client::generators::{generate_authenticator_for_tgs_ap_req, generate_pa_enc_ts_enc};
+use super::crypto::{self, new_cipher};
+use super::utils::{i32_to_integer, integer_to_u32};
+use super::KERBEROS_VERSION;
+use crate::sspi::{Error, ErrorKind, Result};
+
+// KRB-FX-CF2 peppers: [RFC 6113 5.4.1.1](https://www.rfc-editor.org/rfc/rfc6113#section-5.4.1.1)
+const SUBKEY_ARMOR: &[u8] = b"subkeyarmor";
+const TICKET_ARMOR: &[u8] = b"ticketarmor";
+const CLIENT_CHALLENGE_ARMOR: &[u8] = b"clientchallengearmor";
+const KDC_CHALLENGE_ARMOR: &[u8] = b"kdcchallengearmor";
+const CHALLENGE_LONG_TERM: &[u8] = b"challengelongterm";
+const STRENGTHEN_KEY: &[u8] = b"strengthenkey";
+const REPLY_KEY: &[u8] = b"replykey";
+
+const KEY_USAGE_FAST_REQ_CHKSUM: i32 = 50;
+const KEY_USAGE_FAST_ENC: i32 = 51;
+const KEY_USAGE_FAST_REP: i32 = 52;
+const KEY_USAGE_FAST_FINISHED: i32 = 53;
+const KEY_USAGE_ENC_CHALLENGE_CLIENT: i32 = 54;
+const KEY_USAGE_ENC_CHALLENGE_KDC: i32 = 55;
+
+// the client name is not hidden and no critical options are requested
+const DEFAULT_FAST_OPTIONS: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
+
+/// Armor key of the exchange and the armor sent to the KDC
+#[derive(Debug, Clone)]
+pub(crate) struct FastArmor {
+    armor: Option<KrbFastArmor>,
+    key: Vec<u8>,
+    encryption_type: i32,
+}
+
+impl FastArmor {
+    /// Explicit armor: the AP-REQ of the armor ticket with the fresh subkey
+    pub fn from_ticket(armor_ticket: &CachedCredentials, current_date: DateTime<Utc>) -> Result<Self> {
+        let encryption_type = armor_ticket.encryption_type;
+        let subkey = crypto::generate_random_key(encryption_type)?;
+
+        let mut authenticator = generate_authenticator_for_tgs_ap_req(
+            &armor_ticket.client.principal_name()?,
+            &armor_ticket.client.kerberos_realm()?,
+            current_date,
+        )?;
+        authenticator.0.subkey = Optional::from(Some(ExplicitContextTag6::from(EncryptionKey {
+            key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
+            key_value: ExplicitContextTag1::from(OctetStringAsn1::from(subkey.clone())),
+        })));
+
+        let ap_req = ApReq::from(ApReqInner {
+            pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+            msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![AP_REQ_MSG_TYPE])),
+            ap_options: ExplicitContextTag2::from(ApOptions::from(BitString::with_bytes(vec![0x00, 0x00, 0x00, 0x00]))),
+            ticket: ExplicitContextTag3::from(armor_ticket.decode_ticket()?),
+            authenticator: ExplicitContextTag4::from(encrypt(
+                encryption_type,
+                &armor_ticket.key,
+                KEY_USAGE_AP_REQ_AUTHEN,
+                &picky_asn1_der::to_vec(&authenticator)?,
+            )?),
+        });
+
+        Ok(Self {
+            armor: Some(KrbFastArmor {
+                armor_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![FX_FAST_ARMOR_AP_REQUEST])),
+                armor_value: ExplicitContextTag1::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&ap_req)?)),
+            }),
+            key: crypto::cf2(
+                encryption_type,
+                &subkey,
+                SUBKEY_ARMOR,
+                encryption_type,
+                &armor_ticket.key,
+                TICKET_ARMOR,
+            )?,
+            encryption_type,
+        })
+    }
+
+    /// Implicit armor of the TGS exchange: the subkey of the PA-TGS-REQ authenticator and the TGT session key
+    pub fn from_tgs_subkey(encryption_type: i32, subkey: &[u8], session_key: &[u8]) -> Result<Self> {
+        Ok(Self {
+            armor: None,
+            key: crypto::cf2(
+                encryption_type,
+                subkey,
+                SUBKEY_ARMOR,
+                encryption_type,
+                session_key,
+                TICKET_ARMOR,
+            )?,
+            encryption_type,
+        })
+    }
+
+    /// Moves the padata and the body of the request into the encrypted KrbFastReq. The PA-TGS-REQ stays outside
+    pub fn armor_request(&self, kdc_req: &mut KdcReq) -> Result<()> {
+        let (mut padata, fast_padata): (Vec<PaData>, Vec<PaData>) = kdc_req
+            .padata
+            .0
+            .take()
+            .map(|padata| padata.0 .0)
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|pa_data| pa_data.padata_type.0 .0 == PA_TGS_REQ_TYPE);
+
+        // the AS-REQ checksum covers the request body, the TGS-REQ checksum covers the AP-REQ of the PA-TGS-REQ
+        let checksum_data = match padata.first() {
+            Some(pa_tgs_req) => pa_tgs_req.padata_data.0 .0.clone(),
+            None => picky_asn1_der::to_vec(&kdc_req.req_body.0)?,
+        };
+
+        let fast_req = KrbFastReq {
+            fast_options: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(
+                DEFAULT_FAST_OPTIONS.to_vec(),
+            ))),
+            padata: ExplicitContextTag1::from(Asn1SequenceOf::from(fast_padata)),
+            req_body: ExplicitContextTag2::from(kdc_req.req_body.0.clone()),
+        };
+
+        let armored_req = KrbFastArmoredReq {
+            armor: Optional::from(self.armor.clone().map(ExplicitContextTag0::from)),
+            req_checksum: ExplicitContextTag1::from(self.checksum(KEY_USAGE_FAST_REQ_CHKSUM, &checksum_data)?),
+            enc_fast_req: ExplicitContextTag2::from(encrypt(
+                self.encryption_type,
+                &self.key,
+                KEY_USAGE_FAST_ENC,
+                &picky_asn1_der::to_vec(&fast_req)?,
+            )?),
+        };
+
+        padata.push(PaData {
+            padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_FX_FAST.to_vec())),
+            padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(
+                &PaFxFastRequest::from(armored_req),
+            )?)),
+        });
+        kdc_req.padata = Optional::from(Some(ExplicitContextTag3::from(Asn1SequenceOf::from(padata))));
+
+        Ok(())
+    }
+
+    /// Decrypts the KrbFastResponse of the PA-FX-FAST and checks that it replies to the request with the nonce
+    pub fn unwrap_response(&self, padata: &[PaData], nonce: u32) -> Result<KrbFastResponse> {
+        let pa_fx_fast = find_pa_data(padata, &PA_FX_FAST)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidToken, "The KDC reply is not armored".into()))?;
+        let fast_reply: PaFxFastReply = picky_asn1_der::from_bytes(&pa_fx_fast.padata_data.0 .0)?;
+
+        let enc_fast_rep = &fast_reply.0.enc_fast_rep.0;
+        let fast_response = new_cipher(self.encryption_type)?
+            .decrypt(&self.key, KEY_USAGE_FAST_REP, &enc_fast_rep.cipher.0 .0)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::DecryptFailure,
+                    format!("Cannot decrypt the armored reply: {:?}", e),
+                )
+            })?;
+        let fast_response: KrbFastResponse = picky_asn1_der::from_bytes(&fast_response)?;
+
+        if integer_to_u32(&fast_response.nonce.0) != nonce {
+            return Err(Error::new(
+                ErrorKind::MessageAltered,
+                "The nonce of the armored reply does not match the request".into(),
+            ));
+        }
+
+        Ok(fast_response)
+    }
+
+    /// Returns the KDC error of the PA-FX-ERROR and the padata of the armored KRB-ERROR
+    pub fn unwrap_error(&self, error: &KrbError, nonce: u32) -> Result<(KrbError, Vec<PaData>)> {
+        let method_data = error
+            .0
+            .e_data
+            .0
+            .as_ref()
+            .and_then(|e_data| picky_asn1_der::from_bytes::<Asn1SequenceOf<PaData>>(&e_data.0 .0).ok())
+            .filter(|method_data| find_pa_data(&method_data.0, &PA_FX_FAST).is_some());
+        let method_data = match method_data {
+            Some(method_data) => method_data,
+            // the KDC cannot verify the armor made with the skewed clock, so the error is not armored
+            None if integer_to_u32(&error.0.error_code.0) as i32 == KRB_AP_ERR_SKEW => {
+                return Ok((error.clone(), Vec::new()))
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidToken,
+                    format!(
+                        "The KDC error is not armored: {}",
+                        Error::from(error.clone()).description
+                    ),
+                ))
+            }
+        };
+
+        let fast_response = self.unwrap_response(&method_data.0, nonce)?;
+        let padata = fast_response.padata.0 .0;
+
+        let error = match find_pa_data(&padata, &PA_FX_ERROR) {
+            Some(pa_fx_error) => picky_asn1_der::from_bytes(&pa_fx_error.padata_data.0 .0)?,
+            None => error.clone(),
+        };
+
+        Ok((error, padata))
+    }
+
+    /// Checks that the KrbFastFinished binds the issued ticket to the armored exchange
+    pub fn check_finished<'a>(
+        &self,
+        fast_response: &'a KrbFastResponse,
+        ticket: &Ticket,
+    ) -> Result<&'a KrbFastFinished> {
+        let finished = fast_response.finished.0.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::MessageAltered,
+                "The armored reply does not contain the KrbFastFinished".into(),
+            )
+        })?;
+
+        let ticket_checksum = &finished.0.ticket_checksum.0;
+        let expected = self.checksum(KEY_USAGE_FAST_FINISHED, &picky_asn1_der::to_vec(ticket)?)?;
+        if integer_to_u32(&ticket_checksum.cksumtype.0) != integer_to_u32(&expected.cksumtype.0)
+            || !bool::from(ticket_checksum.checksum.0 .0.ct_eq(&expected.checksum.0 .0))
+        {
+            return Err(Error::new(
+                ErrorKind::MessageAltered,
+                "The ticket checksum of the armored reply is invalid".into(),
+            ));
+        }
+
+        Ok(&finished.0)
+    }
+
+    /// PA-ENCRYPTED-CHALLENGE of the client: the timestamp encrypted with the client challenge key
+    pub fn client_challenge(
+        &self,
+        client_key: &[u8],
+        client_encryption_type: i32,
+        current_date: DateTime<Utc>,
+    ) -> Result<PaData> {
+        let challenge_key = crypto::cf2(
+            self.encryption_type,
+            &self.key,
+            CLIENT_CHALLENGE_ARMOR,
+            client_encryption_type,
+            client_key,
+            CHALLENGE_LONG_TERM,
+        )?;
+
+        Ok(PaData {
+            padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_ENCRYPTED_CHALLENGE.to_vec())),
+            padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&encrypt(
+                self.encryption_type,
+                &challenge_key,
+                KEY_USAGE_ENC_CHALLENGE_CLIENT,
+                &generate_pa_enc_ts_enc(current_date)?,
+            )?)?)),
+        })
+    }
+
+    /// Verifies the PA-ENCRYPTED-CHALLENGE of the KDC: it proves that the KDC knows the client long-term key
+    pub fn check_kdc_challenge(&self, padata: &[PaData], client_key: &[u8], client_encryption_type: i32) -> Result<()> {
+        let kdc_challenge = find_pa_data(padata, &PA_ENCRYPTED_CHALLENGE).ok_or_else(|| {
+            Error::new(
+                ErrorKind::MessageAltered,
+                "The armored reply does not contain the KDC encrypted challenge".into(),
+            )
+        })?;
+        let kdc_challenge: EncryptedData = picky_asn1_der::from_bytes(&kdc_challenge.padata_data.0 .0)?;
+
+        let challenge_key = crypto::cf2(
+            self.encryption_type,
+            &self.key,
+            KDC_CHALLENGE_ARMOR,
+            client_encryption_type,
+            client_key,
+            CHALLENGE_LONG_TERM,
+        )?;
+
+        let timestamp = new_cipher(self.encryption_type)?
+            .decrypt(&challenge_key, KEY_USAGE_ENC_CHALLENGE_KDC, &kdc_challenge.cipher.0 .0)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::DecryptFailure,
+                    format!("Cannot decrypt the KDC encrypted challenge: {:?}", e),
+                )
+            })?;
+        let _: PaEncTsEnc = picky_asn1_der::from_bytes(&timestamp)?;
+
+        Ok(())
+    }
+
+    fn checksum(&self, key_usage: i32, data: &[u8]) -> Result<Checksum> {
+        Ok(Checksum {
+            cksumtype: ExplicitContextTag0::from(i32_to_integer(crypto::checksum_type(self.encryption_type)?)),
+            checksum: ExplicitContextTag1::from(OctetStringAsn1::from(crypto::checksum(
+                self.encryption_type,
+                &self.key,
+                key_usage,
+                data,
+            )?)),
+        })
+    }
+}
+
+/// The reply key is combined with the strengthen-key when the KDC provides it
+pub(crate) fn strengthen_reply_key(
+    fast_response: &KrbFastResponse,
+    reply_key: Vec<u8>,
+    encryption_type: i32,
+) -> Result<Vec<u8>> {
+    match fast_response.strengthen_key.0.as_ref() {
+        Some(strengthen_key) => crypto::cf2(
+            integer_to_u32(&strengthen_key.0.key_type.0) as i32,
+            &strengthen_key.0.key_value.0 .0,
+            STRENGTHEN_KEY,
+            encryption_type,
+            &reply_key,
+            REPLY_KEY,
+        ),
+        None => Ok(reply_key),
+    }
+}
+
+/// PA-FX-COOKIE of the KDC error, the client returns it in the next request of the exchange
+pub(crate) fn fx_cookie(padata: &[PaData]) -> Option<PaData> {
+    find_pa_data(padata, &PA_FX_COOKIE).cloned()
+}
+
+fn find_pa_data<'a>(padata: &'a [PaData], padata_type: &[u8]) -> Option<&'a PaData> {
+    padata.iter().find(|pa_data| pa_data.padata_type.0 .0 == padata_type)
+}
+
+fn encrypt(encryption_type: i32, key: &[u8], key_usage: i32, data: &[u8]) -> Result<EncryptedData> {
+    Ok(EncryptedData {
+        etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
+        kvno: Optional::from(None),
+        cipher: ExplicitContextTag2::from(OctetStringAsn1::from(
+            new_cipher(encryption_type)?.encrypt(key, key_usage, data),
+        )),
+    })
+}
@@ -0,0 +1,127 @@
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3,
+    ExplicitContextTag4, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::data_types::{
+    Checksum, EncryptedData, EncryptionKey, KerberosFlags, KerberosTime, Microseconds, PaData, PrincipalName, Realm,
+};
+use picky_krb::messages::KdcReqBody;
+use serde::{Deserialize, Serialize};
+
+pub const PA_FX_COOKIE: [u8; 2] = [0x00, 0x85];
+pub const PA_FX_FAST: [u8; 2] = [0x00, 0x88];
+pub const PA_FX_ERROR: [u8; 2] = [0x00, 0x89];
+pub const PA_ENCRYPTED_CHALLENGE: [u8; 2] = [0x00, 0x8a];
+
+pub const FX_FAST_ARMOR_AP_REQUEST: u8 = 0x01;
+
+/// [RFC 6113 5.4.1](https://www.rfc-editor.org/rfc/rfc6113#section-5.4.1)
+///
+/// ```not_rust
+/// KrbFastArmor ::= SEQUENCE {
+///         armor-type   [0] Int32,
+///         armor-value  [1] OCTET STRING,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbFastArmor {
+    pub armor_type: ExplicitContextTag0<IntegerAsn1>,
+    pub armor_value: ExplicitContextTag1<OctetStringAsn1>,
+}
+
+/// [RFC 6113 5.4.2](https://www.rfc-editor.org/rfc/rfc6113#section-5.4.2)
+///
+/// ```not_rust
+/// KrbFastArmoredReq ::= SEQUENCE {
+///         armor        [0] KrbFastArmor OPTIONAL,
+///         req-checksum [1] Checksum,
+///         enc-fast-req [2] EncryptedData, -- KrbFastReq --
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbFastArmoredReq {
+    #[serde(default)]
+    pub armor: Optional<Option<ExplicitContextTag0<KrbFastArmor>>>,
+    pub req_checksum: ExplicitContextTag1<Checksum>,
+    pub enc_fast_req: ExplicitContextTag2<EncryptedData>,
+}
+
+/// The PA-FX-FAST-REQUEST is the choice of `armored-data [0] KrbFastArmoredReq`
+pub type PaFxFastRequest = ExplicitContextTag0<KrbFastArmoredReq>;
+
+/// [RFC 6113 5.4.2](https://www.rfc-editor.org/rfc/rfc6113#section-5.4.2)
+///
+/// ```not_rust
+/// KrbFastReq ::= SEQUENCE {
+///         fast-options [0] FastOptions,
+///         padata       [1] SEQUENCE OF PA-DATA,
+///         req-body     [2] KDC-REQ-BODY,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbFastReq {
+    pub fast_options: ExplicitContextTag0<KerberosFlags>,
+    pub padata: ExplicitContextTag1<Asn1SequenceOf<PaData>>,
+    pub req_body: ExplicitContextTag2<KdcReqBody>,
+}
+
+/// [RFC 6113 5.4.3](https://www.rfc-editor.org/rfc/rfc6113#section-5.4.3)
+///
+/// ```not_rust
+/// KrbFastArmoredRep ::= SEQUENCE {
+///         enc-fast-rep [0] EncryptedData, -- KrbFastResponse --
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbFastArmoredRep {
+    pub enc_fast_rep: ExplicitContextTag0<EncryptedData>,
+}
+
+/// The PA-FX-FAST-REPLY is the choice of `armored-data [0] KrbFastArmoredRep`
+pub type PaFxFastReply = ExplicitContextTag0<KrbFastArmoredRep>;
+
+/// [RFC 6113 5.4.3](https://www.rfc-editor.org/rfc/rfc6113#section-5.4.3)
+///
+/// ```not_rust
+/// KrbFastResponse ::= SEQUENCE {
+///         padata         [0] SEQUENCE OF PA-DATA,
+///         strengthen-key [1] EncryptionKey OPTIONAL,
+///         finished       [2] KrbFastFinished OPTIONAL,
+///         nonce          [3] UInt32,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbFastResponse {
+    pub padata: ExplicitContextTag0<Asn1SequenceOf<PaData>>,
+    #[serde(default)]
+    pub strengthen_key: Optional<Option<ExplicitContextTag1<EncryptionKey>>>,
+    #[serde(default)]
+    pub finished: Optional<Option<ExplicitContextTag2<KrbFastFinished>>>,
+    pub nonce: ExplicitContextTag3<IntegerAsn1>,
+}
+
+/// [RFC 6113 5.4.3](https://www.rfc-editor.org/rfc/rfc6113#section-5.4.3)
+///
+/// ```not_rust
+/// KrbFastFinished ::= SEQUENCE {
+///         timestamp       [0] KerberosTime,
+///         usec            [1] Microseconds,
+///         crealm          [2] Realm,
+///         cname           [3] PrincipalName,
+///         ticket-checksum [4] Checksum,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KrbFastFinished {
+    pub timestamp: ExplicitContextTag0<KerberosTime>,
+    pub usec: ExplicitContextTag1<Microseconds>,
+    pub crealm: ExplicitContextTag2<Realm>,
+    pub cname: ExplicitContextTag3<PrincipalName>,
+    pub ticket_checksum: ExplicitContextTag4<Checksum>,
+}
@@ -0,0 +1,477 @@
+use chrono::Utc;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag10, ExplicitContextTag12,
+    ExplicitContextTag2, ExplicitContextTag3, ExplicitContextTag4, ExplicitContextTag5, ExplicitContextTag6,
+    ExplicitContextTag9, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::constants::types::{KRB_ERROR_MSG_TYPE, PA_ETYPE_INFO2_TYPE, PA_TGS_REQ_TYPE};
+use picky_krb::data_types::{
+    Authenticator, EncryptedData, EncryptionKey, KerberosTime, PaData, PaEncTsEnc, Ticket, TicketInner,
+};
+use picky_krb::messages::{ApReq, KdcReq, KrbError, KrbErrorInner};
+
+use super::data_types::{
+    KrbFastArmoredRep, KrbFastArmoredReq, KrbFastFinished, KrbFastReq, KrbFastResponse, PaFxFastReply, PaFxFastRequest,
+    PA_ENCRYPTED_CHALLENGE, PA_FX_COOKIE, PA_FX_ERROR, PA_FX_FAST,
+};
+use super::*;
+use crate::sspi::kerberos::ccache::CachedCredentials;
+use crate::sspi::kerberos::client::generators::{generate_as_req_without_pre_auth, generate_pa_pac_request};
+use crate::sspi::kerberos::crypto;
+use crate::sspi::kerberos::encryption_params::EncryptionParams;
+use crate::sspi::kerberos::{Principal, AES256_CTS_HMAC_SHA1_96, KERBEROS_VERSION};
+use crate::sspi::ErrorKind;
+
+const NONCE: u32 = 0x1234_5678;
+const KDC_ERR_PREAUTH_REQUIRED: u8 = 25;
+const CLIENT_KEY: [u8; 32] = [0x42; 32];
+
+fn ticket() -> Ticket {
+    let tgs_principal = Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM");
+
+    Ticket::from(TicketInner {
+        tkt_vno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        realm: ExplicitContextTag1::from(tgs_principal.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag2::from(tgs_principal.principal_name().unwrap()),
+        enc_part: ExplicitContextTag3::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(vec![0; 16])),
+        }),
+    })
+}
+
+// the machine TGT
+fn armor_ticket() -> CachedCredentials {
+    CachedCredentials {
+        client: Principal::new("host/machine.example.com", "EXAMPLE.COM"),
+        server: Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM"),
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        key: crypto::generate_random_key(AES256_CTS_HMAC_SHA1_96).unwrap(),
+        auth_time: 0,
+        start_time: 0,
+        end_time: u32::MAX,
+        renew_till: 0,
+        is_skey: false,
+        ticket_flags: 0,
+        addresses: Vec::new(),
+        auth_data: Vec::new(),
+        ticket: picky_asn1_der::to_vec(&ticket()).unwrap(),
+        second_ticket: Vec::new(),
+    }
+}
+
+fn pa_data(padata_type: &[u8], padata_data: Vec<u8>) -> PaData {
+    PaData {
+        padata_type: ExplicitContextTag1::from(IntegerAsn1::from(padata_type.to_vec())),
+        padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(padata_data)),
+    }
+}
+
+fn krb_error(error_code: u8, e_data: Option<Vec<u8>>) -> KrbError {
+    let principal = Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM");
+
+    KrbError::from(KrbErrorInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![KRB_ERROR_MSG_TYPE])),
+        ctime: Optional::from(None),
+        cusec: Optional::from(None),
+        stime: ExplicitContextTag4::from(KerberosTime::from(GeneralizedTime::from(Utc::now()))),
+        susec: ExplicitContextTag5::from(IntegerAsn1::from(vec![0])),
+        error_code: ExplicitContextTag6::from(IntegerAsn1::from(vec![error_code])),
+        crealm: Optional::from(None),
+        cname: Optional::from(None),
+        realm: ExplicitContextTag9::from(principal.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag10::from(principal.principal_name().unwrap()),
+        e_text: Optional::from(None),
+        e_data: Optional::from(e_data.map(|e_data| ExplicitContextTag12::from(OctetStringAsn1::from(e_data)))),
+    })
+}
+
+// PA-FX-FAST of the KDC reply
+fn pa_fx_fast_reply(armor: &FastArmor, fast_response: &KrbFastResponse) -> PaData {
+    let enc_fast_rep = encrypt(
+        armor.encryption_type,
+        &armor.key,
+        KEY_USAGE_FAST_REP,
+        &picky_asn1_der::to_vec(fast_response).unwrap(),
+    )
+    .unwrap();
+
+    pa_data(
+        &PA_FX_FAST,
+        picky_asn1_der::to_vec(&PaFxFastReply::from(KrbFastArmoredRep {
+            enc_fast_rep: ExplicitContextTag0::from(enc_fast_rep),
+        }))
+        .unwrap(),
+    )
+}
+
+fn fast_response(padata: Vec<PaData>, finished: Option<KrbFastFinished>, nonce: u32) -> KrbFastResponse {
+    KrbFastResponse {
+        padata: ExplicitContextTag0::from(Asn1SequenceOf::from(padata)),
+        strengthen_key: Optional::from(None),
+        finished: Optional::from(finished.map(ExplicitContextTag2::from)),
+        nonce: ExplicitContextTag3::from(IntegerAsn1::from_bytes_be_unsigned(nonce.to_be_bytes().to_vec())),
+    }
+}
+
+fn finished(armor: &FastArmor, ticket: &Ticket) -> KrbFastFinished {
+    let principal = Principal::new("user", "EXAMPLE.COM");
+
+    KrbFastFinished {
+        timestamp: ExplicitContextTag0::from(KerberosTime::from(GeneralizedTime::from(Utc::now()))),
+        usec: ExplicitContextTag1::from(IntegerAsn1::from(vec![0])),
+        crealm: ExplicitContextTag2::from(principal.kerberos_realm().unwrap()),
+        cname: ExplicitContextTag3::from(principal.principal_name().unwrap()),
+        ticket_checksum: ExplicitContextTag4::from(
+            armor
+                .checksum(KEY_USAGE_FAST_FINISHED, &picky_asn1_der::to_vec(ticket).unwrap())
+                .unwrap(),
+        ),
+    }
+}
+
+// the KDC decrypts the KrbFastReq of the PA-FX-FAST
+fn decrypt_fast_req(armor: &FastArmor, padata: &[PaData]) -> (KrbFastArmoredReq, KrbFastReq) {
+    let pa_fx_fast = find_pa_data(padata, &PA_FX_FAST).unwrap();
+    let armored_req: PaFxFastRequest = picky_asn1_der::from_bytes(&pa_fx_fast.padata_data.0 .0).unwrap();
+
+    let fast_req = crypto::new_cipher(armor.encryption_type)
+        .unwrap()
+        .decrypt(
+            &armor.key,
+            KEY_USAGE_FAST_ENC,
+            &armored_req.0.enc_fast_req.0.cipher.0 .0,
+        )
+        .unwrap();
+
+    (armored_req.0, picky_asn1_der::from_bytes(&fast_req).unwrap())
+}
+
+#[test]
+fn armor_key_is_derived_from_subkey_of_armor_ap_req() {
+    let armor_ticket = armor_ticket();
+    let armor = FastArmor::from_ticket(&armor_ticket, Utc::now()).unwrap();
+
+    let fast_armor = armor.armor.as_ref().unwrap();
+    assert_eq!(
+        fast_armor.armor_type.0,
+        IntegerAsn1::from(vec![FX_FAST_ARMOR_AP_REQUEST])
+    );
+
+    let ap_req: ApReq = picky_asn1_der::from_bytes(&fast_armor.armor_value.0 .0).unwrap();
+    assert_eq!(ap_req.0.ticket.0, ticket());
+
+    let authenticator = crypto::new_cipher(AES256_CTS_HMAC_SHA1_96)
+        .unwrap()
+        .decrypt(
+            &armor_ticket.key,
+            KEY_USAGE_AP_REQ_AUTHEN,
+            &ap_req.0.authenticator.0.cipher.0 .0,
+        )
+        .unwrap();
+    let authenticator: Authenticator = picky_asn1_der::from_bytes(&authenticator).unwrap();
+    let subkey = authenticator.0.subkey.0.unwrap().0.key_value.0 .0;
+
+    assert_eq!(
+        armor.key,
+        crypto::cf2(
+            AES256_CTS_HMAC_SHA1_96,
+            &subkey,
+            b"subkeyarmor",
+            AES256_CTS_HMAC_SHA1_96,
+            &armor_ticket.key,
+            b"ticketarmor"
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn as_req_padata_is_moved_into_armored_request() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+
+    let mut as_req =
+        generate_as_req_without_pre_auth("user", "EXAMPLE.COM", &EncryptionParams::default_for_client()).unwrap();
+    let padata = as_req.0.padata.0.clone().unwrap().0 .0;
+
+    armor.armor_request(&mut as_req.0).unwrap();
+
+    let outer_padata = as_req.0.padata.0.as_ref().unwrap().0 .0.clone();
+    assert_eq!(outer_padata.len(), 1);
+
+    let (armored_req, fast_req) = decrypt_fast_req(&armor, &outer_padata);
+    assert_eq!(fast_req.padata.0 .0, padata);
+    assert_eq!(fast_req.req_body.0, as_req.0.req_body.0);
+    assert!(armored_req.armor.0.is_some());
+    assert_eq!(
+        armored_req.req_checksum.0,
+        armor
+            .checksum(
+                KEY_USAGE_FAST_REQ_CHKSUM,
+                &picky_asn1_der::to_vec(&as_req.0.req_body.0).unwrap()
+            )
+            .unwrap()
+    );
+}
+
+#[test]
+fn tgs_req_is_armored_implicitly() {
+    let subkey = crypto::generate_random_key(AES256_CTS_HMAC_SHA1_96).unwrap();
+    let session_key = crypto::generate_random_key(AES256_CTS_HMAC_SHA1_96).unwrap();
+    let armor = FastArmor::from_tgs_subkey(AES256_CTS_HMAC_SHA1_96, &subkey, &session_key).unwrap();
+
+    let as_req =
+        generate_as_req_without_pre_auth("user", "EXAMPLE.COM", &EncryptionParams::default_for_client()).unwrap();
+    let pa_tgs_req = pa_data(&PA_TGS_REQ_TYPE, vec![0x30, 0x00]);
+    let mut tgs_req = KdcReq {
+        padata: Optional::from(Some(ExplicitContextTag3::from(Asn1SequenceOf::from(vec![
+            pa_tgs_req.clone(),
+            generate_pa_pac_request().unwrap(),
+        ])))),
+        ..as_req.0
+    };
+
+    armor.armor_request(&mut tgs_req).unwrap();
+
+    let outer_padata = tgs_req.padata.0.as_ref().unwrap().0 .0.clone();
+    assert_eq!(outer_padata.len(), 2);
+    assert_eq!(outer_padata[0], pa_tgs_req);
+
+    let (armored_req, fast_req) = decrypt_fast_req(&armor, &outer_padata);
+    assert!(armored_req.armor.0.is_none());
+    assert_eq!(fast_req.padata.0 .0, vec![generate_pa_pac_request().unwrap()]);
+    assert_eq!(
+        armored_req.req_checksum.0,
+        armor.checksum(KEY_USAGE_FAST_REQ_CHKSUM, &[0x30, 0x00]).unwrap()
+    );
+}
+
+#[test]
+fn armored_reply_is_unwrapped() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+    let ticket = ticket();
+    let response = fast_response(Vec::new(), Some(finished(&armor, &ticket)), NONCE);
+
+    let unwrapped = armor
+        .unwrap_response(&[pa_fx_fast_reply(&armor, &response)], NONCE)
+        .unwrap();
+    assert_eq!(unwrapped, response);
+
+    let finished = armor.check_finished(&unwrapped, &ticket).unwrap();
+    assert_eq!(
+        finished.cname.0,
+        Principal::new("user", "EXAMPLE.COM").principal_name().unwrap()
+    );
+}
+
+#[test]
+fn armored_reply_with_other_nonce_is_rejected() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+    let response = fast_response(Vec::new(), None, NONCE + 1);
+
+    let error = armor
+        .unwrap_response(&[pa_fx_fast_reply(&armor, &response)], NONCE)
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::MessageAltered);
+}
+
+#[test]
+fn reply_of_other_armor_is_rejected() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+    let other_armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+    let response = fast_response(Vec::new(), None, NONCE);
+
+    let error = armor
+        .unwrap_response(&[pa_fx_fast_reply(&other_armor, &response)], NONCE)
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::DecryptFailure);
+}
+
+#[test]
+fn unarmored_reply_is_rejected() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+
+    let error = armor.unwrap_response(&[], NONCE).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+}
+
+#[test]
+fn finished_with_other_ticket_is_rejected() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+    let response = fast_response(Vec::new(), Some(finished(&armor, &ticket())), NONCE);
+
+    let mut other_ticket = ticket();
+    other_ticket.0.enc_part.0.cipher = ExplicitContextTag2::from(OctetStringAsn1::from(vec![1; 16]));
+
+    let error = armor.check_finished(&response, &other_ticket).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::MessageAltered);
+}
+
+#[test]
+fn finished_with_other_checksum_type_is_rejected() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+    let mut finished = finished(&armor, &ticket());
+    finished.ticket_checksum.0.cksumtype = ExplicitContextTag0::from(IntegerAsn1::from(vec![0x07]));
+    let response = fast_response(Vec::new(), Some(finished), NONCE);
+
+    let error = armor.check_finished(&response, &ticket()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::MessageAltered);
+}
+
+#[test]
+fn error_embedded_in_fast_is_unwrapped() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+
+    let inner_error = krb_error(KDC_ERR_PREAUTH_REQUIRED, None);
+    let padata = vec![
+        pa_data(&PA_FX_ERROR, picky_asn1_der::to_vec(&inner_error).unwrap()),
+        pa_data(&PA_FX_COOKIE, vec![1, 2, 3]),
+        pa_data(&PA_ETYPE_INFO2_TYPE, vec![0x30, 0x00]),
+    ];
+    let method_data = vec![pa_fx_fast_reply(&armor, &fast_response(padata.clone(), None, NONCE))];
+    let error = krb_error(
+        KDC_ERR_PREAUTH_REQUIRED,
+        Some(picky_asn1_der::to_vec(&Asn1SequenceOf::from(method_data)).unwrap()),
+    );
+
+    let (unwrapped_error, unwrapped_padata) = armor.unwrap_error(&error, NONCE).unwrap();
+
+    assert_eq!(unwrapped_error, inner_error);
+    assert_eq!(unwrapped_padata, padata);
+    assert_eq!(
+        fx_cookie(&unwrapped_padata),
+        Some(pa_data(&PA_FX_COOKIE, vec![1, 2, 3]))
+    );
+}
+
+#[test]
+fn unarmored_error_is_rejected() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+
+    let error = armor
+        .unwrap_error(&krb_error(KDC_ERR_PREAUTH_REQUIRED, None), NONCE)
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+}
+
+#[test]
+fn unarmored_clock_skew_error_is_returned() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+    let error = krb_error(KRB_AP_ERR_SKEW as u8, None);
+
+    let (unwrapped_error, unwrapped_padata) = armor.unwrap_error(&error, NONCE).unwrap();
+
+    assert_eq!(unwrapped_error, error);
+    assert!(unwrapped_padata.is_empty());
+}
+
+#[test]
+fn client_challenge_is_encrypted_with_challenge_key() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+
+    let challenge = armor
+        .client_challenge(&CLIENT_KEY, AES256_CTS_HMAC_SHA1_96, Utc::now())
+        .unwrap();
+    assert_eq!(challenge.padata_type.0 .0, PA_ENCRYPTED_CHALLENGE);
+
+    let challenge_key = crypto::cf2(
+        AES256_CTS_HMAC_SHA1_96,
+        &armor.key,
+        b"clientchallengearmor",
+        AES256_CTS_HMAC_SHA1_96,
+        &CLIENT_KEY,
+        b"challengelongterm",
+    )
+    .unwrap();
+    let challenge: EncryptedData = picky_asn1_der::from_bytes(&challenge.padata_data.0 .0).unwrap();
+    let timestamp = crypto::new_cipher(AES256_CTS_HMAC_SHA1_96)
+        .unwrap()
+        .decrypt(&challenge_key, KEY_USAGE_ENC_CHALLENGE_CLIENT, &challenge.cipher.0 .0)
+        .unwrap();
+
+    assert!(picky_asn1_der::from_bytes::<PaEncTsEnc>(&timestamp).is_ok());
+}
+
+#[test]
+fn kdc_challenge_proves_knowledge_of_client_key() {
+    let armor = FastArmor::from_ticket(&armor_ticket(), Utc::now()).unwrap();
+
+    let challenge_key = crypto::cf2(
+        AES256_CTS_HMAC_SHA1_96,
+        &armor.key,
+        b"kdcchallengearmor",
+        AES256_CTS_HMAC_SHA1_96,
+        &CLIENT_KEY,
+        b"challengelongterm",
+    )
+    .unwrap();
+    let kdc_challenge = encrypt(
+        AES256_CTS_HMAC_SHA1_96,
+        &challenge_key,
+        KEY_USAGE_ENC_CHALLENGE_KDC,
+        &generate_pa_enc_ts_enc(Utc::now()).unwrap(),
+    )
+    .unwrap();
+    let padata = vec![pa_data(
+        &PA_ENCRYPTED_CHALLENGE,
+        picky_asn1_der::to_vec(&kdc_challenge).unwrap(),
+    )];
+
+    armor
+        .check_kdc_challenge(&padata, &CLIENT_KEY, AES256_CTS_HMAC_SHA1_96)
+        .unwrap();
+
+    let error = armor
+        .check_kdc_challenge(&padata, &[0x24; 32], AES256_CTS_HMAC_SHA1_96)
+        .unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::DecryptFailure);
+
+    let error = armor
+        .check_kdc_challenge(&[], &CLIENT_KEY, AES256_CTS_HMAC_SHA1_96)
+        .unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::MessageAltered);
+}
+
+#[test]
+fn reply_key_is_strengthened_with_strengthen_key() {
+    let mut response = fast_response(Vec::new(), None, NONCE);
+    assert_eq!(
+        strengthen_reply_key(&response, CLIENT_KEY.to_vec(), AES256_CTS_HMAC_SHA1_96).unwrap(),
+        CLIENT_KEY.to_vec()
+    );
+
+    let strengthen_key = crypto::generate_random_key(AES256_CTS_HMAC_SHA1_96).unwrap();
+    response.strengthen_key = Optional::from(Some(ExplicitContextTag1::from(EncryptionKey {
+        key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+        key_value: ExplicitContextTag1::from(OctetStringAsn1::from(strengthen_key.clone())),
+    })));
+
+    assert_eq!(
+        strengthen_reply_key(&response, CLIENT_KEY.to_vec(), AES256_CTS_HMAC_SHA1_96).unwrap(),
+        crypto::cf2(
+            AES256_CTS_HMAC_SHA1_96,
+            &strengthen_key,
+            b"strengthenkey",
+            AES256_CTS_HMAC_SHA1_96,
+            &CLIENT_KEY,
+            b"replykey"
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn checksum_types_are_encoded_as_minimal_integers() {
+    assert_eq!(i32_to_integer(16), IntegerAsn1::from(vec![0x10]));
+    assert_eq!(i32_to_integer(128), IntegerAsn1::from(vec![0x00, 0x80]));
+    assert_eq!(i32_to_integer(-138), IntegerAsn1::from(vec![0xff, 0x76]));
+}
@@ -0,0 +1,301 @@
+#[cfg(test)]
+mod test;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use url::Url;
+
+use super::SSPI_KDC_URL_ENV;
+use crate::sspi::{Error, ErrorKind, Result};
+
+pub const KRB5_CONFIG_ENV: &str = "KRB5_CONFIG";
+#[cfg(not(windows))]
+const DEFAULT_KRB5_CONFIG_PATH: &str = "/etc/krb5.conf";
+const KDC_TAG: &str = "kdc";
+const REALMS_SECTION: &str = "realms";
+const KDC_SRV_PREFIX: &str = "_kerberos._tcp.";
+const KPASSWD_SERVER_TAG: &str = "kpasswd_server";
+const KPASSWD_SRV_PREFIX: &str = "_kpasswd._tcp.";
+const KPASSWD_PORT: u16 = 464;
+
+/// DNS SRV record: [RFC 2782](https://www.rfc-editor.org/rfc/rfc2782)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+/// Resolves DNS SRV records. The crate does not depend on any DNS client,
+/// so the SRV lookup is performed only when the resolver is provided by the user
+pub trait SrvResolver: Debug + Send + Sync {
+    fn resolve(&self, name: &str) -> Result<Vec<SrvRecord>>;
+}
+
+/// Realm-to-KDC mappings from the `[realms]` section of the krb5.conf file:
+/// [krb5.conf](https://web.mit.edu/kerberos/krb5-latest/doc/admin/conf_files/krb5_conf.html)
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Krb5Conf {
+    // realm -> tag -> values
+    realms: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+impl Krb5Conf {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_str(&fs::read_to_string(path)?)
+    }
+
+    /// Returns the values of the relation specified for the realm, e.g. `kdc` or `admin_server`
+    pub fn realm_values(&self, realm: &str, tag: &str) -> &[String] {
+        self.realms
+            .get(realm)
+            .or_else(|| {
+                self.realms
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(realm))
+                    .map(|(_, relations)| relations)
+            })
+            .and_then(|relations| relations.get(tag))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn kdcs(&self, realm: &str) -> &[String] {
+        self.realm_values(realm, KDC_TAG)
+    }
+}
+
+impl FromStr for Krb5Conf {
+    type Err = Error;
+
+    fn from_str(data: &str) -> Result<Self> {
+        let mut krb5_conf = Self::default();
+        let mut section = String::new();
+        // names of the opened subsections
+        let mut groups = Vec::new();
+
+        for (line_number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') || is_include_directive(line) {
+                continue;
+            }
+
+            let invalid_line = || {
+                Error::new(
+                    ErrorKind::InvalidParameter,
+                    format!("Invalid krb5.conf line {}: {}", line_number + 1, line),
+                )
+            };
+
+            if line.starts_with('[') && groups.is_empty() {
+                section = line
+                    .strip_suffix(']')
+                    .map(|name| name[1..].trim().to_owned())
+                    .ok_or_else(invalid_line)?;
+                continue;
+            }
+
+            if line == "}" {
+                groups.pop().ok_or_else(invalid_line)?;
+                continue;
+            }
+
+            let (tag, value) = line.split_once('=').ok_or_else(invalid_line)?;
+            let (tag, value) = (tag.trim(), value.trim());
+
+            if value == "{" {
+                groups.push(tag.to_owned());
+                continue;
+            }
+
+            if section == REALMS_SECTION && groups.len() == 1 {
+                krb5_conf
+                    .realms
+                    .entry(groups[0].clone())
+                    .or_default()
+                    .entry(tag.to_owned())
+                    .or_default()
+                    .push(value.to_owned());
+            }
+        }
+
+        if !groups.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                "Invalid krb5.conf: unclosed subsection".into(),
+            ));
+        }
+
+        Ok(krb5_conf)
+    }
+}
+
+/// Finds the KDCs of the realm.
+///
+/// The KDC is looked up in the following order:
+/// * the KDC url specified explicitly, e.g. by the `SSPI_KDC_URL` environment variable;
+/// * the `kdc` relations of the realm in the krb5.conf file;
+/// * the `_kerberos._tcp.<realm>` SRV records.
+#[derive(Debug, Clone, Default)]
+pub struct KdcLocator {
+    pub kdc_url: Option<Url>,
+    pub krb5_conf: Option<Krb5Conf>,
+    pub srv_resolver: Option<Arc<dyn SrvResolver>>,
+}
+
+impl KdcLocator {
+    pub fn new(kdc_url: Url) -> Self {
+        Self {
+            kdc_url: Some(kdc_url),
+            ..Default::default()
+        }
+    }
+
+    /// Creates the locator from the `SSPI_KDC_URL` and `KRB5_CONFIG` environment variables.
+    /// The default krb5.conf location is used when `KRB5_CONFIG` is not set
+    pub fn from_env() -> Result<Self> {
+        let kdc_url = match env::var(SSPI_KDC_URL_ENV) {
+            Ok(kdc_url) => Some(parse_kdc_url(&kdc_url)?),
+            Err(_) => None,
+        };
+
+        let krb5_conf = match env::var(KRB5_CONFIG_ENV) {
+            Ok(path) => Some(Krb5Conf::from_file(path)?),
+            #[cfg(not(windows))]
+            Err(_) if Path::new(DEFAULT_KRB5_CONFIG_PATH).exists() => {
+                Some(Krb5Conf::from_file(DEFAULT_KRB5_CONFIG_PATH)?)
+            }
+            Err(_) => None,
+        };
+
+        Ok(Self {
+            kdc_url,
+            krb5_conf,
+            srv_resolver: None,
+        })
+    }
+
+    /// Returns the urls of the realm KDCs in the order they should be tried
+    pub fn locate(&self, realm: &str) -> Result<Vec<Url>> {
+        if let Some(ref kdc_url) = self.kdc_url {
+            return Ok(vec![kdc_url.clone()]);
+        }
+
+        if let Some(ref krb5_conf) = self.krb5_conf {
+            let kdcs = krb5_conf.kdcs(realm);
+            if !kdcs.is_empty() {
+                return kdcs.iter().map(|kdc| parse_kdc_url(kdc)).collect();
+            }
+        }
+
+        let kdcs = self.resolve_srv(KDC_SRV_PREFIX, realm)?;
+        if !kdcs.is_empty() {
+            return Ok(kdcs);
+        }
+
+        Err(Error::new(
+            ErrorKind::NoAuthenticatingAuthority,
+            format!("Unable to locate the KDC of the {:?} realm", realm),
+        ))
+    }
+
+    /// Returns the urls of the password change servers of the realm: [RFC 3244](https://www.rfc-editor.org/rfc/rfc3244).
+    /// The `kpasswd_server` relations and the `_kpasswd._tcp.<realm>` SRV records are used unless the KDC url is
+    /// specified explicitly, otherwise the server is expected on port 464 of the KDC host.
+    /// KDC Proxy urls are kept as is, the proxy forwards the request to the password change server
+    pub fn locate_kpasswd(&self, realm: &str) -> Result<Vec<Url>> {
+        if self.kdc_url.is_none() {
+            if let Some(ref krb5_conf) = self.krb5_conf {
+                let servers = krb5_conf.realm_values(realm, KPASSWD_SERVER_TAG);
+                if !servers.is_empty() {
+                    return servers
+                        .iter()
+                        .map(|server| {
+                            let mut url = parse_kdc_url(server)?;
+                            if url.port().is_none() {
+                                set_kpasswd_port(&mut url);
+                            }
+
+                            Ok(url)
+                        })
+                        .collect();
+                }
+            }
+
+            let servers = self.resolve_srv(KPASSWD_SRV_PREFIX, realm)?;
+            if !servers.is_empty() {
+                return Ok(servers);
+            }
+        }
+
+        let mut kdcs = self.locate(realm)?;
+        kdcs.iter_mut().for_each(set_kpasswd_port);
+
+        Ok(kdcs)
+    }
+
+    fn resolve_srv(&self, prefix: &str, realm: &str) -> Result<Vec<Url>> {
+        let srv_resolver = match self.srv_resolver {
+            Some(ref srv_resolver) => srv_resolver,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut records = srv_resolver.resolve(&format!("{}{}", prefix, realm.to_ascii_lowercase()))?;
+        // records with the lowest priority are tried first and the heavier records are preferred within
+        // the same priority
+        records.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+
+        records
+            .iter()
+            // the "." target means that the service is not available in the domain
+            .filter(|record| record.target != "." && !record.target.is_empty())
+            .map(|record| {
+                parse_kdc_url(&format!(
+                    "tcp://{}:{}",
+                    record.target.trim_end_matches('.'),
+                    record.port
+                ))
+            })
+            .collect()
+    }
+}
+
+// the KDC Proxy urls are not changed
+fn set_kpasswd_port(url: &mut Url) {
+    if matches!(url.scheme(), "tcp" | "udp") {
+        // the tcp and udp urls always have the host, so the port can be set
+        let _ = url.set_port(Some(KPASSWD_PORT));
+    }
+}
+
+fn is_include_directive(line: &str) -> bool {
+    line.starts_with("include ") || line.starts_with("includedir ") || line.starts_with("module ")
+}
+
+// accepts urls, `tcp/host:port` and `udp/host:port` krb5.conf forms and host names.
+// TCP is used when the protocol is not specified
+fn parse_kdc_url(kdc: &str) -> Result<Url> {
+    let kdc = if kdc.contains("://") {
+        kdc.to_owned()
+    } else if let Some(address) = kdc.strip_prefix("tcp/") {
+        format!("tcp://{}", address)
+    } else if let Some(address) = kdc.strip_prefix("udp/") {
+        format!("udp://{}", address)
+    } else {
+        format!("tcp://{}", kdc)
+    };
+
+    Url::parse(&kdc).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidParameter,
+            format!("Invalid KDC address {:?}: {:?}", kdc, err),
+        )
+    })
+}
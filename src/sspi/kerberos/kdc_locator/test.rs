@@ -0,0 +1,171 @@
+use super::*;
+
+const KRB5_CONF: &str = r#"
+# comment
+[libdefaults]
+    default_realm = EXAMPLE.COM
+
+[realms]
+    EXAMPLE.COM = {
+        kdc = kdc1.example.com:88
+        kdc = udp/kdc2.example.com
+        kdc = https://proxy.example.com/KdcProxy
+        admin_server = kdc1.example.com
+    }
+
+    OTHER.COM = {
+        kdc = kdc.other.com
+        kpasswd_server = kpasswd.other.com
+        auth_to_local = {
+            rule = RULE:[1:$1@$0]
+        }
+    }
+
+[domain_realm]
+    .example.com = EXAMPLE.COM
+"#;
+
+#[derive(Debug)]
+struct FakeSrvResolver;
+
+impl SrvResolver for FakeSrvResolver {
+    fn resolve(&self, name: &str) -> Result<Vec<SrvRecord>> {
+        assert_eq!(name, "_kerberos._tcp.srv.example.com");
+
+        Ok(vec![
+            SrvRecord {
+                priority: 10,
+                weight: 0,
+                port: 88,
+                target: "backup.srv.example.com.".into(),
+            },
+            SrvRecord {
+                priority: 0,
+                weight: 10,
+                port: 88,
+                target: "dc1.srv.example.com.".into(),
+            },
+            SrvRecord {
+                priority: 0,
+                weight: 50,
+                port: 1088,
+                target: "dc2.srv.example.com.".into(),
+            },
+        ])
+    }
+}
+
+fn urls(urls: &[&str]) -> Vec<Url> {
+    urls.iter().map(|url| Url::parse(url).unwrap()).collect()
+}
+
+#[test]
+fn krb5_conf_reads_realm_relations() {
+    let krb5_conf = Krb5Conf::from_str(KRB5_CONF).unwrap();
+
+    assert_eq!(
+        krb5_conf.kdcs("EXAMPLE.COM"),
+        [
+            "kdc1.example.com:88",
+            "udp/kdc2.example.com",
+            "https://proxy.example.com/KdcProxy"
+        ]
+    );
+    assert_eq!(
+        krb5_conf.realm_values("example.com", "admin_server"),
+        ["kdc1.example.com"]
+    );
+    assert_eq!(krb5_conf.kdcs("OTHER.COM"), ["kdc.other.com"]);
+    assert!(krb5_conf.kdcs("UNKNOWN.COM").is_empty());
+}
+
+#[test]
+fn krb5_conf_fails_on_unbalanced_braces() {
+    let error = Krb5Conf::from_str("[realms]\nEXAMPLE.COM = {\nkdc = kdc.example.com\n").unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+
+    let error = Krb5Conf::from_str("[realms]\n}\n").unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+}
+
+#[test]
+fn locate_uses_krb5_conf_and_srv_records() {
+    let locator = KdcLocator {
+        kdc_url: None,
+        krb5_conf: Some(Krb5Conf::from_str(KRB5_CONF).unwrap()),
+        srv_resolver: Some(Arc::new(FakeSrvResolver)),
+    };
+
+    assert_eq!(
+        locator.locate("EXAMPLE.COM").unwrap(),
+        urls(&[
+            "tcp://kdc1.example.com:88",
+            "udp://kdc2.example.com",
+            "https://proxy.example.com/KdcProxy"
+        ])
+    );
+    assert_eq!(
+        locator.locate("SRV.EXAMPLE.COM").unwrap(),
+        urls(&[
+            "tcp://dc2.srv.example.com:1088",
+            "tcp://dc1.srv.example.com:88",
+            "tcp://backup.srv.example.com:88"
+        ])
+    );
+}
+
+#[test]
+fn locate_prefers_explicit_kdc_url() {
+    let locator = KdcLocator {
+        krb5_conf: Some(Krb5Conf::from_str(KRB5_CONF).unwrap()),
+        ..KdcLocator::new(Url::parse("tcp://kdc.example.com:88").unwrap())
+    };
+
+    assert_eq!(
+        locator.locate("EXAMPLE.COM").unwrap(),
+        urls(&["tcp://kdc.example.com:88"])
+    );
+}
+
+#[test]
+fn locate_kpasswd_uses_kpasswd_servers_or_kdc_hosts() {
+    let locator = KdcLocator {
+        krb5_conf: Some(Krb5Conf::from_str(KRB5_CONF).unwrap()),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        locator.locate_kpasswd("OTHER.COM").unwrap(),
+        urls(&["tcp://kpasswd.other.com:464"])
+    );
+    assert_eq!(
+        locator.locate_kpasswd("EXAMPLE.COM").unwrap(),
+        urls(&[
+            "tcp://kdc1.example.com:464",
+            "udp://kdc2.example.com:464",
+            "https://proxy.example.com/KdcProxy"
+        ])
+    );
+
+    let locator = KdcLocator {
+        krb5_conf: Some(Krb5Conf::from_str(KRB5_CONF).unwrap()),
+        ..KdcLocator::new(Url::parse("tcp://kdc.example.com:88").unwrap())
+    };
+
+    assert_eq!(
+        locator.locate_kpasswd("OTHER.COM").unwrap(),
+        urls(&["tcp://kdc.example.com:464"])
+    );
+}
+
+#[test]
+fn locate_fails_when_kdc_is_unknown() {
+    let locator = KdcLocator {
+        krb5_conf: Some(Krb5Conf::from_str(KRB5_CONF).unwrap()),
+        ..Default::default()
+    };
+
+    let error = locator.locate("UNKNOWN.COM").unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::NoAuthenticatingAuthority);
+}
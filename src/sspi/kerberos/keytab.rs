@@ -0,0 +1,260 @@
+#[cfg(test)]
+mod test;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder, NativeEndian, ReadBytesExt, WriteBytesExt};
+
+use super::credentials::{Principal, NT_PRINCIPAL};
+use super::server::ServiceKey;
+use crate::sspi::{Error, ErrorKind, Result};
+
+const KEYTAB_FIRST_BYTE: u8 = 0x05;
+
+/// Version of the MIT keytab file format.
+/// Version 1 keytabs use the native byte order and do not contain the principal name type
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum KeytabVersion {
+    V1 = 0x01,
+    #[default]
+    V2 = 0x02,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeytabEntry {
+    pub principal: Principal,
+    /// the time the key was written to the keytab, in seconds since the Unix epoch
+    pub timestamp: u32,
+    pub kvno: u32,
+    pub encryption_type: i32,
+    pub key: Vec<u8>,
+}
+
+impl From<&KeytabEntry> for ServiceKey {
+    fn from(entry: &KeytabEntry) -> Self {
+        Self {
+            encryption_type: entry.encryption_type,
+            kvno: Some(entry.kvno),
+            key: entry.key.clone(),
+        }
+    }
+}
+
+/// Long-term keys stored in the MIT keytab file format:
+/// [keytab.txt](https://web.mit.edu/kerberos/krb5-devel/doc/formats/keytab_file_format.html)
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Keytab {
+    pub version: KeytabVersion,
+    pub entries: Vec<KeytabEntry>,
+}
+
+impl Keytab {
+    pub fn new(entries: Vec<KeytabEntry>) -> Self {
+        Self {
+            version: KeytabVersion::V2,
+            entries,
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_buffer(BufReader::new(File::open(path)?))
+    }
+
+    pub fn from_buffer(mut stream: impl io::Read) -> Result<Self> {
+        let first_byte = stream.read_u8()?;
+        if first_byte != KEYTAB_FIRST_BYTE {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                format!("Invalid keytab file format: {:#04x}", first_byte),
+            ));
+        }
+
+        match stream.read_u8()? {
+            0x01 => read_entries::<NativeEndian>(stream, KeytabVersion::V1),
+            0x02 => read_entries::<BigEndian>(stream, KeytabVersion::V2),
+            version => Err(Error::new(
+                ErrorKind::InvalidParameter,
+                format!("Unsupported keytab version: {}", version),
+            )),
+        }
+    }
+
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.encode(&mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn encode(&self, mut stream: impl io::Write) -> Result<()> {
+        stream.write_u8(KEYTAB_FIRST_BYTE)?;
+        stream.write_u8(self.version as u8)?;
+
+        for entry in &self.entries {
+            match self.version {
+                KeytabVersion::V1 => write_entry::<NativeEndian>(&mut stream, entry, self.version)?,
+                KeytabVersion::V2 => write_entry::<BigEndian>(&mut stream, entry, self.version)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns all entries of the principal
+    pub fn principal_entries<'a>(&'a self, name: &'a str, realm: &'a str) -> impl Iterator<Item = &'a KeytabEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.principal.matches(name, realm))
+    }
+
+    /// Looks for the key of the principal. If `kvno` is `None` then the key with the highest version is returned
+    pub fn find_key(&self, name: &str, realm: &str, kvno: Option<u32>, encryption_type: i32) -> Option<&KeytabEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.principal.matches(name, realm))
+            .filter(|entry| entry.encryption_type == encryption_type)
+            .filter(|entry| kvno.map(|kvno| kvno == entry.kvno).unwrap_or(true))
+            .max_by_key(|entry| entry.kvno)
+    }
+
+    /// Returns the keys of the principal in the form suitable for the acceptor
+    pub fn service_keys(&self, name: &str, realm: &str) -> Vec<ServiceKey> {
+        self.principal_entries(name, realm).map(ServiceKey::from).collect()
+    }
+}
+
+fn read_entries<B: ByteOrder>(mut stream: impl io::Read, version: KeytabVersion) -> Result<Keytab> {
+    let mut entries = Vec::new();
+
+    loop {
+        let size = match stream.read_i32::<B>() {
+            Ok(size) => size,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        // the size is not trusted to allocate the entry, so a corrupted keytab can not exhaust the memory
+        let mut entry = Vec::new();
+        (&mut stream)
+            .take(u64::from(size.unsigned_abs()))
+            .read_to_end(&mut entry)?;
+        if entry.len() != size.unsigned_abs() as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                format!("Invalid keytab entry size: {}", size),
+            ));
+        }
+
+        // a negative size marks a deleted entry (hole)
+        if size > 0 {
+            entries.push(read_entry::<B>(Cursor::new(entry), version)?);
+        }
+    }
+
+    Ok(Keytab { version, entries })
+}
+
+fn read_entry<B: ByteOrder>(mut stream: Cursor<Vec<u8>>, version: KeytabVersion) -> Result<KeytabEntry> {
+    let mut components_count = stream.read_u16::<B>()?;
+    if version == KeytabVersion::V1 {
+        // the realm is counted as a component in the v1 format
+        components_count = components_count.checked_sub(1).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidParameter,
+                "Invalid keytab entry: principal without realm".into(),
+            )
+        })?;
+    }
+
+    let realm = read_string::<B>(&mut stream)?;
+    let components = (0..components_count)
+        .map(|_| read_string::<B>(&mut stream))
+        .collect::<Result<Vec<_>>>()?;
+    let name_type = match version {
+        KeytabVersion::V1 => NT_PRINCIPAL,
+        KeytabVersion::V2 => stream.read_u32::<B>()?,
+    };
+
+    let timestamp = stream.read_u32::<B>()?;
+    let mut kvno = u32::from(stream.read_u8()?);
+    let encryption_type = i32::from(stream.read_u16::<B>()?);
+    let key = read_data::<B>(&mut stream)?;
+
+    // the 32-bit key version overrides the 8-bit one if present and non-zero
+    if stream.get_ref().len() as u64 - stream.position() >= 4 {
+        let kvno32 = stream.read_u32::<B>()?;
+        if kvno32 != 0 {
+            kvno = kvno32;
+        }
+    }
+
+    Ok(KeytabEntry {
+        principal: Principal {
+            realm,
+            components,
+            name_type,
+        },
+        timestamp,
+        kvno,
+        encryption_type,
+        key,
+    })
+}
+
+fn read_data<B: ByteOrder>(mut stream: impl io::Read) -> Result<Vec<u8>> {
+    let len = stream.read_u16::<B>()?;
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+fn read_string<B: ByteOrder>(stream: impl io::Read) -> Result<String> {
+    String::from_utf8(read_data::<B>(stream)?)
+        .map_err(|err| Error::new(ErrorKind::InvalidParameter, format!("Invalid keytab string: {:?}", err)))
+}
+
+fn write_entry<B: ByteOrder>(mut stream: impl io::Write, entry: &KeytabEntry, version: KeytabVersion) -> Result<()> {
+    let mut data = Vec::new();
+
+    // the realm is counted as a component in the v1 format
+    let components_count = entry.principal.components.len() + usize::from(version == KeytabVersion::V1);
+    data.write_u16::<B>(u16::try_from(components_count).map_err(|_| invalid_entry("too many principal components"))?)?;
+
+    write_data::<B>(&mut data, entry.principal.realm.as_bytes())?;
+    for component in &entry.principal.components {
+        write_data::<B>(&mut data, component.as_bytes())?;
+    }
+    if version == KeytabVersion::V2 {
+        data.write_u32::<B>(entry.principal.name_type)?;
+    }
+
+    data.write_u32::<B>(entry.timestamp)?;
+    // the 8-bit kvno is truncated on purpose: the full 32-bit kvno follows the key
+    data.write_u8(entry.kvno as u8)?;
+    data.write_u16::<B>(u16::try_from(entry.encryption_type).map_err(|_| invalid_entry("invalid encryption type"))?)?;
+    write_data::<B>(&mut data, &entry.key)?;
+    data.write_u32::<B>(entry.kvno)?;
+
+    stream.write_i32::<B>(i32::try_from(data.len()).map_err(|_| invalid_entry("entry is too long"))?)?;
+    stream.write_all(&data)?;
+
+    Ok(())
+}
+
+fn write_data<B: ByteOrder>(mut stream: impl io::Write, data: &[u8]) -> Result<()> {
+    stream.write_u16::<B>(u16::try_from(data.len()).map_err(|_| invalid_entry("string or key is too long"))?)?;
+    stream.write_all(data)?;
+
+    Ok(())
+}
+
+fn invalid_entry(description: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidParameter,
+        format!("Invalid keytab entry: {}", description),
+    )
+}
@@ -0,0 +1,151 @@
+use super::*;
+use crate::sspi::kerberos::client::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96};
+
+// HTTP/www.example.com@EXAMPLE.COM, kvno 3, aes128-cts-hmac-sha1-96, followed by a deleted entry
+const KEYTAB_V2: [u8; 85] = [
+    0x05, 0x02, // version
+    0x00, 0x00, 0x00, 0x47, // entry size
+    0x00, 0x02, // components count
+    0x00, 0x0b, b'E', b'X', b'A', b'M', b'P', b'L', b'E', b'.', b'C', b'O', b'M', // realm
+    0x00, 0x04, b'H', b'T', b'T', b'P', // component
+    0x00, 0x0f, b'w', b'w', b'w', b'.', b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c', b'o',
+    b'm', // component
+    0x00, 0x00, 0x00, 0x03, // name type
+    0x62, 0x8c, 0x6f, 0x00, // timestamp
+    0x03, // 8-bit kvno
+    0x00, 0x11, // encryption type
+    0x00, 0x10, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, // key
+    0x00, 0x00, 0x00, 0x03, // 32-bit kvno
+    0xff, 0xff, 0xff, 0xfc, // hole size
+    0x00, 0x00, 0x00, 0x00, // hole
+];
+
+fn entry(name: &str, kvno: u32, encryption_type: i32) -> KeytabEntry {
+    KeytabEntry {
+        principal: Principal::new(name, "EXAMPLE.COM"),
+        timestamp: 1_653_370_624,
+        kvno,
+        encryption_type,
+        key: vec![kvno as u8; 32],
+    }
+}
+
+#[test]
+fn from_buffer_reads_v2_keytab() {
+    let keytab = Keytab::from_buffer(KEYTAB_V2.as_ref()).unwrap();
+
+    assert_eq!(keytab.version, KeytabVersion::V2);
+    assert_eq!(
+        keytab.entries,
+        vec![KeytabEntry {
+            principal: Principal {
+                realm: "EXAMPLE.COM".into(),
+                components: vec!["HTTP".into(), "www.example.com".into()],
+                name_type: 3,
+            },
+            timestamp: 0x628c_6f00,
+            kvno: 3,
+            encryption_type: AES128_CTS_HMAC_SHA1_96,
+            key: (1..=16).collect(),
+        }]
+    );
+}
+
+#[test]
+fn encode_writes_v2_keytab() {
+    let keytab = Keytab::from_buffer(KEYTAB_V2.as_ref()).unwrap();
+
+    let mut buffer = Vec::new();
+    keytab.encode(&mut buffer).unwrap();
+
+    // the deleted entry is not written back
+    assert_eq!(buffer, KEYTAB_V2[..KEYTAB_V2.len() - 8]);
+}
+
+#[test]
+fn v1_keytab_round_trip() {
+    let keytab = Keytab {
+        version: KeytabVersion::V1,
+        entries: vec![
+            entry("user", 2, AES256_CTS_HMAC_SHA1_96),
+            entry("host/client.example.com", 300, AES128_CTS_HMAC_SHA1_96),
+        ],
+    };
+
+    let mut buffer = Vec::new();
+    keytab.encode(&mut buffer).unwrap();
+
+    assert_eq!(&buffer[..2], &[0x05, 0x01]);
+    assert_eq!(Keytab::from_buffer(buffer.as_slice()).unwrap(), keytab);
+}
+
+#[test]
+fn from_buffer_fails_on_unsupported_version() {
+    let error = Keytab::from_buffer([0x05, 0x03].as_ref()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+}
+
+#[test]
+fn from_buffer_fails_on_entry_size_larger_than_keytab() {
+    let mut buffer = vec![0x05, 0x02];
+    buffer.extend_from_slice(&i32::MAX.to_be_bytes());
+    buffer.extend_from_slice(&[0; 16]);
+
+    let error = Keytab::from_buffer(buffer.as_slice()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+}
+
+#[test]
+fn encode_fails_on_too_long_key() {
+    let mut entry = entry("user", 2, AES256_CTS_HMAC_SHA1_96);
+    entry.key = vec![0; usize::from(u16::MAX) + 1];
+    let keytab = Keytab {
+        version: KeytabVersion::V2,
+        entries: vec![entry],
+    };
+
+    let error = keytab.encode(&mut Vec::new()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+}
+
+#[test]
+fn find_key_selects_key_by_principal_kvno_and_encryption_type() {
+    let keytab = Keytab::new(vec![
+        entry("HTTP/www.example.com", 2, AES256_CTS_HMAC_SHA1_96),
+        entry("HTTP/www.example.com", 3, AES256_CTS_HMAC_SHA1_96),
+        entry("HTTP/www.example.com", 4, AES128_CTS_HMAC_SHA1_96),
+        entry("host/www.example.com", 5, AES256_CTS_HMAC_SHA1_96),
+    ]);
+
+    let find_kvno = |name, kvno, encryption_type| {
+        keytab
+            .find_key(name, "EXAMPLE.COM", kvno, encryption_type)
+            .map(|entry| entry.kvno)
+    };
+
+    assert_eq!(
+        find_kvno("HTTP/www.example.com", None, AES256_CTS_HMAC_SHA1_96),
+        Some(3)
+    );
+    assert_eq!(
+        find_kvno("HTTP/www.example.com", Some(2), AES256_CTS_HMAC_SHA1_96),
+        Some(2)
+    );
+    assert_eq!(
+        find_kvno("HTTP/www.example.com", Some(4), AES256_CTS_HMAC_SHA1_96),
+        None
+    );
+    assert_eq!(
+        find_kvno("HTTP/www.example.com", None, AES128_CTS_HMAC_SHA1_96),
+        Some(4)
+    );
+    assert_eq!(
+        find_kvno("host/www.example.com", None, AES256_CTS_HMAC_SHA1_96),
+        Some(5)
+    );
+    assert_eq!(keytab.service_keys("HTTP/www.example.com", "EXAMPLE.COM").len(), 3);
+}
@@ -0,0 +1,346 @@
+//! Kerberos set/change password protocol: [RFC 3244](https://www.rfc-editor.org/rfc/rfc3244)
+//!
+//! The request is the AP-REQ with the initial ticket of the `kadmin/changepw` service followed by the KRB-PRIV
+//! with the new password, encrypted with the authenticator subkey. The server replies with the AP-REP followed
+//! by the KRB-PRIV with the result code, or with the KRB-ERROR
+
+mod data_types;
+#[cfg(test)]
+mod test;
+
+use std::fmt;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use kerberos_constants::key_usages::KEY_USAGE_KRB_PRIV_ENC_PART;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3, ExplicitContextTag4,
+    IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::data_types::{EncryptedData, HostAddress};
+use picky_krb::messages::{ApRep, ApReq, KrbError};
+
+use self::data_types::ChangePasswdData;
+use super::credentials::Principal;
+use super::crypto::new_cipher;
+use super::data_types::{EncKrbPrivPart, EncKrbPrivPartInner, KrbPriv, KrbPrivInner, KRB_PRIV_TYPE};
+use super::encryption_params::EncryptionParams;
+use super::server::extractors::extract_enc_ap_rep_part;
+use super::{KerberosError, KERBEROS_VERSION};
+use crate::sspi::{Error, ErrorKind, Result};
+
+// the set/change password version of the request. The server replies with the version 1
+const SET_PASSWORD_VERSION: u16 = 0xff80;
+const REPLY_VERSION: u16 = 0x0001;
+// the length prefix of the TCP transport
+const TCP_LEN_PREFIX_LEN: usize = 4;
+// message length, protocol version and AP-REQ (AP-REP) length
+const HEADER_LEN: usize = 6;
+const KRB_ERROR_TAG: u8 = 0x7e;
+
+// the directional address of the initiator is sent instead of the network address, which can be translated:
+// [RFC 4120 8.1](https://www.rfc-editor.org/rfc/rfc4120#section-8.1)
+const DIRECTIONAL_ADDR_TYPE: u8 = 3;
+const INITIATOR_DIRECTION: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
+
+pub const KRB5_KPASSWD_SUCCESS: u16 = 0;
+pub const KRB5_KPASSWD_MALFORMED: u16 = 1;
+pub const KRB5_KPASSWD_HARDERROR: u16 = 2;
+pub const KRB5_KPASSWD_AUTHERROR: u16 = 3;
+pub const KRB5_KPASSWD_SOFTERROR: u16 = 4;
+pub const KRB5_KPASSWD_ACCESSDENIED: u16 = 5;
+pub const KRB5_KPASSWD_BAD_VERSION: u16 = 6;
+pub const KRB5_KPASSWD_INITIAL_FLAG_NEEDED: u16 = 7;
+
+// the policy block: two zero bytes, the minimum length, the history length, the properties,
+// the maximum and the minimum age
+const AD_POLICY_INFO_LEN: usize = 30;
+const DOMAIN_PASSWORD_COMPLEX: u32 = 0x01;
+// the ages are in 100-nanosecond intervals
+const INTERVALS_PER_DAY: u64 = 864_000_000_000;
+
+/// The result code and the result string of the kpasswd reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KpasswdResult {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl KpasswdResult {
+    fn from_user_data(user_data: &[u8]) -> Result<Self> {
+        if user_data.len() < 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidToken,
+                "The kpasswd result is too short".into(),
+            ));
+        }
+
+        Ok(Self {
+            code: u16::from_be_bytes([user_data[0], user_data[1]]),
+            data: user_data[2..].to_vec(),
+        })
+    }
+
+    /// The password policy sent by Active Directory with the soft error
+    pub fn policy(&self) -> Option<PasswordPolicy> {
+        if self.code == KRB5_KPASSWD_SOFTERROR {
+            PasswordPolicy::from_result_string(&self.data)
+        } else {
+            None
+        }
+    }
+
+    /// The result string of the server or the description of the policy
+    pub fn message(&self) -> String {
+        match self.policy() {
+            Some(policy) => policy.to_string(),
+            None => String::from_utf8_lossy(&self.data)
+                .trim_end_matches('\0')
+                .trim()
+                .to_owned(),
+        }
+    }
+
+    /// Converts the unsuccessful result to the error
+    pub fn check(&self) -> Result<()> {
+        let (error_type, reason) = match self.code {
+            KRB5_KPASSWD_SUCCESS => return Ok(()),
+            KRB5_KPASSWD_MALFORMED => (ErrorKind::InvalidToken, "the request is malformed"),
+            KRB5_KPASSWD_HARDERROR => (ErrorKind::InternalError, "the server failed to process the request"),
+            KRB5_KPASSWD_AUTHERROR => (ErrorKind::LogonDenied, "the authentication failed"),
+            KRB5_KPASSWD_SOFTERROR => (ErrorKind::InvalidParameter, "the password is rejected by the policy"),
+            KRB5_KPASSWD_ACCESSDENIED => (ErrorKind::LogonDenied, "the access is denied"),
+            KRB5_KPASSWD_BAD_VERSION => (
+                ErrorKind::OperationNotSupported,
+                "the protocol version is not supported",
+            ),
+            KRB5_KPASSWD_INITIAL_FLAG_NEEDED => (ErrorKind::InternalError, "the ticket is not initial"),
+            _ => (ErrorKind::InternalError, "unknown error"),
+        };
+
+        let message = self.message();
+        let description = if message.is_empty() {
+            format!("The password is not changed: {} ({})", reason, self.code)
+        } else {
+            format!("The password is not changed: {} ({}): {}", reason, self.code, message)
+        };
+
+        Err(Error::new(error_type, description))
+    }
+}
+
+/// Password policy of the Active Directory domain. Active Directory sends it in the result string
+/// of the soft error instead of the text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    pub min_length: u32,
+    pub history_length: u32,
+    pub properties: u32,
+    /// 100-nanosecond intervals
+    pub max_age: u64,
+    /// 100-nanosecond intervals
+    pub min_age: u64,
+}
+
+impl PasswordPolicy {
+    fn from_result_string(mut data: &[u8]) -> Option<Self> {
+        if data.len() != AD_POLICY_INFO_LEN || data[0..2] != [0, 0] {
+            return None;
+        }
+        data = &data[2..];
+
+        Some(Self {
+            min_length: data.read_u32::<BigEndian>().ok()?,
+            history_length: data.read_u32::<BigEndian>().ok()?,
+            properties: data.read_u32::<BigEndian>().ok()?,
+            max_age: data.read_u64::<BigEndian>().ok()?,
+            min_age: data.read_u64::<BigEndian>().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for PasswordPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut requirements = Vec::new();
+        if self.min_length > 0 {
+            requirements.push(format!("be at least {} characters long", self.min_length));
+        }
+        if self.properties & DOMAIN_PASSWORD_COMPLEX != 0 {
+            requirements.push("meet the complexity requirements".to_owned());
+        }
+        if self.history_length > 0 {
+            requirements.push(format!("differ from the last {} passwords", self.history_length));
+        }
+        let min_age_days = self.min_age / INTERVALS_PER_DAY;
+        if min_age_days > 0 {
+            requirements.push(format!("not be changed more often than every {} days", min_age_days));
+        }
+
+        if requirements.is_empty() {
+            write!(f, "The password does not meet the password policy")
+        } else {
+            write!(f, "The password must {}", requirements.join(", "))
+        }
+    }
+}
+
+/// The set/change password request with the 4-byte length prefix of the TCP transport.
+/// The password of the target principal is set if it is specified, otherwise the password of the ticket client
+/// is changed
+pub fn generate_kpasswd_request(
+    ap_req: &ApReq,
+    new_password: &str,
+    target: Option<&Principal>,
+    subkey: &[u8],
+    seq_number: u32,
+    enc_params: &EncryptionParams,
+) -> Result<Vec<u8>> {
+    let change_passwd_data = ChangePasswdData {
+        new_passwd: ExplicitContextTag0::from(OctetStringAsn1::from(new_password.as_bytes().to_vec())),
+        targ_name: Optional::from(match target {
+            Some(target) => Some(ExplicitContextTag1::from(target.principal_name()?)),
+            None => None,
+        }),
+        targ_realm: Optional::from(match target {
+            Some(target) => Some(ExplicitContextTag2::from(target.kerberos_realm()?)),
+            None => None,
+        }),
+    };
+
+    let enc_krb_priv_part = EncKrbPrivPart::from(EncKrbPrivPartInner {
+        user_data: ExplicitContextTag0::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&change_passwd_data)?)),
+        timestamp: Optional::from(None),
+        usec: Optional::from(None),
+        seq_number: Optional::from(Some(ExplicitContextTag3::from(IntegerAsn1::from_bytes_be_unsigned(
+            seq_number.to_be_bytes().to_vec(),
+        )))),
+        s_address: ExplicitContextTag4::from(HostAddress {
+            addr_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![DIRECTIONAL_ADDR_TYPE])),
+            address: ExplicitContextTag1::from(OctetStringAsn1::from(INITIATOR_DIRECTION.to_vec())),
+        }),
+        r_address: Optional::from(None),
+    });
+
+    let encryption_type = enc_params.encryption_type();
+    let cipher = new_cipher(encryption_type)?;
+    let enc_part = cipher.encrypt(
+        subkey,
+        KEY_USAGE_KRB_PRIV_ENC_PART,
+        &picky_asn1_der::to_vec(&enc_krb_priv_part)?,
+    );
+
+    let krb_priv = picky_asn1_der::to_vec(&KrbPriv::from(KrbPrivInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![KRB_PRIV_TYPE])),
+        enc_part: ExplicitContextTag3::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(enc_part)),
+        }),
+    }))?;
+    let ap_req = picky_asn1_der::to_vec(ap_req)?;
+
+    let message_len = HEADER_LEN + ap_req.len() + krb_priv.len();
+    if message_len > usize::from(u16::MAX) {
+        return Err(Error::new(
+            ErrorKind::InvalidParameter,
+            "The kpasswd request is too long".into(),
+        ));
+    }
+
+    let mut message = Vec::with_capacity(4 + message_len);
+    message.write_u32::<BigEndian>(message_len as u32)?;
+    message.write_u16::<BigEndian>(message_len as u16)?;
+    message.write_u16::<BigEndian>(SET_PASSWORD_VERSION)?;
+    message.write_u16::<BigEndian>(ap_req.len() as u16)?;
+    message.extend_from_slice(&ap_req);
+    message.extend_from_slice(&krb_priv);
+
+    Ok(message)
+}
+
+/// Extracts the result of the kpasswd reply with the length prefix of the TCP transport.
+/// The AP-REP is decrypted with the session key of the ticket and the KRB-PRIV is decrypted with the subkey
+/// of the AP-REP or, if the AP-REP does not bring it, with the authenticator subkey
+pub fn extract_kpasswd_result(
+    response: &[u8],
+    session_key: &[u8],
+    subkey: &[u8],
+    enc_params: &EncryptionParams,
+) -> Result<KpasswdResult> {
+    if response.len() < TCP_LEN_PREFIX_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            "The kpasswd reply is too short".into(),
+        ));
+    }
+    let reply = &response[TCP_LEN_PREFIX_LEN..];
+
+    // the server which is unable to parse the request can reply with the bare KRB-ERROR
+    if reply.first() == Some(&KRB_ERROR_TAG) {
+        return extract_krb_error_result(reply);
+    }
+
+    if reply.len() < HEADER_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            "The kpasswd reply is too short".into(),
+        ));
+    }
+
+    let mut data = reply;
+    let message_len = usize::from(data.read_u16::<BigEndian>()?);
+    let version = data.read_u16::<BigEndian>()?;
+    let ap_rep_len = usize::from(data.read_u16::<BigEndian>()?);
+
+    if message_len != reply.len() || ap_rep_len > data.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            "Invalid length of the kpasswd reply".into(),
+        ));
+    }
+    if version != REPLY_VERSION && version != SET_PASSWORD_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            format!("Unsupported kpasswd reply version: {:#06x}", version),
+        ));
+    }
+
+    // the error reply has no AP-REP
+    if ap_rep_len == 0 {
+        return extract_krb_error_result(data);
+    }
+
+    let (ap_rep, krb_priv) = data.split_at(ap_rep_len);
+    let ap_rep: ApRep = picky_asn1_der::from_bytes(ap_rep)?;
+    let enc_ap_rep_part = extract_enc_ap_rep_part(&ap_rep, session_key, enc_params)?;
+
+    let key = match enc_ap_rep_part.0.subkey.0.as_ref() {
+        Some(subkey) => subkey.0.key_value.0 .0.as_slice(),
+        None => subkey,
+    };
+
+    let krb_priv: KrbPriv = picky_asn1_der::from_bytes(krb_priv)?;
+    let cipher = new_cipher(enc_params.encryption_type())?;
+    let enc_krb_priv_part = cipher
+        .decrypt(key, KEY_USAGE_KRB_PRIV_ENC_PART, &krb_priv.0.enc_part.0.cipher.0 .0)
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::DecryptFailure,
+                format!("Cannot decrypt the KRB-PRIV of the kpasswd reply: {:?}", err),
+            )
+        })?;
+    let enc_krb_priv_part: EncKrbPrivPart = picky_asn1_der::from_bytes(&enc_krb_priv_part)?;
+
+    KpasswdResult::from_user_data(&enc_krb_priv_part.0.user_data.0 .0)
+}
+
+// the KRB-ERROR of the kpasswd server carries the result in the e-data
+fn extract_krb_error_result(data: &[u8]) -> Result<KpasswdResult> {
+    let krb_error: KrbError = picky_asn1_der::from_bytes(data)?;
+    let error = KerberosError::from(&krb_error);
+
+    match error.e_data.as_deref().map(KpasswdResult::from_user_data) {
+        Some(Ok(result)) if result.code != KRB5_KPASSWD_SUCCESS => Ok(result),
+        _ => Err(Error::from(error)),
+    }
+}
@@ -0,0 +1,21 @@
+use picky_asn1::wrapper::{ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, OctetStringAsn1, Optional};
+use picky_krb::data_types::{PrincipalName, Realm};
+use serde::{Deserialize, Serialize};
+
+/// [RFC 3244 2](https://www.rfc-editor.org/rfc/rfc3244#section-2)
+///
+/// ```not_rust
+/// ChangePasswdData ::=  SEQUENCE {
+///         newpasswd[0]   OCTET STRING,
+///         targname[1]    PrincipalName OPTIONAL,
+///         targrealm[2]   Realm OPTIONAL
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ChangePasswdData {
+    pub new_passwd: ExplicitContextTag0<OctetStringAsn1>,
+    #[serde(default)]
+    pub targ_name: Optional<Option<ExplicitContextTag1<PrincipalName>>>,
+    #[serde(default)]
+    pub targ_realm: Optional<Option<ExplicitContextTag2<Realm>>>,
+}
@@ -0,0 +1,325 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use chrono::Utc;
+use kerberos_constants::error_codes::{KRB_AP_ERR_SKEW, KRB_ERR_GENERIC};
+use kerberos_constants::key_usages::KEY_USAGE_KRB_PRIV_ENC_PART;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag10, ExplicitContextTag12, ExplicitContextTag2,
+    ExplicitContextTag3, ExplicitContextTag4, ExplicitContextTag5, ExplicitContextTag6, ExplicitContextTag9,
+    IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::constants::types::KRB_ERROR_MSG_TYPE;
+use picky_krb::data_types::{EncryptedData, EncryptionKey, HostAddress, KerberosTime, Ticket, TicketInner};
+use picky_krb::messages::{ApReq, KrbError, KrbErrorInner};
+
+use super::data_types::ChangePasswdData;
+use super::{
+    extract_kpasswd_result, generate_kpasswd_request, KpasswdResult, PasswordPolicy, KRB5_KPASSWD_ACCESSDENIED,
+    KRB5_KPASSWD_SOFTERROR, KRB5_KPASSWD_SUCCESS,
+};
+use crate::sspi::kerberos::client::generators::{generate_ap_req, generate_authenticator_for_tgs_ap_req};
+use crate::sspi::kerberos::crypto;
+use crate::sspi::kerberos::data_types::{EncKrbPrivPart, EncKrbPrivPartInner, KrbPriv, KrbPrivInner};
+use crate::sspi::kerberos::encryption_params::EncryptionParams;
+use crate::sspi::kerberos::server::generators::generate_ap_rep;
+use crate::sspi::kerberos::utils::integer_to_u32;
+use crate::sspi::kerberos::{Principal, AES256_CTS_HMAC_SHA1_96, KERBEROS_VERSION};
+use crate::sspi::ErrorKind;
+
+const SESSION_KEY: [u8; 32] = [0x5a; 32];
+const SUBKEY: [u8; 32] = [0x17; 32];
+const ACCEPTOR_SUBKEY: [u8; 32] = [0xc4; 32];
+
+fn enc_params() -> EncryptionParams {
+    EncryptionParams {
+        encryption_type: Some(AES256_CTS_HMAC_SHA1_96),
+        ..EncryptionParams::default_for_client()
+    }
+}
+
+fn kpasswd_ap_req() -> ApReq {
+    let service = Principal::new("kadmin/changepw", "EXAMPLE.COM");
+    let ticket = Ticket::from(TicketInner {
+        tkt_vno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        realm: ExplicitContextTag1::from(service.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag2::from(service.principal_name().unwrap()),
+        enc_part: ExplicitContextTag3::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(vec![0; 16])),
+        }),
+    });
+
+    let authenticator = generate_authenticator_for_tgs_ap_req(
+        &Principal::new("user", "EXAMPLE.COM").principal_name().unwrap(),
+        &service.kerberos_realm().unwrap(),
+        Utc::now(),
+    )
+    .unwrap();
+
+    generate_ap_req(ticket, &SESSION_KEY, &authenticator, [0; 4], &enc_params()).unwrap()
+}
+
+fn krb_priv(key: &[u8], user_data: &[u8]) -> Vec<u8> {
+    let enc_krb_priv_part = EncKrbPrivPart::from(EncKrbPrivPartInner {
+        user_data: ExplicitContextTag0::from(OctetStringAsn1::from(user_data.to_vec())),
+        timestamp: Optional::from(None),
+        usec: Optional::from(None),
+        seq_number: Optional::from(None),
+        s_address: ExplicitContextTag4::from(HostAddress {
+            addr_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![3])),
+            address: ExplicitContextTag1::from(OctetStringAsn1::from(vec![0, 0, 0, 1])),
+        }),
+        r_address: Optional::from(None),
+    });
+
+    let cipher = crypto::new_cipher(AES256_CTS_HMAC_SHA1_96).unwrap();
+
+    picky_asn1_der::to_vec(&KrbPriv::from(KrbPrivInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![21])),
+        enc_part: ExplicitContextTag3::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(cipher.encrypt(
+                key,
+                KEY_USAGE_KRB_PRIV_ENC_PART,
+                &picky_asn1_der::to_vec(&enc_krb_priv_part).unwrap(),
+            ))),
+        }),
+    }))
+    .unwrap()
+}
+
+fn krb_error(error_code: i32, e_data: Option<Vec<u8>>) -> Vec<u8> {
+    let service = Principal::new("kadmin/changepw", "EXAMPLE.COM");
+
+    picky_asn1_der::to_vec(&KrbError::from(KrbErrorInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![KRB_ERROR_MSG_TYPE])),
+        ctime: Optional::from(None),
+        cusec: Optional::from(None),
+        stime: ExplicitContextTag4::from(KerberosTime::from(GeneralizedTime::from(Utc::now()))),
+        susec: ExplicitContextTag5::from(IntegerAsn1::from(vec![0])),
+        error_code: ExplicitContextTag6::from(IntegerAsn1::from(vec![error_code as u8])),
+        crealm: Optional::from(None),
+        cname: Optional::from(None),
+        realm: ExplicitContextTag9::from(service.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag10::from(service.principal_name().unwrap()),
+        e_text: Optional::from(None),
+        e_data: Optional::from(e_data.map(|e_data| ExplicitContextTag12::from(OctetStringAsn1::from(e_data)))),
+    }))
+    .unwrap()
+}
+
+// the reply header is followed by the AP-REP and the KRB-PRIV, or by the KRB-ERROR if the AP-REP is empty
+fn kpasswd_reply(ap_rep: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut reply = Vec::new();
+    reply
+        .write_u16::<BigEndian>((6 + ap_rep.len() + message.len()) as u16)
+        .unwrap();
+    reply.write_u16::<BigEndian>(1).unwrap();
+    reply.write_u16::<BigEndian>(ap_rep.len() as u16).unwrap();
+    reply.extend_from_slice(ap_rep);
+    reply.extend_from_slice(message);
+
+    tcp_message(&reply)
+}
+
+// the reply with the length prefix of the TCP transport
+fn tcp_message(reply: &[u8]) -> Vec<u8> {
+    let mut message = (reply.len() as u32).to_be_bytes().to_vec();
+    message.extend_from_slice(reply);
+
+    message
+}
+
+fn ap_rep(acceptor_subkey: Option<&[u8]>) -> Vec<u8> {
+    let key = |key_value: &[u8]| EncryptionKey {
+        key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+        key_value: ExplicitContextTag1::from(OctetStringAsn1::from(key_value.to_vec())),
+    };
+
+    picky_asn1_der::to_vec(
+        &generate_ap_rep(
+            &key(&SESSION_KEY),
+            KerberosTime::from(GeneralizedTime::from(Utc::now())),
+            IntegerAsn1::from(vec![0]),
+            acceptor_subkey.map(key),
+            1,
+        )
+        .unwrap(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn request_carries_new_password_in_krb_priv_encrypted_with_subkey() {
+    let ap_req = kpasswd_ap_req();
+    let target = Principal::new("alice", "EXAMPLE.COM");
+
+    let request = generate_kpasswd_request(
+        &ap_req,
+        "N3w-passw0rd",
+        Some(&target),
+        &SUBKEY,
+        0x8765_4321,
+        &enc_params(),
+    )
+    .unwrap();
+
+    let ap_req_data = picky_asn1_der::to_vec(&ap_req).unwrap();
+    let message_len = request.len() - 4;
+    assert_eq!(request[0..4], (message_len as u32).to_be_bytes());
+    assert_eq!(request[4..6], (message_len as u16).to_be_bytes());
+    assert_eq!(request[6..8], [0xff, 0x80]);
+    assert_eq!(request[8..10], (ap_req_data.len() as u16).to_be_bytes());
+    assert_eq!(request[10..10 + ap_req_data.len()], ap_req_data);
+
+    let krb_priv: KrbPriv = picky_asn1_der::from_bytes(&request[10 + ap_req_data.len()..]).unwrap();
+    let enc_krb_priv_part = crypto::new_cipher(AES256_CTS_HMAC_SHA1_96)
+        .unwrap()
+        .decrypt(&SUBKEY, KEY_USAGE_KRB_PRIV_ENC_PART, &krb_priv.0.enc_part.0.cipher.0 .0)
+        .unwrap();
+    let enc_krb_priv_part: EncKrbPrivPart = picky_asn1_der::from_bytes(&enc_krb_priv_part).unwrap();
+
+    assert_eq!(
+        integer_to_u32(&enc_krb_priv_part.0.seq_number.0.as_ref().unwrap().0),
+        0x8765_4321
+    );
+    assert_eq!(enc_krb_priv_part.0.s_address.0.addr_type.0 .0, [3]);
+
+    let change_passwd_data: ChangePasswdData = picky_asn1_der::from_bytes(&enc_krb_priv_part.0.user_data.0 .0).unwrap();
+    assert_eq!(change_passwd_data.new_passwd.0 .0, b"N3w-passw0rd");
+    assert_eq!(
+        Principal::from_principal_name(
+            &change_passwd_data.targ_name.0.unwrap().0,
+            &change_passwd_data.targ_realm.0.unwrap().0
+        ),
+        target
+    );
+}
+
+#[test]
+fn own_password_change_request_has_no_target() {
+    let request = generate_kpasswd_request(&kpasswd_ap_req(), "N3w-passw0rd", None, &SUBKEY, 1, &enc_params()).unwrap();
+
+    let ap_req_len = usize::from(u16::from_be_bytes([request[8], request[9]]));
+    let krb_priv: KrbPriv = picky_asn1_der::from_bytes(&request[10 + ap_req_len..]).unwrap();
+    let enc_krb_priv_part = crypto::new_cipher(AES256_CTS_HMAC_SHA1_96)
+        .unwrap()
+        .decrypt(&SUBKEY, KEY_USAGE_KRB_PRIV_ENC_PART, &krb_priv.0.enc_part.0.cipher.0 .0)
+        .unwrap();
+    let enc_krb_priv_part: EncKrbPrivPart = picky_asn1_der::from_bytes(&enc_krb_priv_part).unwrap();
+    let change_passwd_data: ChangePasswdData = picky_asn1_der::from_bytes(&enc_krb_priv_part.0.user_data.0 .0).unwrap();
+
+    assert!(change_passwd_data.targ_name.0.is_none());
+    assert!(change_passwd_data.targ_realm.0.is_none());
+}
+
+#[test]
+fn reply_is_decrypted_with_acceptor_subkey() {
+    let reply = kpasswd_reply(
+        &ap_rep(Some(&ACCEPTOR_SUBKEY)),
+        &krb_priv(&ACCEPTOR_SUBKEY, b"\x00\x00Password changed"),
+    );
+
+    let result = extract_kpasswd_result(&reply, &SESSION_KEY, &SUBKEY, &enc_params()).unwrap();
+
+    assert_eq!(result.code, KRB5_KPASSWD_SUCCESS);
+    assert_eq!(result.message(), "Password changed");
+    result.check().unwrap();
+}
+
+#[test]
+fn reply_without_acceptor_subkey_is_decrypted_with_authenticator_subkey() {
+    let reply = kpasswd_reply(&ap_rep(None), &krb_priv(&SUBKEY, b"\x00\x05Access denied"));
+
+    let result = extract_kpasswd_result(&reply, &SESSION_KEY, &SUBKEY, &enc_params()).unwrap();
+    let error = result.check().unwrap_err();
+
+    assert_eq!(result.code, KRB5_KPASSWD_ACCESSDENIED);
+    assert_eq!(error.error_type, ErrorKind::LogonDenied);
+    assert!(error.description.contains("Access denied"));
+}
+
+#[test]
+fn soft_error_describes_password_policy() {
+    let mut e_data = vec![0x00, 0x04, 0x00, 0x00];
+    e_data.extend_from_slice(&8u32.to_be_bytes());
+    e_data.extend_from_slice(&24u32.to_be_bytes());
+    e_data.extend_from_slice(&1u32.to_be_bytes());
+    e_data.extend_from_slice(&(42 * 864_000_000_000u64).to_be_bytes());
+    e_data.extend_from_slice(&864_000_000_000u64.to_be_bytes());
+    let reply = kpasswd_reply(&[], &krb_error(KRB_ERR_GENERIC, Some(e_data)));
+
+    let result = extract_kpasswd_result(&reply, &SESSION_KEY, &SUBKEY, &enc_params()).unwrap();
+    let error = result.check().unwrap_err();
+
+    assert_eq!(result.code, KRB5_KPASSWD_SOFTERROR);
+    assert_eq!(
+        result.policy(),
+        Some(PasswordPolicy {
+            min_length: 8,
+            history_length: 24,
+            properties: 1,
+            max_age: 42 * 864_000_000_000,
+            min_age: 864_000_000_000,
+        })
+    );
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+    assert!(error.description.contains(
+        "The password must be at least 8 characters long, meet the complexity requirements, \
+         differ from the last 24 passwords, not be changed more often than every 1 days"
+    ));
+}
+
+#[test]
+fn krb_error_without_result_is_returned_as_is() {
+    let error = extract_kpasswd_result(
+        &tcp_message(&krb_error(KRB_AP_ERR_SKEW, None)),
+        &SESSION_KEY,
+        &SUBKEY,
+        &enc_params(),
+    )
+    .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::TimeSkew);
+    assert_eq!(error.kerberos_error().unwrap().error_code, KRB_AP_ERR_SKEW);
+}
+
+#[test]
+fn text_result_string_is_not_parsed_as_policy() {
+    let result = KpasswdResult {
+        code: KRB5_KPASSWD_SOFTERROR,
+        data: b"Password is too short\0".to_vec(),
+    };
+
+    assert_eq!(result.policy(), None);
+    assert_eq!(result.message(), "Password is too short");
+}
+
+#[test]
+fn short_reply_is_rejected() {
+    for reply in [
+        vec![],
+        vec![0x00, 0x00],
+        tcp_message(&[]),
+        tcp_message(&[0x00, 0x06, 0x00, 0x01]),
+    ] {
+        let error = extract_kpasswd_result(&reply, &SESSION_KEY, &SUBKEY, &enc_params()).unwrap_err();
+
+        assert_eq!(error.error_type, ErrorKind::InvalidToken);
+    }
+}
+
+#[test]
+fn reply_with_ap_rep_longer_than_message_is_rejected() {
+    let mut reply = kpasswd_reply(&ap_rep(None), &[]);
+    // AP-REP length
+    reply[8..10].copy_from_slice(&0xffffu16.to_be_bytes());
+
+    let error = extract_kpasswd_result(&reply, &SESSION_KEY, &SUBKEY, &enc_params()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+}
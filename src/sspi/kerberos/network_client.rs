@@ -1,65 +1,171 @@
+#[cfg(test)]
+mod test;
+
+use std::fmt::Debug;
+
+use url::Url;
+
+use crate::Result;
+
+/// Transport used by the Kerberos client to reach the KDC
+pub trait NetworkClient: Debug + Send + Sync {
+    /// Sends the Kerberos message to the KDC over the `tcp` or `udp` url and returns the KDC reply
+    fn send(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Sends the Kerberos message through the KDC Proxy ([MS-KKDCP](https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-kkdcp))
+    fn send_http(&self, url: &Url, data: &[u8], domain: Option<String>) -> Result<Vec<u8>>;
+
+    fn box_clone(&self) -> Box<dyn NetworkClient>;
+}
+
+impl Clone for Box<dyn NetworkClient> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
 #[cfg(feature = "network_client")]
 pub mod reqwest_network_client {
-    use std::io::{Read, Write};
-    use std::net::TcpStream;
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+    use std::time::Duration;
 
     use byteorder::{BigEndian, ReadBytesExt};
+    use kerberos_constants::error_codes::KRB_ERR_RESPONSE_TOO_BIG;
     use picky_asn1::restricted_string::IA5String;
     use picky_asn1::wrapper::{ExplicitContextTag0, ExplicitContextTag1, OctetStringAsn1, Optional};
     use picky_krb::data_types::KerberosStringAsn1;
-    use picky_krb::messages::KdcProxyMessage;
+    use picky_krb::messages::{KdcProxyMessage, KrbError};
     use reqwest::blocking::Client;
     use url::Url;
 
+    use super::NetworkClient;
+    use crate::sspi::kerberos::utils::integer_to_u32;
     use crate::{Error, ErrorKind, Result};
 
+    /// Default time to wait for the KDC reply to the single UDP datagram
+    pub const DEFAULT_UDP_TIMEOUT: Duration = Duration::from_secs(1);
+    /// Default number of attempts to send the UDP datagram before giving up
+    pub const DEFAULT_UDP_RETRIES: u32 = 3;
+
+    // max size of the UDP datagram payload
+    const MAX_UDP_DATAGRAM_LEN: usize = 65_507;
+    // the length prefix of the TCP reply is not trusted to allocate the buffer. The KDC replies over TCP
+    // when the reply does not fit into the UDP datagram, so the limit is well above the datagram size
+    const MAX_TCP_REPLY_LEN: u32 = 0x10_0000;
+    const KRB_ERROR_TAG: u8 = 0x7e;
+
     #[derive(Debug, Clone)]
-    pub struct ReqwestNetworkClient;
+    pub struct ReqwestNetworkClient {
+        pub udp_timeout: Duration,
+        pub udp_retries: u32,
+    }
 
     impl ReqwestNetworkClient {
         pub fn new() -> Self {
-            Self {}
+            Self {
+                udp_timeout: DEFAULT_UDP_TIMEOUT,
+                udp_retries: DEFAULT_UDP_RETRIES,
+            }
+        }
+
+        fn send_tcp(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>> {
+            let mut stream = TcpStream::connect(kdc_address(url))?;
+
+            stream
+                .write_all(data)
+                .map_err(|e| Error::new(ErrorKind::InternalError, format!("{:?}", e)))?;
+
+            let len = stream
+                .read_u32::<BigEndian>()
+                .map_err(|e| Error::new(ErrorKind::InternalError, format!("{:?}", e)))?;
+            if len > MAX_TCP_REPLY_LEN {
+                return Err(Error::new(
+                    ErrorKind::InvalidToken,
+                    format!("The KDC reply is too long: {} > {}", len, MAX_TCP_REPLY_LEN),
+                ));
+            }
+
+            let mut buf = vec![0; len as usize + 4];
+            buf[0..4].copy_from_slice(&(len.to_be_bytes()));
+
+            stream
+                .read_exact(&mut buf[4..])
+                .map_err(|e| Error::new(ErrorKind::InternalError, format!("{:?}", e)))?;
+
+            Ok(buf)
+        }
+
+        // UDP transport: [RFC 4120 7.2.1](https://www.rfc-editor.org/rfc/rfc4120#section-7.2.1)
+        // The message is sent without the 4-byte length prefix. The prefix is added back to the reply,
+        // so the caller receives the same framing as with the TCP transport
+        fn send_udp(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>> {
+            if data.len() < 4 {
+                return Err(Error::new(
+                    ErrorKind::InvalidParameter,
+                    "Kerberos message is too short".into(),
+                ));
+            }
+
+            let address = kdc_address(url).to_socket_addrs()?.next().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NoAuthenticatingAuthority,
+                    format!("Unable to resolve the KDC address: {}", url),
+                )
+            })?;
+            let local_address = match address {
+                SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+                SocketAddr::V6(_) => SocketAddr::from(([0; 16], 0)),
+            };
+
+            let socket = UdpSocket::bind(local_address)?;
+            socket.connect(address)?;
+            socket.set_read_timeout(Some(self.udp_timeout))?;
+
+            let mut buf = vec![0; MAX_UDP_DATAGRAM_LEN + 4];
+            for _ in 0..self.udp_retries.max(1) {
+                socket.send(&data[4..])?;
+
+                match socket.recv(&mut buf[4..]) {
+                    Ok(len) => {
+                        buf.truncate(len + 4);
+                        buf[0..4].copy_from_slice(&(len as u32).to_be_bytes());
+
+                        return Ok(buf);
+                    }
+                    Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+
+            Err(Error::new(
+                ErrorKind::NoAuthenticatingAuthority,
+                format!("The KDC did not reply on {}", url),
+            ))
         }
     }
 
-    impl ReqwestNetworkClient {
-        pub fn send(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>> {
+    impl NetworkClient for ReqwestNetworkClient {
+        fn send(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>> {
             match url.scheme() {
-                "tcp" => {
-                    let mut stream = TcpStream::connect(&format!(
-                        "{}:{}",
-                        url.clone().host_str().unwrap_or_default(),
-                        url.port().unwrap_or(88)
-                    ))?;
-
-                    stream.write(data).map_err(|e| Error {
-                        error_type: ErrorKind::InternalError,
-                        description: format!("{:?}", e),
-                    })?;
-
-                    let len = stream.read_u32::<BigEndian>().map_err(|e| Error {
-                        error_type: ErrorKind::InternalError,
-                        description: format!("{:?}", e),
-                    })?;
-
-                    let mut buf = vec![0; len as usize + 4];
-                    buf[0..4].copy_from_slice(&(len.to_be_bytes()));
-
-                    stream.read_exact(&mut buf[4..]).map_err(|e| Error {
-                        error_type: ErrorKind::InternalError,
-                        description: format!("{:?}", e),
-                    })?;
-
-                    Ok(buf)
+                "tcp" => self.send_tcp(url, data),
+                "udp" => {
+                    let reply = self.send_udp(url, data)?;
+
+                    if is_response_too_big(&reply[4..]) {
+                        self.send_tcp(url, data)
+                    } else {
+                        Ok(reply)
+                    }
                 }
-                scheme => Err(Error {
-                    error_type: ErrorKind::InternalError,
-                    description: format!("Invalid protocol for KDC server: {:?}. Expected only tcp/udp", scheme),
-                }),
+                scheme => Err(Error::new(
+                    ErrorKind::InternalError,
+                    format!("Invalid protocol for KDC server: {:?}. Expected only tcp/udp", scheme),
+                )),
             }
         }
 
-        pub fn send_http(&self, url: &Url, data: &[u8], domain: Option<String>) -> Result<Vec<u8>> {
+        fn send_http(&self, url: &Url, data: &[u8], domain: Option<String>) -> Result<Vec<u8>> {
             let client = Client::new();
 
             let domain = if let Some(domain) = domain {
@@ -80,14 +186,18 @@ pub mod reqwest_network_client {
                 .post(url.clone())
                 .body(picky_asn1_der::to_vec(&kdc_proxy_message)?)
                 .send()
-                .map_err(|err| Error {
-                    error_type: ErrorKind::InternalError,
-                    description: format!("Unable to send the data to the KDC Proxy: {:?}", err),
+                .map_err(|err| {
+                    Error::new(
+                        ErrorKind::InternalError,
+                        format!("Unable to send the data to the KDC Proxy: {:?}", err),
+                    )
                 })?
                 .bytes()
-                .map_err(|err| Error {
-                    error_type: ErrorKind::InternalError,
-                    description: format!("Unable to read the response data from the KDC Proxy: {:?}", err),
+                .map_err(|err| {
+                    Error::new(
+                        ErrorKind::InternalError,
+                        format!("Unable to read the response data from the KDC Proxy: {:?}", err),
+                    )
                 })?
                 .to_vec();
 
@@ -95,6 +205,10 @@ pub mod reqwest_network_client {
 
             Ok(kdc_proxy_message.kerb_message.0 .0)
         }
+
+        fn box_clone(&self) -> Box<dyn NetworkClient> {
+            Box::new(self.clone())
+        }
     }
 
     impl Default for ReqwestNetworkClient {
@@ -102,4 +216,16 @@ pub mod reqwest_network_client {
             Self::new()
         }
     }
+
+    fn kdc_address(url: &Url) -> String {
+        format!("{}:{}", url.host_str().unwrap_or_default(), url.port().unwrap_or(88))
+    }
+
+    // the KDC replies with KRB_ERR_RESPONSE_TOO_BIG when the reply does not fit into the UDP datagram
+    fn is_response_too_big(reply: &[u8]) -> bool {
+        reply.first() == Some(&KRB_ERROR_TAG)
+            && picky_asn1_der::from_bytes::<KrbError>(reply)
+                .map(|krb_error| integer_to_u32(&krb_error.0.error_code.0) == KRB_ERR_RESPONSE_TOO_BIG as u32)
+                .unwrap_or(false)
+    }
 }
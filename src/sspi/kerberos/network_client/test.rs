@@ -0,0 +1,278 @@
+use std::sync::{Arc, Mutex};
+
+use super::*;
+use crate::sspi::kerberos::config::KerberosConfig;
+use crate::sspi::kerberos::kdc_locator::{KdcLocator, Krb5Conf};
+use crate::sspi::kerberos::Kerberos;
+use crate::sspi::{Error, ErrorKind};
+
+// url, message and the target domain of the KDC Proxy message
+type Request = (String, Vec<u8>, Option<String>);
+
+#[derive(Debug, Clone, Default)]
+struct FakeNetworkClient {
+    requests: Arc<Mutex<Vec<Request>>>,
+    unavailable_kdcs: Vec<String>,
+}
+
+impl NetworkClient for FakeNetworkClient {
+    fn send(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((url.to_string(), data.to_vec(), None));
+
+        if self.unavailable_kdcs.contains(&url.to_string()) {
+            return Err(Error::new(ErrorKind::InternalError, "Connection refused".into()));
+        }
+
+        Ok(b"kdc reply".to_vec())
+    }
+
+    fn send_http(&self, url: &Url, data: &[u8], domain: Option<String>) -> Result<Vec<u8>> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((url.to_string(), data.to_vec(), domain));
+
+        Ok(b"kdc proxy reply".to_vec())
+    }
+
+    fn box_clone(&self) -> Box<dyn NetworkClient> {
+        Box::new(self.clone())
+    }
+}
+
+#[test]
+fn kerberos_sends_messages_through_the_configured_network_client() {
+    let network_client = FakeNetworkClient::default();
+    let config = KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(network_client.clone()),
+    );
+
+    let kerberos = Kerberos::new_client_from_config(config.clone()).unwrap();
+
+    assert_eq!(kerberos.send("EXAMPLE.COM", b"as-req").unwrap(), b"kdc reply");
+    assert_eq!(
+        network_client.requests.lock().unwrap().as_slice(),
+        &[("tcp://kdc.example.com:88".to_owned(), b"as-req".to_vec(), None)]
+    );
+}
+
+#[test]
+fn kerberos_sends_messages_to_the_kdc_proxy() {
+    let network_client = FakeNetworkClient::default();
+    let config = KerberosConfig::new(
+        Url::parse("https://proxy.example.com/KdcProxy").unwrap(),
+        Box::new(network_client.clone()),
+    );
+
+    let kerberos = Kerberos::new_client_from_config(config).unwrap();
+
+    assert_eq!(kerberos.send("EXAMPLE.COM", b"tgs-req").unwrap(), b"kdc proxy reply");
+    assert_eq!(
+        network_client.requests.lock().unwrap().as_slice(),
+        &[(
+            "https://proxy.example.com/KdcProxy".to_owned(),
+            b"tgs-req".to_vec(),
+            Some("EXAMPLE.COM".to_owned())
+        )]
+    );
+}
+
+#[cfg(feature = "network_client")]
+mod reqwest_network_client {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use kerberos_constants::error_codes::KRB_ERR_RESPONSE_TOO_BIG;
+    use picky_asn1::date::GeneralizedTime;
+    use picky_asn1::restricted_string::IA5String;
+    use picky_asn1::wrapper::{
+        Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag10, ExplicitContextTag4,
+        ExplicitContextTag5, ExplicitContextTag6, ExplicitContextTag9, IntegerAsn1, Optional,
+    };
+    use picky_krb::data_types::{KerberosStringAsn1, KerberosTime, Microseconds, PrincipalName};
+    use picky_krb::messages::{KrbError, KrbErrorInner};
+    use url::Url;
+
+    use crate::sspi::kerberos::network_client::reqwest_network_client::ReqwestNetworkClient;
+    use crate::sspi::kerberos::network_client::NetworkClient;
+    use crate::sspi::ErrorKind;
+
+    fn krb_error(error_code: i32) -> KrbError {
+        KrbError::from(KrbErrorInner {
+            pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![5])),
+            msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![30])),
+            ctime: Optional::from(None),
+            cusec: Optional::from(None),
+            stime: ExplicitContextTag4::from(KerberosTime::from(GeneralizedTime::from(Utc::now()))),
+            susec: ExplicitContextTag5::from(Microseconds::from(vec![0])),
+            error_code: ExplicitContextTag6::from(IntegerAsn1::from(vec![error_code as u8])),
+            crealm: Optional::from(None),
+            cname: Optional::from(None),
+            realm: ExplicitContextTag9::from(KerberosStringAsn1::from(
+                IA5String::from_string("EXAMPLE.COM".into()).unwrap(),
+            )),
+            sname: ExplicitContextTag10::from(PrincipalName {
+                name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![2])),
+                name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(vec![
+                    KerberosStringAsn1::from(IA5String::from_string("krbtgt".into()).unwrap()),
+                    KerberosStringAsn1::from(IA5String::from_string("EXAMPLE.COM".into()).unwrap()),
+                ])),
+            }),
+            e_text: Optional::from(None),
+            e_data: Optional::from(None),
+        })
+    }
+
+    fn client(udp_retries: u32) -> ReqwestNetworkClient {
+        ReqwestNetworkClient {
+            udp_timeout: Duration::from_millis(200),
+            udp_retries,
+        }
+    }
+
+    #[test]
+    fn send_frames_udp_messages() {
+        let kdc = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("udp://{}", kdc.local_addr().unwrap())).unwrap();
+
+        let server = thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (len, peer) = kdc.recv_from(&mut buf).unwrap();
+            kdc.send_to(b"as-rep", peer).unwrap();
+
+            buf[..len].to_vec()
+        });
+
+        let reply = client(3)
+            .send(&url, &[0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'q'])
+            .unwrap();
+
+        assert_eq!(server.join().unwrap(), b"as-req");
+        assert_eq!(reply, [0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'p']);
+    }
+
+    #[test]
+    fn send_retries_udp_messages_until_the_kdc_replies() {
+        let kdc = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("udp://{}", kdc.local_addr().unwrap())).unwrap();
+
+        let server = thread::spawn(move || {
+            let mut buf = [0; 64];
+            // the first datagram is lost
+            kdc.recv_from(&mut buf).unwrap();
+            let (_, peer) = kdc.recv_from(&mut buf).unwrap();
+            kdc.send_to(b"as-rep", peer).unwrap();
+        });
+
+        let reply = client(3)
+            .send(&url, &[0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'q'])
+            .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(&reply[4..], b"as-rep");
+    }
+
+    #[test]
+    fn send_fails_when_the_kdc_does_not_reply_over_udp() {
+        let kdc = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("udp://{}", kdc.local_addr().unwrap())).unwrap();
+
+        let error = client(2).send(&url, &[0, 0, 0, 2, 0x30, 0x00]).unwrap_err();
+
+        assert_eq!(error.error_type, ErrorKind::NoAuthenticatingAuthority);
+    }
+
+    #[test]
+    fn send_falls_back_to_tcp_when_the_response_is_too_big() {
+        let tcp_kdc = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp_kdc.local_addr().unwrap();
+        let udp_kdc = UdpSocket::bind(address).unwrap();
+        let url = Url::parse(&format!("udp://{}", address)).unwrap();
+
+        let udp_server = thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (_, peer) = udp_kdc.recv_from(&mut buf).unwrap();
+            let krb_error = picky_asn1_der::to_vec(&krb_error(KRB_ERR_RESPONSE_TOO_BIG)).unwrap();
+            udp_kdc.send_to(&krb_error, peer).unwrap();
+        });
+        let tcp_server = thread::spawn(move || {
+            let (mut stream, _) = tcp_kdc.accept().unwrap();
+            let mut buf = [0; 10];
+            stream.read_exact(&mut buf).unwrap();
+            stream
+                .write_all(&[0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'p'])
+                .unwrap();
+
+            buf
+        });
+
+        let reply = client(3)
+            .send(&url, &[0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'q'])
+            .unwrap();
+        udp_server.join().unwrap();
+
+        assert_eq!(
+            tcp_server.join().unwrap(),
+            [0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'q']
+        );
+        assert_eq!(reply, [0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'p']);
+    }
+
+    #[test]
+    fn send_fails_on_too_long_tcp_reply() {
+        let kdc = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("tcp://{}", kdc.local_addr().unwrap())).unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = kdc.accept().unwrap();
+            let mut buf = [0; 10];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        });
+
+        let error = client(3)
+            .send(&url, &[0, 0, 0, 6, b'a', b's', b'-', b'r', b'e', b'q'])
+            .unwrap_err();
+        server.join().unwrap();
+
+        assert_eq!(error.error_type, ErrorKind::InvalidToken);
+    }
+}
+
+#[test]
+fn kerberos_fails_over_to_the_next_kdc_of_the_realm() {
+    let network_client = FakeNetworkClient {
+        unavailable_kdcs: vec!["tcp://kdc1.example.com:88".to_owned()],
+        ..Default::default()
+    };
+    let kdc_locator = KdcLocator {
+        krb5_conf: Some(
+            "[realms]\nEXAMPLE.COM = {\nkdc = kdc1.example.com:88\nkdc = kdc2.example.com:88\n}\n"
+                .parse::<Krb5Conf>()
+                .unwrap(),
+        ),
+        ..Default::default()
+    };
+    let config = KerberosConfig::new_with_kdc_locator(kdc_locator, Box::new(network_client.clone()));
+
+    let kerberos = Kerberos::new_client_from_config(config).unwrap();
+
+    assert_eq!(kerberos.send("EXAMPLE.COM", b"as-req").unwrap(), b"kdc reply");
+    assert_eq!(
+        network_client
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(url, _, _)| url.as_str())
+            .collect::<Vec<_>>(),
+        ["tcp://kdc1.example.com:88", "tcp://kdc2.example.com:88"]
+    );
+}
@@ -0,0 +1,713 @@
+//! Privilege Attribute Certificate: [MS-PAC](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/166d8064-c863-41e1-9c23-edaaa5f36962)
+//!
+//! The KDC puts the PAC into the AD-WIN2K-PAC element of the AD-IF-RELEVANT authorization data of the ticket.
+//! It contains the groups of the client, so the service does not need to look them up in the directory
+
+mod ndr;
+#[cfg(test)]
+pub(crate) mod test;
+
+use std::fmt;
+use std::ops::Range;
+
+use kerberos_constants::ad_types::{AD_IF_RELEVANT, AD_WIN2K_PACK};
+use picky_krb::data_types::AuthorizationData;
+use subtle::ConstantTimeEq;
+
+use self::ndr::NdrReader;
+use super::crypto;
+use super::server::ServiceKey;
+use super::utils::{integer_to_u32, utf16_bytes_to_utf8_string};
+use crate::sspi::{Error, ErrorKind, Result};
+
+// PAC_INFO_BUFFER types: [MS-PAC 2.4](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/3341cfa2-6ef5-42e0-b7bc-4544884bf399)
+const LOGON_INFO_TYPE: u32 = 1;
+const SERVER_CHECKSUM_TYPE: u32 = 6;
+const PRIVSVR_CHECKSUM_TYPE: u32 = 7;
+const UPN_DNS_INFO_TYPE: u32 = 12;
+const CLIENT_CLAIMS_INFO_TYPE: u32 = 13;
+
+const PAC_VERSION: u32 = 0;
+// cBuffers and Version
+const PAC_HEADER_LEN: usize = 8;
+// ulType, cbBufferSize and Offset
+const PAC_INFO_BUFFER_LEN: usize = 16;
+// KERB_NON_KERB_CKSUM_SALT
+const PAC_CHECKSUM_KEY_USAGE: i32 = 17;
+
+// the SamName and the SID follow the DNS domain name
+const UPN_DNS_INFO_EXTENDED_FLAG: u32 = 0x02;
+
+// [MS-ADTS 2.2.18.4](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-adts/2e5ffd1f-2f93-4a1a-8ad4-9d4a3e1b3d1b)
+const CLAIMS_COMPRESSION_FORMAT_NONE: u16 = 0;
+const CLAIM_TYPE_INT64: u16 = 1;
+const CLAIM_TYPE_UINT64: u16 = 2;
+const CLAIM_TYPE_STRING: u16 = 3;
+const CLAIM_TYPE_BOOLEAN: u16 = 6;
+
+/// Security identifier: [MS-DTYP 2.4.2](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-dtyp/78eb9013-1c3a-4970-ad1f-2b1dad588a25)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sid {
+    pub revision: u8,
+    pub identifier_authority: [u8; 6],
+    pub sub_authorities: Vec<u32>,
+}
+
+impl Sid {
+    /// SID of the account of the domain, e.g. of its group
+    pub fn with_rid(&self, rid: u32) -> Self {
+        let mut sid = self.clone();
+        sid.sub_authorities.push(rid);
+
+        sid
+    }
+
+    // binary SID: [MS-DTYP 2.4.2.2](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-dtyp/f992ad60-0fe4-4b87-9fed-beb478836861)
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        let (header, sub_authorities) = data
+            .split_at_checked(8)
+            .ok_or_else(|| invalid_pac("The SID is truncated"))?;
+        if sub_authorities.len() != usize::from(header[1]) * 4 {
+            return Err(invalid_pac("Invalid SID length"));
+        }
+
+        Ok(Self {
+            revision: header[0],
+            identifier_authority: header[2..8].try_into().unwrap(),
+            sub_authorities: sub_authorities
+                .chunks(4)
+                .map(|sub_authority| u32::from_le_bytes(sub_authority.try_into().unwrap()))
+                .collect(),
+        })
+    }
+}
+
+/// String representation of the SID, e.g. S-1-5-21-1004336348-1177238915-682003330-512
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let identifier_authority = self
+            .identifier_authority
+            .iter()
+            .fold(0_u64, |value, byte| (value << 8) | u64::from(*byte));
+
+        if identifier_authority >> 32 == 0 {
+            write!(f, "S-{}-{}", self.revision, identifier_authority)?;
+        } else {
+            write!(f, "S-{}-0x{:012X}", self.revision, identifier_authority)?;
+        }
+
+        self.sub_authorities
+            .iter()
+            .try_for_each(|sub_authority| write!(f, "-{}", sub_authority))
+    }
+}
+
+/// GROUP_MEMBERSHIP: the RID of the group of the domain and the attributes of the membership
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupMembership {
+    pub relative_id: u32,
+    pub attributes: u32,
+}
+
+/// KERB_SID_AND_ATTRIBUTES: the group of another domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SidAndAttributes {
+    pub sid: Sid,
+    pub attributes: u32,
+}
+
+/// KERB_VALIDATION_INFO: [MS-PAC 2.5](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/69e86ccc-85e3-41b9-b514-7d969cd0ed73)
+///
+/// The times are FILETIMEs: 100-nanosecond intervals since January 1, 1601 (UTC)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationInfo {
+    pub logon_time: u64,
+    pub logoff_time: u64,
+    pub kick_off_time: u64,
+    pub password_last_set: u64,
+    pub password_can_change: u64,
+    pub password_must_change: u64,
+    pub effective_name: String,
+    pub full_name: String,
+    pub logon_script: String,
+    pub profile_path: String,
+    pub home_directory: String,
+    pub home_directory_drive: String,
+    pub logon_count: u16,
+    pub bad_password_count: u16,
+    pub user_id: u32,
+    pub primary_group_id: u32,
+    pub group_ids: Vec<GroupMembership>,
+    pub user_flags: u32,
+    pub logon_server: String,
+    pub logon_domain_name: String,
+    pub logon_domain_id: Option<Sid>,
+    pub user_account_control: u32,
+    pub extra_sids: Vec<SidAndAttributes>,
+    pub resource_group_domain_sid: Option<Sid>,
+    pub resource_group_ids: Vec<GroupMembership>,
+}
+
+impl ValidationInfo {
+    /// SID of the user: the RID of the user in the logon domain
+    pub fn user_sid(&self) -> Option<Sid> {
+        self.logon_domain_id
+            .as_ref()
+            .map(|domain| domain.with_rid(self.user_id))
+    }
+
+    /// SIDs of the groups of the user: the groups of the logon domain, the extra SIDs and the resource groups
+    pub fn group_sids(&self) -> Vec<Sid> {
+        let domain_groups = self.logon_domain_id.iter().flat_map(|domain| {
+            self.group_ids
+                .iter()
+                .map(move |group| domain.with_rid(group.relative_id))
+        });
+        let resource_groups = self.resource_group_domain_sid.iter().flat_map(|domain| {
+            self.resource_group_ids
+                .iter()
+                .map(move |group| domain.with_rid(group.relative_id))
+        });
+
+        domain_groups
+            .chain(self.extra_sids.iter().map(|extra_sid| extra_sid.sid.clone()))
+            .chain(resource_groups)
+            .collect()
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = NdrReader::new(data)?;
+        if !reader.read_pointer()? {
+            return Err(invalid_pac("The logon information is null"));
+        }
+
+        let logon_time = reader.read_filetime()?;
+        let logoff_time = reader.read_filetime()?;
+        let kick_off_time = reader.read_filetime()?;
+        let password_last_set = reader.read_filetime()?;
+        let password_can_change = reader.read_filetime()?;
+        let password_must_change = reader.read_filetime()?;
+        let effective_name = read_unicode_string_header(&mut reader)?;
+        let full_name = read_unicode_string_header(&mut reader)?;
+        let logon_script = read_unicode_string_header(&mut reader)?;
+        let profile_path = read_unicode_string_header(&mut reader)?;
+        let home_directory = read_unicode_string_header(&mut reader)?;
+        let home_directory_drive = read_unicode_string_header(&mut reader)?;
+        let logon_count = reader.read_u16()?;
+        let bad_password_count = reader.read_u16()?;
+        let user_id = reader.read_u32()?;
+        let primary_group_id = reader.read_u32()?;
+        let group_count = reader.read_u32()?;
+        let has_group_ids = reader.read_pointer()?;
+        let user_flags = reader.read_u32()?;
+        // UserSessionKey is not used
+        reader.read_bytes(16)?;
+        let logon_server = read_unicode_string_header(&mut reader)?;
+        let logon_domain_name = read_unicode_string_header(&mut reader)?;
+        let has_logon_domain_id = reader.read_pointer()?;
+        // Reserved1
+        reader.read_u32()?;
+        reader.read_u32()?;
+        let user_account_control = reader.read_u32()?;
+        // SubAuthStatus, LastSuccessfulILogon, LastFailedILogon, FailedILogonCount and Reserved3
+        reader.read_u32()?;
+        reader.read_filetime()?;
+        reader.read_filetime()?;
+        reader.read_u32()?;
+        reader.read_u32()?;
+        let sid_count = reader.read_u32()?;
+        let has_extra_sids = reader.read_pointer()?;
+        let has_resource_group_domain_sid = reader.read_pointer()?;
+        let resource_group_count = reader.read_u32()?;
+        let has_resource_group_ids = reader.read_pointer()?;
+
+        // the referents in the order of the pointers
+        let effective_name = read_unicode_string(&mut reader, effective_name)?;
+        let full_name = read_unicode_string(&mut reader, full_name)?;
+        let logon_script = read_unicode_string(&mut reader, logon_script)?;
+        let profile_path = read_unicode_string(&mut reader, profile_path)?;
+        let home_directory = read_unicode_string(&mut reader, home_directory)?;
+        let home_directory_drive = read_unicode_string(&mut reader, home_directory_drive)?;
+        let group_ids = read_group_ids(&mut reader, has_group_ids, group_count)?;
+        let logon_server = read_unicode_string(&mut reader, logon_server)?;
+        let logon_domain_name = read_unicode_string(&mut reader, logon_domain_name)?;
+        let logon_domain_id = has_logon_domain_id.then(|| reader.read_sid()).transpose()?;
+        let extra_sids = if has_extra_sids {
+            read_array_len(&mut reader, sid_count)?;
+            let extra_sids = (0..sid_count)
+                .map(|_| Ok((reader.read_pointer()?, reader.read_u32()?)))
+                .collect::<Result<Vec<_>>>()?;
+
+            extra_sids
+                .into_iter()
+                .filter(|(has_sid, _)| *has_sid)
+                .map(|(_, attributes)| {
+                    Ok(SidAndAttributes {
+                        sid: reader.read_sid()?,
+                        attributes,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        let resource_group_domain_sid = has_resource_group_domain_sid.then(|| reader.read_sid()).transpose()?;
+        let resource_group_ids = read_group_ids(&mut reader, has_resource_group_ids, resource_group_count)?;
+
+        Ok(Self {
+            logon_time,
+            logoff_time,
+            kick_off_time,
+            password_last_set,
+            password_can_change,
+            password_must_change,
+            effective_name,
+            full_name,
+            logon_script,
+            profile_path,
+            home_directory,
+            home_directory_drive,
+            logon_count,
+            bad_password_count,
+            user_id,
+            primary_group_id,
+            group_ids,
+            user_flags,
+            logon_server,
+            logon_domain_name,
+            logon_domain_id,
+            user_account_control,
+            extra_sids,
+            resource_group_domain_sid,
+            resource_group_ids,
+        })
+    }
+}
+
+/// UPN_DNS_INFO: [MS-PAC 2.10](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/1c0d6e11-6443-4846-b744-f9f810a504eb)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpnDnsInfo {
+    pub upn: String,
+    pub dns_domain_name: String,
+    pub flags: u32,
+    /// the SAM name and the SID of the client are present in the extended UPN_DNS_INFO only
+    pub sam_name: Option<String>,
+    pub sid: Option<Sid>,
+}
+
+impl UpnDnsInfo {
+    // the offsets of the strings are relative to the start of the buffer
+    fn decode(data: &[u8]) -> Result<Self> {
+        let u16_at = |offset: usize| -> Result<usize> {
+            data.get(offset..offset + 2)
+                .map(|value| usize::from(u16::from_le_bytes(value.try_into().unwrap())))
+                .ok_or_else(|| invalid_pac("UPN_DNS_INFO is truncated"))
+        };
+        let field_at = |offset: usize| -> Result<&[u8]> {
+            let len = u16_at(offset)?;
+            let start = u16_at(offset + 2)?;
+
+            data.get(start..start + len)
+                .ok_or_else(|| invalid_pac("UPN_DNS_INFO is truncated"))
+        };
+        let string_at = |offset: usize| -> Result<String> {
+            let field = field_at(offset)?;
+            if field.len() % 2 != 0 {
+                return Err(invalid_pac("UPN_DNS_INFO string is not UTF-16"));
+            }
+
+            Ok(utf16_bytes_to_utf8_string(field))
+        };
+
+        let flags = data
+            .get(8..12)
+            .map(|flags| u32::from_le_bytes(flags.try_into().unwrap()))
+            .ok_or_else(|| invalid_pac("UPN_DNS_INFO is truncated"))?;
+
+        let (sam_name, sid) = if flags & UPN_DNS_INFO_EXTENDED_FLAG != 0 {
+            (Some(string_at(12)?), Some(Sid::from_bytes(field_at(16)?)?))
+        } else {
+            (None, None)
+        };
+
+        Ok(Self {
+            upn: string_at(0)?,
+            dns_domain_name: string_at(4)?,
+            flags,
+            sam_name,
+            sid,
+        })
+    }
+}
+
+/// CLAIMS_ARRAY: the claims of one source, e.g. of the directory: [MS-ADTS 2.2.18.5](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-adts/bb9bee7a-d9d1-4fd7-8f48-ec2c21d9a9a5)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimsArray {
+    pub source_type: u16,
+    pub claims: Vec<ClaimEntry>,
+}
+
+/// CLAIM_ENTRY: the ID of the claim and its values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimEntry {
+    pub id: String,
+    pub values: ClaimValues,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimValues {
+    Int64(Vec<i64>),
+    Uint64(Vec<u64>),
+    String(Vec<String>),
+    Boolean(Vec<bool>),
+}
+
+/// PAC of the client accepted by the service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pac {
+    pub logon_info: Option<ValidationInfo>,
+    pub upn_dns_info: Option<UpnDnsInfo>,
+    // PAC_CLIENT_CLAIMS_INFO is decoded on request, so the authentication does not depend on the claims
+    client_claims: Option<Vec<u8>>,
+}
+
+impl Pac {
+    /// Decodes the PAC and verifies its signatures: [MS-PAC 2.8](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/6e95edd3-af93-41d4-8303-6c7955297315)
+    ///
+    /// The server signature is made with the key which encrypts the ticket. The KDC signature is skipped only if the KDC
+    /// keys are `None`
+    pub fn decode(data: &[u8], server_key: &ServiceKey, kdc_keys: Option<&[ServiceKey]>) -> Result<Self> {
+        let buffers = pac_buffers(data)?;
+        let buffer = |buffer_type: u32| buffers.iter().find(|buffer| buffer.buffer_type == buffer_type);
+
+        let server_signature = PacSignature::decode(
+            data,
+            buffer(SERVER_CHECKSUM_TYPE).ok_or_else(|| invalid_pac("The server signature is missing"))?,
+        )?;
+        let kdc_signature = PacSignature::decode(
+            data,
+            buffer(PRIVSVR_CHECKSUM_TYPE).ok_or_else(|| invalid_pac("The KDC signature is missing"))?,
+        )?;
+
+        // the server signature is computed with the zeroed signatures
+        let mut signed_data = data.to_vec();
+        signed_data[server_signature.range.clone()].fill(0);
+        signed_data[kdc_signature.range.clone()].fill(0);
+
+        server_signature.verify(&signed_data, server_key)?;
+
+        if let Some(kdc_keys) = kdc_keys {
+            let kdc_key = kdc_keys
+                .iter()
+                .find(|key| key.encryption_type == kdc_signature.encryption_type)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NoKerdKey,
+                        format!(
+                            "There is no KDC key for the encryption type {}",
+                            kdc_signature.encryption_type
+                        ),
+                    )
+                })?;
+
+            // the KDC signs the server signature
+            kdc_signature.verify(&data[server_signature.range.clone()], kdc_key)?;
+        }
+
+        let buffer_data = |buffer_type: u32| buffer(buffer_type).map(|buffer| &data[buffer.range.clone()]);
+
+        Ok(Self {
+            logon_info: buffer_data(LOGON_INFO_TYPE).map(ValidationInfo::decode).transpose()?,
+            upn_dns_info: buffer_data(UPN_DNS_INFO_TYPE).map(UpnDnsInfo::decode).transpose()?,
+            client_claims: buffer_data(CLIENT_CLAIMS_INFO_TYPE).map(|claims| claims.to_vec()),
+        })
+    }
+
+    /// Claims of the client: [MS-PAC 2.11](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/e19d7ae9-7fc3-41c6-b3ec-8a1d3e6a6c3d).
+    /// Compressed claims are not supported
+    pub fn client_claims(&self) -> Result<Vec<ClaimsArray>> {
+        match self.client_claims.as_deref() {
+            Some(data) => decode_claims_set_metadata(data),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Extracts the PAC from the AD-WIN2K-PAC element of the AD-IF-RELEVANT authorization data
+pub fn extract_pac(authorization_data: &AuthorizationData) -> Result<Option<Vec<u8>>> {
+    for if_relevant in authorization_data
+        .0
+        .iter()
+        .filter(|element| integer_to_u32(&element.ad_type.0) as i32 == AD_IF_RELEVANT)
+    {
+        let elements: AuthorizationData = picky_asn1_der::from_bytes(&if_relevant.ad_data.0 .0)
+            .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+
+        if let Some(pac) = elements
+            .0
+            .into_iter()
+            .find(|element| integer_to_u32(&element.ad_type.0) as i32 == AD_WIN2K_PACK)
+        {
+            return Ok(Some(pac.ad_data.0 .0));
+        }
+    }
+
+    Ok(None)
+}
+
+// PAC_INFO_BUFFER
+struct PacBuffer {
+    buffer_type: u32,
+    range: Range<usize>,
+}
+
+fn pac_buffers(data: &[u8]) -> Result<Vec<PacBuffer>> {
+    let u32_at = |offset: usize| -> Result<u32> {
+        data.get(offset..offset + 4)
+            .map(|value| u32::from_le_bytes(value.try_into().unwrap()))
+            .ok_or_else(|| invalid_pac("The PAC is truncated"))
+    };
+
+    let buffers_count = u32_at(0)? as usize;
+    if u32_at(4)? != PAC_VERSION {
+        return Err(invalid_pac("Unsupported PAC version"));
+    }
+
+    (0..buffers_count)
+        .map(|index| {
+            let offset = PAC_HEADER_LEN + index * PAC_INFO_BUFFER_LEN;
+            let len = u32_at(offset + 4)? as usize;
+            let start = u64::from(u32_at(offset + 8)?) | u64::from(u32_at(offset + 12)?) << 32;
+            let start = usize::try_from(start).map_err(|_| invalid_pac("Invalid buffer offset"))?;
+
+            start
+                .checked_add(len)
+                .filter(|end| *end <= data.len())
+                .map(|end| PacBuffer {
+                    buffer_type: u32_at(offset).unwrap_or_default(),
+                    range: start..end,
+                })
+                .ok_or_else(|| invalid_pac("The buffer is out of the PAC"))
+        })
+        .collect()
+}
+
+// PAC_SIGNATURE_DATA: the signature type and the signature. The RODC identifier is not used
+struct PacSignature {
+    encryption_type: i32,
+    // position of the signature in the PAC
+    range: Range<usize>,
+    signature: Vec<u8>,
+}
+
+impl PacSignature {
+    fn decode(data: &[u8], buffer: &PacBuffer) -> Result<Self> {
+        let buffer_data = &data[buffer.range.clone()];
+        let checksum_type = buffer_data
+            .get(0..4)
+            .map(|checksum_type| i32::from_le_bytes(checksum_type.try_into().unwrap()))
+            .ok_or_else(|| invalid_pac("The signature is truncated"))?;
+        let encryption_type = crypto::checksum_encryption_type(checksum_type)?;
+
+        let start = buffer.range.start + 4;
+        let range = start..start + crypto::checksum_len(encryption_type)?;
+        if range.end > buffer.range.end {
+            return Err(invalid_pac("The signature is truncated"));
+        }
+
+        Ok(Self {
+            encryption_type,
+            signature: data[range.clone()].to_vec(),
+            range,
+        })
+    }
+
+    fn verify(&self, data: &[u8], key: &ServiceKey) -> Result<()> {
+        if key.encryption_type != self.encryption_type
+            || !bool::from(
+                crypto::checksum(self.encryption_type, &key.key, PAC_CHECKSUM_KEY_USAGE, data)?.ct_eq(&self.signature),
+            )
+        {
+            return Err(Error::new(ErrorKind::MessageAltered, "Invalid PAC signature".into()));
+        }
+
+        Ok(())
+    }
+}
+
+// PAC_CLIENT_CLAIMS_INFO: CLAIMS_SET_METADATA containing the serialized CLAIMS_SET
+fn decode_claims_set_metadata(data: &[u8]) -> Result<Vec<ClaimsArray>> {
+    let mut reader = NdrReader::new(data)?;
+    if !reader.read_pointer()? {
+        return Ok(Vec::new());
+    }
+
+    let claims_set_size = reader.read_u32()?;
+    let has_claims_set = reader.read_pointer()?;
+    let compression_format = reader.read_u16()?;
+    // ulUncompressedClaimsSetSize, usReservedType, ulReservedFieldSize and ReservedField
+    reader.read_u32()?;
+    reader.read_u16()?;
+    reader.read_u32()?;
+    reader.read_pointer()?;
+
+    if compression_format != CLAIMS_COMPRESSION_FORMAT_NONE {
+        return Err(Error::new(
+            ErrorKind::UnsupportedFunction,
+            format!("Compressed claims are not supported: {}", compression_format),
+        ));
+    }
+    if !has_claims_set {
+        return Ok(Vec::new());
+    }
+
+    read_array_len(&mut reader, claims_set_size)?;
+    decode_claims_set(reader.read_bytes(claims_set_size as usize)?)
+}
+
+// CLAIMS_SET: [MS-ADTS 2.2.18.2](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-adts/a2c6b8da-8a19-4e9a-9e4b-6b06cb0bcfad)
+fn decode_claims_set(data: &[u8]) -> Result<Vec<ClaimsArray>> {
+    let mut reader = NdrReader::new(data)?;
+    if !reader.read_pointer()? {
+        return Ok(Vec::new());
+    }
+
+    let claims_array_count = reader.read_u32()?;
+    let has_claims_arrays = reader.read_pointer()?;
+    // usReservedType, ulReservedFieldSize and ReservedField
+    reader.read_u16()?;
+    reader.read_u32()?;
+    reader.read_pointer()?;
+
+    if !has_claims_arrays {
+        return Ok(Vec::new());
+    }
+
+    read_array_len(&mut reader, claims_array_count)?;
+    let claims_arrays = (0..claims_array_count)
+        .map(|_| Ok((reader.read_u16()?, reader.read_u32()?, reader.read_pointer()?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    claims_arrays
+        .into_iter()
+        .map(|(source_type, claims_count, has_claims)| {
+            let claims = if has_claims {
+                read_claim_entries(&mut reader, claims_count)?
+            } else {
+                Vec::new()
+            };
+
+            Ok(ClaimsArray { source_type, claims })
+        })
+        .collect()
+}
+
+fn read_claim_entries(reader: &mut NdrReader<'_>, count: u32) -> Result<Vec<ClaimEntry>> {
+    read_array_len(reader, count)?;
+    let entries = (0..count)
+        .map(|_| {
+            let has_id = reader.read_pointer()?;
+            let claim_type = reader.read_u16()?;
+            // the 16-bit discriminant of the values union is aligned as the union
+            reader.read_u32()?;
+            let value_count = reader.read_u32()?;
+            let has_values = reader.read_pointer()?;
+
+            Ok((has_id, claim_type, value_count, has_values))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    entries
+        .into_iter()
+        .map(|(has_id, claim_type, value_count, has_values)| {
+            let id = if has_id { reader.read_string()? } else { String::new() };
+
+            let value_count = if has_values { value_count } else { 0 };
+            if has_values {
+                read_array_len(reader, value_count)?;
+            }
+
+            let values = match claim_type {
+                CLAIM_TYPE_INT64 => ClaimValues::Int64(
+                    (0..value_count)
+                        .map(|_| reader.read_u64().map(|value| value as i64))
+                        .collect::<Result<_>>()?,
+                ),
+                CLAIM_TYPE_UINT64 => {
+                    ClaimValues::Uint64((0..value_count).map(|_| reader.read_u64()).collect::<Result<_>>()?)
+                }
+                CLAIM_TYPE_BOOLEAN => ClaimValues::Boolean(
+                    (0..value_count)
+                        .map(|_| reader.read_u64().map(|value| value != 0))
+                        .collect::<Result<_>>()?,
+                ),
+                CLAIM_TYPE_STRING => {
+                    let strings = (0..value_count)
+                        .map(|_| reader.read_pointer())
+                        .collect::<Result<Vec<_>>>()?;
+
+                    ClaimValues::String(
+                        strings
+                            .into_iter()
+                            .map(|has_string| {
+                                if has_string {
+                                    reader.read_string()
+                                } else {
+                                    Ok(String::new())
+                                }
+                            })
+                            .collect::<Result<_>>()?,
+                    )
+                }
+                _ => return Err(invalid_pac(&format!("Unknown claim type: {}", claim_type))),
+            };
+
+            Ok(ClaimEntry { id, values })
+        })
+        .collect()
+}
+
+// the conformant count must match the count of the elements in the structure
+fn read_array_len(reader: &mut NdrReader<'_>, count: u32) -> Result<()> {
+    if reader.read_conformant_count()? != count as usize {
+        return Err(invalid_pac("The array length does not match the count of elements"));
+    }
+
+    Ok(())
+}
+
+// RPC_UNICODE_STRING: the length, the maximum length and the pointer to the characters
+fn read_unicode_string_header(reader: &mut NdrReader<'_>) -> Result<bool> {
+    reader.read_u16()?;
+    reader.read_u16()?;
+
+    reader.read_pointer()
+}
+
+fn read_unicode_string(reader: &mut NdrReader<'_>, has_buffer: bool) -> Result<String> {
+    if has_buffer {
+        reader.read_string()
+    } else {
+        Ok(String::new())
+    }
+}
+
+fn read_group_ids(reader: &mut NdrReader<'_>, has_group_ids: bool, count: u32) -> Result<Vec<GroupMembership>> {
+    if !has_group_ids {
+        return Ok(Vec::new());
+    }
+
+    read_array_len(reader, count)?;
+    (0..count)
+        .map(|_| {
+            Ok(GroupMembership {
+                relative_id: reader.read_u32()?,
+                attributes: reader.read_u32()?,
+            })
+        })
+        .collect()
+}
+
+fn invalid_pac(description: &str) -> Error {
+    Error::new(ErrorKind::InvalidToken, format!("Invalid PAC: {}", description))
+}
@@ -0,0 +1,136 @@
+//! NDR type serialization version 1 of the PAC buffers: [MS-RPCE 2.2.6](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rpce/9a1d0f97-eac0-49ab-a197-f1a581c2d6a0)
+//!
+//! Only the little-endian NDR20 transfer syntax is supported. The referents of the embedded pointers follow
+//! the structure which contains them, so the structure is read first and then the referents of its non-null pointers
+
+use super::Sid;
+use crate::sspi::kerberos::utils::utf16_bytes_to_utf8_string;
+use crate::sspi::{Error, ErrorKind, Result};
+
+const TYPE_SERIALIZATION_VERSION: u8 = 1;
+const LITTLE_ENDIAN: u8 = 0x10;
+const COMMON_HEADER_LEN: u16 = 8;
+// the common header and the private header with the length of the serialized object
+const HEADERS_LEN: usize = 16;
+
+pub struct NdrReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> NdrReader<'a> {
+    /// Skips the common and the private type serialization headers
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADERS_LEN
+            || data[0] != TYPE_SERIALIZATION_VERSION
+            || data[1] != LITTLE_ENDIAN
+            || u16::from_le_bytes([data[2], data[3]]) != COMMON_HEADER_LEN
+        {
+            return Err(invalid_ndr("Invalid type serialization header"));
+        }
+
+        let object_len = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
+        let data = data[HEADERS_LEN..]
+            .get(..object_len)
+            .ok_or_else(|| invalid_ndr("The serialized object is truncated"))?;
+
+        Ok(Self { data, position: 0 })
+    }
+
+    // primitives are aligned to their size relative to the start of the serialized object
+    fn align(&mut self, alignment: usize) {
+        self.position = self.position.div_ceil(alignment) * alignment;
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(|| invalid_ndr("The serialized object is truncated"))?;
+        self.position += len;
+
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        self.align(2);
+
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        self.align(4);
+
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        self.align(8);
+
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /// FILETIME: the low and the high parts are aligned as u32
+    pub fn read_filetime(&mut self) -> Result<u64> {
+        let low = self.read_u32()?;
+        let high = self.read_u32()?;
+
+        Ok(u64::from(high) << 32 | u64::from(low))
+    }
+
+    /// Referent ID of the unique pointer. The referent follows only if the pointer is not null
+    pub fn read_pointer(&mut self) -> Result<bool> {
+        Ok(self.read_u32()? != 0)
+    }
+
+    /// The maximum count of the conformant array precedes its elements
+    pub fn read_conformant_count(&mut self) -> Result<usize> {
+        Ok(self.read_u32()? as usize)
+    }
+
+    /// Conformant varying array of UTF-16 characters: RPC_UNICODE_STRING buffer or `[string] wchar_t*`
+    pub fn read_string(&mut self) -> Result<String> {
+        let _max_count = self.read_u32()?;
+        let offset = self.read_u32()?;
+        let actual_count = self.read_u32()? as usize;
+        if offset != 0 {
+            return Err(invalid_ndr("Strings with the offset are not supported"));
+        }
+
+        let data = self.read_bytes(
+            actual_count
+                .checked_mul(2)
+                .ok_or_else(|| invalid_ndr("Invalid string length"))?,
+        )?;
+        let string = utf16_bytes_to_utf8_string(data);
+
+        // `[string]` arrays contain the terminating null character
+        Ok(string.trim_end_matches('\0').to_owned())
+    }
+
+    /// RPC_SID: the count of the sub-authorities is also the conformant count
+    pub fn read_sid(&mut self) -> Result<Sid> {
+        let _max_count = self.read_conformant_count()?;
+        let revision = self.read_u8()?;
+        let sub_authority_count = self.read_u8()?;
+        let identifier_authority = self.read_bytes(6)?.try_into().unwrap();
+        let sub_authorities = (0..sub_authority_count)
+            .map(|_| self.read_u32())
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Sid {
+            revision,
+            identifier_authority,
+            sub_authorities,
+        })
+    }
+}
+
+fn invalid_ndr(description: &str) -> Error {
+    Error::new(ErrorKind::InvalidToken, format!("Invalid PAC buffer: {}", description))
+}
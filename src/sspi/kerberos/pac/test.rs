@@ -0,0 +1,464 @@
+use kerberos_constants::etypes::{AES256_CTS_HMAC_SHA1_96, RC4_HMAC};
+use picky_asn1::wrapper::{Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, OctetStringAsn1};
+use picky_krb::data_types::AuthorizationDataInner;
+
+use super::*;
+use crate::sspi::kerberos::utils::i32_to_integer;
+
+const SERVER_KEY: [u8; 32] = [0x11; 32];
+const KDC_KEY: [u8; 32] = [0x22; 32];
+
+fn aes_key(key: &[u8]) -> ServiceKey {
+    ServiceKey {
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        kvno: None,
+        key: key.to_vec(),
+    }
+}
+
+fn sid(identifier_authority: u8, sub_authorities: &[u32]) -> Sid {
+    Sid {
+        revision: 1,
+        identifier_authority: [0, 0, 0, 0, 0, identifier_authority],
+        sub_authorities: sub_authorities.to_vec(),
+    }
+}
+
+fn utf16(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+// NDR type serialization version 1: the referents follow the structure which contains the pointers
+#[derive(Default)]
+struct NdrWriter {
+    data: Vec<u8>,
+}
+
+impl NdrWriter {
+    fn align(&mut self, alignment: usize) {
+        self.data.resize(self.data.len().div_ceil(alignment) * alignment, 0);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.align(2);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.align(8);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn pointer(&mut self, is_present: bool) {
+        self.u32(if is_present { 0x0002_0000 } else { 0 });
+    }
+
+    fn unicode_string_header(&mut self, value: &str) {
+        self.u16(value.len() as u16 * 2);
+        self.u16(value.len() as u16 * 2);
+        self.pointer(!value.is_empty());
+    }
+
+    fn string(&mut self, value: &str) {
+        let len = value.encode_utf16().count() as u32;
+        self.u32(len);
+        self.u32(0);
+        self.u32(len);
+        self.data.extend_from_slice(&utf16(value));
+    }
+
+    fn sid(&mut self, sid: &Sid) {
+        self.u32(sid.sub_authorities.len() as u32);
+        self.data.push(sid.revision);
+        self.data.push(sid.sub_authorities.len() as u8);
+        self.data.extend_from_slice(&sid.identifier_authority);
+        sid.sub_authorities
+            .iter()
+            .for_each(|sub_authority| self.u32(*sub_authority));
+    }
+
+    fn serialize(mut self) -> Vec<u8> {
+        self.align(8);
+
+        let mut data = vec![1, 0x10, 8, 0, 0xcc, 0xcc, 0xcc, 0xcc];
+        data.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&self.data);
+
+        data
+    }
+}
+
+fn logon_info() -> Vec<u8> {
+    let mut ndr = NdrWriter::default();
+    ndr.pointer(true);
+    // LogonTime and the other times
+    ndr.u32(0x8000_0000);
+    ndr.u32(0x01d9_0000);
+    (0..10).for_each(|_| ndr.u32(0));
+    ndr.unicode_string_header("alice");
+    ndr.unicode_string_header("Alice Smith");
+    (0..4).for_each(|_| ndr.unicode_string_header(""));
+    ndr.u16(12);
+    ndr.u16(0);
+    ndr.u32(1104);
+    ndr.u32(513);
+    ndr.u32(2);
+    ndr.pointer(true);
+    // UserFlags: extra SIDs and resource groups
+    ndr.u32(0x220);
+    ndr.data.extend_from_slice(&[0; 16]);
+    ndr.unicode_string_header("DC1");
+    ndr.unicode_string_header("EXAMPLE");
+    ndr.pointer(true);
+    (0..2).for_each(|_| ndr.u32(0));
+    ndr.u32(0x10);
+    (0..7).for_each(|_| ndr.u32(0));
+    ndr.u32(1);
+    ndr.pointer(true);
+    ndr.pointer(true);
+    ndr.u32(1);
+    ndr.pointer(true);
+
+    ndr.string("alice");
+    ndr.string("Alice Smith");
+    ndr.u32(2);
+    [(513, 7), (512, 7)].into_iter().for_each(|(relative_id, attributes)| {
+        ndr.u32(relative_id);
+        ndr.u32(attributes);
+    });
+    ndr.string("DC1");
+    ndr.string("EXAMPLE");
+    ndr.sid(&sid(5, &[21, 1, 2, 3]));
+    ndr.u32(1);
+    ndr.pointer(true);
+    ndr.u32(7);
+    ndr.sid(&sid(18, &[1]));
+    ndr.sid(&sid(5, &[21, 4, 5, 6]));
+    ndr.u32(1);
+    ndr.u32(1000);
+    ndr.u32(0x2000_0007);
+
+    ndr.serialize()
+}
+
+fn upn_dns_info() -> Vec<u8> {
+    let upn = utf16("alice@example.com");
+    let dns_domain_name = utf16("EXAMPLE.COM");
+    let sam_name = utf16("alice");
+    let mut sid = vec![1, 5, 0, 0, 0, 0, 0, 5];
+    [21, 1, 2, 3, 1104_u32]
+        .iter()
+        .for_each(|sub_authority| sid.extend_from_slice(&sub_authority.to_le_bytes()));
+
+    let mut offset = 24;
+    let mut header = Vec::new();
+    let mut data = Vec::new();
+    for (index, field) in [&upn, &dns_domain_name, &sam_name, &sid].into_iter().enumerate() {
+        header.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(offset as u16).to_le_bytes());
+        if index == 1 {
+            header.extend_from_slice(&UPN_DNS_INFO_EXTENDED_FLAG.to_le_bytes());
+        }
+
+        data.extend_from_slice(field);
+        offset += field.len();
+    }
+    header.resize(24, 0);
+    header.extend_from_slice(&data);
+
+    header
+}
+
+fn client_claims() -> Vec<u8> {
+    let mut claims_set = NdrWriter::default();
+    claims_set.pointer(true);
+    claims_set.u32(1);
+    claims_set.pointer(true);
+    claims_set.u16(0);
+    claims_set.u32(0);
+    claims_set.pointer(false);
+
+    // CLAIMS_ARRAY of the directory with the string and the integer claims
+    claims_set.u32(1);
+    claims_set.u16(1);
+    claims_set.u32(2);
+    claims_set.pointer(true);
+
+    claims_set.u32(2);
+    claims_set.pointer(true);
+    claims_set.u16(CLAIM_TYPE_STRING);
+    // the discriminant of the union is aligned as the union
+    claims_set.u32(CLAIM_TYPE_STRING.into());
+    claims_set.u32(2);
+    claims_set.pointer(true);
+    claims_set.pointer(true);
+    claims_set.u16(CLAIM_TYPE_INT64);
+    claims_set.u32(CLAIM_TYPE_INT64.into());
+    claims_set.u32(1);
+    claims_set.pointer(true);
+
+    claims_set.string("ad://ext/department");
+    claims_set.u32(2);
+    claims_set.pointer(true);
+    claims_set.pointer(true);
+    claims_set.string("Engineering");
+    claims_set.string("Research");
+    claims_set.string("ad://ext/clearance");
+    claims_set.u32(1);
+    claims_set.u64(-3_i64 as u64);
+    let claims_set = claims_set.serialize();
+
+    let mut metadata = NdrWriter::default();
+    metadata.pointer(true);
+    metadata.u32(claims_set.len() as u32);
+    metadata.pointer(true);
+    metadata.u16(CLAIMS_COMPRESSION_FORMAT_NONE);
+    metadata.u32(claims_set.len() as u32);
+    metadata.u16(0);
+    metadata.u32(0);
+    metadata.pointer(false);
+    metadata.u32(claims_set.len() as u32);
+    metadata.data.extend_from_slice(&claims_set);
+
+    metadata.serialize()
+}
+
+// the buffers are 8-byte aligned and the signatures are computed as the KDC does
+fn signed_pac(buffers: Vec<(u32, Vec<u8>)>, server_key: &ServiceKey, kdc_key: &ServiceKey) -> Vec<u8> {
+    let signature = |key: &ServiceKey| {
+        let mut signature = crypto::checksum_type(key.encryption_type)
+            .unwrap()
+            .to_le_bytes()
+            .to_vec();
+        signature.resize(4 + crypto::checksum_len(key.encryption_type).unwrap(), 0);
+        signature
+    };
+    let mut buffers = buffers;
+    buffers.push((SERVER_CHECKSUM_TYPE, signature(server_key)));
+    buffers.push((PRIVSVR_CHECKSUM_TYPE, signature(kdc_key)));
+
+    let mut pac = Vec::new();
+    pac.extend_from_slice(&(buffers.len() as u32).to_le_bytes());
+    pac.extend_from_slice(&PAC_VERSION.to_le_bytes());
+
+    let mut offset = PAC_HEADER_LEN + buffers.len() * PAC_INFO_BUFFER_LEN;
+    let mut data = Vec::new();
+    let mut signatures = Vec::new();
+    for (buffer_type, buffer) in &buffers {
+        pac.extend_from_slice(&buffer_type.to_le_bytes());
+        pac.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        pac.extend_from_slice(&(offset as u64).to_le_bytes());
+        signatures.push(offset + 4..offset + buffer.len());
+
+        data.extend_from_slice(buffer);
+        data.resize(data.len().div_ceil(8) * 8, 0);
+        offset = PAC_HEADER_LEN + buffers.len() * PAC_INFO_BUFFER_LEN + data.len();
+    }
+    pac.extend_from_slice(&data);
+
+    let kdc_signature = signatures.pop().unwrap();
+    let server_signature = signatures.pop().unwrap();
+
+    let server_checksum = crypto::checksum(
+        server_key.encryption_type,
+        &server_key.key,
+        PAC_CHECKSUM_KEY_USAGE,
+        &pac,
+    )
+    .unwrap();
+    pac[server_signature.clone()].copy_from_slice(&server_checksum);
+
+    let kdc_checksum = crypto::checksum(
+        kdc_key.encryption_type,
+        &kdc_key.key,
+        PAC_CHECKSUM_KEY_USAGE,
+        &server_checksum,
+    )
+    .unwrap();
+    pac[kdc_signature].copy_from_slice(&kdc_checksum);
+
+    pac
+}
+
+fn test_pac() -> Vec<u8> {
+    signed_pac(
+        vec![
+            (LOGON_INFO_TYPE, logon_info()),
+            (UPN_DNS_INFO_TYPE, upn_dns_info()),
+            (CLIENT_CLAIMS_INFO_TYPE, client_claims()),
+        ],
+        &aes_key(&SERVER_KEY),
+        &aes_key(&KDC_KEY),
+    )
+}
+
+#[test]
+fn logon_info_is_decoded_with_group_sids() {
+    let pac = Pac::decode(&test_pac(), &aes_key(&SERVER_KEY), Some(&[aes_key(&KDC_KEY)])).unwrap();
+
+    let logon_info = pac.logon_info.unwrap();
+    assert_eq!(logon_info.logon_time, 0x01d9_0000_8000_0000);
+    assert_eq!(logon_info.effective_name, "alice");
+    assert_eq!(logon_info.full_name, "Alice Smith");
+    assert_eq!(logon_info.logon_script, "");
+    assert_eq!(logon_info.logon_count, 12);
+    assert_eq!(logon_info.logon_server, "DC1");
+    assert_eq!(logon_info.logon_domain_name, "EXAMPLE");
+    assert_eq!(logon_info.user_account_control, 0x10);
+    assert_eq!(logon_info.user_sid().unwrap().to_string(), "S-1-5-21-1-2-3-1104");
+    assert_eq!(
+        logon_info
+            .group_sids()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        vec![
+            "S-1-5-21-1-2-3-513",
+            "S-1-5-21-1-2-3-512",
+            "S-1-18-1",
+            "S-1-5-21-4-5-6-1000",
+        ]
+    );
+}
+
+#[test]
+fn upn_dns_info_is_decoded_with_sam_name_and_sid() {
+    let pac = Pac::decode(&test_pac(), &aes_key(&SERVER_KEY), Some(&[aes_key(&KDC_KEY)])).unwrap();
+
+    assert_eq!(
+        pac.upn_dns_info.unwrap(),
+        UpnDnsInfo {
+            upn: "alice@example.com".into(),
+            dns_domain_name: "EXAMPLE.COM".into(),
+            flags: UPN_DNS_INFO_EXTENDED_FLAG,
+            sam_name: Some("alice".into()),
+            sid: Some(sid(5, &[21, 1, 2, 3, 1104])),
+        }
+    );
+}
+
+#[test]
+fn upn_dns_info_with_odd_string_len_is_rejected() {
+    let mut upn_dns_info = upn_dns_info();
+    let upn_len = u16::from_le_bytes([upn_dns_info[0], upn_dns_info[1]]);
+    upn_dns_info[0..2].copy_from_slice(&(upn_len - 1).to_le_bytes());
+
+    let error = UpnDnsInfo::decode(&upn_dns_info).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+}
+
+#[test]
+fn client_claims_are_decoded() {
+    let pac = Pac::decode(&test_pac(), &aes_key(&SERVER_KEY), Some(&[aes_key(&KDC_KEY)])).unwrap();
+
+    assert_eq!(
+        pac.client_claims().unwrap(),
+        vec![ClaimsArray {
+            source_type: 1,
+            claims: vec![
+                ClaimEntry {
+                    id: "ad://ext/department".into(),
+                    values: ClaimValues::String(vec!["Engineering".into(), "Research".into()]),
+                },
+                ClaimEntry {
+                    id: "ad://ext/clearance".into(),
+                    values: ClaimValues::Int64(vec![-3]),
+                },
+            ],
+        }]
+    );
+}
+
+#[test]
+fn altered_pac_is_rejected() {
+    let mut pac = test_pac();
+    let len = pac.len();
+    pac[len - 100] ^= 1;
+
+    let error = Pac::decode(&pac, &aes_key(&SERVER_KEY), Some(&[aes_key(&KDC_KEY)])).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::MessageAltered);
+}
+
+#[test]
+fn kdc_signature_is_verified_with_kdc_key() {
+    let error = Pac::decode(&test_pac(), &aes_key(&SERVER_KEY), Some(&[aes_key(&[0x33; 32])])).unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::MessageAltered);
+
+    let rc4_key = ServiceKey {
+        encryption_type: RC4_HMAC,
+        kvno: None,
+        key: vec![0x22; 16],
+    };
+    let error = Pac::decode(&test_pac(), &aes_key(&SERVER_KEY), Some(&[rc4_key])).unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::NoKerdKey);
+}
+
+#[test]
+fn kdc_signature_is_skipped_only_on_request() {
+    let error = Pac::decode(&test_pac(), &aes_key(&SERVER_KEY), Some(&[])).unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::NoKerdKey);
+
+    let pac = Pac::decode(&test_pac(), &aes_key(&SERVER_KEY), None).unwrap();
+    assert_eq!(pac.upn_dns_info.unwrap().upn, "alice@example.com");
+}
+
+#[test]
+fn pac_without_signatures_is_rejected() {
+    let mut pac = Vec::new();
+    pac.extend_from_slice(&0_u32.to_le_bytes());
+    pac.extend_from_slice(&PAC_VERSION.to_le_bytes());
+
+    let error = Pac::decode(&pac, &aes_key(&SERVER_KEY), Some(&[aes_key(&KDC_KEY)])).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+}
+
+fn authorization_data(ad_type: i32, ad_data: Vec<u8>) -> AuthorizationData {
+    Asn1SequenceOf::from(vec![AuthorizationDataInner {
+        ad_type: ExplicitContextTag0::from(i32_to_integer(ad_type)),
+        ad_data: ExplicitContextTag1::from(OctetStringAsn1::from(ad_data)),
+    }])
+}
+
+/// Authorization data of a ticket encrypted with `service_key`, as the KDC of Active Directory issues it
+pub(crate) fn ticket_authorization_data(service_key: &[u8]) -> AuthorizationData {
+    let pac = signed_pac(
+        vec![(UPN_DNS_INFO_TYPE, upn_dns_info())],
+        &aes_key(service_key),
+        &aes_key(&KDC_KEY),
+    );
+    let win2k_pac = authorization_data(AD_WIN2K_PACK, pac);
+
+    authorization_data(AD_IF_RELEVANT, picky_asn1_der::to_vec(&win2k_pac).unwrap())
+}
+
+#[test]
+fn pac_is_extracted_from_if_relevant_authorization_data() {
+    let win2k_pac = authorization_data(AD_WIN2K_PACK, vec![1, 2, 3]);
+    let if_relevant = authorization_data(AD_IF_RELEVANT, picky_asn1_der::to_vec(&win2k_pac).unwrap());
+
+    assert_eq!(extract_pac(&if_relevant).unwrap(), Some(vec![1, 2, 3]));
+    assert_eq!(extract_pac(&win2k_pac).unwrap(), None);
+}
+
+#[test]
+fn sid_is_formatted_as_string() {
+    assert_eq!(sid(5, &[32, 544]).to_string(), "S-1-5-32-544");
+    assert_eq!(
+        Sid {
+            revision: 1,
+            identifier_authority: [0, 1, 0, 0, 0, 0],
+            sub_authorities: vec![1],
+        }
+        .to_string(),
+        "S-1-0x000100000000-1"
+    );
+}
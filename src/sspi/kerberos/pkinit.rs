@@ -0,0 +1,320 @@
+//! Public key cryptography for the initial authentication: [RFC 4556](https://www.rfc-editor.org/rfc/rfc4556)
+//!
+//! The client authenticates with the certificate and the AS-REP is encrypted with the key
+//! agreed with the Diffie-Hellman key exchange
+
+mod cms;
+mod data_types;
+mod dh;
+#[cfg(test)]
+mod test;
+
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use oid::ObjectIdentifier;
+use picky::hash::HashAlgorithm;
+use picky::key::PrivateKey;
+use picky::signature::SignatureAlgorithm;
+use picky::x509::Cert;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3, ImplicitContextTag0,
+    IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_asn1_x509::{ExtensionView, GeneralName, PrivateKeyInfo, PrivateKeyValue};
+use picky_krb::data_types::{KerberosTime, PaData};
+use picky_krb::messages::{AsRep, KdcReqBody, KrbError};
+use sha1::{Digest, Sha1};
+
+use self::data_types::{
+    AuthPack, DhRepInfo, KdcDhKeyInfo, Krb5PrincipalName, PaPkAsReq, PkAuthenticator, PA_PK_AS_REP, PA_PK_AS_REQ,
+};
+pub(crate) use self::dh::DhKeyPair;
+use super::utils::integer_to_u32;
+use super::{crypto, tgt_principal, KerberosError, Principal};
+use crate::sspi::{Error, ErrorKind, Result};
+
+// id-pkinit-authData
+const AUTH_DATA_CONTENT_TYPE: &str = "1.3.6.1.5.2.3.1";
+// id-pkinit-DHKeyData
+const DH_KEY_DATA_CONTENT_TYPE: &str = "1.3.6.1.5.2.3.2";
+// id-pkinit-KPKdc
+const KDC_KEY_PURPOSE: &str = "1.3.6.1.5.2.3.5";
+// id-pkinit-san
+const KRB5_PRINCIPAL_NAME_TYPE: &str = "1.3.6.1.5.2.2";
+
+// PA-PK-AS-REP choices
+const DH_INFO_TAG: u8 = 0xa0;
+
+const MAX_MICROSECONDS_IN_SECOND: u32 = 999_999;
+
+const KDC_ERR_CLIENT_NOT_TRUSTED: u32 = 62;
+const KDC_ERR_CLIENT_NAME_MISMATCH: u32 = 75;
+const KDC_ERR_KDC_NAME_MISMATCH: u32 = 76;
+
+/// Signs the PKINIT requests with the private key of the client certificate.
+/// The key can stay on a smart card: the signature corresponds to the PKCS#11 `C_Sign`
+/// with the `CKM_SHA256_RSA_PKCS` mechanism
+pub trait PkInitSigner: fmt::Debug + Send + Sync {
+    /// DER-encoded X.509 certificate of the client
+    fn certificate(&self) -> &[u8];
+
+    /// RSASSA-PKCS1-v1_5 signature of the SHA-256 digest of the data
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Signer with the RSA private key held in memory
+pub struct PrivateKeySigner {
+    certificate: Vec<u8>,
+    private_key: PrivateKey,
+}
+
+impl PrivateKeySigner {
+    /// Takes the DER-encoded certificate and the DER-encoded PKCS#8 or PKCS#1 RSA private key
+    pub fn new(certificate: Vec<u8>, private_key: &[u8]) -> Result<Self> {
+        let private_key = PrivateKey::from_pkcs8(private_key)
+            .or_else(|_| PrivateKey::from_rsa_der(private_key))
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidParameter,
+                    format!("Cannot decode the RSA private key: {:?}", e),
+                )
+            })?;
+
+        if !matches!(
+            PrivateKeyInfo::from(private_key.clone()).private_key,
+            PrivateKeyValue::RSA(_)
+        ) {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                "Only RSA private keys are supported".into(),
+            ));
+        }
+
+        Ok(Self {
+            certificate,
+            private_key,
+        })
+    }
+}
+
+impl fmt::Debug for PrivateKeySigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrivateKeySigner")
+            .field("certificate", &self.certificate)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PkInitSigner for PrivateKeySigner {
+    fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        SignatureAlgorithm::RsaPkcs1v15(HashAlgorithm::SHA2_256)
+            .sign(data, &self.private_key)
+            .map_err(|e| Error::new(ErrorKind::InternalError, format!("Cannot sign the data: {:?}", e)))
+    }
+}
+
+/// Certificate credentials of the client principal
+#[derive(Debug, Clone)]
+pub struct PkInitCredentials {
+    pub username: String,
+    pub domain: String,
+    pub signer: Arc<dyn PkInitSigner>,
+}
+
+impl PartialEq for PkInitCredentials {
+    fn eq(&self, other: &Self) -> bool {
+        self.username == other.username
+            && self.domain == other.domain
+            && self.signer.certificate() == other.signer.certificate()
+    }
+}
+
+impl Eq for PkInitCredentials {}
+
+/// PA-PK-AS-REQ with the AuthPack signed by the client
+pub(crate) fn generate_pa_pk_as_req(
+    signer: &dyn PkInitSigner,
+    req_body: &KdcReqBody,
+    dh_key_pair: &DhKeyPair,
+    current_date: DateTime<Utc>,
+) -> Result<PaData> {
+    let microseconds = current_date.timestamp_subsec_micros().min(MAX_MICROSECONDS_IN_SECOND);
+
+    let auth_pack = AuthPack {
+        pk_authenticator: ExplicitContextTag0::from(PkAuthenticator {
+            cusec: ExplicitContextTag0::from(IntegerAsn1::from_bytes_be_unsigned(microseconds.to_be_bytes().to_vec())),
+            ctime: ExplicitContextTag1::from(KerberosTime::from(GeneralizedTime::from(current_date))),
+            nonce: ExplicitContextTag2::from(req_body.nonce.0.clone()),
+            pa_checksum: Optional::from(Some(ExplicitContextTag3::from(OctetStringAsn1::from(
+                Sha1::digest(picky_asn1_der::to_vec(req_body)?).to_vec(),
+            )))),
+        }),
+        client_public_value: Optional::from(Some(ExplicitContextTag1::from(dh_key_pair.public_key_info()?))),
+        supported_cms_types: Optional::from(None),
+        client_dh_nonce: Optional::from(None),
+    };
+
+    let signed_auth_pack = cms::sign(
+        signer,
+        &ObjectIdentifier::try_from(AUTH_DATA_CONTENT_TYPE).unwrap(),
+        &picky_asn1_der::to_vec(&auth_pack)?,
+    )?;
+
+    Ok(PaData {
+        padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_PK_AS_REQ.to_vec())),
+        padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&PaPkAsReq {
+            signed_auth_pack: ImplicitContextTag0::from(OctetStringAsn1::from(signed_auth_pack)),
+        })?)),
+    })
+}
+
+/// Verifies the PA-PK-AS-REP signed by the KDC of the realm and derives the AS reply key
+pub(crate) fn extract_reply_key(
+    as_rep: &AsRep,
+    realm: &str,
+    nonce: u32,
+    dh_key_pair: &DhKeyPair,
+    trust_anchors: &[Vec<u8>],
+    encryption_type: i32,
+) -> Result<Vec<u8>> {
+    if trust_anchors.is_empty() {
+        return Err(Error::new(
+            ErrorKind::PkInitClientFailure,
+            "KDC trust anchors are not configured".into(),
+        ));
+    }
+
+    let pa_pk_as_rep = as_rep
+        .0
+        .padata
+        .0
+        .as_ref()
+        .and_then(|padata| {
+            padata
+                .0
+                 .0
+                .iter()
+                .find(|pa_data| pa_data.padata_type.0 .0 == PA_PK_AS_REP)
+        })
+        .ok_or_else(|| Error::new(ErrorKind::PkInitClientFailure, "The PA-PK-AS-REP is missing".into()))?;
+
+    let pa_pk_as_rep = &pa_pk_as_rep.padata_data.0 .0;
+    if pa_pk_as_rep.first() != Some(&DH_INFO_TAG) {
+        return Err(Error::new(
+            ErrorKind::PkInitClientFailure,
+            "Only the Diffie-Hellman key exchange is supported".into(),
+        ));
+    }
+    let dh_rep_info: ExplicitContextTag0<DhRepInfo> = picky_asn1_der::from_bytes(pa_pk_as_rep)?;
+
+    let (kdc_dh_key_info, kdc_certificate) = cms::verify(
+        &dh_rep_info.dh_signed_data.0 .0,
+        &ObjectIdentifier::try_from(DH_KEY_DATA_CONTENT_TYPE).unwrap(),
+        trust_anchors,
+        Utc::now(),
+    )?;
+    check_kdc_certificate(&kdc_certificate, realm)?;
+    let kdc_dh_key_info: KdcDhKeyInfo = picky_asn1_der::from_bytes(&kdc_dh_key_info)?;
+
+    if integer_to_u32(&kdc_dh_key_info.nonce.0) != nonce {
+        return Err(Error::new(
+            ErrorKind::PkInitClientFailure,
+            "The nonce of the KDC Diffie-Hellman key info does not match the request".into(),
+        ));
+    }
+
+    let kdc_public_key: IntegerAsn1 =
+        picky_asn1_der::from_bytes(kdc_dh_key_info.subject_public_key.0 .0.payload_view())?;
+    let shared_secret = dh_key_pair.shared_secret(kdc_public_key.as_unsigned_bytes_be())?;
+
+    // the client does not send the clientDHNonce, so the shared secret is used alone
+    Ok(dh::octet_string_to_key(
+        &shared_secret,
+        crypto::key_len(encryption_type)?,
+    ))
+}
+
+// the certificate must be issued to the KDC of the realm: [RFC 4556 3.2.4](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.4).
+// The certificates of the Windows KDCs name the domain with the DNS name instead of the krb5PrincipalName
+fn check_kdc_certificate(certificate: &Cert, realm: &str) -> Result<()> {
+    let kdc_key_purpose = ObjectIdentifier::try_from(KDC_KEY_PURPOSE).unwrap();
+    let is_kdc_certificate = certificate
+        .extensions()
+        .iter()
+        .any(|extension| match extension.extn_value() {
+            ExtensionView::ExtendedKeyUsage(extended_key_usage) => extended_key_usage.contains(kdc_key_purpose.clone()),
+            _ => false,
+        });
+    if !is_kdc_certificate {
+        return Err(Error::new(
+            ErrorKind::PkInitClientFailure,
+            "The certificate of the signed data is not a KDC certificate".into(),
+        ));
+    }
+
+    let names_kdc = certificate
+        .extensions()
+        .iter()
+        .any(|extension| match extension.extn_value() {
+            ExtensionView::SubjectAltName(names) => names.0.iter().any(|name| is_kdc_name(name, realm)),
+            _ => false,
+        });
+    if !names_kdc {
+        return Err(Error::new(
+            ErrorKind::PkInitClientFailure,
+            format!("The KDC certificate is not issued to the KDC of the {} realm", realm),
+        ));
+    }
+
+    Ok(())
+}
+
+fn is_kdc_name(name: &GeneralName, realm: &str) -> bool {
+    match name {
+        GeneralName::OtherName(other_name)
+            if other_name.type_id.0 == ObjectIdentifier::try_from(KRB5_PRINCIPAL_NAME_TYPE).unwrap() =>
+        {
+            picky_asn1_der::from_bytes::<Krb5PrincipalName>(&other_name.value.0 .0)
+                .map(|kdc| {
+                    Principal::from_principal_name(&kdc.principal_name.0, &kdc.realm.0)
+                        .matches(&tgt_principal(realm).name(), realm)
+                })
+                .unwrap_or(false)
+        }
+        GeneralName::DnsName(dns_name) => dns_name.to_string().eq_ignore_ascii_case(realm),
+        _ => false,
+    }
+}
+
+// maps the PKINIT errors of the KDC: [RFC 4556 3.1.3](https://www.rfc-editor.org/rfc/rfc4556#section-3.1.3)
+pub(crate) fn pk_init_error(error: KrbError) -> Error {
+    let error_code = integer_to_u32(&error.0.error_code.0);
+
+    let (error_type, description) = match error_code {
+        KDC_ERR_CLIENT_NAME_MISMATCH => (
+            ErrorKind::PkInitNameMismatch,
+            format!(
+                "The client certificate does not match the principal: {}",
+                error.0.to_string()
+            ),
+        ),
+        KDC_ERR_CLIENT_NOT_TRUSTED..=KDC_ERR_KDC_NAME_MISMATCH => (
+            ErrorKind::PkInitClientFailure,
+            format!(
+                "PKINIT failed with the {} KDC error: {}",
+                error_code,
+                error.0.to_string()
+            ),
+        ),
+        _ => return Error::from(error),
+    };
+
+    Error::new(error_type, description).with_kerberos_error(KerberosError::from(&error))
+}
@@ -0,0 +1,281 @@
+//! CMS SignedData of PKINIT: [RFC 5652 5](https://www.rfc-editor.org/rfc/rfc5652#section-5)
+
+use chrono::{DateTime, Utc};
+use oid::ObjectIdentifier;
+use picky::hash::HashAlgorithm;
+use picky::signature::SignatureAlgorithm;
+use picky::x509::date::UtcDate;
+use picky::x509::Cert;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::wrapper::{Asn1SequenceOf, Asn1SetOf, ExplicitContextTag0, OctetStringAsn1, Optional};
+use picky_asn1_der::Asn1RawDer;
+use picky_asn1_x509::cmsversion::CmsVersion;
+use picky_asn1_x509::content_info::{ContentValue, EncapsulatedContentInfo};
+use picky_asn1_x509::signed_data::{
+    CertificateChoices, CertificateSet, DigestAlgorithmIdentifiers, SignedData, SignersInfos,
+};
+use picky_asn1_x509::signer_info::{
+    Attributes, CertificateSerialNumber, DigestAlgorithmIdentifier, IssuerAndSerialNumber,
+    SignatureAlgorithmIdentifier, SignatureValue, SignerIdentifier, SignerInfo, UnsignedAttributes,
+};
+use picky_asn1_x509::{oids, AlgorithmIdentifier, Attribute, AttributeValues, Name, Pkcs7Certificate, ShaVariant};
+
+use super::PkInitSigner;
+use crate::sspi::{Error, ErrorKind, Result};
+
+// the KDC certificate, the intermediate CAs and the trust anchor
+const MAX_CHAIN_LEN: usize = 8;
+
+/// Encodes the ContentInfo with the content signed by the signer
+pub fn sign(signer: &dyn PkInitSigner, content_type: &ObjectIdentifier, content: &[u8]) -> Result<Vec<u8>> {
+    let certificate = Cert::from_der(signer.certificate()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidParameter,
+            format!("Cannot decode the client certificate: {:?}", e),
+        )
+    })?;
+
+    // DER orders SET OF elements by their encodings: the content type is shorter than the SHA-256 digest
+    let signed_attributes = vec![
+        Attribute {
+            ty: oids::content_type().into(),
+            value: AttributeValues::ContentType(Asn1SetOf::from(vec![content_type.clone().into()])),
+        },
+        Attribute::new_message_digest(HashAlgorithm::SHA2_256.digest(content)),
+    ];
+    let signature = signer.sign(&picky_asn1_der::to_vec(&Asn1SetOf::from(signed_attributes.clone()))?)?;
+
+    let digest_algorithm = AlgorithmIdentifier::new_sha(ShaVariant::SHA2_256);
+
+    let signer_info = SignerInfo {
+        version: CmsVersion::V1,
+        sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: Name::from(certificate.issuer_name()),
+            serial_number: CertificateSerialNumber(certificate.serial_number().clone()),
+        }),
+        digest_algorithm: DigestAlgorithmIdentifier(digest_algorithm.clone()),
+        signed_attrs: Optional::from(Attributes(Asn1SequenceOf::from(signed_attributes))),
+        signature_algorithm: SignatureAlgorithmIdentifier(AlgorithmIdentifier::new_sha256_with_rsa_encryption()),
+        signature: SignatureValue(OctetStringAsn1::from(signature)),
+        unsigned_attrs: Optional::from(UnsignedAttributes::default()),
+    };
+
+    let signed_data = SignedData {
+        // eContentType is not id-data
+        version: CmsVersion::V3,
+        digest_algorithms: DigestAlgorithmIdentifiers(Asn1SetOf::from(vec![digest_algorithm])),
+        content_info: EncapsulatedContentInfo {
+            content_type: content_type.clone().into(),
+            content: Some(ExplicitContextTag0::from(ContentValue::OctetString(
+                OctetStringAsn1::from(content.to_vec()),
+            ))),
+        },
+        certificates: Optional::from(CertificateSet(vec![CertificateChoices::Certificate(Asn1RawDer(
+            signer.certificate().to_vec(),
+        ))])),
+        crls: None,
+        signers_infos: SignersInfos(Asn1SetOf::from(vec![signer_info])),
+    };
+
+    Ok(picky_asn1_der::to_vec(&Pkcs7Certificate {
+        oid: oids::signed_data().into(),
+        signed_data: ExplicitContextTag0::from(signed_data),
+    })?)
+}
+
+/// Verifies the ContentInfo signed by the KDC and returns its content with the signer certificate.
+///
+/// The signer certificate must be one of the trust anchors or must chain up to one of them through
+/// the certificates of the signed data. A trust anchor which issues certificates must be a root CA
+pub fn verify(
+    content_info: &[u8],
+    content_type: &ObjectIdentifier,
+    trust_anchors: &[Vec<u8>],
+    now: DateTime<Utc>,
+) -> Result<(Vec<u8>, Cert)> {
+    let content_info: Pkcs7Certificate = picky_asn1_der::from_bytes(content_info)
+        .map_err(|e| malformed(&format!("Cannot decode the signed data: {:?}", e)))?;
+    let signed_data = content_info.signed_data.0;
+
+    if &signed_data.content_info.content_type.0 != content_type {
+        return Err(malformed("Unexpected content type of the signed data"));
+    }
+    let content = match signed_data.content_info.content {
+        Some(ExplicitContextTag0(ContentValue::OctetString(content))) => content.0,
+        _ => return Err(malformed("The content is missing")),
+    };
+
+    let signer_info = match signed_data.signers_infos.0 .0.as_slice() {
+        [signer_info] => signer_info,
+        _ => return Err(malformed("Expected exactly one signer")),
+    };
+
+    let digest_algorithm = hash_algorithm(&signer_info.digest_algorithm.0)?;
+    let signed_attributes = &signer_info.signed_attrs.0 .0 .0;
+    check_signed_attributes(signed_attributes, content_type, &digest_algorithm.digest(&content))?;
+
+    let certificates = signed_data
+        .certificates
+        .0
+         .0
+        .iter()
+        .filter_map(|certificate| match certificate {
+            CertificateChoices::Certificate(certificate) => Cert::from_der(&certificate.0).ok(),
+            CertificateChoices::Other(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let trust_anchors = trust_anchors
+        .iter()
+        .map(|trust_anchor| {
+            Cert::from_der(trust_anchor).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidParameter,
+                    format!("Cannot decode the KDC trust anchor: {:?}", e),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let signer_certificate = find_signer_certificate(&certificates, &signer_info.sid)?;
+    verify_certificate(
+        signer_certificate,
+        &certificates,
+        &trust_anchors,
+        &UtcDate::from(GeneralizedTime::from(now)),
+    )?;
+
+    signature_algorithm(&signer_info.signature_algorithm.0, digest_algorithm)?
+        .verify(
+            signer_certificate.public_key(),
+            &picky_asn1_der::to_vec(&Asn1SetOf::from(signed_attributes.clone()))?,
+            &signer_info.signature.0 .0,
+        )
+        .map_err(|_| Error::new(ErrorKind::PkInitClientFailure, "Invalid signature".into()))?;
+
+    Ok((content, signer_certificate.clone()))
+}
+
+fn check_signed_attributes(
+    signed_attributes: &[Attribute],
+    content_type: &ObjectIdentifier,
+    content_digest: &[u8],
+) -> Result<()> {
+    let content_type_matches = signed_attributes.iter().any(|attribute| match &attribute.value {
+        AttributeValues::ContentType(value) => value.0.iter().any(|value| &value.0 == content_type),
+        _ => false,
+    });
+    let digest_matches = signed_attributes.iter().any(|attribute| match &attribute.value {
+        AttributeValues::MessageDigest(value) => value.0.iter().any(|value| value.0 == content_digest),
+        _ => false,
+    });
+
+    if !content_type_matches || !digest_matches {
+        return Err(Error::new(
+            ErrorKind::PkInitClientFailure,
+            "The signed attributes do not match the signed data".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn find_signer_certificate<'a>(certificates: &'a [Cert], signer_identifier: &SignerIdentifier) -> Result<&'a Cert> {
+    certificates
+        .iter()
+        .find(|certificate| match signer_identifier {
+            SignerIdentifier::IssuerAndSerialNumber(issuer_and_serial_number) => {
+                issuer_and_serial_number.issuer == Name::from(certificate.issuer_name())
+                    && &issuer_and_serial_number.serial_number.0 == certificate.serial_number()
+            }
+            SignerIdentifier::SubjectKeyIdentifier(subject_key_identifier) => {
+                certificate.subject_key_identifier().ok() == Some(&subject_key_identifier.0 .0)
+            }
+        })
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::PkInitClientFailure,
+                "The KDC certificate is missing in the signed data".into(),
+            )
+        })
+}
+
+// builds the chain from the certificates of the signed data up to the trust anchor
+fn verify_certificate(certificate: &Cert, certificates: &[Cert], trust_anchors: &[Cert], now: &UtcDate) -> Result<()> {
+    let result = if trust_anchors.contains(certificate) {
+        certificate
+            .verifier::<std::slice::Iter<Cert>>()
+            .exact_date(now)
+            .ignore_chain_check()
+            .verify()
+    } else {
+        let mut chain = Vec::new();
+        let mut current = certificate;
+
+        while !trust_anchors.contains(current) {
+            if chain.len() == MAX_CHAIN_LEN {
+                return Err(untrusted_certificate("The certificate chain is too long"));
+            }
+
+            current = trust_anchors
+                .iter()
+                .chain(certificates)
+                .find(|issuer| *issuer != current && issuer.is_parent_of(current).is_ok())
+                .ok_or_else(|| untrusted_certificate("The issuer is not found"))?;
+            chain.push(current);
+        }
+
+        certificate.verifier().exact_date(now).chain(chain.into_iter()).verify()
+    };
+
+    result.map_err(|e| untrusted_certificate(&e.to_string()))
+}
+
+// the RSA signature algorithm may be specified without the digest algorithm
+fn signature_algorithm(
+    signature_algorithm: &AlgorithmIdentifier,
+    digest_algorithm: HashAlgorithm,
+) -> Result<SignatureAlgorithm> {
+    if signature_algorithm.oid() == &oids::rsa_encryption() {
+        return Ok(SignatureAlgorithm::RsaPkcs1v15(digest_algorithm));
+    }
+
+    SignatureAlgorithm::from_algorithm_identifier(signature_algorithm).map_err(|e| {
+        Error::new(
+            ErrorKind::PkInitClientFailure,
+            format!("Unsupported signature algorithm: {:?}", e),
+        )
+    })
+}
+
+fn hash_algorithm(digest_algorithm: &AlgorithmIdentifier) -> Result<HashAlgorithm> {
+    let digest_algorithm = digest_algorithm.oid();
+
+    if digest_algorithm == &oids::sha1() {
+        Ok(HashAlgorithm::SHA1)
+    } else if digest_algorithm == &oids::sha256() {
+        Ok(HashAlgorithm::SHA2_256)
+    } else if digest_algorithm == &oids::sha384() {
+        Ok(HashAlgorithm::SHA2_384)
+    } else if digest_algorithm == &oids::sha512() {
+        Ok(HashAlgorithm::SHA2_512)
+    } else {
+        Err(Error::new(
+            ErrorKind::PkInitClientFailure,
+            format!("Unsupported digest algorithm: {:?}", digest_algorithm),
+        ))
+    }
+}
+
+fn untrusted_certificate(description: &str) -> Error {
+    Error::new(
+        ErrorKind::PkInitClientFailure,
+        format!("The KDC certificate is not trusted: {}", description),
+    )
+}
+
+fn malformed(description: &str) -> Error {
+    Error::new(
+        ErrorKind::PkInitClientFailure,
+        format!("Malformed signed data: {}", description),
+    )
+}
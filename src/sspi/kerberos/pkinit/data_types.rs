@@ -0,0 +1,148 @@
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, BitStringAsn1, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3,
+    ImplicitContextTag0, IntegerAsn1, ObjectIdentifierAsn1, OctetStringAsn1, Optional,
+};
+use picky_asn1_x509::AlgorithmIdentifier;
+use picky_krb::data_types::{KerberosTime, PrincipalName, Realm};
+use serde::{Deserialize, Serialize};
+
+pub const PA_PK_AS_REQ: [u8; 1] = [0x10];
+pub const PA_PK_AS_REP: [u8; 1] = [0x11];
+
+/// [RFC 3279 2.3.3](https://www.rfc-editor.org/rfc/rfc3279#section-2.3.3)
+///
+/// ```not_rust
+/// DomainParameters ::= SEQUENCE {
+///         p       INTEGER, -- odd prime, p=jq +1
+///         g       INTEGER, -- generator, g
+///         q       INTEGER, -- factor of p-1
+///         j       INTEGER OPTIONAL, -- subgroup factor
+///         validationParms  ValidationParms OPTIONAL
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DhDomainParameters {
+    pub p: IntegerAsn1,
+    pub g: IntegerAsn1,
+    pub q: IntegerAsn1,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DhPublicKeyAlgorithm {
+    pub algorithm: ObjectIdentifierAsn1,
+    pub parameters: DhDomainParameters,
+}
+
+/// SubjectPublicKeyInfo with the DH public key: the subjectPublicKey contains the DER-encoded INTEGER
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DhSubjectPublicKeyInfo {
+    pub algorithm: DhPublicKeyAlgorithm,
+    pub subject_public_key: BitStringAsn1,
+}
+
+/// [RFC 4556 3.2.1](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.1)
+///
+/// ```not_rust
+/// PKAuthenticator ::= SEQUENCE {
+///         cusec                   [0] INTEGER (0..999999),
+///         ctime                   [1] KerberosTime,
+///         nonce                   [2] INTEGER (0..4294967295),
+///         paChecksum              [3] OCTET STRING OPTIONAL,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PkAuthenticator {
+    pub cusec: ExplicitContextTag0<IntegerAsn1>,
+    pub ctime: ExplicitContextTag1<KerberosTime>,
+    pub nonce: ExplicitContextTag2<IntegerAsn1>,
+    #[serde(default)]
+    pub pa_checksum: Optional<Option<ExplicitContextTag3<OctetStringAsn1>>>,
+}
+
+/// [RFC 4556 3.2.1](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.1)
+///
+/// ```not_rust
+/// AuthPack ::= SEQUENCE {
+///         pkAuthenticator         [0] PKAuthenticator,
+///         clientPublicValue       [1] SubjectPublicKeyInfo OPTIONAL,
+///         supportedCMSTypes       [2] SEQUENCE OF AlgorithmIdentifier OPTIONAL,
+///         clientDHNonce           [3] DHNonce OPTIONAL,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AuthPack {
+    pub pk_authenticator: ExplicitContextTag0<PkAuthenticator>,
+    #[serde(default)]
+    pub client_public_value: Optional<Option<ExplicitContextTag1<DhSubjectPublicKeyInfo>>>,
+    #[serde(default)]
+    pub supported_cms_types: Optional<Option<ExplicitContextTag2<Asn1SequenceOf<AlgorithmIdentifier>>>>,
+    #[serde(default)]
+    pub client_dh_nonce: Optional<Option<ExplicitContextTag3<OctetStringAsn1>>>,
+}
+
+/// [RFC 4556 3.2.1](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.1)
+///
+/// ```not_rust
+/// PA-PK-AS-REQ ::= SEQUENCE {
+///         signedAuthPack          [0] IMPLICIT OCTET STRING,
+///         trustedCertifiers       [1] SEQUENCE OF ExternalPrincipalIdentifier OPTIONAL,
+///         kdcPkId                 [2] IMPLICIT OCTET STRING OPTIONAL,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PaPkAsReq {
+    pub signed_auth_pack: ImplicitContextTag0<OctetStringAsn1>,
+}
+
+/// [RFC 4556 3.2.3](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.3)
+///
+/// ```not_rust
+/// DHRepInfo ::= SEQUENCE {
+///         dhSignedData            [0] IMPLICIT OCTET STRING,
+///         serverDHNonce           [1] DHNonce OPTIONAL,
+///         ...
+/// }
+/// ```
+///
+/// The PA-PK-AS-REP is the choice of `dhInfo [0] DHRepInfo` and `encKeyPack [1] IMPLICIT OCTET STRING`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DhRepInfo {
+    pub dh_signed_data: ImplicitContextTag0<OctetStringAsn1>,
+    #[serde(default)]
+    pub server_dh_nonce: Optional<Option<ExplicitContextTag1<OctetStringAsn1>>>,
+}
+
+/// [RFC 4556 3.2.3.1](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.3.1)
+///
+/// ```not_rust
+/// KDCDHKeyInfo ::= SEQUENCE {
+///         subjectPublicKey        [0] BIT STRING,
+///         nonce                   [1] INTEGER (0..4294967295),
+///         dhKeyExpiration         [2] KerberosTime OPTIONAL,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct KdcDhKeyInfo {
+    pub subject_public_key: ExplicitContextTag0<BitStringAsn1>,
+    pub nonce: ExplicitContextTag1<IntegerAsn1>,
+    #[serde(default)]
+    pub dh_key_expiration: Optional<Option<ExplicitContextTag2<KerberosTime>>>,
+}
+
+/// [RFC 4556 3.2.2](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.2)
+///
+/// ```not_rust
+/// KRB5PrincipalName ::= SEQUENCE {
+///         realm                   [0] Realm,
+///         principalName           [1] PrincipalName
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Krb5PrincipalName {
+    pub realm: ExplicitContextTag0<Realm>,
+    pub principal_name: ExplicitContextTag1<PrincipalName>,
+}
@@ -0,0 +1,115 @@
+//! Diffie-Hellman key agreement of PKINIT: [RFC 4556 3.2.3.1](https://www.rfc-editor.org/rfc/rfc4556#section-3.2.3.1)
+
+use num_bigint::BigUint;
+use oid::ObjectIdentifier;
+use picky_asn1::bit_string::BitString;
+use picky_asn1::wrapper::{BitStringAsn1, IntegerAsn1, ObjectIdentifierAsn1};
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha1::{Digest, Sha1};
+
+use super::data_types::{DhDomainParameters, DhPublicKeyAlgorithm, DhSubjectPublicKeyInfo};
+use crate::sspi::{Error, ErrorKind, Result};
+
+// dhpublicnumber: [RFC 3279 2.3.3](https://www.rfc-editor.org/rfc/rfc3279#section-2.3.3)
+const DH_PUBLIC_NUMBER_OID: &str = "1.2.840.10046.2.1";
+
+const GENERATOR: u32 = 2;
+// 2048-bit MODP group 14: [RFC 3526 3](https://www.rfc-editor.org/rfc/rfc3526#section-3)
+const PRIME: [u8; 256] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc9, 0x0f, 0xda, 0xa2, 0x21, 0x68, 0xc2, 0x34, 0xc4, 0xc6, 0x62,
+    0x8b, 0x80, 0xdc, 0x1c, 0xd1, 0x29, 0x02, 0x4e, 0x08, 0x8a, 0x67, 0xcc, 0x74, 0x02, 0x0b, 0xbe, 0xa6, 0x3b, 0x13,
+    0x9b, 0x22, 0x51, 0x4a, 0x08, 0x79, 0x8e, 0x34, 0x04, 0xdd, 0xef, 0x95, 0x19, 0xb3, 0xcd, 0x3a, 0x43, 0x1b, 0x30,
+    0x2b, 0x0a, 0x6d, 0xf2, 0x5f, 0x14, 0x37, 0x4f, 0xe1, 0x35, 0x6d, 0x6d, 0x51, 0xc2, 0x45, 0xe4, 0x85, 0xb5, 0x76,
+    0x62, 0x5e, 0x7e, 0xc6, 0xf4, 0x4c, 0x42, 0xe9, 0xa6, 0x37, 0xed, 0x6b, 0x0b, 0xff, 0x5c, 0xb6, 0xf4, 0x06, 0xb7,
+    0xed, 0xee, 0x38, 0x6b, 0xfb, 0x5a, 0x89, 0x9f, 0xa5, 0xae, 0x9f, 0x24, 0x11, 0x7c, 0x4b, 0x1f, 0xe6, 0x49, 0x28,
+    0x66, 0x51, 0xec, 0xe4, 0x5b, 0x3d, 0xc2, 0x00, 0x7c, 0xb8, 0xa1, 0x63, 0xbf, 0x05, 0x98, 0xda, 0x48, 0x36, 0x1c,
+    0x55, 0xd3, 0x9a, 0x69, 0x16, 0x3f, 0xa8, 0xfd, 0x24, 0xcf, 0x5f, 0x83, 0x65, 0x5d, 0x23, 0xdc, 0xa3, 0xad, 0x96,
+    0x1c, 0x62, 0xf3, 0x56, 0x20, 0x85, 0x52, 0xbb, 0x9e, 0xd5, 0x29, 0x07, 0x70, 0x96, 0x96, 0x6d, 0x67, 0x0c, 0x35,
+    0x4e, 0x4a, 0xbc, 0x98, 0x04, 0xf1, 0x74, 0x6c, 0x08, 0xca, 0x18, 0x21, 0x7c, 0x32, 0x90, 0x5e, 0x46, 0x2e, 0x36,
+    0xce, 0x3b, 0xe3, 0x9e, 0x77, 0x2c, 0x18, 0x0e, 0x86, 0x03, 0x9b, 0x27, 0x83, 0xa2, 0xec, 0x07, 0xa2, 0x8f, 0xb5,
+    0xc5, 0x5d, 0xf0, 0x6f, 0x4c, 0x52, 0xc9, 0xde, 0x2b, 0xcb, 0xf6, 0x95, 0x58, 0x17, 0x18, 0x39, 0x95, 0x49, 0x7c,
+    0xea, 0x95, 0x6a, 0xe5, 0x15, 0xd2, 0x26, 0x18, 0x98, 0xfa, 0x05, 0x10, 0x15, 0x72, 0x8e, 0x5a, 0x8a, 0xac, 0xaa,
+    0x68, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+const PRIVATE_KEY_LEN: usize = 64;
+
+pub struct DhKeyPair {
+    private_key: BigUint,
+    public_key: BigUint,
+}
+
+impl DhKeyPair {
+    pub fn generate() -> Result<Self> {
+        let mut private_key = [0; PRIVATE_KEY_LEN];
+        OsRng::new()?.fill(&mut private_key);
+
+        let private_key = BigUint::from_bytes_be(&private_key);
+        let public_key = BigUint::from(GENERATOR).modpow(&private_key, &prime());
+
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+
+    /// The clientPublicValue of the AuthPack
+    pub fn public_key_info(&self) -> Result<DhSubjectPublicKeyInfo> {
+        let prime = prime();
+        let public_key = IntegerAsn1::from_bytes_be_unsigned(self.public_key.to_bytes_be());
+
+        Ok(DhSubjectPublicKeyInfo {
+            algorithm: DhPublicKeyAlgorithm {
+                algorithm: ObjectIdentifierAsn1::from(ObjectIdentifier::try_from(DH_PUBLIC_NUMBER_OID).unwrap()),
+                parameters: DhDomainParameters {
+                    p: IntegerAsn1::from_bytes_be_unsigned(prime.to_bytes_be()),
+                    g: IntegerAsn1::from_bytes_be_unsigned(vec![GENERATOR as u8]),
+                    q: IntegerAsn1::from_bytes_be_unsigned(((prime - 1_u32) >> 1_u32).to_bytes_be()),
+                },
+            },
+            subject_public_key: BitStringAsn1::from(BitString::with_bytes(picky_asn1_der::to_vec(&public_key)?)),
+        })
+    }
+
+    /// DHSharedSecret padded to the size of the prime
+    pub fn shared_secret(&self, peer_public_key: &[u8]) -> Result<Vec<u8>> {
+        let prime = prime();
+        let peer_public_key = BigUint::from_bytes_be(peer_public_key);
+
+        if peer_public_key <= BigUint::from(1_u32) || peer_public_key >= &prime - 1_u32 {
+            return Err(Error::new(
+                ErrorKind::PkInitClientFailure,
+                "Invalid Diffie-Hellman public key of the KDC".into(),
+            ));
+        }
+
+        let secret = peer_public_key.modpow(&self.private_key, &prime).to_bytes_be();
+
+        let mut padded_secret = vec![0; PRIME.len() - secret.len()];
+        padded_secret.extend_from_slice(&secret);
+
+        Ok(padded_secret)
+    }
+}
+
+/// octetstring2key: the reply key of the AS-REP is derived from the DH shared secret
+pub fn octet_string_to_key(data: &[u8], key_len: usize) -> Vec<u8> {
+    let mut key = Vec::with_capacity(key_len + 20);
+
+    let mut counter = 0_u8;
+    while key.len() < key_len {
+        let mut sha1 = Sha1::new();
+        sha1.update([counter]);
+        sha1.update(data);
+        key.extend_from_slice(&sha1.finalize());
+
+        counter += 1;
+    }
+    key.truncate(key_len);
+
+    key
+}
+
+fn prime() -> BigUint {
+    BigUint::from_bytes_be(&PRIME)
+}
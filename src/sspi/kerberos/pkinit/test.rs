@@ -0,0 +1,588 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use num_bigint::BigUint;
+use oid::ObjectIdentifier;
+use picky::hash::HashAlgorithm;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, Asn1SetOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag10, ExplicitContextTag2,
+    ExplicitContextTag3, ExplicitContextTag4, ExplicitContextTag5, ExplicitContextTag6, ExplicitContextTag9,
+    ImplicitContextTag0, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_asn1_der::Asn1RawDer;
+use picky_asn1_x509::signed_data::CertificateChoices;
+use picky_asn1_x509::signer_info::SignatureValue;
+use picky_asn1_x509::{AlgorithmIdentifier, DigestInfo, Pkcs7Certificate, RsaPrivateKey, ShaVariant};
+use picky_krb::constants::types::{AS_REP_MSG_TYPE, KRB_ERROR_MSG_TYPE};
+use picky_krb::data_types::{EncryptedData, KerberosTime, PaData, Ticket, TicketInner};
+use picky_krb::messages::{AsRep, KdcRep, KrbError, KrbErrorInner};
+use sha1::{Digest, Sha1};
+
+use super::data_types::{AuthPack, DhRepInfo, KdcDhKeyInfo, PaPkAsReq, PA_PK_AS_REP};
+use super::{
+    cms, dh, extract_reply_key, generate_pa_pk_as_req, pk_init_error, DhKeyPair, PkInitCredentials, PrivateKeySigner,
+    AUTH_DATA_CONTENT_TYPE, DH_KEY_DATA_CONTENT_TYPE,
+};
+use crate::sspi::kerberos::client::generators::generate_as_req_body;
+use crate::sspi::kerberos::encryption_params::EncryptionParams;
+use crate::sspi::kerberos::utils::integer_to_u32;
+use crate::sspi::kerberos::{Principal, AES256_CTS_HMAC_SHA1_96, KERBEROS_VERSION};
+use crate::sspi::ErrorKind;
+
+// self-signed "CN=Example CA"
+const CA_CERTIFICATE: [u8; 338] = [
+    0x30, 0x82, 0x01, 0x4e, 0x30, 0x81, 0xf9, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01, 0x30, 0x0d, 0x06, 0x09,
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x30, 0x15, 0x31, 0x13, 0x30, 0x11, 0x06, 0x03,
+    0x55, 0x04, 0x03, 0x0c, 0x0a, 0x45, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x20, 0x43, 0x41, 0x30, 0x20, 0x17, 0x0d,
+    0x32, 0x30, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x32, 0x31, 0x32, 0x30,
+    0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x30, 0x15, 0x31, 0x13, 0x30, 0x11, 0x06, 0x03,
+    0x55, 0x04, 0x03, 0x0c, 0x0a, 0x45, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x20, 0x43, 0x41, 0x30, 0x5c, 0x30, 0x0d,
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00, 0x03, 0x4b, 0x00, 0x30, 0x48, 0x02,
+    0x41, 0x00, 0xc4, 0x51, 0xbc, 0xbb, 0x9c, 0x40, 0x0f, 0x7c, 0xbf, 0xdf, 0xd1, 0x45, 0x1f, 0x73, 0x5b, 0x87, 0x82,
+    0x39, 0xfa, 0x86, 0x7b, 0xcb, 0xad, 0x0c, 0x6d, 0xbe, 0x3b, 0xbe, 0x19, 0xfd, 0xb6, 0x7a, 0x0a, 0x86, 0x5f, 0x12,
+    0xc1, 0x20, 0xe4, 0xf8, 0x14, 0xbe, 0xbd, 0x1d, 0xfa, 0x40, 0x97, 0xf1, 0x1d, 0x61, 0x5a, 0x3b, 0xc4, 0xf7, 0xe3,
+    0x7e, 0x11, 0x92, 0x1c, 0xca, 0xbd, 0x25, 0x35, 0x31, 0x02, 0x03, 0x01, 0x00, 0x01, 0xa3, 0x32, 0x30, 0x30, 0x30,
+    0x0f, 0x06, 0x03, 0x55, 0x1d, 0x13, 0x01, 0x01, 0xff, 0x04, 0x05, 0x30, 0x03, 0x01, 0x01, 0xff, 0x30, 0x1d, 0x06,
+    0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0xc9, 0xb5, 0x9b, 0xd5, 0xa2, 0x27, 0xcf, 0x7c, 0x32, 0xbe, 0xec,
+    0xc2, 0x3a, 0x4b, 0x59, 0xad, 0x99, 0x8c, 0xaa, 0xe4, 0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d,
+    0x01, 0x01, 0x0b, 0x05, 0x00, 0x03, 0x41, 0x00, 0x70, 0x9b, 0xa0, 0xbf, 0x53, 0x4f, 0x0e, 0xd3, 0x30, 0x3f, 0x3e,
+    0xce, 0x91, 0x5e, 0xd7, 0x9b, 0x7e, 0x29, 0xed, 0x6e, 0x2c, 0x12, 0xe9, 0x8b, 0xcc, 0x09, 0xb9, 0x9b, 0xb3, 0x42,
+    0xa3, 0x8f, 0x47, 0x61, 0x7f, 0x25, 0x88, 0x8e, 0x18, 0x84, 0x7c, 0x62, 0xb0, 0x4b, 0xe7, 0x04, 0x16, 0x63, 0xa2,
+    0x46, 0x7d, 0xa4, 0x1f, 0x32, 0x86, 0xd2, 0x9b, 0xeb, 0x59, 0xea, 0x62, 0x61, 0xf6, 0x06,
+];
+
+// "CN=Example Intermediate CA" issued by the CA
+const INTERMEDIATE_CA_CERTIFICATE: [u8; 385] = [
+    0x30, 0x82, 0x01, 0x7d, 0x30, 0x82, 0x01, 0x27, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x02, 0x30, 0x0d, 0x06,
+    0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x30, 0x15, 0x31, 0x13, 0x30, 0x11, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x0a, 0x45, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x20, 0x43, 0x41, 0x30, 0x20, 0x17,
+    0x0d, 0x32, 0x30, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x32, 0x31, 0x32,
+    0x30, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x30, 0x22, 0x31, 0x20, 0x30, 0x1e, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x17, 0x45, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x20, 0x49, 0x6e, 0x74, 0x65, 0x72,
+    0x6d, 0x65, 0x64, 0x69, 0x61, 0x74, 0x65, 0x20, 0x43, 0x41, 0x30, 0x5c, 0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48,
+    0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00, 0x03, 0x4b, 0x00, 0x30, 0x48, 0x02, 0x41, 0x00, 0xbf, 0x86, 0x4c,
+    0x31, 0x55, 0x88, 0x32, 0x03, 0xd3, 0x0a, 0xc0, 0x1f, 0xe2, 0x69, 0xf2, 0xdc, 0xc9, 0xd0, 0x8c, 0x43, 0xda, 0xe3,
+    0xda, 0x19, 0x95, 0x5d, 0x3e, 0x1e, 0x61, 0x84, 0xaf, 0x7a, 0x69, 0xc8, 0xce, 0xdc, 0xc5, 0x5d, 0x0f, 0x1f, 0x50,
+    0x7e, 0x6e, 0x28, 0x00, 0x82, 0x11, 0x01, 0x0d, 0x65, 0xdb, 0x5f, 0xcd, 0x9e, 0xb3, 0xba, 0x7f, 0x42, 0xb8, 0x34,
+    0x30, 0x10, 0x33, 0x33, 0x02, 0x03, 0x01, 0x00, 0x01, 0xa3, 0x53, 0x30, 0x51, 0x30, 0x0f, 0x06, 0x03, 0x55, 0x1d,
+    0x13, 0x01, 0x01, 0xff, 0x04, 0x05, 0x30, 0x03, 0x01, 0x01, 0xff, 0x30, 0x1d, 0x06, 0x03, 0x55, 0x1d, 0x0e, 0x04,
+    0x16, 0x04, 0x14, 0x24, 0x63, 0xfb, 0x11, 0xc8, 0x68, 0x47, 0x9e, 0xc7, 0xc8, 0x46, 0xeb, 0x0a, 0xdc, 0x50, 0xfd,
+    0xf5, 0x60, 0x92, 0xcf, 0x30, 0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0xc9, 0xb5,
+    0x9b, 0xd5, 0xa2, 0x27, 0xcf, 0x7c, 0x32, 0xbe, 0xec, 0xc2, 0x3a, 0x4b, 0x59, 0xad, 0x99, 0x8c, 0xaa, 0xe4, 0x30,
+    0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x03, 0x41, 0x00, 0x94, 0x4f,
+    0x41, 0x59, 0xe7, 0xd0, 0x0d, 0x6e, 0xc3, 0xc9, 0x55, 0x08, 0x2d, 0xf6, 0xad, 0x5a, 0xbb, 0x7e, 0xfa, 0xe5, 0xe6,
+    0x33, 0x46, 0xcb, 0x8a, 0x02, 0x27, 0x5f, 0x26, 0x54, 0x2c, 0xde, 0xa4, 0x7a, 0x71, 0x2f, 0xd4, 0x31, 0x40, 0x8b,
+    0x7e, 0x9d, 0xf3, 0xaf, 0x00, 0x15, 0x61, 0x87, 0xd3, 0x3a, 0x76, 0x9c, 0x50, 0x2e, 0x60, 0x98, 0x6e, 0xee, 0xfb,
+    0x4c, 0xcf, 0x95, 0x8e, 0x04,
+];
+
+// "CN=kdc.example.com" issued by the intermediate CA with the id-pkinit-KPKdc key purpose and the
+// krbtgt/EXAMPLE.COM@EXAMPLE.COM krb5PrincipalName
+const KDC_CERTIFICATE: [u8; 480] = [
+    0x30, 0x82, 0x01, 0xdc, 0x30, 0x82, 0x01, 0x86, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x03, 0x30, 0x0d, 0x06,
+    0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x30, 0x22, 0x31, 0x20, 0x30, 0x1e, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x17, 0x45, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x20, 0x49, 0x6e, 0x74, 0x65, 0x72,
+    0x6d, 0x65, 0x64, 0x69, 0x61, 0x74, 0x65, 0x20, 0x43, 0x41, 0x30, 0x20, 0x17, 0x0d, 0x32, 0x30, 0x30, 0x31, 0x30,
+    0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x32, 0x31, 0x32, 0x30, 0x30, 0x31, 0x30, 0x31, 0x30,
+    0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x30, 0x1a, 0x31, 0x18, 0x30, 0x16, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x0f,
+    0x6b, 0x64, 0x63, 0x2e, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d, 0x30, 0x5c, 0x30, 0x0d,
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00, 0x03, 0x4b, 0x00, 0x30, 0x48, 0x02,
+    0x41, 0x00, 0xb4, 0x3e, 0xc1, 0x50, 0xcb, 0xa3, 0x29, 0xaf, 0x02, 0x2e, 0xaa, 0x17, 0xca, 0xbf, 0x0c, 0x0d, 0xa9,
+    0xb4, 0xcd, 0x92, 0x26, 0x31, 0xdb, 0x48, 0xa2, 0xe6, 0xfb, 0x19, 0x9f, 0x43, 0x04, 0x77, 0x38, 0x82, 0xe8, 0x34,
+    0xa0, 0xca, 0x9c, 0x43, 0x16, 0x35, 0x74, 0x12, 0x89, 0xb3, 0xae, 0xb1, 0x5c, 0x2e, 0x95, 0x97, 0x27, 0x55, 0x89,
+    0x8c, 0x14, 0xbb, 0x14, 0xc2, 0x8b, 0xfc, 0xd6, 0x79, 0x02, 0x03, 0x01, 0x00, 0x01, 0xa3, 0x81, 0xac, 0x30, 0x81,
+    0xa9, 0x30, 0x09, 0x06, 0x03, 0x55, 0x1d, 0x13, 0x04, 0x02, 0x30, 0x00, 0x30, 0x1d, 0x06, 0x03, 0x55, 0x1d, 0x0e,
+    0x04, 0x16, 0x04, 0x14, 0x99, 0xea, 0xb4, 0x94, 0xf8, 0x6f, 0x59, 0x82, 0xe0, 0xc5, 0x6d, 0x70, 0xe5, 0x91, 0x39,
+    0xb9, 0x9c, 0x69, 0x49, 0x64, 0x30, 0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0x24,
+    0x63, 0xfb, 0x11, 0xc8, 0x68, 0x47, 0x9e, 0xc7, 0xc8, 0x46, 0xeb, 0x0a, 0xdc, 0x50, 0xfd, 0xf5, 0x60, 0x92, 0xcf,
+    0x30, 0x12, 0x06, 0x03, 0x55, 0x1d, 0x25, 0x04, 0x0b, 0x30, 0x09, 0x06, 0x07, 0x2b, 0x06, 0x01, 0x05, 0x02, 0x03,
+    0x05, 0x30, 0x48, 0x06, 0x03, 0x55, 0x1d, 0x11, 0x04, 0x41, 0x30, 0x3f, 0xa0, 0x3d, 0x06, 0x06, 0x2b, 0x06, 0x01,
+    0x05, 0x02, 0x02, 0xa0, 0x33, 0x30, 0x31, 0xa0, 0x0d, 0x1b, 0x0b, 0x45, 0x58, 0x41, 0x4d, 0x50, 0x4c, 0x45, 0x2e,
+    0x43, 0x4f, 0x4d, 0xa1, 0x20, 0x30, 0x1e, 0xa0, 0x03, 0x02, 0x01, 0x02, 0xa1, 0x17, 0x30, 0x15, 0x1b, 0x06, 0x6b,
+    0x72, 0x62, 0x74, 0x67, 0x74, 0x1b, 0x0b, 0x45, 0x58, 0x41, 0x4d, 0x50, 0x4c, 0x45, 0x2e, 0x43, 0x4f, 0x4d, 0x30,
+    0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x03, 0x41, 0x00, 0x9d, 0x01,
+    0xcc, 0xf9, 0x14, 0x20, 0x63, 0x51, 0x8c, 0x06, 0xaa, 0x44, 0x34, 0x22, 0x7d, 0x83, 0xbf, 0x19, 0x32, 0x22, 0x1c,
+    0x18, 0x76, 0x25, 0x83, 0x12, 0xc4, 0xa7, 0x0e, 0x6f, 0xd3, 0x95, 0xba, 0x24, 0xcd, 0x4c, 0xbc, 0xba, 0x67, 0x04,
+    0x14, 0x04, 0x43, 0x4a, 0xcb, 0x5c, 0x6a, 0xef, 0x2a, 0x03, 0x32, 0xf0, 0x41, 0x19, 0x1b, 0x41, 0x62, 0xda, 0x8d,
+    0xc7, 0xd3, 0x1b, 0x0f, 0x16,
+];
+
+// PKCS#1
+const KDC_PRIVATE_KEY: [u8; 317] = [
+    0x30, 0x82, 0x01, 0x39, 0x02, 0x01, 0x00, 0x02, 0x41, 0x00, 0xb4, 0x3e, 0xc1, 0x50, 0xcb, 0xa3, 0x29, 0xaf, 0x02,
+    0x2e, 0xaa, 0x17, 0xca, 0xbf, 0x0c, 0x0d, 0xa9, 0xb4, 0xcd, 0x92, 0x26, 0x31, 0xdb, 0x48, 0xa2, 0xe6, 0xfb, 0x19,
+    0x9f, 0x43, 0x04, 0x77, 0x38, 0x82, 0xe8, 0x34, 0xa0, 0xca, 0x9c, 0x43, 0x16, 0x35, 0x74, 0x12, 0x89, 0xb3, 0xae,
+    0xb1, 0x5c, 0x2e, 0x95, 0x97, 0x27, 0x55, 0x89, 0x8c, 0x14, 0xbb, 0x14, 0xc2, 0x8b, 0xfc, 0xd6, 0x79, 0x02, 0x03,
+    0x01, 0x00, 0x01, 0x02, 0x40, 0x5f, 0x61, 0x35, 0xfb, 0x03, 0x47, 0x7c, 0x2f, 0x58, 0x00, 0x49, 0x91, 0xfd, 0x7f,
+    0xcb, 0x69, 0x0f, 0x7c, 0xd5, 0xab, 0x98, 0xfa, 0x99, 0x07, 0x7e, 0xec, 0xab, 0xc3, 0x8b, 0x3b, 0x73, 0x88, 0x9f,
+    0x77, 0xa6, 0xbb, 0x77, 0x52, 0xc7, 0xa0, 0x55, 0x4a, 0x7b, 0x56, 0xfc, 0x96, 0x73, 0x03, 0x30, 0xb7, 0xfc, 0x09,
+    0x87, 0x0c, 0xfe, 0x6a, 0xb8, 0x5a, 0x85, 0x93, 0x05, 0x4c, 0x62, 0xe1, 0x02, 0x21, 0x00, 0xe2, 0xea, 0xd0, 0xb1,
+    0x19, 0xf4, 0xb1, 0xc9, 0xb3, 0xb4, 0x03, 0x17, 0x54, 0x53, 0x64, 0x5c, 0x2f, 0x28, 0xa1, 0xbb, 0xa2, 0x3d, 0xb5,
+    0x68, 0x80, 0x78, 0x9b, 0x00, 0x6d, 0x63, 0xe8, 0x1b, 0x02, 0x21, 0x00, 0xcb, 0x58, 0x9f, 0x32, 0x84, 0xc8, 0x1a,
+    0xe0, 0x29, 0x70, 0x40, 0x7c, 0xa7, 0xe6, 0xb3, 0x60, 0xc8, 0x31, 0xbc, 0x55, 0xdb, 0x80, 0x13, 0xfa, 0x8b, 0xa8,
+    0x7a, 0x3f, 0xb4, 0xe5, 0x0c, 0xfb, 0x02, 0x20, 0x38, 0xba, 0x7b, 0x71, 0xcb, 0x0b, 0x61, 0x67, 0x0b, 0x85, 0xf5,
+    0x3f, 0xdf, 0xd4, 0xf9, 0x42, 0x12, 0x88, 0x15, 0xe6, 0xd2, 0xa8, 0x9a, 0xb1, 0x85, 0x6c, 0xa1, 0x53, 0x7a, 0x05,
+    0x80, 0x01, 0x02, 0x20, 0x17, 0xb9, 0x49, 0x29, 0x7d, 0xb0, 0x52, 0xe2, 0x60, 0x33, 0x46, 0x72, 0xc4, 0xab, 0xf7,
+    0xb5, 0x8c, 0xe5, 0xb0, 0x91, 0x5b, 0xc0, 0x7f, 0x94, 0x35, 0x82, 0xfc, 0x70, 0x3e, 0x4c, 0x92, 0xcd, 0x02, 0x20,
+    0x34, 0x3d, 0x40, 0x49, 0x82, 0x78, 0x86, 0xbe, 0xc1, 0xb0, 0xd5, 0x7e, 0xf8, 0x41, 0x9d, 0x96, 0xe6, 0x6e, 0xc6,
+    0x0c, 0x3c, 0x00, 0x3b, 0x92, 0xdb, 0x43, 0x05, 0x2d, 0x4f, 0x42, 0xb8, 0xd4,
+];
+
+// "CN=user" issued by the CA
+const CLIENT_CERTIFICATE: [u8; 381] = [
+    0x30, 0x82, 0x01, 0x79, 0x30, 0x82, 0x01, 0x23, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x04, 0x30, 0x0d, 0x06,
+    0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x30, 0x15, 0x31, 0x13, 0x30, 0x11, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x0a, 0x45, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x20, 0x43, 0x41, 0x30, 0x20, 0x17,
+    0x0d, 0x32, 0x30, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x32, 0x31, 0x32,
+    0x30, 0x30, 0x31, 0x30, 0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x04, 0x75, 0x73, 0x65, 0x72, 0x30, 0x5c, 0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48,
+    0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00, 0x03, 0x4b, 0x00, 0x30, 0x48, 0x02, 0x41, 0x00, 0xc1, 0xfd, 0x06,
+    0x2e, 0x46, 0x65, 0xba, 0x86, 0x71, 0x86, 0x99, 0x00, 0x93, 0xfc, 0x65, 0x7e, 0x16, 0x9b, 0x20, 0xfd, 0x98, 0x1b,
+    0xfe, 0x94, 0xa6, 0x52, 0xa3, 0xd6, 0xc0, 0x5b, 0x96, 0x16, 0xc1, 0xa1, 0x5d, 0xee, 0x80, 0x7c, 0x9f, 0x65, 0x28,
+    0x05, 0xf4, 0x15, 0xca, 0x0b, 0x81, 0xb6, 0xb4, 0xdb, 0x0b, 0x3c, 0x82, 0x88, 0x9d, 0x09, 0x3f, 0x7f, 0x81, 0x1f,
+    0x56, 0x39, 0xbe, 0xef, 0x02, 0x03, 0x01, 0x00, 0x01, 0xa3, 0x62, 0x30, 0x60, 0x30, 0x09, 0x06, 0x03, 0x55, 0x1d,
+    0x13, 0x04, 0x02, 0x30, 0x00, 0x30, 0x1d, 0x06, 0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0x1f, 0x9f, 0x50,
+    0xff, 0xda, 0x71, 0xd9, 0x02, 0x80, 0xf5, 0x3d, 0xe8, 0x45, 0xe9, 0x42, 0x56, 0xf9, 0x20, 0xd8, 0xd5, 0x30, 0x1f,
+    0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0xc9, 0xb5, 0x9b, 0xd5, 0xa2, 0x27, 0xcf, 0x7c,
+    0x32, 0xbe, 0xec, 0xc2, 0x3a, 0x4b, 0x59, 0xad, 0x99, 0x8c, 0xaa, 0xe4, 0x30, 0x13, 0x06, 0x03, 0x55, 0x1d, 0x25,
+    0x04, 0x0c, 0x30, 0x0a, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02, 0x30, 0x0d, 0x06, 0x09, 0x2a,
+    0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x03, 0x41, 0x00, 0x98, 0x2d, 0x2f, 0x0d, 0x90, 0x81,
+    0xc3, 0xa6, 0xee, 0xed, 0x9c, 0x17, 0xc9, 0x11, 0xdd, 0xba, 0x15, 0x29, 0x60, 0xea, 0x1f, 0xae, 0xae, 0xec, 0xbf,
+    0x51, 0xa7, 0x9c, 0xf3, 0x08, 0xdc, 0x60, 0xa8, 0xc8, 0xc5, 0x30, 0x75, 0x20, 0xdc, 0xe5, 0x70, 0x49, 0x25, 0xd6,
+    0x5e, 0xd7, 0x16, 0x14, 0xbc, 0x1e, 0x27, 0x5d, 0x86, 0xe4, 0x22, 0x6d, 0xf8, 0x15, 0xbd, 0x5c, 0x8b, 0xdf, 0x8d,
+    0x4b,
+];
+
+// PKCS#8
+const CLIENT_PRIVATE_KEY: [u8; 345] = [
+    0x30, 0x82, 0x01, 0x55, 0x02, 0x01, 0x00, 0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01,
+    0x01, 0x05, 0x00, 0x04, 0x82, 0x01, 0x3f, 0x30, 0x82, 0x01, 0x3b, 0x02, 0x01, 0x00, 0x02, 0x41, 0x00, 0xc1, 0xfd,
+    0x06, 0x2e, 0x46, 0x65, 0xba, 0x86, 0x71, 0x86, 0x99, 0x00, 0x93, 0xfc, 0x65, 0x7e, 0x16, 0x9b, 0x20, 0xfd, 0x98,
+    0x1b, 0xfe, 0x94, 0xa6, 0x52, 0xa3, 0xd6, 0xc0, 0x5b, 0x96, 0x16, 0xc1, 0xa1, 0x5d, 0xee, 0x80, 0x7c, 0x9f, 0x65,
+    0x28, 0x05, 0xf4, 0x15, 0xca, 0x0b, 0x81, 0xb6, 0xb4, 0xdb, 0x0b, 0x3c, 0x82, 0x88, 0x9d, 0x09, 0x3f, 0x7f, 0x81,
+    0x1f, 0x56, 0x39, 0xbe, 0xef, 0x02, 0x03, 0x01, 0x00, 0x01, 0x02, 0x41, 0x00, 0xa5, 0xc6, 0x19, 0x33, 0x10, 0x5b,
+    0xbc, 0xc3, 0x49, 0x5e, 0xb1, 0x7f, 0x89, 0xf7, 0x45, 0xb0, 0x33, 0x97, 0x4b, 0xe1, 0x0a, 0xcc, 0xd9, 0x88, 0xa4,
+    0x7d, 0xb8, 0x9f, 0x3b, 0x70, 0xc8, 0xd7, 0x99, 0xbd, 0xeb, 0x6c, 0x9c, 0x59, 0x3a, 0x5a, 0xbf, 0x2d, 0x3a, 0xa4,
+    0x4e, 0x7c, 0xd6, 0xe8, 0xb1, 0x34, 0x58, 0xda, 0x69, 0x32, 0xe7, 0xd9, 0x84, 0xdd, 0x73, 0xd8, 0xde, 0x30, 0xfc,
+    0xd1, 0x02, 0x21, 0x00, 0xef, 0x0e, 0x20, 0x19, 0xe6, 0xd6, 0xaf, 0x73, 0xe8, 0xba, 0xad, 0x0c, 0xa9, 0x55, 0x1b,
+    0x95, 0x4b, 0x06, 0x2f, 0x9b, 0xf9, 0xa0, 0x6c, 0x08, 0x56, 0x7e, 0xce, 0x6c, 0xf5, 0x2a, 0x90, 0x47, 0x02, 0x21,
+    0x00, 0xcf, 0xbd, 0x1e, 0xd6, 0x26, 0x9b, 0x01, 0x37, 0x64, 0x46, 0x12, 0x69, 0x5f, 0xe5, 0xa4, 0xa8, 0xde, 0xd9,
+    0x73, 0x97, 0x7e, 0x3a, 0x47, 0x0f, 0xb5, 0x36, 0x93, 0xf5, 0xb4, 0x9d, 0x18, 0x19, 0x02, 0x20, 0x73, 0x29, 0xc6,
+    0xe5, 0x33, 0x83, 0xb1, 0x3a, 0x91, 0x9f, 0x7c, 0xc6, 0x60, 0xbd, 0x13, 0x70, 0xb4, 0x97, 0xe2, 0x4f, 0xad, 0xce,
+    0x4e, 0xbd, 0x25, 0x8e, 0x29, 0x3b, 0xec, 0x4f, 0xf8, 0xf9, 0x02, 0x20, 0x15, 0x80, 0xfe, 0x30, 0x2c, 0xce, 0xde,
+    0xd1, 0x5d, 0x0f, 0xe3, 0xd8, 0x04, 0x95, 0x40, 0xf6, 0x3a, 0x1b, 0x48, 0x5b, 0xe6, 0x35, 0x22, 0x25, 0xfe, 0x1c,
+    0x28, 0x34, 0xc6, 0xfd, 0x7c, 0x01, 0x02, 0x21, 0x00, 0x9f, 0xa7, 0xd7, 0xbb, 0x4c, 0x74, 0x08, 0xfc, 0x2e, 0xfd,
+    0xdb, 0xed, 0x88, 0x36, 0x0f, 0x4a, 0x22, 0x32, 0xff, 0x4f, 0x6a, 0x3a, 0xa6, 0xca, 0x91, 0xde, 0x66, 0x05, 0x6b,
+    0x23, 0xab, 0xa8,
+];
+
+const CONTENT: &[u8] = b"PKINIT signed content";
+
+fn kdc_signer() -> PrivateKeySigner {
+    PrivateKeySigner::new(KDC_CERTIFICATE.to_vec(), &KDC_PRIVATE_KEY).unwrap()
+}
+
+fn client_signer() -> PrivateKeySigner {
+    PrivateKeySigner::new(CLIENT_CERTIFICATE.to_vec(), &CLIENT_PRIVATE_KEY).unwrap()
+}
+
+fn content_type(content_type: &str) -> ObjectIdentifier {
+    ObjectIdentifier::try_from(content_type).unwrap()
+}
+
+fn decode_signed_data(signed_data: &[u8]) -> Pkcs7Certificate {
+    picky_asn1_der::from_bytes(signed_data).unwrap()
+}
+
+// signed data with the certificate of the intermediate CA
+fn sign_with_chain(signer: &PrivateKeySigner, content_type: &ObjectIdentifier, content: &[u8]) -> Vec<u8> {
+    let mut signed_data = decode_signed_data(&cms::sign(signer, content_type, content).unwrap());
+    signed_data
+        .signed_data
+        .0
+        .certificates
+        .0
+         .0
+        .push(CertificateChoices::Certificate(Asn1RawDer(
+            INTERMEDIATE_CA_CERTIFICATE.to_vec(),
+        )));
+
+    picky_asn1_der::to_vec(&signed_data).unwrap()
+}
+
+fn verify(signed_data: &[u8], content_type_oid: &str, trust_anchor: &[u8]) -> crate::sspi::Result<Vec<u8>> {
+    cms::verify(
+        signed_data,
+        &content_type(content_type_oid),
+        &[trust_anchor.to_vec()],
+        Utc::now(),
+    )
+    .map(|(content, _)| content)
+}
+
+fn dh_public_key(key_pair: &DhKeyPair) -> Vec<u8> {
+    let public_key_info = key_pair.public_key_info().unwrap();
+    let public_key: IntegerAsn1 =
+        picky_asn1_der::from_bytes(public_key_info.subject_public_key.0.payload_view()).unwrap();
+
+    public_key.as_unsigned_bytes_be().to_vec()
+}
+
+fn krb_error(error_code: u8) -> KrbError {
+    let principal = Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM");
+
+    KrbError::from(KrbErrorInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![KRB_ERROR_MSG_TYPE])),
+        ctime: Optional::from(None),
+        cusec: Optional::from(None),
+        stime: ExplicitContextTag4::from(KerberosTime::from(GeneralizedTime::from(Utc::now()))),
+        susec: ExplicitContextTag5::from(IntegerAsn1::from(vec![0])),
+        error_code: ExplicitContextTag6::from(IntegerAsn1::from(vec![error_code])),
+        crealm: Optional::from(None),
+        cname: Optional::from(None),
+        realm: ExplicitContextTag9::from(principal.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag10::from(principal.principal_name().unwrap()),
+        e_text: Optional::from(None),
+        e_data: Optional::from(None),
+    })
+}
+
+fn encrypted_data() -> EncryptedData {
+    EncryptedData {
+        etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+        kvno: Optional::from(None),
+        cipher: ExplicitContextTag2::from(OctetStringAsn1::from(vec![0; 16])),
+    }
+}
+
+// AS-REP of the KDC that verifies the PA-PK-AS-REQ and signs its own DH public key with the certificate
+fn as_rep_with_pa_pk_as_rep(pa_pk_as_req: &PaData, kdc_dh_key_pair: &DhKeyPair, signer: &PrivateKeySigner) -> AsRep {
+    let pa_pk_as_req: PaPkAsReq = picky_asn1_der::from_bytes(&pa_pk_as_req.padata_data.0 .0).unwrap();
+    let auth_pack = verify(
+        &pa_pk_as_req.signed_auth_pack.0 .0,
+        AUTH_DATA_CONTENT_TYPE,
+        &CA_CERTIFICATE,
+    )
+    .unwrap();
+    let auth_pack: AuthPack = picky_asn1_der::from_bytes(&auth_pack).unwrap();
+
+    let kdc_dh_key_info = KdcDhKeyInfo {
+        subject_public_key: ExplicitContextTag0::from(kdc_dh_key_pair.public_key_info().unwrap().subject_public_key),
+        nonce: ExplicitContextTag1::from(auth_pack.pk_authenticator.0.nonce.0),
+        dh_key_expiration: Optional::from(None),
+    };
+    let dh_signed_data = sign_with_chain(
+        signer,
+        &content_type(DH_KEY_DATA_CONTENT_TYPE),
+        &picky_asn1_der::to_vec(&kdc_dh_key_info).unwrap(),
+    );
+
+    let dh_rep_info = picky_asn1_der::to_vec(&ExplicitContextTag0::from(DhRepInfo {
+        dh_signed_data: ImplicitContextTag0::from(OctetStringAsn1::from(dh_signed_data)),
+        server_dh_nonce: Optional::from(None),
+    }))
+    .unwrap();
+
+    let principal = Principal::new("user", "EXAMPLE.COM");
+    let tgs_principal = Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM");
+
+    AsRep::from(KdcRep {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![AS_REP_MSG_TYPE])),
+        padata: Optional::from(Some(ExplicitContextTag2::from(Asn1SequenceOf::from(vec![PaData {
+            padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_PK_AS_REP.to_vec())),
+            padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(dh_rep_info)),
+        }])))),
+        crealm: ExplicitContextTag3::from(principal.kerberos_realm().unwrap()),
+        cname: ExplicitContextTag4::from(principal.principal_name().unwrap()),
+        ticket: ExplicitContextTag5::from(Ticket::from(TicketInner {
+            tkt_vno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+            realm: ExplicitContextTag1::from(tgs_principal.kerberos_realm().unwrap()),
+            sname: ExplicitContextTag2::from(tgs_principal.principal_name().unwrap()),
+            enc_part: ExplicitContextTag3::from(encrypted_data()),
+        })),
+        enc_part: ExplicitContextTag6::from(encrypted_data()),
+    })
+}
+
+// AS-REP of the KDC for the PA-PK-AS-REQ of the generated request. Returns the nonce of the request
+fn exchange(client_dh_key_pair: &DhKeyPair, kdc_dh_key_pair: &DhKeyPair, signer: &PrivateKeySigner) -> (AsRep, u32) {
+    let req_body = generate_as_req_body("user", "EXAMPLE.COM", &EncryptionParams::default_for_client()).unwrap();
+
+    let pa_pk_as_req = generate_pa_pk_as_req(&client_signer(), &req_body, client_dh_key_pair, Utc::now()).unwrap();
+
+    (
+        as_rep_with_pa_pk_as_rep(&pa_pk_as_req, kdc_dh_key_pair, signer),
+        integer_to_u32(&req_body.nonce.0),
+    )
+}
+
+#[test]
+fn signed_data_is_verified_through_intermediate_ca() {
+    let signed_data = sign_with_chain(&kdc_signer(), &content_type(DH_KEY_DATA_CONTENT_TYPE), CONTENT);
+
+    let content = verify(&signed_data, DH_KEY_DATA_CONTENT_TYPE, &CA_CERTIFICATE).unwrap();
+
+    assert_eq!(CONTENT, content.as_slice());
+}
+
+#[test]
+fn signed_data_without_intermediate_ca_is_rejected() {
+    let signed_data = cms::sign(&kdc_signer(), &content_type(DH_KEY_DATA_CONTENT_TYPE), CONTENT).unwrap();
+
+    let error = verify(&signed_data, DH_KEY_DATA_CONTENT_TYPE, &CA_CERTIFICATE).unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn signed_data_is_verified_with_trusted_signer_certificate() {
+    let signed_data = cms::sign(&client_signer(), &content_type(AUTH_DATA_CONTENT_TYPE), CONTENT).unwrap();
+
+    let content = verify(&signed_data, AUTH_DATA_CONTENT_TYPE, &CLIENT_CERTIFICATE).unwrap();
+
+    assert_eq!(CONTENT, content.as_slice());
+}
+
+#[test]
+fn signed_data_of_untrusted_certificate_is_rejected() {
+    let signed_data = sign_with_chain(&kdc_signer(), &content_type(DH_KEY_DATA_CONTENT_TYPE), CONTENT);
+
+    let error = verify(&signed_data, DH_KEY_DATA_CONTENT_TYPE, &CLIENT_CERTIFICATE).unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn altered_signed_data_is_rejected() {
+    let mut signed_data = sign_with_chain(&kdc_signer(), &content_type(DH_KEY_DATA_CONTENT_TYPE), CONTENT);
+    let content_position = signed_data
+        .windows(CONTENT.len())
+        .position(|window| window == CONTENT)
+        .unwrap();
+    signed_data[content_position] ^= 0x01;
+
+    let error = verify(&signed_data, DH_KEY_DATA_CONTENT_TYPE, &CA_CERTIFICATE).unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn signed_data_of_other_content_type_is_rejected() {
+    let signed_data = sign_with_chain(&kdc_signer(), &content_type(AUTH_DATA_CONTENT_TYPE), CONTENT);
+
+    assert!(verify(&signed_data, DH_KEY_DATA_CONTENT_TYPE, &CA_CERTIFICATE).is_err());
+}
+
+#[test]
+fn signature_with_trailing_garbage_is_rejected() {
+    let mut signed_data = decode_signed_data(&sign_with_chain(
+        &kdc_signer(),
+        &content_type(DH_KEY_DATA_CONTENT_TYPE),
+        CONTENT,
+    ));
+    let signer_info = &mut signed_data.signed_data.0.signers_infos.0 .0[0];
+
+    // 0x00 || 0x01 || PS || 0x00 || DigestInfo || garbage, with the shortest PS
+    let digest_info = picky_asn1_der::to_vec(&DigestInfo {
+        oid: AlgorithmIdentifier::new_sha(ShaVariant::SHA2_256),
+        digest: HashAlgorithm::SHA2_256
+            .digest(&picky_asn1_der::to_vec(&Asn1SetOf::from(signer_info.signed_attrs.0 .0 .0.clone())).unwrap())
+            .into(),
+    })
+    .unwrap();
+    let private_key: RsaPrivateKey = picky_asn1_der::from_bytes(&KDC_PRIVATE_KEY).unwrap();
+    let modulus = BigUint::from_bytes_be(private_key.modulus.as_unsigned_bytes_be());
+    let mut encoded_message = vec![0x00, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+    encoded_message.extend_from_slice(&digest_info);
+    encoded_message.resize(modulus.bits().div_ceil(8) as usize, 0x42);
+
+    let signature = BigUint::from_bytes_be(&encoded_message).modpow(
+        &BigUint::from_bytes_be(private_key.private_exponent.as_unsigned_bytes_be()),
+        &modulus,
+    );
+    signer_info.signature = SignatureValue(OctetStringAsn1::from(signature.to_bytes_be()));
+
+    let error = verify(
+        &picky_asn1_der::to_vec(&signed_data).unwrap(),
+        DH_KEY_DATA_CONTENT_TYPE,
+        &CA_CERTIFICATE,
+    )
+    .unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn dh_key_pairs_agree_on_shared_secret() {
+    let client = DhKeyPair::generate().unwrap();
+    let kdc = DhKeyPair::generate().unwrap();
+
+    let client_secret = client.shared_secret(&dh_public_key(&kdc)).unwrap();
+    let kdc_secret = kdc.shared_secret(&dh_public_key(&client)).unwrap();
+
+    assert_eq!(client_secret, kdc_secret);
+    assert_eq!(256, client_secret.len());
+}
+
+#[test]
+fn invalid_dh_public_key_is_rejected() {
+    let client = DhKeyPair::generate().unwrap();
+
+    assert_eq!(
+        ErrorKind::PkInitClientFailure,
+        client.shared_secret(&[0x01]).unwrap_err().error_type
+    );
+}
+
+#[test]
+fn pa_pk_as_req_contains_checksum_of_request_body() {
+    let req_body = generate_as_req_body("user", "EXAMPLE.COM", &EncryptionParams::default_for_client()).unwrap();
+    let dh_key_pair = DhKeyPair::generate().unwrap();
+
+    let pa_pk_as_req = generate_pa_pk_as_req(&client_signer(), &req_body, &dh_key_pair, Utc::now()).unwrap();
+
+    let pa_pk_as_req: PaPkAsReq = picky_asn1_der::from_bytes(&pa_pk_as_req.padata_data.0 .0).unwrap();
+    let auth_pack = verify(
+        &pa_pk_as_req.signed_auth_pack.0 .0,
+        AUTH_DATA_CONTENT_TYPE,
+        &CA_CERTIFICATE,
+    )
+    .unwrap();
+    let auth_pack: AuthPack = picky_asn1_der::from_bytes(&auth_pack).unwrap();
+
+    let pk_authenticator = auth_pack.pk_authenticator.0;
+    assert_eq!(req_body.nonce.0, pk_authenticator.nonce.0);
+    assert_eq!(
+        Sha1::digest(picky_asn1_der::to_vec(&req_body).unwrap()).to_vec(),
+        pk_authenticator.pa_checksum.0.unwrap().0 .0
+    );
+    assert_eq!(
+        dh_key_pair.public_key_info().unwrap(),
+        auth_pack.client_public_value.0.unwrap().0
+    );
+}
+
+#[test]
+fn reply_key_is_derived_from_dh_key_agreement() {
+    let client_dh_key_pair = DhKeyPair::generate().unwrap();
+    let kdc_dh_key_pair = DhKeyPair::generate().unwrap();
+    let (as_rep, nonce) = exchange(&client_dh_key_pair, &kdc_dh_key_pair, &kdc_signer());
+
+    let key = extract_reply_key(
+        &as_rep,
+        "EXAMPLE.COM",
+        nonce,
+        &client_dh_key_pair,
+        &[CA_CERTIFICATE.to_vec()],
+        AES256_CTS_HMAC_SHA1_96,
+    )
+    .unwrap();
+
+    let kdc_secret = kdc_dh_key_pair
+        .shared_secret(&dh_public_key(&client_dh_key_pair))
+        .unwrap();
+    assert_eq!(dh::octet_string_to_key(&kdc_secret, 32), key);
+}
+
+#[test]
+fn reply_with_other_nonce_is_rejected() {
+    let client_dh_key_pair = DhKeyPair::generate().unwrap();
+    let (as_rep, nonce) = exchange(&client_dh_key_pair, &DhKeyPair::generate().unwrap(), &kdc_signer());
+
+    let error = extract_reply_key(
+        &as_rep,
+        "EXAMPLE.COM",
+        nonce.wrapping_add(1),
+        &client_dh_key_pair,
+        &[CA_CERTIFICATE.to_vec()],
+        AES256_CTS_HMAC_SHA1_96,
+    )
+    .unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn reply_signed_without_kdc_certificate_is_rejected() {
+    let client_dh_key_pair = DhKeyPair::generate().unwrap();
+    // the user certificate is issued by the same CA
+    let (as_rep, nonce) = exchange(&client_dh_key_pair, &DhKeyPair::generate().unwrap(), &client_signer());
+
+    let error = extract_reply_key(
+        &as_rep,
+        "EXAMPLE.COM",
+        nonce,
+        &client_dh_key_pair,
+        &[CA_CERTIFICATE.to_vec()],
+        AES256_CTS_HMAC_SHA1_96,
+    )
+    .unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn reply_signed_by_kdc_of_other_realm_is_rejected() {
+    let client_dh_key_pair = DhKeyPair::generate().unwrap();
+    let (as_rep, nonce) = exchange(&client_dh_key_pair, &DhKeyPair::generate().unwrap(), &kdc_signer());
+
+    let error = extract_reply_key(
+        &as_rep,
+        "OTHER.COM",
+        nonce,
+        &client_dh_key_pair,
+        &[CA_CERTIFICATE.to_vec()],
+        AES256_CTS_HMAC_SHA1_96,
+    )
+    .unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn reply_key_is_not_derived_without_trust_anchors() {
+    let client_dh_key_pair = DhKeyPair::generate().unwrap();
+    let (as_rep, nonce) = exchange(&client_dh_key_pair, &DhKeyPair::generate().unwrap(), &kdc_signer());
+
+    let error = extract_reply_key(
+        &as_rep,
+        "EXAMPLE.COM",
+        nonce,
+        &client_dh_key_pair,
+        &[],
+        AES256_CTS_HMAC_SHA1_96,
+    )
+    .unwrap_err();
+
+    assert_eq!(ErrorKind::PkInitClientFailure, error.error_type);
+}
+
+#[test]
+fn pk_init_errors_of_kdc_are_mapped() {
+    // KDC_ERR_CLIENT_NAME_MISMATCH
+    assert_eq!(ErrorKind::PkInitNameMismatch, pk_init_error(krb_error(75)).error_type);
+    // KDC_ERR_INVALID_SIG
+    assert_eq!(ErrorKind::PkInitClientFailure, pk_init_error(krb_error(64)).error_type);
+    // KDC_ERR_C_PRINCIPAL_UNKNOWN
+    assert_eq!(ErrorKind::InternalError, pk_init_error(krb_error(6)).error_type);
+}
+
+#[test]
+fn pk_init_credentials_are_compared_by_certificate() {
+    let credentials = PkInitCredentials {
+        username: "user".into(),
+        domain: "EXAMPLE.COM".into(),
+        signer: Arc::new(client_signer()),
+    };
+    let other_credentials = PkInitCredentials {
+        signer: Arc::new(kdc_signer()),
+        ..credentials.clone()
+    };
+
+    assert_eq!(CLIENT_CERTIFICATE, credentials.signer.certificate());
+    assert_eq!(credentials, credentials.clone());
+    assert_ne!(credentials, other_credentials);
+}
@@ -0,0 +1,266 @@
+//! [RFC 4757 7](https://www.rfc-editor.org/rfc/rfc4757#section-7): per-message tokens of RC4-HMAC contexts
+
+use rand::rngs::OsRng;
+use rand::Rng;
+use subtle::ConstantTimeEq;
+
+use crate::crypto::{compute_hmac_md5, compute_md5, Rc4};
+use crate::sspi::{Error, ErrorKind, Result};
+
+// GSS-API framing: [RFC 2743 3.1](https://www.rfc-editor.org/rfc/rfc2743#section-3.1)
+const GSS_TOKEN_TAG: u8 = 0x60;
+const KRB5_OID: [u8; 11] = [0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02];
+
+const MIC_TOKEN_ID: [u8; 2] = [0x01, 0x01];
+const WRAP_TOKEN_ID: [u8; 2] = [0x02, 0x01];
+const SGN_ALG_HMAC_MD5: [u8; 2] = [0x11, 0x00];
+const SEAL_ALG_RC4: [u8; 2] = [0x10, 0x00];
+const SEAL_ALG_NONE: [u8; 2] = [0xff, 0xff];
+
+const MIC_KEY_USAGE: i32 = 15;
+const WRAP_KEY_USAGE: i32 = 13;
+
+const HEADER_LEN: usize = 8;
+const MIC_TOKEN_LEN: usize = 24;
+const WRAP_TOKEN_LEN: usize = 32;
+const CONFOUNDER_LEN: usize = 8;
+
+// RC4 is a stream cipher, so the padding is always one byte. DCE-style tokens are not padded,
+// so the data is encrypted in place
+const PADDING: u8 = 0x01;
+
+/// Max len of the wrap token header: framing with a 4-byte length and the token up to the confounder
+pub const MAX_WRAP_HEADER_LEN: usize = 6 + KRB5_OID.len() + WRAP_TOKEN_LEN;
+/// Len of the MIC token which is short enough for the framing with a 1-byte length
+pub const MIC_TOKEN_FRAMED_LEN: usize = 2 + KRB5_OID.len() + MIC_TOKEN_LEN;
+
+/// Unwrapped RC4 wrap token
+pub struct Unwrapped {
+    pub data: Vec<u8>,
+    pub sealed: bool,
+    pub seq_number: u32,
+}
+
+pub fn generate_mic_token(key: &[u8], seq_number: u32, is_acceptor: bool, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut token = MIC_TOKEN_ID.to_vec();
+    token.extend_from_slice(&SGN_ALG_HMAC_MD5);
+    token.extend_from_slice(&[0xff; 4]);
+
+    let checksum = sign(key, MIC_KEY_USAGE, &token, payload)?;
+    token.extend_from_slice(&process_seq_number(
+        key,
+        &checksum,
+        &seq_number_plain(seq_number, is_acceptor),
+    )?);
+    token.extend_from_slice(&checksum);
+
+    Ok(frame(&token))
+}
+
+/// Returns the sequence number of the valid MIC token
+pub fn validate_mic_token(key: &[u8], raw_token: &[u8], from_acceptor: bool, payload: &[u8]) -> Result<u32> {
+    let token = unframe(raw_token)?;
+
+    if token.len() != MIC_TOKEN_LEN || token[0..2] != MIC_TOKEN_ID || token[2..4] != SGN_ALG_HMAC_MD5 {
+        return Err(Error::new(ErrorKind::InvalidToken, "Invalid RC4 MIC token".into()));
+    }
+
+    let checksum = &token[16..24];
+    if !bool::from(sign(key, MIC_KEY_USAGE, &token[0..HEADER_LEN], payload)?.ct_eq(checksum)) {
+        return Err(Error::new(
+            ErrorKind::MessageAltered,
+            "bad checksum of the mic token".into(),
+        ));
+    }
+
+    seq_number_from_plain(
+        &process_seq_number(key, checksum, &token[HEADER_LEN..16])?,
+        from_acceptor,
+    )
+}
+
+/// Returns the token header and the protected data which follows it
+pub fn wrap(
+    key: &[u8],
+    seq_number: u32,
+    is_acceptor: bool,
+    seal: bool,
+    dce_style: bool,
+    data: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut token = WRAP_TOKEN_ID.to_vec();
+    token.extend_from_slice(&SGN_ALG_HMAC_MD5);
+    token.extend_from_slice(if seal { &SEAL_ALG_RC4 } else { &SEAL_ALG_NONE });
+    token.extend_from_slice(&[0xff; 2]);
+
+    let mut plaintext = OsRng::new()?.gen::<[u8; CONFOUNDER_LEN]>().to_vec();
+    plaintext.extend_from_slice(data);
+    if !dce_style {
+        plaintext.push(PADDING);
+    }
+
+    let checksum = sign(key, WRAP_KEY_USAGE, &token, &plaintext)?;
+    token.extend_from_slice(&process_seq_number(
+        key,
+        &checksum,
+        &seq_number_plain(seq_number, is_acceptor),
+    )?);
+    token.extend_from_slice(&checksum);
+
+    if seal {
+        token.extend_from_slice(&data_cipher(key, seq_number)?.process(&plaintext));
+    } else {
+        token.extend_from_slice(&plaintext);
+    }
+
+    let mut header = frame(&token);
+    let protected_data = header.split_off(header.len() - (plaintext.len() - CONFOUNDER_LEN));
+
+    Ok((header, protected_data))
+}
+
+pub fn unwrap(key: &[u8], raw_token: &[u8], from_acceptor: bool, dce_style: bool) -> Result<Unwrapped> {
+    let token = unframe(raw_token)?;
+
+    if token.len() < WRAP_TOKEN_LEN || token[0..2] != WRAP_TOKEN_ID || token[2..4] != SGN_ALG_HMAC_MD5 {
+        return Err(Error::new(ErrorKind::InvalidToken, "Invalid RC4 wrap token".into()));
+    }
+    let sealed = match [token[4], token[5]] {
+        SEAL_ALG_RC4 => true,
+        SEAL_ALG_NONE => false,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidToken,
+                "Unsupported seal algorithm of the RC4 wrap token".into(),
+            ))
+        }
+    };
+
+    let checksum = &token[16..24];
+    let seq_number = seq_number_from_plain(
+        &process_seq_number(key, checksum, &token[HEADER_LEN..16])?,
+        from_acceptor,
+    )?;
+
+    let mut plaintext = token[24..].to_vec();
+    if sealed {
+        plaintext = data_cipher(key, seq_number)?.process(&plaintext);
+    }
+
+    if !bool::from(sign(key, WRAP_KEY_USAGE, &token[0..HEADER_LEN], &plaintext)?.ct_eq(checksum)) {
+        return Err(Error::new(
+            ErrorKind::MessageAltered,
+            "bad checksum of the wrap token".into(),
+        ));
+    }
+
+    if !dce_style {
+        let padding = usize::from(*plaintext.last().unwrap_or(&0));
+        if padding == 0 || padding > plaintext.len() - CONFOUNDER_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidToken,
+                "Invalid padding of the RC4 wrap token".into(),
+            ));
+        }
+        plaintext.truncate(plaintext.len() - padding);
+    }
+    plaintext.drain(..CONFOUNDER_LEN);
+
+    Ok(Unwrapped {
+        data: plaintext,
+        sealed,
+        seq_number,
+    })
+}
+
+// SGN_CKSUM: the first 8 bytes of HMAC-MD5(Ksign, MD5(usage | header | data))
+fn sign(key: &[u8], key_usage: i32, header: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let signature_key = compute_hmac_md5(key, b"signaturekey\0")?;
+
+    let mut to_hash = key_usage.to_le_bytes().to_vec();
+    to_hash.extend_from_slice(header);
+    to_hash.extend_from_slice(data);
+
+    Ok(compute_hmac_md5(&signature_key, &compute_md5(&to_hash))?[..8].to_vec())
+}
+
+fn seq_number_plain(seq_number: u32, is_acceptor: bool) -> Vec<u8> {
+    let mut plain = seq_number.to_be_bytes().to_vec();
+    plain.extend_from_slice(&[if is_acceptor { 0xff } else { 0x00 }; 4]);
+
+    plain
+}
+
+fn seq_number_from_plain(plain: &[u8], from_acceptor: bool) -> Result<u32> {
+    let direction = if from_acceptor { 0xff } else { 0x00 };
+    if plain[4..8].iter().any(|byte| *byte != direction) {
+        return Err(Error::new(
+            ErrorKind::MessageAltered,
+            "Invalid direction of the RC4 token".into(),
+        ));
+    }
+
+    Ok(u32::from_be_bytes([plain[0], plain[1], plain[2], plain[3]]))
+}
+
+// SND_SEQ is encrypted with Kseq = HMAC-MD5(HMAC-MD5(key, 0), SGN_CKSUM)
+fn process_seq_number(key: &[u8], checksum: &[u8], seq_number: &[u8]) -> Result<Vec<u8>> {
+    let seq_key = compute_hmac_md5(&compute_hmac_md5(key, &[0; 4])?, checksum)?;
+
+    Ok(Rc4::new(&seq_key).process(seq_number))
+}
+
+// the data is encrypted with Kcrypt = HMAC-MD5(HMAC-MD5(key ^ 0xf0, 0), seq_number)
+fn data_cipher(key: &[u8], seq_number: u32) -> Result<Rc4> {
+    let local_key = key.iter().map(|byte| byte ^ 0xf0).collect::<Vec<_>>();
+    let crypt_key = compute_hmac_md5(&compute_hmac_md5(&local_key, &[0; 4])?, &seq_number.to_be_bytes())?;
+
+    Ok(Rc4::new(&crypt_key))
+}
+
+fn frame(token: &[u8]) -> Vec<u8> {
+    let len = KRB5_OID.len() + token.len();
+
+    let mut framed = vec![GSS_TOKEN_TAG];
+    if len < 0x80 {
+        framed.push(len as u8);
+    } else {
+        let len_bytes = (len as u32).to_be_bytes();
+        let len_bytes = &len_bytes[len_bytes.iter().position(|byte| *byte != 0).unwrap_or(3)..];
+        framed.push(0x80 | len_bytes.len() as u8);
+        framed.extend_from_slice(len_bytes);
+    }
+    framed.extend_from_slice(&KRB5_OID);
+    framed.extend_from_slice(token);
+
+    framed
+}
+
+fn unframe(data: &[u8]) -> Result<&[u8]> {
+    let invalid = || Error::new(ErrorKind::InvalidToken, "Invalid GSS-API token framing".into());
+
+    if data.len() < 2 || data[0] != GSS_TOKEN_TAG {
+        return Err(invalid());
+    }
+
+    let (len, header_len) = if data[1] < 0x80 {
+        (usize::from(data[1]), 2)
+    } else {
+        let len_size = usize::from(data[1] & 0x7f);
+        if len_size == 0 || len_size > 4 || data.len() < 2 + len_size {
+            return Err(invalid());
+        }
+        let len = data[2..2 + len_size]
+            .iter()
+            .fold(0, |len, byte| (len << 8) | usize::from(*byte));
+
+        (len, 2 + len_size)
+    };
+
+    let body = &data[header_len..];
+    if body.len() != len || !body.starts_with(&KRB5_OID) {
+        return Err(invalid());
+    }
+
+    Ok(&body[KRB5_OID.len()..])
+}
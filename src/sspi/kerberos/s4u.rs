@@ -0,0 +1,109 @@
+//! Service for user extensions: [MS-SFU](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-sfu/3bff5864-8135-400e-bdd9-33b552051d94)
+//!
+//! S4U2Self: the service obtains the ticket to itself on behalf of the user, authenticated by the PA-FOR-USER
+//! and PA-S4U-X509-USER padata. S4U2Proxy: the service obtains the ticket to another service on behalf of the user
+//! with the S4U2Self ticket (the evidence ticket) as the additional ticket
+
+mod data_types;
+#[cfg(test)]
+mod test;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use kerberos_constants::checksum_types::HMAC_MD5;
+use kerberos_constants::error_codes::{KDC_ERR_BADOPTION, KDC_ERR_PADATA_TYPE_NOSUPP};
+use picky_asn1::restricted_string::IA5String;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3, IntegerAsn1, OctetStringAsn1,
+    Optional,
+};
+use picky_krb::data_types::{Checksum, KerberosStringAsn1, PaData};
+
+use self::data_types::{PaForUser, PaS4uX509User, S4uUserId, PA_FOR_USER, PA_S4U_X509_USER};
+use super::credentials::Principal;
+use super::crypto;
+use super::utils::i32_to_integer;
+use crate::sspi::{Error, ErrorKind, Result};
+
+// KERB_NON_KERB_SALT and KERB_NON_KERB_CKSUM_SALT key usages of MS-SFU
+const KEY_USAGE_PA_FOR_USER: i32 = 17;
+const KEY_USAGE_PA_S4U_X509_USER: i32 = 26;
+
+const AUTH_PACKAGE: &str = "Kerberos";
+
+/// PA-FOR-USER: the checksum is HMAC-MD5 keyed with the session key of the TGT of the service
+/// whatever the encryption type of the key is
+pub fn generate_pa_for_user(user: &Principal, session_key: &[u8]) -> Result<PaData> {
+    // S4UByteArray: the name type, the name components, the realm and the auth package
+    let mut data = Vec::new();
+    data.write_u32::<LittleEndian>(user.name_type)?;
+    user.components
+        .iter()
+        .for_each(|component| data.extend_from_slice(component.as_bytes()));
+    data.extend_from_slice(user.realm.as_bytes());
+    data.extend_from_slice(AUTH_PACKAGE.as_bytes());
+
+    let pa_for_user = PaForUser {
+        user_name: ExplicitContextTag0::from(user.principal_name()?),
+        user_realm: ExplicitContextTag1::from(user.kerberos_realm()?),
+        cksum: ExplicitContextTag2::from(Checksum {
+            cksumtype: ExplicitContextTag0::from(i32_to_integer(HMAC_MD5)),
+            checksum: ExplicitContextTag1::from(OctetStringAsn1::from(crypto::checksum(
+                super::RC4_HMAC,
+                session_key,
+                KEY_USAGE_PA_FOR_USER,
+                &data,
+            )?)),
+        }),
+        auth_package: ExplicitContextTag3::from(KerberosStringAsn1::from(IA5String::from_string(AUTH_PACKAGE.into())?)),
+    };
+
+    Ok(PaData {
+        padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_FOR_USER.to_vec())),
+        padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&pa_for_user)?)),
+    })
+}
+
+/// PA-S4U-X509-USER: the user id is bound to the request by the nonce of the request body
+pub fn generate_pa_s4u_x509_user(
+    user: &Principal,
+    nonce: u32,
+    session_key: &[u8],
+    encryption_type: i32,
+) -> Result<PaData> {
+    let user_id = S4uUserId {
+        nonce: ExplicitContextTag0::from(IntegerAsn1::from_bytes_be_unsigned(nonce.to_be_bytes().to_vec())),
+        cname: Optional::from(Some(ExplicitContextTag1::from(user.principal_name()?))),
+        crealm: ExplicitContextTag2::from(user.kerberos_realm()?),
+        subject_certificate: Optional::from(None),
+        options: Optional::from(None),
+    };
+
+    let pa_s4u_x509_user = PaS4uX509User {
+        checksum: ExplicitContextTag1::from(Checksum {
+            cksumtype: ExplicitContextTag0::from(i32_to_integer(crypto::checksum_type(encryption_type)?)),
+            checksum: ExplicitContextTag1::from(OctetStringAsn1::from(crypto::checksum(
+                encryption_type,
+                session_key,
+                KEY_USAGE_PA_S4U_X509_USER,
+                &picky_asn1_der::to_vec(&user_id)?,
+            )?)),
+        }),
+        user_id: ExplicitContextTag0::from(user_id),
+    };
+
+    Ok(PaData {
+        padata_type: ExplicitContextTag1::from(IntegerAsn1::from(PA_S4U_X509_USER.to_vec())),
+        padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(picky_asn1_der::to_vec(&pa_s4u_x509_user)?)),
+    })
+}
+
+/// The KDC refuses the S4U request when the extensions are not supported or the delegation is not allowed
+pub fn s4u_error(error: Error) -> Error {
+    let error_type = match error.kerberos_error().map(|error| error.error_code) {
+        Some(KDC_ERR_PADATA_TYPE_NOSUPP) => ErrorKind::NoS4uProtSupport,
+        Some(KDC_ERR_BADOPTION) => ErrorKind::DelegationPolicy,
+        _ => return error,
+    };
+
+    Error { error_type, ..error }
+}
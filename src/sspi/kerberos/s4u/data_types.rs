@@ -0,0 +1,65 @@
+use picky_asn1::wrapper::{
+    BitStringAsn1, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3,
+    ExplicitContextTag4, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::data_types::{Checksum, KerberosStringAsn1, PrincipalName, Realm};
+use serde::{Deserialize, Serialize};
+
+pub const PA_FOR_USER: [u8; 2] = [0x00, 0x81];
+pub const PA_S4U_X509_USER: [u8; 2] = [0x00, 0x82];
+
+/// [MS-SFU 2.2.1](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-sfu/aceb70de-40f0-4409-87fa-df00ca145f5a)
+///
+/// ```not_rust
+/// PA-FOR-USER ::= SEQUENCE {
+///         userName        [0] PrincipalName,
+///         userRealm       [1] Realm,
+///         cksum           [2] Checksum,
+///         auth-package    [3] KerberosString
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PaForUser {
+    pub user_name: ExplicitContextTag0<PrincipalName>,
+    pub user_realm: ExplicitContextTag1<Realm>,
+    pub cksum: ExplicitContextTag2<Checksum>,
+    pub auth_package: ExplicitContextTag3<KerberosStringAsn1>,
+}
+
+/// [MS-SFU 2.2.2](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-sfu/cd9d5ca7-ce20-4693-872b-2f5dd41cbff6)
+///
+/// ```not_rust
+/// PA-S4U-X509-USER ::= SEQUENCE {
+///         user-id         [0] S4UUserID,
+///         checksum        [1] Checksum
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PaS4uX509User {
+    pub user_id: ExplicitContextTag0<S4uUserId>,
+    pub checksum: ExplicitContextTag1<Checksum>,
+}
+
+/// [MS-SFU 2.2.2](https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-sfu/cd9d5ca7-ce20-4693-872b-2f5dd41cbff6)
+///
+/// ```not_rust
+/// S4UUserID ::= SEQUENCE {
+///         nonce           [0] UInt32, -- the nonce in KDC-REQ-BODY
+///         cname           [1] PrincipalName OPTIONAL,
+///         crealm          [2] Realm,
+///         subject-certificate [3] OCTET STRING OPTIONAL,
+///         options         [4] BIT STRING OPTIONAL,
+///         ...
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct S4uUserId {
+    pub nonce: ExplicitContextTag0<IntegerAsn1>,
+    #[serde(default)]
+    pub cname: Optional<Option<ExplicitContextTag1<PrincipalName>>>,
+    pub crealm: ExplicitContextTag2<Realm>,
+    #[serde(default)]
+    pub subject_certificate: Optional<Option<ExplicitContextTag3<OctetStringAsn1>>>,
+    #[serde(default)]
+    pub options: Optional<Option<ExplicitContextTag4<BitStringAsn1>>>,
+}
@@ -0,0 +1,90 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use kerberos_constants::error_codes::{KDC_ERR_BADOPTION, KDC_ERR_C_PRINCIPAL_UNKNOWN, KDC_ERR_PADATA_TYPE_NOSUPP};
+use picky_krb::data_types::PaData;
+
+use super::data_types::{PaForUser, PaS4uX509User, PA_FOR_USER, PA_S4U_X509_USER};
+use super::{generate_pa_for_user, generate_pa_s4u_x509_user, s4u_error, KEY_USAGE_PA_FOR_USER};
+use crate::sspi::kerberos::credentials::Principal;
+use crate::sspi::kerberos::utils::integer_to_u32;
+use crate::sspi::kerberos::{crypto, KerberosError, AES256_CTS_HMAC_SHA1_96, RC4_HMAC};
+use crate::sspi::{Error, ErrorKind};
+
+const SESSION_KEY: [u8; 32] = [0x3c; 32];
+
+fn padata_value<T: serde::de::DeserializeOwned>(pa_data: &PaData, padata_type: &[u8]) -> T {
+    assert_eq!(pa_data.padata_type.0 .0, padata_type);
+
+    picky_asn1_der::from_bytes(&pa_data.padata_data.0 .0).unwrap()
+}
+
+fn kdc_error(error_code: i32) -> Error {
+    Error::from(KerberosError {
+        error_code,
+        e_text: None,
+        e_data: None,
+        server_time: chrono::Utc::now(),
+        client_realm: None,
+    })
+}
+
+#[test]
+fn pa_for_user_checksum_covers_user_name_realm_and_auth_package() {
+    let user = Principal::new("alice", "EXAMPLE.COM");
+
+    let pa_for_user: PaForUser = padata_value(&generate_pa_for_user(&user, &SESSION_KEY).unwrap(), &PA_FOR_USER);
+
+    let mut data = Vec::new();
+    data.write_u32::<LittleEndian>(1).unwrap();
+    data.extend_from_slice(b"aliceEXAMPLE.COMKerberos");
+
+    assert_eq!(
+        Principal::from_principal_name(&pa_for_user.user_name.0, &pa_for_user.user_realm.0),
+        user
+    );
+    assert_eq!(pa_for_user.auth_package.0.to_string(), "Kerberos");
+    assert_eq!(pa_for_user.cksum.0.cksumtype.0 .0, [0xff, 0x76]);
+    assert_eq!(
+        pa_for_user.cksum.0.checksum.0 .0,
+        crypto::checksum(RC4_HMAC, &SESSION_KEY, KEY_USAGE_PA_FOR_USER, &data).unwrap()
+    );
+}
+
+#[test]
+fn pa_s4u_x509_user_is_bound_to_request_nonce() {
+    let user = Principal::new("alice", "EXAMPLE.COM");
+
+    let pa_s4u_x509_user: PaS4uX509User = padata_value(
+        &generate_pa_s4u_x509_user(&user, 0x8765_4321, &SESSION_KEY, AES256_CTS_HMAC_SHA1_96).unwrap(),
+        &PA_S4U_X509_USER,
+    );
+
+    let user_id = &pa_s4u_x509_user.user_id.0;
+    assert_eq!(integer_to_u32(&user_id.nonce.0), 0x8765_4321);
+    assert_eq!(user_id.crealm.0.to_string(), "EXAMPLE.COM");
+    assert_eq!(
+        pa_s4u_x509_user.checksum.0.checksum.0 .0,
+        crypto::checksum(
+            AES256_CTS_HMAC_SHA1_96,
+            &SESSION_KEY,
+            26,
+            &picky_asn1_der::to_vec(user_id).unwrap()
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn s4u_errors_of_kdc_are_mapped_to_error_kinds() {
+    assert_eq!(
+        s4u_error(kdc_error(KDC_ERR_PADATA_TYPE_NOSUPP)).error_type,
+        ErrorKind::NoS4uProtSupport
+    );
+    assert_eq!(
+        s4u_error(kdc_error(KDC_ERR_BADOPTION)).error_type,
+        ErrorKind::DelegationPolicy
+    );
+    assert_eq!(
+        s4u_error(kdc_error(KDC_ERR_C_PRINCIPAL_UNKNOWN)).error_type,
+        ErrorKind::InternalError
+    );
+}
@@ -1,14 +1,29 @@
+use std::convert::TryFrom;
 use std::io::Read;
 
-use kerberos_constants::key_usages::KEY_USAGE_AP_REP_ENC_PART;
-use kerberos_crypto::new_kerberos_cipher;
+use byteorder::{LittleEndian, ReadBytesExt};
+use kerberos_constants::key_usages::{
+    KEY_USAGE_AP_REP_ENC_PART, KEY_USAGE_AP_REQ_AUTHEN, KEY_USAGE_AS_REP_TICKET, KEY_USAGE_KRB_CRED_ENC_PART,
+};
+use oid::ObjectIdentifier;
+use picky_asn1::wrapper::{ExplicitContextTag0, ObjectIdentifierAsn1};
 use picky_asn1_der::application_tag::ApplicationTag;
 use picky_asn1_der::Asn1RawDer;
-use picky_krb::data_types::{EncApRepPart, Ticket};
-use picky_krb::gss_api::NegTokenTarg1;
-use picky_krb::messages::{ApRep, TgtRep};
+use picky_asn1_x509::oids::SPNEGO;
+use picky_krb::data_types::{Authenticator, AuthenticatorInner, EncApRepPart, EncryptionKey, Ticket};
+use picky_krb::gss_api::{MechTypeList, NegTokenInit, NegTokenTarg1};
+use picky_krb::messages::{ApRep, ApReq, TgtRep};
 
+use super::generators::TGT_REP_TOKEN_ID;
+use super::ServiceKey;
+use crate::sspi::kerberos::ccache::CachedCredentials;
+use crate::sspi::kerberos::client::generators::{
+    CHANNEL_BINDING_LEN, GSS_CHECKSUM_LEN, GSS_CHECKSUM_TYPE, GSS_C_DELEG_FLAG, KRB_CRED_DELEGATION_OPTION,
+};
 use crate::sspi::kerberos::client::AES256_CTS_HMAC_SHA1_96;
+use crate::sspi::kerberos::crypto::new_cipher;
+use crate::sspi::kerberos::data_types::{EncKrbCredPart, EncTicketPart, KrbCred};
+use crate::sspi::kerberos::utils::integer_to_u32;
 use crate::sspi::kerberos::EncryptionParams;
 use crate::sspi::{Error, ErrorKind, Result};
 
@@ -18,9 +33,11 @@ pub fn extract_ap_rep_from_neg_token_targ(token: &NegTokenTarg1) -> Result<ApRep
         .response_token
         .0
         .as_ref()
-        .ok_or_else(|| Error {
-            error_type: ErrorKind::InvalidToken,
-            description: "Missing responce token in NegTokenTarg".to_owned(),
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidToken,
+                "Missing responce token in NegTokenTarg".to_owned(),
+            )
         })?
         .0
          .0;
@@ -35,32 +52,37 @@ pub fn extract_ap_rep_from_neg_token_targ(token: &NegTokenTarg1) -> Result<ApRep
     Ok(picky_asn1_der::from_reader(&mut data)?)
 }
 
-pub fn extract_sub_session_key_from_ap_rep(
+pub fn extract_enc_ap_rep_part(
     ap_rep: &ApRep,
     session_key: &[u8],
     enc_params: &EncryptionParams,
-) -> Result<Vec<u8>> {
-    let cipher = new_kerberos_cipher(enc_params.encryption_type.unwrap_or(AES256_CTS_HMAC_SHA1_96))?;
+) -> Result<EncApRepPart> {
+    let cipher = new_cipher(enc_params.encryption_type.unwrap_or(AES256_CTS_HMAC_SHA1_96))?;
 
     let res = cipher
         .decrypt(session_key, KEY_USAGE_AP_REP_ENC_PART, &ap_rep.0.enc_part.cipher.0 .0)
-        .map_err(|err| Error {
-            error_type: ErrorKind::DecryptFailure,
-            description: format!("Cannot decrypt ap_rep.enc_part: {:?}", err),
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::DecryptFailure,
+                format!("Cannot decrypt ap_rep.enc_part: {:?}", err),
+            )
         })?;
 
-    let ap_rep_enc_part: EncApRepPart =
-        picky_asn1_der::from_bytes(&res).map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+    picky_asn1_der::from_bytes(&res).map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))
+}
 
+pub fn extract_sub_session_key_from_ap_rep(ap_rep_enc_part: &EncApRepPart) -> Result<Vec<u8>> {
     Ok(ap_rep_enc_part
         .0
         .subkey
         .0
+        .as_ref()
         .ok_or_else(|| Error::new(ErrorKind::InvalidToken, "Missing sub-key in ap_req".to_owned()))?
         .0
         .key_value
         .0
-         .0)
+         .0
+        .clone())
 }
 
 pub fn extract_tgt_ticket(data: &[u8]) -> Result<Option<Ticket>> {
@@ -76,6 +98,12 @@ pub fn extract_tgt_ticket(data: &[u8]) -> Result<Option<Ticket>> {
         let mut t = [0, 0];
 
         c.read_exact(&mut t)?;
+        if t != TGT_REP_TOKEN_ID {
+            return Err(Error::new(
+                ErrorKind::InvalidToken,
+                format!("Expected the TGT-REP token, got the token {:?}", t),
+            ));
+        }
 
         let tgt_rep: TgtRep =
             picky_asn1_der::from_reader(&mut c).map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
@@ -85,3 +113,262 @@ pub fn extract_tgt_ticket(data: &[u8]) -> Result<Option<Ticket>> {
         Ok(None)
     }
 }
+
+/// Kerberos message received from the initiator together with its GSS-API/SPNEGO framing
+#[derive(Debug, Default)]
+pub struct InitiatorMessage {
+    /// mechanisms proposed by the initiator in the SPNEGO NegTokenInit
+    pub mech_types: Option<MechTypeList>,
+    pub mech_list_mic: Option<Vec<u8>>,
+    pub is_spnego: bool,
+    pub krb5_oid: Option<ObjectIdentifier>,
+    pub token_id: Option<[u8; 2]>,
+    /// raw Kerberos message. It can be empty if the SPNEGO token carries only the mechListMIC
+    pub krb_message: Vec<u8>,
+}
+
+pub fn extract_initiator_message(data: &[u8]) -> Result<InitiatorMessage> {
+    extract_message(data, false)
+}
+
+// SPNEGO carries the Kerberos token, so the SPNEGO token nested into SPNEGO is rejected
+// and the recursion depth is limited by one level
+fn extract_message(data: &[u8], is_nested: bool) -> Result<InitiatorMessage> {
+    let nested_spnego = || Error::new(ErrorKind::InvalidToken, "SPNEGO token is nested into SPNEGO".into());
+
+    match data.first() {
+        // [APPLICATION 0]: GSS-API InitialContextToken
+        Some(0x60) => {
+            let mut reader = data;
+
+            let oid: ApplicationTag<Asn1RawDer, 0> = picky_asn1_der::from_reader(&mut reader)
+                .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+            let oid: ObjectIdentifierAsn1 = picky_asn1_der::from_bytes(&oid.0 .0)?;
+
+            if oid.0 == ObjectIdentifier::try_from(SPNEGO).unwrap() {
+                if is_nested {
+                    return Err(nested_spnego());
+                }
+
+                // NegTokenInit is expected to be wrapped into the [0] tag but some initiators omit it
+                let neg_token_init: NegTokenInit = if reader.first() == Some(&0xa0) {
+                    picky_asn1_der::from_reader::<ExplicitContextTag0<NegTokenInit>>(&mut reader).map(|token| token.0)
+                } else {
+                    picky_asn1_der::from_reader(&mut reader)
+                }
+                .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+                let NegTokenInit {
+                    mech_types,
+                    mech_token,
+                    mech_list_mic,
+                    ..
+                } = neg_token_init;
+
+                let mech_token = mech_token
+                    .0
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidToken, "Missing mech token in NegTokenInit".into()))?;
+
+                let mut message = extract_message(&mech_token.0 .0, true)?;
+                message.mech_types = mech_types.0.map(|mech_types| mech_types.0);
+                message.mech_list_mic = mech_list_mic.0.map(|mic| mic.0 .0);
+                message.is_spnego = true;
+
+                Ok(message)
+            } else {
+                let mut token_id = [0, 0];
+                reader.read_exact(&mut token_id)?;
+
+                Ok(InitiatorMessage {
+                    krb5_oid: Some(oid.0),
+                    token_id: Some(token_id),
+                    krb_message: reader.to_vec(),
+                    ..Default::default()
+                })
+            }
+        }
+        // [1]: SPNEGO NegTokenResp
+        Some(0xa1) => {
+            if is_nested {
+                return Err(nested_spnego());
+            }
+
+            let neg_token_targ: NegTokenTarg1 = picky_asn1_der::from_bytes(data)
+                .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+
+            let mut message = if let Some(response_token) = neg_token_targ.0.response_token.0 {
+                extract_message(&response_token.0 .0, true)?
+            } else {
+                InitiatorMessage::default()
+            };
+            message.mech_list_mic = neg_token_targ.0.mech_list_mic.0.map(|mic| mic.0 .0);
+            message.is_spnego = true;
+
+            Ok(message)
+        }
+        Some(_) => Ok(InitiatorMessage {
+            krb_message: data.to_vec(),
+            ..Default::default()
+        }),
+        None => Err(Error::new(ErrorKind::InvalidToken, "Input token is empty".into())),
+    }
+}
+
+/// Decrypts the ticket and returns the service key which decrypts it, e.g. to verify the PAC
+pub fn extract_enc_ticket_part<'a>(
+    ticket: &Ticket,
+    service_keys: &'a [ServiceKey],
+) -> Result<(EncTicketPart, &'a ServiceKey)> {
+    let enc_part = &ticket.0.enc_part.0;
+
+    let encryption_type = integer_to_u32(&enc_part.etype.0) as i32;
+    let kvno = enc_part.kvno.0.as_ref().map(|kvno| integer_to_u32(&kvno.0));
+
+    let cipher = new_cipher(encryption_type)?;
+
+    let mut service_keys = service_keys
+        .iter()
+        .filter(|key| key.encryption_type == encryption_type)
+        .filter(|key| kvno.is_none() || key.kvno.is_none() || key.kvno == kvno)
+        .peekable();
+
+    if service_keys.peek().is_none() {
+        return Err(Error::new(
+            ErrorKind::NoKerdKey,
+            format!(
+                "There is no service key for the encryption type {} and kvno {:?}",
+                encryption_type, kvno
+            ),
+        ));
+    }
+
+    for service_key in service_keys {
+        if let Ok(data) = cipher.decrypt(&service_key.key, KEY_USAGE_AS_REP_TICKET, &enc_part.cipher.0 .0) {
+            return Ok((picky_asn1_der::from_bytes(&data)?, service_key));
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::DecryptFailure,
+        "Cannot decrypt the ticket with any of the service keys".into(),
+    ))
+}
+
+pub fn extract_authenticator(ap_req: &ApReq, session_key: &EncryptionKey) -> Result<Authenticator> {
+    let encryption_type = integer_to_u32(&session_key.key_type.0) as i32;
+    let cipher = new_cipher(encryption_type)?;
+
+    let data = cipher
+        .decrypt(
+            &session_key.key_value.0 .0,
+            KEY_USAGE_AP_REQ_AUTHEN,
+            &ap_req.0.authenticator.0.cipher.0 .0,
+        )
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::DecryptFailure,
+                format!("Cannot decrypt ap_req.authenticator: {:?}", err),
+            )
+        })?;
+
+    Ok(picky_asn1_der::from_bytes(&data)?)
+}
+
+/// Context flags requested by the initiator in the authenticator checksum:
+/// [RFC 4121 4.1.1.1](https://www.rfc-editor.org/rfc/rfc4121#section-4.1.1.1).
+/// The authenticator without the GSS checksum requests no flags
+pub fn extract_context_flags(authenticator: &AuthenticatorInner) -> u32 {
+    match authenticator.cksum.0.as_ref() {
+        Some(checksum) if checksum.0.cksumtype.0 .0 == GSS_CHECKSUM_TYPE => checksum
+            .0
+            .checksum
+            .0
+             .0
+            .get(4 + CHANNEL_BINDING_LEN..GSS_CHECKSUM_LEN)
+            .map(|flags| u32::from_le_bytes(flags.try_into().unwrap()))
+            .unwrap_or_default(),
+        _ => 0,
+    }
+}
+
+/// Credentials delegated by the initiator in the checksum of the authenticator:
+/// [RFC 4121 4.1.1](https://www.rfc-editor.org/rfc/rfc4121#section-4.1.1).
+/// The KRB-CRED is encrypted with the session key of the ticket or with the subkey of the authenticator
+pub fn extract_delegated_credentials(
+    authenticator: &AuthenticatorInner,
+    session_key: &EncryptionKey,
+) -> Result<Vec<CachedCredentials>> {
+    let checksum = match authenticator.cksum.0.as_ref() {
+        Some(checksum) if checksum.0.cksumtype.0 .0 == GSS_CHECKSUM_TYPE => &checksum.0.checksum.0 .0,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut reader = checksum.as_slice();
+    if checksum.len() < GSS_CHECKSUM_LEN || reader.read_u32::<LittleEndian>()? as usize != CHANNEL_BINDING_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            "Invalid length of the authenticator checksum".into(),
+        ));
+    }
+    reader = &reader[CHANNEL_BINDING_LEN..];
+
+    let flags = reader.read_u32::<LittleEndian>()?;
+    if flags & GSS_C_DELEG_FLAG == 0 {
+        return Ok(Vec::new());
+    }
+
+    let delegation_option = reader.read_u16::<LittleEndian>()?;
+    let krb_cred_len = reader.read_u16::<LittleEndian>()? as usize;
+    if delegation_option != KRB_CRED_DELEGATION_OPTION || reader.len() < krb_cred_len {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            "Invalid delegated credentials in the authenticator checksum".into(),
+        ));
+    }
+
+    let krb_cred: KrbCred = picky_asn1_der::from_bytes(&reader[..krb_cred_len])
+        .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+    let enc_part = &krb_cred.0.enc_part.0;
+    let encryption_type = integer_to_u32(&enc_part.etype.0) as i32;
+
+    let keys = [
+        Some(session_key),
+        authenticator.subkey.0.as_ref().map(|subkey| &subkey.0),
+    ];
+    let enc_krb_cred_part: EncKrbCredPart = if encryption_type == 0 {
+        // some initiators do not encrypt the credentials as the authenticator is already encrypted
+        picky_asn1_der::from_bytes(&enc_part.cipher.0 .0)?
+    } else {
+        let cipher = new_cipher(encryption_type)?;
+        let data = keys
+            .iter()
+            .flatten()
+            .find_map(|key| {
+                cipher
+                    .decrypt(&key.key_value.0 .0, KEY_USAGE_KRB_CRED_ENC_PART, &enc_part.cipher.0 .0)
+                    .ok()
+            })
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::DecryptFailure,
+                    "Cannot decrypt the delegated credentials".into(),
+                )
+            })?;
+
+        picky_asn1_der::from_bytes(&data)?
+    };
+
+    let tickets = &krb_cred.0.tickets.0 .0;
+    let ticket_info = &enc_krb_cred_part.0.ticket_info.0 .0;
+    if tickets.len() != ticket_info.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            "The number of the delegated tickets does not match the ticket info".into(),
+        ));
+    }
+
+    tickets
+        .iter()
+        .zip(ticket_info.iter())
+        .map(|(ticket, info)| CachedCredentials::from_krb_cred_info(ticket, info))
+        .collect()
+}
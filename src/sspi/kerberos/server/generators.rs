@@ -0,0 +1,139 @@
+use std::convert::TryFrom;
+
+use kerberos_constants::key_usages::KEY_USAGE_AP_REP_ENC_PART;
+use oid::ObjectIdentifier;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3, IntegerAsn1,
+    ObjectIdentifierAsn1, OctetStringAsn1, Optional,
+};
+use picky_asn1_der::application_tag::ApplicationTag;
+use picky_asn1_der::Asn1RawDer;
+use picky_asn1_x509::oids::KRB5;
+use picky_asn1_x509::oids::KRB5_USER_TO_USER;
+use picky_krb::constants::gss_api::{ACCEPT_COMPLETE, ACCEPT_INCOMPLETE};
+use picky_krb::constants::types::{AP_REP_MSG_TYPE, TGT_REP_MSG_TYPE};
+use picky_krb::data_types::{
+    EncApRepPart, EncApRepPartInner, EncryptedData, EncryptionKey, KerberosTime, Microseconds, Ticket,
+};
+use picky_krb::gss_api::{KrbMessage, MechType, NegTokenTarg, NegTokenTarg1};
+use picky_krb::messages::{ApRep, ApRepInner, TgtRep};
+use rand::rngs::OsRng;
+use rand::Rng;
+
+use crate::sspi::kerberos::crypto::{self, new_cipher};
+use crate::sspi::kerberos::utils::integer_to_u32;
+use crate::sspi::kerberos::KERBEROS_VERSION;
+use crate::sspi::Result;
+
+pub const AP_REP_TOKEN_ID: [u8; 2] = [0x02, 0x00];
+pub const TGT_REP_TOKEN_ID: [u8; 2] = [0x04, 0x01];
+
+pub fn generate_acceptor_sub_key(encryption_type: i32) -> Result<EncryptionKey> {
+    let mut key = vec![0; crypto::key_len(encryption_type)?];
+    OsRng::new()?.fill(key.as_mut_slice());
+
+    Ok(EncryptionKey {
+        key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![encryption_type as u8])),
+        key_value: ExplicitContextTag1::from(OctetStringAsn1::from(key)),
+    })
+}
+
+/// The AP-REP of the acceptor carries its subkey. The DCE-style AP-REP of the initiator has none
+pub fn generate_ap_rep(
+    session_key: &EncryptionKey,
+    ctime: KerberosTime,
+    cusec: Microseconds,
+    sub_key: Option<EncryptionKey>,
+    seq_number: u32,
+) -> Result<ApRep> {
+    let encryption_type = session_key.key_type.0.clone();
+    let cipher = new_cipher(integer_to_u32(&encryption_type) as i32)?;
+
+    let enc_ap_rep_part = EncApRepPart::from(EncApRepPartInner {
+        ctime: ExplicitContextTag0::from(ctime),
+        cusec: ExplicitContextTag1::from(cusec),
+        subkey: Optional::from(sub_key.map(ExplicitContextTag2::from)),
+        seq_number: Optional::from(Some(ExplicitContextTag3::from(IntegerAsn1::from_bytes_be_unsigned(
+            seq_number.to_be_bytes().to_vec(),
+        )))),
+    });
+
+    let encrypted_enc_ap_rep_part = cipher.encrypt(
+        &session_key.key_value.0 .0,
+        KEY_USAGE_AP_REP_ENC_PART,
+        &picky_asn1_der::to_vec(&enc_ap_rep_part)?,
+    );
+
+    Ok(ApRep::from(ApRepInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![AP_REP_MSG_TYPE])),
+        enc_part: ExplicitContextTag2::from(EncryptedData {
+            etype: ExplicitContextTag0::from(encryption_type),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(encrypted_enc_ap_rep_part)),
+        }),
+    }))
+}
+
+/// Wraps the AP-REP into the GSS-API KRB5 token: [RFC 1964 1.1.2](https://datatracker.ietf.org/doc/html/rfc1964#section-1.1.2)
+pub fn generate_krb_ap_rep_token(ap_rep: ApRep, krb5_oid: Option<ObjectIdentifier>) -> Result<Vec<u8>> {
+    let krb_blob: ApplicationTag<_, 0> = ApplicationTag(KrbMessage {
+        krb5_oid: ObjectIdentifierAsn1::from(krb5_oid.unwrap_or_else(|| ObjectIdentifier::try_from(KRB5).unwrap())),
+        krb5_token_id: AP_REP_TOKEN_ID,
+        krb_msg: ap_rep,
+    });
+
+    Ok(picky_asn1_der::to_vec(&krb_blob)?)
+}
+
+/// Wraps the TGT of the service into the user-to-user KRB_TGT_REP token:
+/// [draft-swift-win2k-krb-user2user 2](https://datatracker.ietf.org/doc/html/draft-swift-win2k-krb-user2user-03#section-2)
+pub fn generate_krb_tgt_rep_token(tgt: Ticket) -> Result<Vec<u8>> {
+    let krb_blob: ApplicationTag<_, 0> = ApplicationTag(KrbMessage {
+        krb5_oid: ObjectIdentifierAsn1::from(ObjectIdentifier::try_from(KRB5_USER_TO_USER).unwrap()),
+        krb5_token_id: TGT_REP_TOKEN_ID,
+        krb_msg: TgtRep {
+            pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+            msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![TGT_REP_MSG_TYPE])),
+            ticket: ExplicitContextTag2::from(tgt),
+        },
+    });
+
+    Ok(picky_asn1_der::to_vec(&krb_blob)?)
+}
+
+/// Answers the SPNEGO NegTokenInit carrying the TGT-REQ. Without the TGT the initiator falls back to the ticket
+/// encrypted with the long-term key of the service
+pub fn generate_neg_tgt_rep(krb_tgt_rep_token: Option<Vec<u8>>, supported_mech: Option<MechType>) -> NegTokenTarg1 {
+    NegTokenTarg1::from(NegTokenTarg {
+        neg_result: Optional::from(Some(ExplicitContextTag0::from(Asn1RawDer(ACCEPT_INCOMPLETE.to_vec())))),
+        supported_mech: Optional::from(supported_mech.map(ExplicitContextTag1::from)),
+        response_token: Optional::from(
+            krb_tgt_rep_token.map(|token| ExplicitContextTag2::from(OctetStringAsn1::from(token))),
+        ),
+        mech_list_mic: Optional::from(None),
+    })
+}
+
+// the AP-REP is not sent if the initiator did not request the mutual authentication
+pub fn generate_neg_ap_rep(
+    krb_ap_rep_token: Option<Vec<u8>>,
+    supported_mech: Option<MechType>,
+    mech_list_mic: Option<Vec<u8>>,
+) -> NegTokenTarg1 {
+    NegTokenTarg1::from(NegTokenTarg {
+        neg_result: Optional::from(Some(ExplicitContextTag0::from(Asn1RawDer(ACCEPT_INCOMPLETE.to_vec())))),
+        supported_mech: Optional::from(supported_mech.map(ExplicitContextTag1::from)),
+        response_token: Optional::from(krb_ap_rep_token.map(|v| ExplicitContextTag2::from(OctetStringAsn1::from(v)))),
+        mech_list_mic: Optional::from(mech_list_mic.map(|v| ExplicitContextTag3::from(OctetStringAsn1::from(v)))),
+    })
+}
+
+pub fn generate_final_neg_token_resp() -> NegTokenTarg1 {
+    NegTokenTarg1::from(NegTokenTarg {
+        neg_result: Optional::from(Some(ExplicitContextTag0::from(Asn1RawDer(ACCEPT_COMPLETE.to_vec())))),
+        supported_mech: Optional::from(None),
+        response_token: Optional::from(None),
+        mech_list_mic: Optional::from(None),
+    })
+}
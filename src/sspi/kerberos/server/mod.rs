@@ -1 +1,105 @@
 pub mod extractors;
+pub mod generators;
+#[cfg(test)]
+mod test;
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use picky_krb::data_types::{KerberosTime, Microseconds};
+
+use super::ccache::CachedCredentials;
+use super::pac::Pac;
+use crate::{ContextNames, Result};
+
+// RFC 4120 recommends 5 minutes as the maximum allowed clock skew
+const DEFAULT_MAX_TIME_SKEW_MINUTES: i64 = 5;
+
+/// Long-term key of the service principal. It is used by the acceptor to decrypt incoming tickets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceKey {
+    pub encryption_type: i32,
+    /// key version number. `None` matches any kvno
+    pub kvno: Option<u32>,
+    pub key: Vec<u8>,
+}
+
+/// Authenticators which were already accepted by the service.
+/// The cache is shared between all clones, so one instance should be used for all security contexts of the service.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayCache {
+    entries: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl ReplayCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers the authenticator until `expires_at`.
+    /// Returns `false` if the same authenticator was already seen
+    pub fn insert(&self, authenticator_id: String, expires_at: DateTime<Utc>) -> bool {
+        let mut entries = self.entries.lock().expect("replay cache lock is poisoned");
+
+        let now = Utc::now();
+        entries.retain(|_, expiration| *expiration > now);
+
+        match entries.entry(authenticator_id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(expires_at);
+
+                true
+            }
+        }
+    }
+}
+
+/// Acceptor-side settings of the Kerberos security package
+#[derive(Debug, Clone)]
+pub struct ServerProperties {
+    /// Keys of the service principal. If empty, the keys are derived from the inbound credentials.
+    /// The password is tried only with the default salts and iteration count, so the keytab is recommended
+    /// for the service accounts with other ones
+    pub service_keys: Vec<ServiceKey>,
+    /// Keys of the krbtgt account. The KDC signature of the PAC is verified only if they are provided, because
+    /// a service usually does not know them
+    pub kdc_keys: Vec<ServiceKey>,
+    pub max_time_skew: Duration,
+    pub replay_cache: ReplayCache,
+    pub(crate) client: Option<ContextNames>,
+    // DER-encoded SPNEGO mechTypes protected by the mechListMIC
+    pub(crate) mech_types: Option<Vec<u8>>,
+    pub(crate) delegated_credentials: Vec<CachedCredentials>,
+    // session key of the TGT sent to the initiator. The user-to-user ticket is encrypted with it
+    pub(crate) user_to_user_key: Option<ServiceKey>,
+    // the PAC of the client ticket or the error of its decoding
+    pub(crate) pac: Option<Result<Box<Pac>>>,
+    // ctime and cusec of the authenticator, echoed by the DCE-style AP-REP of the initiator
+    pub(crate) authenticator_time: Option<(KerberosTime, Microseconds)>,
+}
+
+impl ServerProperties {
+    pub fn new(service_keys: Vec<ServiceKey>) -> Self {
+        Self {
+            service_keys,
+            kdc_keys: Vec::new(),
+            max_time_skew: Duration::minutes(DEFAULT_MAX_TIME_SKEW_MINUTES),
+            replay_cache: ReplayCache::new(),
+            client: None,
+            mech_types: None,
+            delegated_credentials: Vec::new(),
+            user_to_user_key: None,
+            pac: None,
+            authenticator_time: None,
+        }
+    }
+}
+
+impl Default for ServerProperties {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
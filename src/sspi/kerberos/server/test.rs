@@ -0,0 +1,371 @@
+use chrono::{Duration, Utc};
+use kerberos_constants::key_usages::KEY_USAGE_AS_REP_TICKET;
+use kerberos_crypto::new_kerberos_cipher;
+use oid::ObjectIdentifier;
+use picky_asn1::bit_string::BitString;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::restricted_string::IA5String;
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3,
+    ExplicitContextTag4, ExplicitContextTag5, ExplicitContextTag7, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_asn1_der::Asn1RawDer;
+use picky_asn1_x509::oids::KRB5;
+use picky_krb::constants::gss_api::ACCEPT_INCOMPLETE;
+use picky_krb::data_types::{
+    Authenticator, AuthenticatorInner, EncryptedData, EncryptionKey, KerberosFlags, KerberosStringAsn1, KerberosTime,
+    PrincipalName, Ticket, TicketInner,
+};
+use picky_krb::gss_api::{NegTokenTarg, NegTokenTarg1};
+use picky_krb::messages::ApReq;
+
+use super::extractors::{
+    extract_context_flags, extract_delegated_credentials, extract_enc_ticket_part, extract_initiator_message,
+    extract_tgt_ticket,
+};
+use super::generators::{generate_krb_tgt_rep_token, generate_neg_tgt_rep};
+use super::{ReplayCache, ServiceKey};
+use crate::sspi::kerberos::ccache::CachedCredentials;
+use crate::sspi::kerberos::client::generators::{
+    generate_ap_req, generate_authenticator_for_ap_req, generate_krb_cred, generate_neg_ap_req,
+    generate_neg_token_init, get_mech_list, DEFAULT_AP_REQ_OPTIONS, GSS_C_CONF_FLAG, GSS_C_DCE_STYLE, GSS_C_DELEG_FLAG,
+    GSS_C_MUTUAL_FLAG,
+};
+use crate::sspi::kerberos::client::AES256_CTS_HMAC_SHA1_96;
+use crate::sspi::kerberos::data_types::{EncTicketPart, EncTicketPartInner, TransitedEncoding};
+use crate::sspi::kerberos::encryption_params::EncryptionParams;
+use crate::sspi::kerberos::Principal;
+use crate::sspi::ErrorKind;
+
+const SERVICE_KEY: [u8; 32] = [
+    0x0e, 0x3b, 0x2d, 0x11, 0x49, 0xc2, 0x4f, 0x5a, 0x6d, 0x0c, 0x5e, 0x6f, 0x2b, 0x14, 0x88, 0x39, 0x1d, 0x7a, 0x52,
+    0x93, 0x30, 0x44, 0xae, 0x01, 0x62, 0x7b, 0x13, 0xcd, 0x99, 0x05, 0x2f, 0x8e,
+];
+
+fn principal_name(components: &[&str]) -> PrincipalName {
+    PrincipalName {
+        name_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![1])),
+        name_string: ExplicitContextTag1::from(Asn1SequenceOf::from(
+            components
+                .iter()
+                .map(|component| KerberosStringAsn1::from(IA5String::from_string((*component).to_owned()).unwrap()))
+                .collect::<Vec<_>>(),
+        )),
+    }
+}
+
+fn test_ticket(kvno: u8) -> Ticket {
+    let now = Utc::now();
+
+    let enc_ticket_part = EncTicketPart::from(EncTicketPartInner {
+        flags: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(vec![0x40, 0xa1, 0, 0]))),
+        key: ExplicitContextTag1::from(EncryptionKey {
+            key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            key_value: ExplicitContextTag1::from(OctetStringAsn1::from(vec![0x42; 32])),
+        }),
+        crealm: ExplicitContextTag2::from(KerberosStringAsn1::from(
+            IA5String::from_string("EXAMPLE.COM".into()).unwrap(),
+        )),
+        cname: ExplicitContextTag3::from(principal_name(&["user"])),
+        transited: ExplicitContextTag4::from(TransitedEncoding {
+            tr_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![1])),
+            contents: ExplicitContextTag1::from(OctetStringAsn1::from(Vec::new())),
+        }),
+        authtime: ExplicitContextTag5::from(KerberosTime::from(GeneralizedTime::from(now))),
+        starttime: Optional::from(None),
+        endtime: ExplicitContextTag7::from(KerberosTime::from(GeneralizedTime::from(now + Duration::hours(10)))),
+        renew_till: Optional::from(None),
+        caddr: Optional::from(None),
+        authorization_data: Optional::from(None),
+    });
+
+    let cipher = new_kerberos_cipher(AES256_CTS_HMAC_SHA1_96).unwrap();
+    let encrypted = cipher.encrypt(
+        &SERVICE_KEY,
+        KEY_USAGE_AS_REP_TICKET,
+        &picky_asn1_der::to_vec(&enc_ticket_part).unwrap(),
+    );
+
+    Ticket::from(TicketInner {
+        tkt_vno: ExplicitContextTag0::from(IntegerAsn1::from(vec![5])),
+        realm: ExplicitContextTag1::from(KerberosStringAsn1::from(
+            IA5String::from_string("EXAMPLE.COM".into()).unwrap(),
+        )),
+        sname: ExplicitContextTag2::from(principal_name(&["HTTP", "www.example.com"])),
+        enc_part: ExplicitContextTag3::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            kvno: Optional::from(Some(ExplicitContextTag1::from(IntegerAsn1::from(vec![kvno])))),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(encrypted)),
+        }),
+    })
+}
+
+#[test]
+fn replay_cache_rejects_the_same_authenticator() {
+    let cache = ReplayCache::new();
+    let expires_at = Utc::now() + Duration::minutes(5);
+
+    assert!(cache.insert("user@EXAMPLE.COM:1.1".into(), expires_at));
+    assert!(cache.clone().insert("user@EXAMPLE.COM:1.2".into(), expires_at));
+    assert!(!cache.insert("user@EXAMPLE.COM:1.1".into(), expires_at));
+}
+
+#[test]
+fn replay_cache_forgets_expired_authenticators() {
+    let cache = ReplayCache::new();
+
+    assert!(cache.insert("user@EXAMPLE.COM:1.1".into(), Utc::now() - Duration::seconds(1)));
+    assert!(cache.insert("user@EXAMPLE.COM:1.1".into(), Utc::now() + Duration::minutes(5)));
+}
+
+#[test]
+fn extract_enc_ticket_part_decrypts_ticket_with_matching_service_key() {
+    let service_keys = vec![
+        ServiceKey {
+            encryption_type: AES256_CTS_HMAC_SHA1_96,
+            kvno: Some(2),
+            key: vec![0; 32],
+        },
+        ServiceKey {
+            encryption_type: AES256_CTS_HMAC_SHA1_96,
+            kvno: Some(3),
+            key: SERVICE_KEY.to_vec(),
+        },
+    ];
+
+    let (enc_ticket_part, service_key) = extract_enc_ticket_part(&test_ticket(3), &service_keys).unwrap();
+
+    assert_eq!(service_key, &service_keys[1]);
+    assert_eq!(enc_ticket_part.0.key.0.key_value.0 .0, vec![0x42; 32]);
+    assert_eq!(enc_ticket_part.0.cname.0, principal_name(&["user"]));
+}
+
+#[test]
+fn extract_enc_ticket_part_fails_without_key_for_kvno() {
+    let service_keys = vec![ServiceKey {
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        kvno: Some(2),
+        key: SERVICE_KEY.to_vec(),
+    }];
+
+    let error = extract_enc_ticket_part(&test_ticket(3), &service_keys).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::NoKerdKey);
+}
+
+#[test]
+fn extract_enc_ticket_part_fails_with_wrong_key() {
+    let service_keys = vec![ServiceKey {
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        kvno: None,
+        key: vec![0; 32],
+    }];
+
+    let error = extract_enc_ticket_part(&test_ticket(3), &service_keys).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::DecryptFailure);
+}
+
+#[test]
+fn extract_initiator_message_unwraps_spnego_neg_token_resp() {
+    let authenticator = Authenticator::from(AuthenticatorInner {
+        authenticator_bno: ExplicitContextTag0::from(IntegerAsn1::from(vec![5])),
+        crealm: ExplicitContextTag1::from(KerberosStringAsn1::from(
+            IA5String::from_string("EXAMPLE.COM".into()).unwrap(),
+        )),
+        cname: ExplicitContextTag2::from(principal_name(&["user"])),
+        cksum: Optional::from(None),
+        cusec: ExplicitContextTag4::from(IntegerAsn1::from(vec![1])),
+        ctime: ExplicitContextTag5::from(KerberosTime::from(GeneralizedTime::from(Utc::now()))),
+        subkey: Optional::from(None),
+        seq_number: Optional::from(None),
+        authorization_data: Optional::from(None),
+    });
+    let ap_req = generate_ap_req(
+        test_ticket(3),
+        &[0x42; 32],
+        &authenticator,
+        DEFAULT_AP_REQ_OPTIONS,
+        &EncryptionParams::default_for_client(),
+    )
+    .unwrap();
+
+    let token = picky_asn1_der::to_vec(
+        &generate_neg_ap_req(ap_req.clone(), ObjectIdentifier::try_from(KRB5).unwrap()).unwrap(),
+    )
+    .unwrap();
+    let message = extract_initiator_message(&token).unwrap();
+
+    assert!(message.is_spnego);
+    assert_eq!(message.token_id, Some([0x01, 0x00]));
+    assert_eq!(
+        picky_asn1_der::from_bytes::<ApReq>(&message.krb_message).unwrap(),
+        ap_req
+    );
+}
+
+#[test]
+fn extract_initiator_message_reads_mech_types_from_neg_token_init() {
+    let token = picky_asn1_der::to_vec(
+        &generate_neg_token_init(
+            &Principal::new("TERMSRV/websvc.example.com", "EXAMPLE.COM"),
+            get_mech_list(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let message = extract_initiator_message(&token).unwrap();
+
+    assert!(message.is_spnego);
+    assert_eq!(message.mech_types.unwrap().0.len(), 2);
+    assert_eq!(message.token_id, Some([0x04, 0x00]));
+}
+
+#[test]
+fn extract_initiator_message_rejects_nested_spnego_tokens() {
+    let neg_token_init = picky_asn1_der::to_vec(
+        &generate_neg_token_init(
+            &Principal::new("TERMSRV/websvc.example.com", "EXAMPLE.COM"),
+            get_mech_list(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let token = picky_asn1_der::to_vec(&NegTokenTarg1::from(NegTokenTarg {
+        neg_result: Optional::from(Some(ExplicitContextTag0::from(Asn1RawDer(ACCEPT_INCOMPLETE.to_vec())))),
+        supported_mech: Optional::from(None),
+        response_token: Optional::from(Some(ExplicitContextTag2::from(OctetStringAsn1::from(neg_token_init)))),
+        mech_list_mic: Optional::from(None),
+    }))
+    .unwrap();
+
+    let error = extract_initiator_message(&token).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+}
+
+#[test]
+fn tgt_of_service_is_extracted_from_tgt_rep() {
+    let tgt = test_ticket(3);
+    let token = generate_neg_tgt_rep(Some(generate_krb_tgt_rep_token(tgt.clone()).unwrap()), None);
+
+    assert_eq!(
+        extract_tgt_ticket(&picky_asn1_der::to_vec(&token).unwrap()).unwrap(),
+        Some(tgt)
+    );
+    assert_eq!(
+        extract_tgt_ticket(&picky_asn1_der::to_vec(&generate_neg_tgt_rep(None, None)).unwrap()).unwrap(),
+        None
+    );
+}
+
+fn forwarded_tgt() -> CachedCredentials {
+    CachedCredentials {
+        client: Principal::new("user", "EXAMPLE.COM"),
+        server: Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM"),
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        key: vec![0x24; 32],
+        auth_time: 1_700_000_000,
+        start_time: 1_700_000_100,
+        end_time: 1_700_036_000,
+        renew_till: 1_700_604_800,
+        is_skey: false,
+        ticket_flags: 0x60a1_0000,
+        addresses: Vec::new(),
+        auth_data: Vec::new(),
+        ticket: picky_asn1_der::to_vec(&test_ticket(3)).unwrap(),
+        second_ticket: Vec::new(),
+    }
+}
+
+fn session_key() -> EncryptionKey {
+    EncryptionKey {
+        key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+        key_value: ExplicitContextTag1::from(OctetStringAsn1::from(vec![0x42; 32])),
+    }
+}
+
+#[test]
+fn delegated_credentials_are_extracted_from_authenticator_checksum() {
+    let forwarded_tgt = forwarded_tgt();
+    let krb_cred = generate_krb_cred(&forwarded_tgt, &[0x42; 32], AES256_CTS_HMAC_SHA1_96, Utc::now()).unwrap();
+    let authenticator = generate_authenticator_for_ap_req(
+        &principal_name(&["user"]),
+        &forwarded_tgt.client.kerberos_realm().unwrap(),
+        1,
+        AES256_CTS_HMAC_SHA1_96,
+        Utc::now(),
+        Some(&krb_cred),
+        0,
+    )
+    .unwrap();
+
+    let delegated_credentials = extract_delegated_credentials(&authenticator.0, &session_key()).unwrap();
+
+    assert_eq!(delegated_credentials, [forwarded_tgt]);
+}
+
+#[test]
+fn authenticator_without_delegation_has_no_delegated_credentials() {
+    let authenticator = generate_authenticator_for_ap_req(
+        &principal_name(&["user"]),
+        &forwarded_tgt().client.kerberos_realm().unwrap(),
+        1,
+        AES256_CTS_HMAC_SHA1_96,
+        Utc::now(),
+        None,
+        0,
+    )
+    .unwrap();
+
+    assert!(extract_delegated_credentials(&authenticator.0, &session_key())
+        .unwrap()
+        .is_empty());
+    assert_eq!(extract_context_flags(&authenticator.0), 0);
+}
+
+#[test]
+fn context_flags_are_requested_in_authenticator_checksum() {
+    let forwarded_tgt = forwarded_tgt();
+    let krb_cred = generate_krb_cred(&forwarded_tgt, &[0x42; 32], AES256_CTS_HMAC_SHA1_96, Utc::now()).unwrap();
+    let authenticator = generate_authenticator_for_ap_req(
+        &principal_name(&["user"]),
+        &forwarded_tgt.client.kerberos_realm().unwrap(),
+        1,
+        AES256_CTS_HMAC_SHA1_96,
+        Utc::now(),
+        Some(&krb_cred),
+        GSS_C_MUTUAL_FLAG | GSS_C_CONF_FLAG | GSS_C_DCE_STYLE,
+    )
+    .unwrap();
+
+    // the delegation flag is set together with the KRB-CRED
+    assert_eq!(
+        extract_context_flags(&authenticator.0),
+        GSS_C_DELEG_FLAG | GSS_C_MUTUAL_FLAG | GSS_C_CONF_FLAG | GSS_C_DCE_STYLE
+    );
+    assert_eq!(
+        extract_delegated_credentials(&authenticator.0, &session_key()).unwrap(),
+        [forwarded_tgt]
+    );
+}
+
+#[test]
+fn delegated_credentials_encrypted_with_other_key_are_rejected() {
+    let krb_cred = generate_krb_cred(&forwarded_tgt(), &[0x11; 32], AES256_CTS_HMAC_SHA1_96, Utc::now()).unwrap();
+    let mut authenticator = generate_authenticator_for_ap_req(
+        &principal_name(&["user"]),
+        &forwarded_tgt().client.kerberos_realm().unwrap(),
+        1,
+        AES256_CTS_HMAC_SHA1_96,
+        Utc::now(),
+        Some(&krb_cred),
+        0,
+    )
+    .unwrap();
+    authenticator.0.subkey = Optional::from(None);
+
+    let error = extract_delegated_credentials(&authenticator.0, &session_key()).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::DecryptFailure);
+}
@@ -0,0 +1,1685 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Timelike, Utc};
+use kerberos_constants::error_codes::{
+    KDC_ERR_BADOPTION, KDC_ERR_C_PRINCIPAL_UNKNOWN, KDC_ERR_PREAUTH_FAILED, KDC_ERR_PREAUTH_REQUIRED,
+    KDC_ERR_WRONG_REALM, KRB_AP_ERR_SKEW,
+};
+use kerberos_constants::key_usages::{KEY_USAGE_AS_REP_TICKET, KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY};
+use picky_asn1::bit_string::BitString;
+use picky_asn1::date::GeneralizedTime;
+use picky_asn1::restricted_string::IA5String;
+use picky_asn1::wrapper::{
+    Asn1SequenceOf, ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag10, ExplicitContextTag11,
+    ExplicitContextTag12, ExplicitContextTag2, ExplicitContextTag3, ExplicitContextTag4, ExplicitContextTag5,
+    ExplicitContextTag6, ExplicitContextTag7, ExplicitContextTag9, IntegerAsn1, OctetStringAsn1, Optional,
+};
+use picky_krb::constants::types::{KRB_ERROR_MSG_TYPE, PA_ENC_TIMESTAMP, TGS_REP_MSG_TYPE};
+use picky_krb::data_types::{
+    AuthorizationData, EncryptedData, EncryptionKey, KerberosFlags, KerberosStringAsn1, KerberosTime, PaData, Ticket,
+    TicketInner,
+};
+use picky_krb::gss_api::{NegTokenTarg1, WrapToken};
+use picky_krb::messages::{
+    ApRep, ApReq, AsReq, EncKdcRepPart, EncTgsRepPart, KdcRep, KrbError, KrbErrorInner, TgsRep, TgsReq, TgtReq,
+};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+use super::ccache::{CachedCredentials, CredentialsCache, MemoryCredentialsCache};
+use super::client::generators::{
+    generate_as_req_without_pre_auth, DEFAULT_TGS_REQ_OPTIONS, FORWARDED_TGT_REQ_OPTIONS, GSS_C_DCE_STYLE,
+    S4U2PROXY_TGS_REQ_OPTIONS, S4U2SELF_TGS_REQ_OPTIONS, USER_TO_USER_TGS_REQ_OPTIONS,
+};
+use super::client::{AES128_CTS_HMAC_SHA256_128, AES256_CTS_HMAC_SHA384_192, RC4_HMAC};
+use super::config::KerberosConfig;
+use super::credentials::NT_SRV_INST;
+use super::data_types::{EncTicketPart, EncTicketPartInner, TransitedEncoding};
+use super::kdc_locator::{KdcLocator, Krb5Conf};
+use super::network_client::NetworkClient;
+use super::pac::test::ticket_authorization_data;
+use super::server::extractors::extract_initiator_message;
+use super::utils::{integer_to_u32, serialize_message};
+use super::{
+    crypto, principal_name_to_string, CredentialsBuffers, Kerberos, KerberosError, KerberosState, Principal,
+    ServerProperties, ServiceKey, AES256_CTS_HMAC_SHA1_96, KERBEROS_VERSION, SEALED_FLAG, SENT_BY_ACCEPTOR_FLAG,
+};
+use crate::sspi::{
+    AuthIdentity, ClientRequestFlags, ClientResponseFlags, DataRepresentation, Error, ErrorKind, Result,
+    SecurityBuffer, SecurityBufferType, SecurityStatus, ServerRequestFlags, ServerResponseFlags, Sspi,
+};
+use crate::{AcceptSecurityContextResult, DecryptionFlags, EncryptionFlags, InitializeSecurityContextResult};
+
+const SESSION_KEY: [u8; 32] = [
+    0x5d, 0x17, 0x8b, 0x31, 0xe0, 0x2a, 0x4c, 0x96, 0x0f, 0x73, 0xb8, 0x1e, 0x44, 0xc5, 0x29, 0x6a, 0xd3, 0x08, 0x7f,
+    0x61, 0x9c, 0x35, 0xea, 0x12, 0x4b, 0x80, 0x57, 0xf6, 0x2d, 0xa9, 0x03, 0xbe,
+];
+
+#[derive(Debug, Clone)]
+struct NoNetworkClient;
+
+impl NetworkClient for NoNetworkClient {
+    fn send(&self, _url: &Url, _data: &[u8]) -> Result<Vec<u8>> {
+        Err(Error::new(ErrorKind::InternalError, "Network is not available".into()))
+    }
+
+    fn send_http(&self, _url: &Url, _data: &[u8], _domain: Option<String>) -> Result<Vec<u8>> {
+        Err(Error::new(ErrorKind::InternalError, "Network is not available".into()))
+    }
+
+    fn box_clone(&self) -> Box<dyn NetworkClient> {
+        Box::new(self.clone())
+    }
+}
+
+fn established_contexts() -> (Kerberos, Kerberos) {
+    established_contexts_with(AES256_CTS_HMAC_SHA1_96)
+}
+
+fn established_contexts_with(encryption_type: i32) -> (Kerberos, Kerberos) {
+    let config = KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(NoNetworkClient),
+    );
+
+    let mut client = Kerberos::new_client_from_config(config.clone()).unwrap();
+    let mut server = Kerberos::new_server_from_config(config).unwrap();
+
+    for context in [&mut client, &mut server] {
+        context.encryption_params.encryption_type = Some(encryption_type);
+        context.encryption_params.sub_session_key =
+            Some(SESSION_KEY[..crypto::key_len(encryption_type).unwrap()].to_vec());
+        context.has_acceptor_subkey = true;
+        context.state = KerberosState::Established;
+    }
+
+    (client, server)
+}
+
+fn signed_message(context: &mut Kerberos, data: &[u8]) -> Vec<SecurityBuffer> {
+    let mut message = vec![
+        SecurityBuffer::new(data.to_vec(), SecurityBufferType::Data),
+        SecurityBuffer::new(Vec::new(), SecurityBufferType::Token),
+    ];
+    context.make_signature(0, &mut message, 0).unwrap();
+
+    message
+}
+
+fn wrapped_message(context: &mut Kerberos, flags: EncryptionFlags, data: &[u8]) -> Vec<SecurityBuffer> {
+    let mut message = vec![
+        SecurityBuffer::new(Vec::new(), SecurityBufferType::Token),
+        SecurityBuffer::new(data.to_vec(), SecurityBufferType::Data),
+    ];
+    context.encrypt_message(flags, &mut message, 0).unwrap();
+
+    message
+}
+
+#[test]
+fn signature_of_client_is_verified_by_server() {
+    let (mut client, mut server) = established_contexts();
+
+    let mut message = signed_message(&mut client, b"ldap request");
+
+    assert_eq!(message[0].buffer, b"ldap request");
+    assert_eq!(&message[1].buffer[0..2], &[0x04, 0x04]);
+    server.verify_signature(&mut message, 0).unwrap();
+}
+
+#[test]
+fn signature_of_server_is_verified_by_client() {
+    let (mut client, mut server) = established_contexts();
+
+    let mut message = signed_message(&mut server, b"ldap response");
+
+    client.verify_signature(&mut message, 0).unwrap();
+}
+
+#[test]
+fn verify_signature_fails_on_altered_message() {
+    let (mut client, mut server) = established_contexts();
+
+    let mut message = signed_message(&mut client, b"ldap request");
+    message[0].buffer[0] ^= 0xff;
+
+    assert_eq!(
+        server.verify_signature(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
+#[test]
+fn verify_signature_fails_on_own_signature() {
+    let (mut client, _) = established_contexts();
+
+    let mut message = signed_message(&mut client, b"ldap request");
+
+    assert_eq!(
+        client.verify_signature(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
+// the acceptor subkey of the MIT krb5 context which wrapped "ldap request" without confidentiality
+const MIT_ACCEPTOR_SUBKEY: [u8; 32] = [
+    0x8f, 0x55, 0xe5, 0x03, 0x4d, 0x72, 0x80, 0x91, 0xcc, 0xfe, 0x1a, 0x2f, 0xa2, 0x71, 0xae, 0xcc, 0xfb, 0x9d, 0x91,
+    0xfa, 0x9b, 0x8b, 0x1b, 0x73, 0x0a, 0x46, 0x40, 0x05, 0xad, 0x4e, 0x38, 0xbe,
+];
+
+// gss_wrap of MIT krb5 by the initiator with sequence number 0x052e943a: EC = 12, RRC = 0
+const MIT_INITIATOR_WRAP_TOKEN: [u8; 40] = [
+    0x05, 0x04, 0x04, 0xff, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x2e, 0x94, 0x3a, 0x6c, 0x64, 0x61,
+    0x70, 0x20, 0x72, 0x65, 0x71, 0x75, 0x65, 0x73, 0x74, 0x3d, 0x16, 0x12, 0x1a, 0xda, 0xe1, 0x54, 0x87, 0x5d, 0xd9,
+    0x0c, 0x8e,
+];
+
+// gss_wrap of MIT krb5 by the acceptor with sequence number 0x341c3cc8
+const MIT_ACCEPTOR_WRAP_TOKEN: [u8; 40] = [
+    0x05, 0x04, 0x05, 0xff, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34, 0x1c, 0x3c, 0xc8, 0x6c, 0x64, 0x61,
+    0x70, 0x20, 0x72, 0x65, 0x71, 0x75, 0x65, 0x73, 0x74, 0xc8, 0x3e, 0x35, 0xe6, 0xb4, 0x9f, 0xc5, 0xa0, 0x46, 0xdd,
+    0xdf, 0xa7,
+];
+
+fn mit_contexts() -> (Kerberos, Kerberos) {
+    let (mut client, mut server) = established_contexts();
+    client.encryption_params.sub_session_key = Some(MIT_ACCEPTOR_SUBKEY.to_vec());
+    server.encryption_params.sub_session_key = Some(MIT_ACCEPTOR_SUBKEY.to_vec());
+
+    (client, server)
+}
+
+fn mit_message(token: &[u8]) -> Vec<SecurityBuffer> {
+    vec![
+        SecurityBuffer::new(token[..16].to_vec(), SecurityBufferType::Token),
+        SecurityBuffer::new(token[16..].to_vec(), SecurityBufferType::Data),
+    ]
+}
+
+#[test]
+fn integrity_only_wrap_token_of_mit_is_unwrapped() {
+    let (mut client, mut server) = mit_contexts();
+
+    let mut message = mit_message(&MIT_INITIATOR_WRAP_TOKEN);
+    assert_eq!(
+        server.decrypt_message(&mut message, 0).unwrap(),
+        DecryptionFlags::WRAP_NO_ENCRYPT
+    );
+    assert_eq!(message[1].buffer, b"ldap request");
+
+    let mut message = mit_message(&MIT_ACCEPTOR_WRAP_TOKEN);
+    assert_eq!(
+        client.decrypt_message(&mut message, 0).unwrap(),
+        DecryptionFlags::WRAP_NO_ENCRYPT
+    );
+    assert_eq!(message[1].buffer, b"ldap request");
+}
+
+#[test]
+fn integrity_only_wrap_token_matches_mit() {
+    let (mut client, _) = mit_contexts();
+    client.seq_number = 0x052e943a - 1;
+
+    let message = wrapped_message(&mut client, EncryptionFlags::WRAP_NO_ENCRYPT, b"ldap request");
+
+    // the same token with the checksum rotated into the header: RRC = 12
+    let mut expected_header = MIT_INITIATOR_WRAP_TOKEN[..16].to_vec();
+    expected_header[7] = 0x0c;
+    expected_header.extend_from_slice(&MIT_INITIATOR_WRAP_TOKEN[28..]);
+    assert_eq!(message[0].buffer, expected_header);
+    assert_eq!(message[1].buffer, b"ldap request");
+}
+
+#[test]
+fn wrap_token_with_rrc_above_its_len_is_unwrapped() {
+    let (_, mut server) = mit_contexts();
+
+    // the rotation by the len of the plaintext and the checksum is no rotation
+    let mut message = mit_message(&MIT_INITIATOR_WRAP_TOKEN);
+    message[0].buffer[7] = 48;
+
+    server.decrypt_message(&mut message, 0).unwrap();
+    assert_eq!(message[1].buffer, b"ldap request");
+}
+
+#[test]
+fn sealed_wrap_token_is_reported_as_encrypted() {
+    let (mut client, mut server) = established_contexts();
+
+    let mut message = wrapped_message(&mut server, EncryptionFlags::empty(), b"ldap response");
+
+    assert_eq!(&message[0].buffer[0..3], &[0x05, 0x04, 0x07]);
+    assert_ne!(message[1].buffer, b"ldap response");
+
+    let flags = client.decrypt_message(&mut message, 0).unwrap();
+
+    assert_eq!(flags, DecryptionFlags::empty());
+    assert_eq!(message[1].buffer, b"ldap response");
+}
+
+#[test]
+fn sealed_wrap_token_fails_on_altered_header() {
+    let (mut client, mut server) = established_contexts();
+
+    let mut message = wrapped_message(&mut client, EncryptionFlags::empty(), b"ldap request");
+    // the acceptor subkey flag is not used otherwise
+    message[0].buffer[2] ^= 0x04;
+
+    assert_eq!(
+        server.decrypt_message(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
+#[test]
+fn sealed_wrap_token_shorter_than_its_header_is_rejected() {
+    let (_, mut server) = established_contexts();
+
+    let mut wrap_token = WrapToken::with_seq_number(0);
+    wrap_token.set_checksum(crypto::new_cipher(AES256_CTS_HMAC_SHA1_96).unwrap().encrypt(
+        &SESSION_KEY,
+        server.encryption_params.sspi_decrypt_key_usage,
+        b"data",
+    ));
+    let mut raw_wrap_token = Vec::new();
+    wrap_token.encode(&mut raw_wrap_token).unwrap();
+    let mut message = vec![
+        SecurityBuffer::new(raw_wrap_token, SecurityBufferType::Token),
+        SecurityBuffer::new(Vec::new(), SecurityBufferType::Data),
+    ];
+
+    assert_eq!(
+        server.decrypt_message(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::InvalidToken
+    );
+}
+
+#[test]
+fn integrity_only_wrap_token_fails_on_altered_message() {
+    let (mut client, mut server) = established_contexts();
+
+    let mut message = wrapped_message(&mut client, EncryptionFlags::WRAP_NO_ENCRYPT, b"ldap request");
+    message[1].buffer[0] ^= 0xff;
+
+    assert_eq!(
+        server.decrypt_message(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
+#[test]
+fn messages_are_wrapped_in_both_directions_after_establishment() {
+    let (mut client, mut server) = established_contexts();
+
+    for _ in 0..5 {
+        for flags in [EncryptionFlags::empty(), EncryptionFlags::WRAP_NO_ENCRYPT] {
+            let mut message = wrapped_message(&mut client, flags, b"request");
+            server.decrypt_message(&mut message, 0).unwrap();
+            assert_eq!(message[1].buffer, b"request");
+
+            let mut message = wrapped_message(&mut server, flags, b"response");
+            client.decrypt_message(&mut message, 0).unwrap();
+            assert_eq!(message[1].buffer, b"response");
+        }
+    }
+}
+
+#[test]
+fn replayed_wrap_token_is_rejected() {
+    let (mut client, mut server) = established_contexts();
+
+    let message = wrapped_message(&mut client, EncryptionFlags::empty(), b"request");
+    server.decrypt_message(&mut message.clone(), 0).unwrap();
+
+    assert_eq!(
+        server.decrypt_message(&mut message.clone(), 0).unwrap_err().error_type,
+        ErrorKind::OutOfSequence
+    );
+}
+
+#[test]
+fn out_of_order_wrap_token_is_rejected() {
+    let (mut client, mut server) = established_contexts();
+
+    let mut first = wrapped_message(&mut client, EncryptionFlags::empty(), b"first");
+    let mut second = wrapped_message(&mut client, EncryptionFlags::empty(), b"second");
+
+    server.decrypt_message(&mut second, 0).unwrap();
+
+    assert_eq!(
+        server.decrypt_message(&mut first, 0).unwrap_err().error_type,
+        ErrorKind::OutOfSequence
+    );
+}
+
+#[test]
+fn replayed_signature_is_rejected() {
+    let (mut client, mut server) = established_contexts();
+
+    let message = signed_message(&mut client, b"ldap request");
+    server.verify_signature(&mut message.clone(), 0).unwrap();
+
+    assert_eq!(
+        server.verify_signature(&mut message.clone(), 0).unwrap_err().error_type,
+        ErrorKind::OutOfSequence
+    );
+}
+
+#[test]
+fn make_signature_fails_before_establishment() {
+    let (mut client, _) = established_contexts();
+    client.state = KerberosState::ApExchange;
+
+    let mut message = vec![
+        SecurityBuffer::new(b"request".to_vec(), SecurityBufferType::Data),
+        SecurityBuffer::new(Vec::new(), SecurityBufferType::Token),
+    ];
+
+    assert_eq!(
+        client.make_signature(0, &mut message, 0).unwrap_err().error_type,
+        ErrorKind::OutOfSequence
+    );
+}
+
+#[test]
+fn verify_signature_fails_before_establishment() {
+    let (mut client, mut server) = established_contexts();
+    let mut message = signed_message(&mut client, b"request");
+    server.state = KerberosState::ApExchange;
+
+    assert_eq!(
+        server.verify_signature(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::OutOfSequence
+    );
+}
+
+#[test]
+fn make_signature_rejects_quality_of_protection() {
+    let (mut client, _) = established_contexts();
+
+    let mut message = vec![
+        SecurityBuffer::new(b"request".to_vec(), SecurityBufferType::Data),
+        SecurityBuffer::new(Vec::new(), SecurityBufferType::Token),
+    ];
+
+    assert_eq!(
+        client
+            .make_signature(EncryptionFlags::WRAP_NO_ENCRYPT.bits(), &mut message, 0)
+            .unwrap_err()
+            .error_type,
+        ErrorKind::OperationNotSupported
+    );
+}
+
+#[test]
+fn max_signature_is_len_of_mic_token() {
+    for (encryption_type, max_signature) in [
+        (AES256_CTS_HMAC_SHA1_96, 28),
+        (AES128_CTS_HMAC_SHA256_128, 32),
+        (AES256_CTS_HMAC_SHA384_192, 40),
+        (RC4_HMAC, 37),
+    ] {
+        let (mut client, _) = established_contexts_with(encryption_type);
+
+        assert_eq!(client.query_context_sizes().unwrap().max_signature, max_signature);
+
+        // the buffer sized by the caller is filled entirely
+        let mut message = vec![
+            SecurityBuffer::new(b"request".to_vec(), SecurityBufferType::Data),
+            SecurityBuffer::new(vec![0; max_signature as usize], SecurityBufferType::Token),
+        ];
+        client.make_signature(0, &mut message, 0).unwrap();
+        assert_eq!(message[1].buffer.len(), max_signature as usize);
+    }
+}
+
+#[test]
+fn make_signature_fails_on_too_small_buffer() {
+    let (mut client, _) = established_contexts();
+
+    let mut message = vec![
+        SecurityBuffer::new(b"request".to_vec(), SecurityBufferType::Data),
+        SecurityBuffer::new(vec![0; 16], SecurityBufferType::Token),
+    ];
+
+    assert_eq!(
+        client.make_signature(0, &mut message, 0).unwrap_err().error_type,
+        ErrorKind::BufferTooSmall
+    );
+    assert_eq!(message[1].buffer, [0; 16]);
+}
+
+#[test]
+fn encrypt_message_fails_before_establishment() {
+    let (mut client, _) = established_contexts();
+    client.state = KerberosState::ApExchange;
+
+    let mut message = vec![
+        SecurityBuffer::new(Vec::new(), SecurityBufferType::Token),
+        SecurityBuffer::new(b"request".to_vec(), SecurityBufferType::Data),
+    ];
+
+    assert_eq!(
+        client
+            .encrypt_message(EncryptionFlags::empty(), &mut message, 0)
+            .unwrap_err()
+            .error_type,
+        ErrorKind::OutOfSequence
+    );
+}
+
+#[test]
+fn messages_are_protected_with_every_encryption_type() {
+    for encryption_type in [AES128_CTS_HMAC_SHA256_128, AES256_CTS_HMAC_SHA384_192, RC4_HMAC] {
+        let (mut client, mut server) = established_contexts_with(encryption_type);
+
+        for flags in [EncryptionFlags::empty(), EncryptionFlags::WRAP_NO_ENCRYPT] {
+            let mut message = wrapped_message(&mut client, flags, b"ldap request");
+            let decryption_flags = server.decrypt_message(&mut message, 0).unwrap();
+            assert_eq!(message[1].buffer, b"ldap request");
+            assert_eq!(
+                decryption_flags.contains(DecryptionFlags::WRAP_NO_ENCRYPT),
+                flags.contains(EncryptionFlags::WRAP_NO_ENCRYPT)
+            );
+
+            let mut message = wrapped_message(&mut server, flags, b"ldap response");
+            client.decrypt_message(&mut message, 0).unwrap();
+            assert_eq!(message[1].buffer, b"ldap response");
+        }
+
+        let mut message = signed_message(&mut client, b"ldap request");
+        server.verify_signature(&mut message, 0).unwrap();
+
+        let mut message = signed_message(&mut server, b"ldap response");
+        client.verify_signature(&mut message, 0).unwrap();
+    }
+}
+
+#[test]
+fn rc4_wrap_token_fails_on_altered_message() {
+    let (mut client, mut server) = established_contexts_with(RC4_HMAC);
+
+    let mut message = wrapped_message(&mut client, EncryptionFlags::empty(), b"ldap request");
+    message[1].buffer[0] ^= 0xff;
+
+    assert_eq!(
+        server.decrypt_message(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
+#[test]
+fn rc4_dce_style_wrap_token_is_not_padded() {
+    let (mut client, mut server) = established_contexts_with(RC4_HMAC);
+    client.context_flags = GSS_C_DCE_STYLE;
+    server.context_flags = GSS_C_DCE_STYLE;
+
+    let mut message = wrapped_message(&mut client, EncryptionFlags::empty(), b"sixteen byte pdu");
+    assert_eq!(message[1].buffer.len(), 16);
+
+    server.decrypt_message(&mut message, 0).unwrap();
+    assert_eq!(message[1].buffer, b"sixteen byte pdu");
+}
+
+#[test]
+fn rc4_wrap_token_of_own_direction_is_rejected() {
+    let (mut client, _) = established_contexts_with(RC4_HMAC);
+
+    let mut message = wrapped_message(&mut client, EncryptionFlags::empty(), b"ldap request");
+
+    assert_eq!(
+        client.decrypt_message(&mut message, 0).unwrap_err().error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
+fn config_with_encryption_types(permitted_encryption_types: Vec<i32>) -> KerberosConfig {
+    KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(NoNetworkClient),
+    )
+    .with_permitted_encryption_types(permitted_encryption_types)
+}
+
+#[test]
+fn kdc_requests_contain_only_permitted_encryption_types() {
+    let client = Kerberos::new_client_from_config(config_with_encryption_types(vec![
+        AES128_CTS_HMAC_SHA256_128,
+        AES256_CTS_HMAC_SHA1_96,
+    ]))
+    .unwrap();
+
+    let as_req = generate_as_req_without_pre_auth("user", "EXAMPLE.COM", &client.encryption_params).unwrap();
+    let etypes = as_req
+        .0
+        .req_body
+        .0
+        .etype
+        .0
+         .0
+        .iter()
+        .map(|etype| integer_to_u32(etype) as i32)
+        .collect::<Vec<_>>();
+
+    assert_eq!(etypes, [AES128_CTS_HMAC_SHA256_128, AES256_CTS_HMAC_SHA1_96]);
+    assert_eq!(client.encryption_params.encryption_type(), AES128_CTS_HMAC_SHA256_128);
+}
+
+#[test]
+fn not_permitted_encryption_type_is_rejected() {
+    let client = Kerberos::new_client_from_config(config_with_encryption_types(vec![AES256_CTS_HMAC_SHA1_96])).unwrap();
+
+    client
+        .encryption_params
+        .check_permitted(AES256_CTS_HMAC_SHA1_96)
+        .unwrap();
+    assert_eq!(
+        client
+            .encryption_params
+            .check_permitted(RC4_HMAC)
+            .unwrap_err()
+            .error_type,
+        ErrorKind::KdcUnknownEType
+    );
+}
+
+#[test]
+fn context_creation_fails_with_unsupported_encryption_type() {
+    // DES3-CBC-SHA1
+    let error = Kerberos::new_client_from_config(config_with_encryption_types(vec![16])).unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::KdcUnknownEType);
+
+    let error = Kerberos::new_server_from_config(config_with_encryption_types(Vec::new())).unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::InvalidParameter);
+}
+
+// KDC url and the request sent to it
+type KdcRequest = (String, Vec<u8>);
+
+// replies with the scripted KDC messages and records the requests with the KDC urls
+#[derive(Debug, Clone, Default)]
+struct ScriptedNetworkClient {
+    replies: Arc<Mutex<Vec<Vec<u8>>>>,
+    requests: Arc<Mutex<Vec<KdcRequest>>>,
+}
+
+impl ScriptedNetworkClient {
+    fn new(mut replies: Vec<Vec<u8>>) -> Self {
+        replies.reverse();
+
+        Self {
+            replies: Arc::new(Mutex::new(replies)),
+            requests: Arc::default(),
+        }
+    }
+
+    fn requests<T: DeserializeOwned>(&self) -> Vec<T> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, request)| picky_asn1_der::from_bytes(&request[4..]).unwrap())
+            .collect()
+    }
+
+    fn kdc_urls(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+}
+
+impl NetworkClient for ScriptedNetworkClient {
+    fn send(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>> {
+        self.requests.lock().unwrap().push((url.to_string(), data.to_vec()));
+
+        self.replies
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or_else(|| Error::new(ErrorKind::InternalError, "No more KDC replies".into()))
+    }
+
+    fn send_http(&self, url: &Url, data: &[u8], _domain: Option<String>) -> Result<Vec<u8>> {
+        self.send(url, data)
+    }
+
+    fn box_clone(&self) -> Box<dyn NetworkClient> {
+        Box::new(self.clone())
+    }
+}
+
+fn krb_error(error_code: i32, server_time: DateTime<Utc>, e_data: Option<Vec<u8>>) -> KrbError {
+    let principal = Principal::new("krbtgt/EXAMPLE.COM", "EXAMPLE.COM");
+
+    KrbError::from(KrbErrorInner {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![KRB_ERROR_MSG_TYPE])),
+        ctime: Optional::from(None),
+        cusec: Optional::from(None),
+        stime: ExplicitContextTag4::from(KerberosTime::from(GeneralizedTime::from(server_time))),
+        susec: ExplicitContextTag5::from(IntegerAsn1::from(vec![0])),
+        error_code: ExplicitContextTag6::from(IntegerAsn1::from(vec![error_code as u8])),
+        crealm: Optional::from(None),
+        cname: Optional::from(None),
+        realm: ExplicitContextTag9::from(principal.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag10::from(principal.principal_name().unwrap()),
+        e_text: Optional::from(None),
+        e_data: Optional::from(e_data.map(|e_data| ExplicitContextTag12::from(OctetStringAsn1::from(e_data)))),
+    })
+}
+
+// METHOD-DATA of the KDC_ERR_PREAUTH_REQUIRED error
+fn method_data(padata_types: &[&[u8]]) -> Vec<u8> {
+    picky_asn1_der::to_vec(&Asn1SequenceOf::from(
+        padata_types
+            .iter()
+            .map(|padata_type| PaData {
+                padata_type: ExplicitContextTag1::from(IntegerAsn1::from(padata_type.to_vec())),
+                padata_data: ExplicitContextTag2::from(OctetStringAsn1::from(Vec::new())),
+            })
+            .collect::<Vec<_>>(),
+    ))
+    .unwrap()
+}
+
+fn client_with_kdc_replies(replies: Vec<KrbError>) -> (Kerberos, ScriptedNetworkClient) {
+    let network_client =
+        ScriptedNetworkClient::new(replies.iter().map(|reply| serialize_message(reply).unwrap()).collect());
+    let config = KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(network_client.clone()),
+    );
+
+    (Kerberos::new_client_from_config(config).unwrap(), network_client)
+}
+
+fn password_credentials() -> CredentialsBuffers {
+    CredentialsBuffers::AuthIdentity(
+        AuthIdentity {
+            username: "user".into(),
+            password: "password".into(),
+            domain: Some("EXAMPLE.COM".into()),
+        }
+        .into(),
+    )
+}
+
+#[test]
+fn kerberos_error_is_exposed_on_error() {
+    let server_time = Utc::now() + Duration::hours(1);
+    let mut krb_error = krb_error(KRB_AP_ERR_SKEW, server_time, Some(vec![1, 2, 3]));
+    krb_error.0.e_text = Optional::from(Some(ExplicitContextTag11::from(KerberosStringAsn1::from(
+        IA5String::from_string("Clock skew too great".into()).unwrap(),
+    ))));
+
+    let error = Error::from(krb_error);
+
+    assert_eq!(error.error_type, ErrorKind::TimeSkew);
+    assert_eq!(
+        error.kerberos_error(),
+        Some(&KerberosError {
+            error_code: KRB_AP_ERR_SKEW,
+            e_text: Some("Clock skew too great".into()),
+            e_data: Some(vec![1, 2, 3]),
+            server_time: server_time.with_nanosecond(0).unwrap(),
+            client_realm: None,
+        })
+    );
+    assert!(error.description.contains("KRB_AP_ERR_SKEW"));
+    assert!(error.description.contains("Clock skew too great"));
+}
+
+#[test]
+fn kerberos_error_kinds_follow_error_codes() {
+    let error_type = |error_code| Error::from(krb_error(error_code, Utc::now(), None)).error_type;
+
+    assert_eq!(error_type(KDC_ERR_PREAUTH_FAILED), ErrorKind::LogonDenied);
+    assert_eq!(error_type(KDC_ERR_C_PRINCIPAL_UNKNOWN), ErrorKind::InternalError);
+}
+
+#[test]
+fn pre_authentication_is_sent_after_preauth_required_error() {
+    let (mut client, network_client) = client_with_kdc_replies(vec![
+        krb_error(
+            KDC_ERR_PREAUTH_REQUIRED,
+            Utc::now(),
+            Some(method_data(&[&PA_ENC_TIMESTAMP])),
+        ),
+        krb_error(KDC_ERR_PREAUTH_FAILED, Utc::now(), None),
+    ]);
+
+    let error = client
+        .request_tgt(&password_credentials(), "user", "EXAMPLE.COM")
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::LogonDenied);
+    assert_eq!(error.kerberos_error().unwrap().error_code, KDC_ERR_PREAUTH_FAILED);
+
+    let padata_types = network_client
+        .requests::<AsReq>()
+        .iter()
+        .map(|as_req| {
+            as_req
+                .0
+                .padata
+                .0
+                .as_ref()
+                .unwrap()
+                .0
+                 .0
+                .iter()
+                .map(|pa_data| pa_data.padata_type.0 .0.clone())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(padata_types.len(), 2);
+    assert!(!padata_types[0].contains(&PA_ENC_TIMESTAMP.to_vec()));
+    assert!(padata_types[1].contains(&PA_ENC_TIMESTAMP.to_vec()));
+}
+
+#[test]
+fn unsupported_pre_authentication_methods_are_rejected() {
+    // PA-PK-AS-REQ only
+    let (mut client, network_client) = client_with_kdc_replies(vec![krb_error(
+        KDC_ERR_PREAUTH_REQUIRED,
+        Utc::now(),
+        Some(method_data(&[&[0x10]])),
+    )]);
+
+    let error = client
+        .request_tgt(&password_credentials(), "user", "EXAMPLE.COM")
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::UnsupportedPreAuth);
+    assert_eq!(network_client.requests::<AsReq>().len(), 1);
+}
+
+#[test]
+fn kdc_reply_without_length_prefix_is_rejected() {
+    let tgt = credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32]);
+    let (mut client, _) = client_with_realm_kdcs(vec![vec![0x30, 0x00], vec![0x30, 0x00]], 10);
+
+    let error = client
+        .request_tgt(&password_credentials(), "user", "EXAMPLE.COM")
+        .unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+
+    let error = client
+        .request_service_ticket(&tgt, &Principal::new("HTTP/web.example.com", "EXAMPLE.COM"), None)
+        .unwrap_err();
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+}
+
+#[test]
+fn request_is_retried_once_with_time_of_kdc_on_clock_skew() {
+    let server_time = Utc::now() + Duration::hours(1);
+    let (mut client, network_client) = client_with_kdc_replies(vec![
+        krb_error(KRB_AP_ERR_SKEW, server_time, None),
+        krb_error(KRB_AP_ERR_SKEW, server_time, None),
+    ]);
+
+    let credentials = password_credentials();
+    let error = client
+        .with_skew_retry(|kerberos| kerberos.request_tgt(&credentials, "user", "EXAMPLE.COM"))
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::TimeSkew);
+    assert_eq!(network_client.requests::<AsReq>().len(), 2);
+    assert!((client.current_time() - server_time).num_seconds().abs() < 5);
+}
+
+pub(crate) fn credentials_for(server: &str, realm: &str, key: &[u8]) -> CachedCredentials {
+    let server = Principal::new(server, realm);
+
+    CachedCredentials {
+        client: Principal::new("user", "EXAMPLE.COM"),
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        key: key.to_vec(),
+        auth_time: 0,
+        start_time: 0,
+        end_time: u32::MAX,
+        renew_till: 0,
+        is_skey: false,
+        ticket_flags: 0,
+        addresses: Vec::new(),
+        auth_data: Vec::new(),
+        ticket: picky_asn1_der::to_vec(&Ticket::from(TicketInner {
+            tkt_vno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+            realm: ExplicitContextTag1::from(server.kerberos_realm().unwrap()),
+            sname: ExplicitContextTag2::from(server.principal_name().unwrap()),
+            enc_part: ExplicitContextTag3::from(EncryptedData {
+                etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+                kvno: Optional::from(None),
+                cipher: ExplicitContextTag2::from(OctetStringAsn1::from(vec![0; 16])),
+            }),
+        }))
+        .unwrap(),
+        second_ticket: Vec::new(),
+        server,
+    }
+}
+
+// TGS-REP with the ticket of the server encrypted with the session key of the TGT
+fn tgs_rep(server: &str, realm: &str, tgt_key: &[u8], key: &[u8]) -> Vec<u8> {
+    let ticket = credentials_for(server, realm, key).decode_ticket().unwrap();
+
+    tgs_rep_with_ticket(server, realm, ticket, tgt_key, key)
+}
+
+fn tgs_rep_with_ticket(server: &str, realm: &str, ticket: Ticket, tgt_key: &[u8], key: &[u8]) -> Vec<u8> {
+    let credentials = credentials_for(server, realm, key);
+    let server = &credentials.server;
+    let time = KerberosTime::from(GeneralizedTime::from(Utc::now()));
+
+    let enc_tgs_rep_part = EncTgsRepPart::from(EncKdcRepPart {
+        key: ExplicitContextTag0::from(EncryptionKey {
+            key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            key_value: ExplicitContextTag1::from(OctetStringAsn1::from(key.to_vec())),
+        }),
+        last_req: ExplicitContextTag1::from(Asn1SequenceOf::from(Vec::new())),
+        nonce: ExplicitContextTag2::from(IntegerAsn1::from(vec![1])),
+        key_expiration: Optional::from(None),
+        flags: ExplicitContextTag4::from(KerberosFlags::from(BitString::with_bytes(vec![0; 4]))),
+        auth_time: ExplicitContextTag5::from(time.clone()),
+        start_time: Optional::from(None),
+        end_time: ExplicitContextTag7::from(KerberosTime::from(GeneralizedTime::from(
+            Utc::now() + Duration::hours(10),
+        ))),
+        renew_till: Optional::from(None),
+        srealm: ExplicitContextTag9::from(server.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag10::from(server.principal_name().unwrap()),
+        caadr: Optional::from(None),
+        encrypted_pa_data: Optional::from(None),
+    });
+    let cipher = crypto::new_cipher(AES256_CTS_HMAC_SHA1_96).unwrap();
+
+    serialize_message(&TgsRep::from(KdcRep {
+        pvno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        msg_type: ExplicitContextTag1::from(IntegerAsn1::from(vec![TGS_REP_MSG_TYPE])),
+        padata: Optional::from(None),
+        crealm: ExplicitContextTag3::from(credentials.client.kerberos_realm().unwrap()),
+        cname: ExplicitContextTag4::from(credentials.client.principal_name().unwrap()),
+        ticket: ExplicitContextTag5::from(ticket),
+        enc_part: ExplicitContextTag6::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(cipher.encrypt(
+                tgt_key,
+                KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY,
+                &picky_asn1_der::to_vec(&enc_tgs_rep_part).unwrap(),
+            ))),
+        }),
+    }))
+    .unwrap()
+}
+
+fn client_with_realm_kdcs(replies: Vec<Vec<u8>>, max_referrals: u32) -> (Kerberos, ScriptedNetworkClient) {
+    let network_client = ScriptedNetworkClient::new(replies);
+    let kdc_locator = KdcLocator {
+        krb5_conf: Some(
+            "[realms]\nEXAMPLE.COM = {\nkdc = kdc.example.com:88\n}\nOTHER.COM = {\nkdc = kdc.other.com:88\n}\n\
+             THIRD.COM = {\nkdc = kdc.third.com:88\n}\n"
+                .parse::<Krb5Conf>()
+                .unwrap(),
+        ),
+        ..Default::default()
+    };
+    let config = KerberosConfig::new_with_kdc_locator(kdc_locator, Box::new(network_client.clone()))
+        .with_max_referrals(max_referrals);
+
+    (Kerberos::new_client_from_config(config).unwrap(), network_client)
+}
+
+#[test]
+fn tgs_referrals_are_followed_to_the_realm_of_the_service() {
+    let tgt = credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32]);
+    let (mut client, network_client) = client_with_realm_kdcs(
+        vec![
+            tgs_rep("krbtgt/OTHER.COM", "EXAMPLE.COM", &[1; 32], &[2; 32]),
+            tgs_rep("HTTP/web.other.com", "OTHER.COM", &[2; 32], &[3; 32]),
+        ],
+        10,
+    );
+
+    let service_ticket = client
+        .request_service_ticket(&tgt, &Principal::new("HTTP/web.other.com", "EXAMPLE.COM"), None)
+        .unwrap();
+
+    assert_eq!(service_ticket.server, Principal::new("HTTP/web.other.com", "OTHER.COM"));
+    assert_eq!(service_ticket.key, [3; 32]);
+    assert_eq!(
+        network_client.kdc_urls(),
+        ["tcp://kdc.example.com:88", "tcp://kdc.other.com:88"]
+    );
+    let tgs_reqs = network_client.requests::<TgsReq>();
+    assert_eq!(tgs_reqs[1].0.req_body.0.realm.0.to_string(), "OTHER.COM");
+}
+
+#[test]
+fn cross_realm_tgt_is_requested_for_service_of_other_realm() {
+    let tgt = credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32]);
+    let (mut client, network_client) = client_with_realm_kdcs(
+        vec![
+            tgs_rep("krbtgt/OTHER.COM", "EXAMPLE.COM", &[1; 32], &[2; 32]),
+            tgs_rep("HTTP/web.other.com", "OTHER.COM", &[2; 32], &[3; 32]),
+        ],
+        10,
+    );
+
+    client
+        .request_service_ticket(&tgt, &Principal::new("HTTP/web.other.com", "OTHER.COM"), None)
+        .unwrap();
+
+    let snames = network_client
+        .requests::<TgsReq>()
+        .iter()
+        .map(|tgs_req| principal_name_to_string(&tgs_req.0.req_body.0.sname.0.as_ref().unwrap().0))
+        .collect::<Vec<_>>();
+    assert_eq!(snames, ["krbtgt/OTHER.COM", "HTTP/web.other.com"]);
+}
+
+#[test]
+fn tgs_referrals_are_limited() {
+    let tgt = credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32]);
+    let (mut client, network_client) = client_with_realm_kdcs(
+        vec![
+            tgs_rep("krbtgt/OTHER.COM", "EXAMPLE.COM", &[1; 32], &[2; 32]),
+            tgs_rep("krbtgt/THIRD.COM", "OTHER.COM", &[2; 32], &[3; 32]),
+        ],
+        1,
+    );
+
+    let error = client
+        .request_service_ticket(&tgt, &Principal::new("HTTP/web.third.com", "EXAMPLE.COM"), None)
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::MaxReferralsExceeded);
+    assert_eq!(network_client.kdc_urls().len(), 2);
+}
+
+#[test]
+fn as_request_is_sent_to_realm_of_wrong_realm_error() {
+    let mut wrong_realm = krb_error(KDC_ERR_WRONG_REALM, Utc::now(), None);
+    wrong_realm.0.crealm = Optional::from(Some(ExplicitContextTag7::from(
+        Principal::new("user", "OTHER.COM").kerberos_realm().unwrap(),
+    )));
+    let (mut client, network_client) = client_with_realm_kdcs(
+        vec![
+            serialize_message(&wrong_realm).unwrap(),
+            serialize_message(&krb_error(KDC_ERR_C_PRINCIPAL_UNKNOWN, Utc::now(), None)).unwrap(),
+        ],
+        10,
+    );
+
+    let error = client
+        .request_tgt(&password_credentials(), "user", "EXAMPLE.COM")
+        .unwrap_err();
+
+    assert_eq!(error.kerberos_error().unwrap().error_code, KDC_ERR_C_PRINCIPAL_UNKNOWN);
+    assert_eq!(
+        network_client.kdc_urls(),
+        ["tcp://kdc.example.com:88", "tcp://kdc.other.com:88"]
+    );
+    assert_eq!(
+        network_client.requests::<AsReq>()[1].0.req_body.0.realm.0.to_string(),
+        "OTHER.COM"
+    );
+}
+
+#[test]
+fn forwarded_tgt_is_requested_for_delegation() {
+    let tgt = credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32]);
+    let (mut client, network_client) = client_with_realm_kdcs(
+        vec![tgs_rep("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32], &[2; 32])],
+        10,
+    );
+
+    let forwarded_tgt = client.request_forwarded_tgt(&tgt).unwrap();
+
+    assert_eq!(forwarded_tgt.key, [2; 32]);
+    let tgs_req = &network_client.requests::<TgsReq>()[0].0.req_body.0;
+    assert_eq!(tgs_req.kdc_options.0 .0.payload_view(), FORWARDED_TGT_REQ_OPTIONS);
+    assert_eq!(
+        principal_name_to_string(&tgs_req.sname.0.as_ref().unwrap().0),
+        "krbtgt/EXAMPLE.COM"
+    );
+}
+
+// service with its TGT in the credentials cache
+fn service_with_kdc_replies(replies: Vec<Vec<u8>>) -> (Kerberos, ScriptedNetworkClient) {
+    let network_client = ScriptedNetworkClient::new(replies);
+    let credentials_cache = Arc::new(MemoryCredentialsCache::new());
+    credentials_cache
+        .store(credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32]))
+        .unwrap();
+    let config = KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(network_client.clone()),
+    )
+    .with_credentials_cache(credentials_cache);
+
+    let mut service = Kerberos::new_client_from_config(config).unwrap();
+    service.credentials = Some(password_credentials());
+
+    (service, network_client)
+}
+
+#[test]
+fn s4u2self_ticket_is_requested_to_service_itself_for_user() {
+    let (mut service, network_client) =
+        service_with_kdc_replies(vec![tgs_rep("user", "EXAMPLE.COM", &[1; 32], &[2; 32])]);
+
+    service
+        .request_s4u2self_ticket(&Principal::new("alice", "EXAMPLE.COM"))
+        .unwrap();
+
+    let tgs_req = &network_client.requests::<TgsReq>()[0].0;
+    assert_eq!(
+        principal_name_to_string(&tgs_req.req_body.0.sname.0.as_ref().unwrap().0),
+        "user"
+    );
+    assert_eq!(
+        tgs_req.req_body.0.kdc_options.0 .0.payload_view(),
+        S4U2SELF_TGS_REQ_OPTIONS
+    );
+    let padata_types = tgs_req
+        .padata
+        .0
+        .as_ref()
+        .unwrap()
+        .0
+         .0
+        .iter()
+        .map(|pa_data| pa_data.padata_type.0 .0.clone());
+    assert_eq!(
+        padata_types.skip(2).collect::<Vec<_>>(),
+        [vec![0x00, 0x81], vec![0x00, 0x82]]
+    );
+}
+
+#[test]
+fn s4u2proxy_ticket_is_requested_with_evidence_ticket() {
+    let (mut service, network_client) = service_with_kdc_replies(vec![tgs_rep(
+        "MSSQLSvc/sql.example.com",
+        "EXAMPLE.COM",
+        &[1; 32],
+        &[3; 32],
+    )]);
+    let evidence_ticket = credentials_for("user", "EXAMPLE.COM", &[2; 32]);
+
+    service
+        .request_s4u2proxy_ticket(
+            &evidence_ticket,
+            &Principal::new("MSSQLSvc/sql.example.com", "EXAMPLE.COM"),
+        )
+        .unwrap();
+
+    let tgs_req = &network_client.requests::<TgsReq>()[0].0;
+    assert_eq!(
+        tgs_req.req_body.0.kdc_options.0 .0.payload_view(),
+        S4U2PROXY_TGS_REQ_OPTIONS
+    );
+    assert_eq!(
+        tgs_req.req_body.0.additional_tickets.0.as_ref().unwrap().0 .0,
+        [evidence_ticket.decode_ticket().unwrap()]
+    );
+}
+
+#[test]
+fn s4u2proxy_refused_by_kdc_is_delegation_policy_error() {
+    let (mut service, _) =
+        service_with_kdc_replies(vec![
+            serialize_message(&krb_error(KDC_ERR_BADOPTION, Utc::now(), None)).unwrap()
+        ]);
+
+    let error = service
+        .request_s4u2proxy_ticket(
+            &credentials_for("user", "EXAMPLE.COM", &[2; 32]),
+            &Principal::new("cifs/files.example.com", "EXAMPLE.COM"),
+        )
+        .unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::DelegationPolicy);
+}
+
+#[test]
+fn password_change_requests_initial_ticket_of_kpasswd_service() {
+    let (mut client, network_client) =
+        client_with_kdc_replies(vec![krb_error(KDC_ERR_C_PRINCIPAL_UNKNOWN, Utc::now(), None)]);
+    let identity = AuthIdentity {
+        username: "user".into(),
+        password: "expired".into(),
+        domain: Some("EXAMPLE.COM".into()),
+    };
+
+    let error = client.change_password(&identity, "N3w-passw0rd").unwrap_err();
+
+    assert_eq!(error.kerberos_error().unwrap().error_code, KDC_ERR_C_PRINCIPAL_UNKNOWN);
+    let sname = &network_client.requests::<AsReq>()[0]
+        .0
+        .req_body
+        .0
+        .sname
+        .0
+        .clone()
+        .unwrap()
+        .0;
+    assert_eq!(principal_name_to_string(sname), "kadmin/changepw");
+    assert_eq!(integer_to_u32(&sname.name_type.0), NT_SRV_INST);
+}
+
+// ticket of the user encrypted with the key of the service
+fn ticket_for(server: &str, service_key: &[u8], session_key: &[u8]) -> Ticket {
+    ticket_with_authorization_data(server, service_key, session_key, None)
+}
+
+fn ticket_with_authorization_data(
+    server: &str,
+    service_key: &[u8],
+    session_key: &[u8],
+    authorization_data: Option<AuthorizationData>,
+) -> Ticket {
+    let server = Principal::new(server, "EXAMPLE.COM");
+    let client = Principal::new("user", "EXAMPLE.COM");
+    let now = Utc::now();
+
+    let enc_ticket_part = EncTicketPart::from(EncTicketPartInner {
+        flags: ExplicitContextTag0::from(KerberosFlags::from(BitString::with_bytes(vec![0x40, 0xa1, 0, 0]))),
+        key: ExplicitContextTag1::from(EncryptionKey {
+            key_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            key_value: ExplicitContextTag1::from(OctetStringAsn1::from(session_key.to_vec())),
+        }),
+        crealm: ExplicitContextTag2::from(client.kerberos_realm().unwrap()),
+        cname: ExplicitContextTag3::from(client.principal_name().unwrap()),
+        transited: ExplicitContextTag4::from(TransitedEncoding {
+            tr_type: ExplicitContextTag0::from(IntegerAsn1::from(vec![1])),
+            contents: ExplicitContextTag1::from(OctetStringAsn1::from(Vec::new())),
+        }),
+        authtime: ExplicitContextTag5::from(KerberosTime::from(GeneralizedTime::from(now))),
+        starttime: Optional::from(None),
+        endtime: ExplicitContextTag7::from(KerberosTime::from(GeneralizedTime::from(now + Duration::hours(10)))),
+        renew_till: Optional::from(None),
+        caddr: Optional::from(None),
+        authorization_data: Optional::from(authorization_data.map(ExplicitContextTag10::from)),
+    });
+    let cipher = crypto::new_cipher(AES256_CTS_HMAC_SHA1_96).unwrap();
+
+    Ticket::from(TicketInner {
+        tkt_vno: ExplicitContextTag0::from(IntegerAsn1::from(vec![KERBEROS_VERSION])),
+        realm: ExplicitContextTag1::from(server.kerberos_realm().unwrap()),
+        sname: ExplicitContextTag2::from(server.principal_name().unwrap()),
+        enc_part: ExplicitContextTag3::from(EncryptedData {
+            etype: ExplicitContextTag0::from(IntegerAsn1::from(vec![AES256_CTS_HMAC_SHA1_96 as u8])),
+            kvno: Optional::from(None),
+            cipher: ExplicitContextTag2::from(OctetStringAsn1::from(cipher.encrypt(
+                service_key,
+                KEY_USAGE_AS_REP_TICKET,
+                &picky_asn1_der::to_vec(&enc_ticket_part).unwrap(),
+            ))),
+        }),
+    })
+}
+
+fn initialize(client: &mut Kerberos, input: Option<Vec<u8>>) -> Result<(SecurityStatus, Vec<u8>)> {
+    initialize_with(client, ClientRequestFlags::MUTUAL_AUTH, input).map(|(result, output)| (result.status, output))
+}
+
+fn initialize_with(
+    client: &mut Kerberos,
+    flags: ClientRequestFlags,
+    input: Option<Vec<u8>>,
+) -> Result<(InitializeSecurityContextResult, Vec<u8>)> {
+    initialize_for(client, "TERMSRV/websvc.example.com", flags, input)
+}
+
+fn initialize_for(
+    client: &mut Kerberos,
+    target_name: &str,
+    flags: ClientRequestFlags,
+    input: Option<Vec<u8>>,
+) -> Result<(InitializeSecurityContextResult, Vec<u8>)> {
+    let mut input = input
+        .map(|token| vec![SecurityBuffer::new(token, SecurityBufferType::Token)])
+        .unwrap_or_default();
+    let mut output = vec![SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+
+    let result = client
+        .initialize_security_context()
+        .with_credentials_handle(&mut Some(password_credentials()))
+        .with_context_requirements(flags)
+        .with_target_data_representation(DataRepresentation::Native)
+        .with_target_name(target_name)
+        .with_input(&mut input)
+        .with_output(&mut output)
+        .execute()?;
+
+    Ok((result, output.remove(0).buffer))
+}
+
+fn accept(server: &mut Kerberos, input: Vec<u8>) -> Result<(SecurityStatus, Vec<u8>)> {
+    accept_with_result(server, input).map(|(result, output)| (result.status, output))
+}
+
+fn accept_with_result(server: &mut Kerberos, input: Vec<u8>) -> Result<(AcceptSecurityContextResult, Vec<u8>)> {
+    let mut input = vec![SecurityBuffer::new(input, SecurityBufferType::Token)];
+    let mut output = vec![SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+
+    let result = server
+        .accept_security_context()
+        .with_credentials_handle(&mut None)
+        .with_context_requirements(ServerRequestFlags::empty())
+        .with_target_data_representation(DataRepresentation::Native)
+        .with_input(&mut input)
+        .with_output(&mut output)
+        .execute()?;
+
+    Ok((result, output.remove(0).buffer))
+}
+
+#[test]
+fn user_to_user_ticket_is_decrypted_with_tgt_session_key_of_service() {
+    // the service has no long-term keys, only its TGT
+    let service_tgt = CachedCredentials {
+        client: Principal::new("websvc", "EXAMPLE.COM"),
+        ..credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[4; 32])
+    };
+    let credentials_cache = Arc::new(MemoryCredentialsCache::new());
+    credentials_cache.store(service_tgt.clone()).unwrap();
+    let config = KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(NoNetworkClient),
+    )
+    .with_credentials_cache(credentials_cache);
+    let mut server = Kerberos::new_server_from_config(config).unwrap();
+    server.credentials = Some(CredentialsBuffers::AuthIdentity(
+        AuthIdentity {
+            username: "websvc".into(),
+            password: String::new(),
+            domain: Some("EXAMPLE.COM".into()),
+        }
+        .into(),
+    ));
+
+    let (mut client, network_client) = service_with_kdc_replies(vec![tgs_rep_with_ticket(
+        "TERMSRV/websvc.example.com",
+        "EXAMPLE.COM",
+        ticket_for("TERMSRV/websvc.example.com", &[4; 32], &[5; 32]),
+        &[1; 32],
+        &[5; 32],
+    )]);
+
+    let (status, tgt_req) = initialize(&mut client, None).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let (status, tgt_rep) = accept(&mut server, tgt_req).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let (status, ap_req) = initialize(&mut client, Some(tgt_rep)).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let (status, ap_rep) = accept(&mut server, ap_req).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let (status, mech_list_mic) = initialize(&mut client, Some(ap_rep)).unwrap();
+    assert_eq!(status, SecurityStatus::Ok);
+    let (status, _) = accept(&mut server, mech_list_mic).unwrap();
+    assert_eq!(status, SecurityStatus::Ok);
+
+    let tgs_req = &network_client.requests::<TgsReq>()[0].0.req_body.0;
+    assert_eq!(tgs_req.kdc_options.0 .0.payload_view(), USER_TO_USER_TGS_REQ_OPTIONS);
+    assert_eq!(
+        tgs_req.additional_tickets.0.as_ref().unwrap().0 .0,
+        [service_tgt.decode_ticket().unwrap()]
+    );
+}
+
+#[test]
+fn service_keys_are_derived_from_password_with_default_salts() {
+    let mut server = Kerberos::new_server_from_config(KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(NoNetworkClient),
+    ))
+    .unwrap();
+    server.credentials = Some(CredentialsBuffers::AuthIdentity(
+        AuthIdentity {
+            username: "WEBSVC$".into(),
+            password: "password".into(),
+            domain: Some("example.com".into()),
+        }
+        .into(),
+    ));
+    let ticket = ticket_for("HTTP/websvc.example.com", &[4; 32], &[5; 32]);
+
+    let keys = server
+        .service_keys_from_credentials(&ticket.0)
+        .unwrap()
+        .into_iter()
+        .map(|key| key.key)
+        .collect::<Vec<_>>();
+
+    let key = |salt| crypto::string_to_key(AES256_CTS_HMAC_SHA1_96, "password", salt, None).unwrap();
+    assert_eq!(
+        keys,
+        [
+            key("EXAMPLE.COMWEBSVC$"),
+            key("EXAMPLE.COMhostwebsvc.example.com"),
+            key("EXAMPLE.COMHTTPwebsvc.example.com"),
+        ]
+    );
+}
+
+#[test]
+fn service_with_long_term_keys_does_not_send_its_tgt() {
+    let mut server = service_with_long_term_keys();
+    let (mut client, network_client) = service_with_kdc_replies(vec![tgs_rep_with_ticket(
+        "TERMSRV/websvc.example.com",
+        "EXAMPLE.COM",
+        ticket_for("TERMSRV/websvc.example.com", &[4; 32], &[5; 32]),
+        &[1; 32],
+        &[5; 32],
+    )]);
+
+    let (_, tgt_req) = initialize(&mut client, None).unwrap();
+    let (_, neg_token_targ) = accept(&mut server, tgt_req).unwrap();
+    let (_, ap_req) = initialize(&mut client, Some(neg_token_targ)).unwrap();
+    let (status, _) = accept(&mut server, ap_req).unwrap();
+
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let tgs_req = &network_client.requests::<TgsReq>()[0].0.req_body.0;
+    assert_eq!(tgs_req.kdc_options.0 .0.payload_view(), DEFAULT_TGS_REQ_OPTIONS);
+    assert!(tgs_req.additional_tickets.0.is_none());
+}
+
+#[test]
+fn dce_style_context_is_established_in_three_legs() {
+    let mut server = service_with_long_term_keys();
+    let mut client = client_of_service();
+
+    let (result, ap_req) = initialize_with(&mut client, ClientRequestFlags::USE_DCE_STYLE, None).unwrap();
+    assert_eq!(result.status, SecurityStatus::ContinueNeeded);
+    // neither SPNEGO nor GSS-API framing
+    let _: ApReq = picky_asn1_der::from_bytes(&ap_req).unwrap();
+
+    let (status, ap_rep) = accept(&mut server, ap_req).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let _: ApRep = picky_asn1_der::from_bytes(&ap_rep).unwrap();
+
+    let (result, initiator_ap_rep) =
+        initialize_with(&mut client, ClientRequestFlags::USE_DCE_STYLE, Some(ap_rep)).unwrap();
+    assert_eq!(result.status, SecurityStatus::Ok);
+    // the DCE-style context is always mutual
+    assert_eq!(
+        result.flags,
+        ClientResponseFlags::MUTUAL_AUTH | ClientResponseFlags::USED_DCE_STYLE
+    );
+
+    let (result, output) = accept_with_result(&mut server, initiator_ap_rep).unwrap();
+    assert_eq!(result.status, SecurityStatus::Ok);
+    assert_eq!(
+        result.flags,
+        ServerResponseFlags::MUTUAL_AUTH | ServerResponseFlags::USED_DCE_STYLE
+    );
+    assert!(output.is_empty());
+
+    let mut message = wrapped_message(&mut client, EncryptionFlags::empty(), b"dcerpc request");
+    assert_eq!(message[1].buffer.len(), b"dcerpc request".len());
+    server.decrypt_message(&mut message, 0).unwrap();
+    assert_eq!(message[1].buffer, b"dcerpc request");
+}
+
+fn service_with_long_term_keys() -> Kerberos {
+    let config = KerberosConfig::new(
+        Url::parse("tcp://kdc.example.com:88").unwrap(),
+        Box::new(NoNetworkClient),
+    );
+
+    Kerberos::new_server_from_config_with_properties(
+        config,
+        ServerProperties::new(vec![ServiceKey {
+            encryption_type: AES256_CTS_HMAC_SHA1_96,
+            kvno: None,
+            key: vec![4; 32],
+        }]),
+    )
+    .unwrap()
+}
+
+fn client_of_service() -> Kerberos {
+    service_with_kdc_replies(vec![tgs_rep_with_ticket(
+        "TERMSRV/websvc.example.com",
+        "EXAMPLE.COM",
+        ticket_for("TERMSRV/websvc.example.com", &[4; 32], &[5; 32]),
+        &[1; 32],
+        &[5; 32],
+    )])
+    .0
+}
+
+fn client_with_pac_of_service() -> Kerberos {
+    let ticket = ticket_with_authorization_data(
+        "TERMSRV/websvc.example.com",
+        &[4; 32],
+        &[5; 32],
+        Some(ticket_authorization_data(&[4; 32])),
+    );
+
+    service_with_kdc_replies(vec![tgs_rep_with_ticket(
+        "TERMSRV/websvc.example.com",
+        "EXAMPLE.COM",
+        ticket,
+        &[1; 32],
+        &[5; 32],
+    )])
+    .0
+}
+
+#[test]
+fn ticket_with_pac_is_accepted_without_kdc_keys() {
+    let mut server = service_with_long_term_keys();
+    let mut client = client_with_pac_of_service();
+
+    let (_, tgt_req) = initialize(&mut client, None).unwrap();
+    let (_, neg_token_targ) = accept(&mut server, tgt_req).unwrap();
+    let (_, ap_req) = initialize(&mut client, Some(neg_token_targ)).unwrap();
+    let (status, _) = accept(&mut server, ap_req).unwrap();
+
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    assert_eq!(
+        server.query_context_pac().unwrap().upn_dns_info.unwrap().upn,
+        "alice@example.com"
+    );
+}
+
+#[test]
+fn pac_with_invalid_kdc_signature_is_reported_by_query() {
+    let mut server = service_with_long_term_keys();
+    server.server.as_mut().unwrap().kdc_keys = vec![ServiceKey {
+        encryption_type: AES256_CTS_HMAC_SHA1_96,
+        kvno: None,
+        key: vec![0x33; 32],
+    }];
+    let mut client = client_with_pac_of_service();
+
+    let (_, tgt_req) = initialize(&mut client, None).unwrap();
+    let (_, neg_token_targ) = accept(&mut server, tgt_req).unwrap();
+    let (_, ap_req) = initialize(&mut client, Some(neg_token_targ)).unwrap();
+    let (status, _) = accept(&mut server, ap_req).unwrap();
+
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    assert_eq!(
+        server.query_context_pac().unwrap_err().error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
+#[test]
+fn granted_flags_are_reported_to_both_peers() {
+    let mut server = service_with_long_term_keys();
+    let mut client = client_of_service();
+    let flags = ClientRequestFlags::MUTUAL_AUTH
+        | ClientRequestFlags::REPLAY_DETECT
+        | ClientRequestFlags::SEQUENCE_DETECT
+        | ClientRequestFlags::CONFIDENTIALITY
+        | ClientRequestFlags::INTEGRITY
+        | ClientRequestFlags::NO_INTEGRITY;
+
+    let (_, tgt_req) = initialize_with(&mut client, flags, None).unwrap();
+    let (_, neg_token_targ) = accept(&mut server, tgt_req).unwrap();
+    let (_, ap_req) = initialize_with(&mut client, flags, Some(neg_token_targ)).unwrap();
+    let (_, ap_rep) = accept(&mut server, ap_req).unwrap();
+    let (client_result, mech_list_mic) = initialize_with(&mut client, flags, Some(ap_rep)).unwrap();
+    let (server_result, _) = accept_with_result(&mut server, mech_list_mic).unwrap();
+
+    assert_eq!(client_result.status, SecurityStatus::Ok);
+    // the integrity is not requested together with NO_INTEGRITY
+    assert_eq!(
+        client_result.flags,
+        ClientResponseFlags::MUTUAL_AUTH
+            | ClientResponseFlags::REPLAY_DETECT
+            | ClientResponseFlags::SEQUENCE_DETECT
+            | ClientResponseFlags::CONFIDENTIALITY
+    );
+    assert_eq!(server_result.status, SecurityStatus::Ok);
+    assert_eq!(
+        server_result.flags,
+        ServerResponseFlags::MUTUAL_AUTH
+            | ServerResponseFlags::REPLAY_DETECT
+            | ServerResponseFlags::SEQUENCE_DETECT
+            | ServerResponseFlags::CONFIDENTIALITY
+    );
+}
+
+#[test]
+fn context_without_mutual_authentication_is_established_without_ap_rep() {
+    let mut server = service_with_long_term_keys();
+    let mut client = client_of_service();
+    let flags = ClientRequestFlags::CONFIDENTIALITY | ClientRequestFlags::INTEGRITY;
+
+    let (_, tgt_req) = initialize_with(&mut client, flags, None).unwrap();
+    let (_, neg_token_targ) = accept(&mut server, tgt_req).unwrap();
+    let (_, ap_req) = initialize_with(&mut client, flags, Some(neg_token_targ)).unwrap();
+
+    let ap_options = extract_initiator_message(&ap_req)
+        .and_then(|message| Ok(picky_asn1_der::from_bytes::<ApReq>(&message.krb_message)?))
+        .unwrap()
+        .0
+        .ap_options
+        .0
+         .0
+        .payload_view()
+        .to_vec();
+    assert_eq!(ap_options, [0; 4]);
+
+    let (status, neg_token_targ) = accept(&mut server, ap_req).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let neg_token_targ: NegTokenTarg1 = picky_asn1_der::from_bytes(&neg_token_targ).unwrap();
+    assert!(neg_token_targ.0.response_token.0.is_none());
+
+    let (result, mech_list_mic) = initialize_with(
+        &mut client,
+        flags,
+        Some(picky_asn1_der::to_vec(&neg_token_targ).unwrap()),
+    )
+    .unwrap();
+    assert_eq!(result.status, SecurityStatus::Ok);
+    assert_eq!(
+        result.flags,
+        ClientResponseFlags::CONFIDENTIALITY | ClientResponseFlags::INTEGRITY
+    );
+    let (result, _) = accept_with_result(&mut server, mech_list_mic).unwrap();
+    assert_eq!(result.status, SecurityStatus::Ok);
+    assert_eq!(
+        result.flags,
+        ServerResponseFlags::CONFIDENTIALITY | ServerResponseFlags::INTEGRITY
+    );
+
+    // both peers protect the messages with the subkey of the authenticator, so the AcceptorSubkey flag is clear
+    let mut message = wrapped_message(&mut client, EncryptionFlags::empty(), b"request");
+    assert_eq!(message[0].buffer[2], SEALED_FLAG);
+    server.decrypt_message(&mut message, 0).unwrap();
+    assert_eq!(message[1].buffer, b"request");
+
+    let mut message = wrapped_message(&mut server, EncryptionFlags::empty(), b"response");
+    assert_eq!(message[0].buffer[2], SENT_BY_ACCEPTOR_FLAG | SEALED_FLAG);
+    client.decrypt_message(&mut message, 0).unwrap();
+    assert_eq!(message[1].buffer, b"response");
+}
+
+#[test]
+fn service_principal_names_are_parsed() {
+    let service = Principal::from_service_principal_name("HTTP/WebSvc.Example.COM.", "EXAMPLE.COM").unwrap();
+    assert_eq!(service.components, ["HTTP", "websvc.example.com"]);
+    assert_eq!(service.realm, "EXAMPLE.COM");
+    assert_eq!(service.name_type, NT_SRV_INST);
+
+    let service =
+        Principal::from_service_principal_name("MSSQLSvc/DB.example.com:1433@other.com", "EXAMPLE.COM").unwrap();
+    assert_eq!(service.components, ["MSSQLSvc", "db.example.com:1433"]);
+    assert_eq!(service.realm, "OTHER.COM");
+
+    let service = Principal::from_service_principal_name("ldap/DC.example.com/example.com", "EXAMPLE.COM").unwrap();
+    assert_eq!(service.components, ["ldap", "dc.example.com", "example.com"]);
+
+    // the host-based service name
+    let service = Principal::from_service_principal_name("cifs@FileServer.example.com", "EXAMPLE.COM").unwrap();
+    assert_eq!(service.components, ["cifs", "fileserver.example.com"]);
+    assert_eq!(service.realm, "EXAMPLE.COM");
+
+    for spn in [
+        "HTTP",
+        "HTTP/",
+        "/websvc.example.com",
+        "HTTP/websvc.example.com@",
+        "@websvc.example.com",
+    ] {
+        assert_eq!(
+            Principal::from_service_principal_name(spn, "EXAMPLE.COM")
+                .unwrap_err()
+                .error_type,
+            ErrorKind::InvalidParameter
+        );
+    }
+}
+
+#[test]
+fn tgt_of_target_service_is_requested_in_neg_token_init() {
+    let (mut client, _) = service_with_kdc_replies(Vec::new());
+
+    let (_, tgt_req) = initialize_for(
+        &mut client,
+        "ldap@DC.Example.COM",
+        ClientRequestFlags::MUTUAL_AUTH,
+        None,
+    )
+    .unwrap();
+
+    let message = extract_initiator_message(&tgt_req).unwrap();
+    let tgt_req: TgtReq = picky_asn1_der::from_bytes(&message.krb_message).unwrap();
+    assert_eq!(principal_name_to_string(&tgt_req.server_name.0), "ldap/dc.example.com");
+}
+
+#[test]
+fn service_ticket_is_requested_for_target_service_principal_name() {
+    let (mut client, network_client) = service_with_kdc_replies(vec![tgs_rep_with_ticket(
+        "HTTP/websvc.example.com:8080",
+        "EXAMPLE.COM",
+        ticket_for("HTTP/websvc.example.com:8080", &[4; 32], &[5; 32]),
+        &[1; 32],
+        &[5; 32],
+    )]);
+
+    initialize_for(
+        &mut client,
+        "HTTP/WebSvc.Example.COM:8080@example.com",
+        ClientRequestFlags::USE_DCE_STYLE,
+        None,
+    )
+    .unwrap();
+
+    let tgs_req = &network_client.requests::<TgsReq>()[0].0.req_body.0;
+    assert_eq!(
+        principal_name_to_string(&tgs_req.sname.0.as_ref().unwrap().0),
+        "HTTP/websvc.example.com:8080"
+    );
+    assert_eq!(tgs_req.realm.0.to_string(), "EXAMPLE.COM");
+}
@@ -1,13 +1,15 @@
 use std::convert::TryInto;
 use std::io::Write;
 
-use kerberos_crypto::{checksum_sha_aes, AesSizes};
-use picky_krb::constants::key_usages::INITIATOR_SIGN;
+use picky_asn1::wrapper::IntegerAsn1;
+use picky_krb::constants::key_usages::{ACCEPTOR_SIGN, INITIATOR_SIGN};
 use picky_krb::gss_api::MicToken;
 use serde::Serialize;
+use subtle::ConstantTimeEq;
 
-use crate::sspi::kerberos::client::generators::get_mech_list;
+use crate::sspi::kerberos::client::RC4_HMAC;
 use crate::sspi::kerberos::encryption_params::EncryptionParams;
+use crate::sspi::kerberos::{crypto, rc4_tokens, SENT_BY_ACCEPTOR_FLAG};
 use crate::sspi::{Error, ErrorKind, Result};
 
 pub fn serialize_message<T: ?Sized + Serialize>(v: &T) -> Result<Vec<u8>> {
@@ -23,6 +25,15 @@ pub fn serialize_message<T: ?Sized + Serialize>(v: &T) -> Result<Vec<u8>> {
     Ok(data)
 }
 
+/// Returns the KDC reply without the 4-byte length prefix of the TCP transport
+pub fn unframe_kdc_reply(response: &[u8]) -> Result<&[u8]> {
+    if response.len() < 4 {
+        return Err(Error::new(ErrorKind::InvalidToken, "The KDC reply is too short".into()));
+    }
+
+    Ok(&response[4..])
+}
+
 pub fn utf16_bytes_to_utf8_string(data: &[u8]) -> String {
     debug_assert_eq!(data.len() % 2, 0);
     String::from_utf16_lossy(
@@ -33,52 +44,121 @@ pub fn utf16_bytes_to_utf8_string(data: &[u8]) -> String {
     )
 }
 
-pub fn validate_mic_token(raw_token: &[u8], key_usage: i32, params: &EncryptionParams) -> Result<()> {
-    let token = MicToken::decode(raw_token)?;
+pub fn integer_to_u32(integer: &IntegerAsn1) -> u32 {
+    integer
+        .as_unsigned_bytes_be()
+        .iter()
+        .fold(0, |value, byte| (value << 8) | u32::from(*byte))
+}
 
-    let mut payload = picky_asn1_der::to_vec(&get_mech_list())?;
-    payload.extend_from_slice(&token.header());
+/// Minimal two's complement encoding of the signed integer, e.g. of the -138 HMAC-MD5 checksum type
+pub fn i32_to_integer(value: i32) -> IntegerAsn1 {
+    let bytes = value.to_be_bytes();
+    let redundant_len = bytes
+        .windows(2)
+        .take_while(|pair| (pair[0] == 0x00 && pair[1] < 0x80) || (pair[0] == 0xff && pair[1] >= 0x80))
+        .count();
+
+    IntegerAsn1::from(bytes[redundant_len..].to_vec())
+}
 
+/// Returns the sequence number of the valid MIC token
+pub fn validate_mic_token_with_payload(
+    raw_token: &[u8],
+    key_usage: i32,
+    params: &EncryptionParams,
+    mut payload: Vec<u8>,
+) -> Result<u64> {
     // the sub-session key is always preferred over the session key
     let key = if let Some(key) = params.sub_session_key.as_ref() {
         key
     } else if let Some(key) = params.session_key.as_ref() {
         key
     } else {
-        return Err(Error {
-            error_type: ErrorKind::DecryptFailure,
-            description: "unable to obtain decryption key".into(),
-        });
+        return Err(Error::new(
+            ErrorKind::DecryptFailure,
+            "unable to obtain decryption key".into(),
+        ));
     };
 
-    let checksum = checksum_sha_aes(
-        key,
-        key_usage,
-        &payload,
-        &params.aes_sizes().unwrap_or(AesSizes::Aes256),
-    );
-
-    if checksum != token.checksum {
-        return Err(Error {
-            error_type: ErrorKind::MessageAltered,
-            description: "bad checksum of the mic token".into(),
-        });
+    let encryption_type = params.encryption_type();
+    if encryption_type == RC4_HMAC {
+        return Ok(rc4_tokens::validate_mic_token(key, raw_token, key_usage == ACCEPTOR_SIGN, &payload)?.into());
     }
 
-    Ok(())
-}
+    let token = MicToken::decode(raw_token)?;
+
+    // the token of the own side is never accepted
+    let from_acceptor = token.flags & SENT_BY_ACCEPTOR_FLAG != 0;
+    if from_acceptor != (key_usage == ACCEPTOR_SIGN) {
+        return Err(Error::new(
+            ErrorKind::MessageAltered,
+            "Invalid direction of the mic token".into(),
+        ));
+    }
+
+    payload.extend_from_slice(&token.header());
 
-pub fn generate_initiator_raw(mut payload: Vec<u8>, seq_number: u64, session_key: &[u8]) -> Result<Vec<u8>> {
-    let mut mic_token = MicToken::with_initiator_flags().with_seq_number(seq_number);
+    let checksum = crypto::checksum(encryption_type, key, key_usage, &payload)?;
 
-    payload.extend_from_slice(&mic_token.header());
+    if !bool::from(checksum.ct_eq(&token.checksum)) {
+        return Err(Error::new(
+            ErrorKind::MessageAltered,
+            "bad checksum of the mic token".into(),
+        ));
+    }
+
+    Ok(token.seq_num)
+}
+
+pub fn generate_initiator_raw(
+    payload: Vec<u8>,
+    seq_number: u64,
+    session_key: &[u8],
+    encryption_type: i32,
+) -> Result<Vec<u8>> {
+    if encryption_type == RC4_HMAC {
+        return rc4_tokens::generate_mic_token(session_key, seq_number as u32, false, &payload);
+    }
 
-    mic_token.set_checksum(checksum_sha_aes(
+    generate_mic_token_raw(
+        MicToken::with_initiator_flags().with_seq_number(seq_number),
+        payload,
         session_key,
         INITIATOR_SIGN,
-        &payload,
-        &AesSizes::Aes256,
-    ));
+        encryption_type,
+    )
+}
+
+pub fn generate_acceptor_raw(
+    payload: Vec<u8>,
+    seq_number: u64,
+    session_key: &[u8],
+    encryption_type: i32,
+) -> Result<Vec<u8>> {
+    if encryption_type == RC4_HMAC {
+        return rc4_tokens::generate_mic_token(session_key, seq_number as u32, true, &payload);
+    }
+
+    generate_mic_token_raw(
+        MicToken::with_acceptor_flags().with_seq_number(seq_number),
+        payload,
+        session_key,
+        ACCEPTOR_SIGN,
+        encryption_type,
+    )
+}
+
+fn generate_mic_token_raw(
+    mut mic_token: MicToken,
+    mut payload: Vec<u8>,
+    session_key: &[u8],
+    key_usage: i32,
+    encryption_type: i32,
+) -> Result<Vec<u8>> {
+    payload.extend_from_slice(&mic_token.header());
+
+    mic_token.set_checksum(crypto::checksum(encryption_type, session_key, key_usage, &payload)?);
 
     let mut mic_token_raw = Vec::new();
     mic_token.encode(&mut mic_token_raw)?;
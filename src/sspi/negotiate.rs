@@ -0,0 +1,945 @@
+#[cfg(test)]
+mod test;
+
+use std::io::Write;
+
+use lazy_static::lazy_static;
+use oid::ObjectIdentifier;
+use picky_asn1::wrapper::{
+    ExplicitContextTag0, ExplicitContextTag1, ExplicitContextTag2, ExplicitContextTag3, ObjectIdentifierAsn1,
+    OctetStringAsn1, Optional,
+};
+use picky_asn1_der::application_tag::ApplicationTag;
+use picky_asn1_der::Asn1RawDer;
+use picky_asn1_x509::oids::{KRB5, MS_KRB5, NTLM_SSP, SPNEGO};
+use picky_krb::constants::gss_api::{ACCEPT_COMPLETE, ACCEPT_INCOMPLETE};
+use picky_krb::gss_api::{
+    ApplicationTag0, GssApiNegInit, MechType, MechTypeList, NegTokenInit, NegTokenTarg, NegTokenTarg1,
+};
+use serde_derive::Deserialize;
+
+use crate::sspi::internal::SspiImpl;
+use crate::sspi::kerberos::config::KerberosConfig;
+use crate::sspi::kerberos::pac::Pac;
+use crate::sspi::kerberos::{Credentials, CredentialsBuffers, Kerberos, ServerProperties};
+use crate::sspi::{
+    CertTrustStatus, ClientRequestFlags, ClientResponseFlags, ContextNames, ContextSizes, CredentialUse,
+    DataRepresentation, DecryptionFlags, EncryptionFlags, Error, ErrorKind, FilledAcceptSecurityContext,
+    FilledAcquireCredentialsHandle, FilledInitializeSecurityContext, PackageCapabilities, PackageInfo, Result,
+    SecurityBuffer, SecurityBufferType, SecurityPackageType, SecurityStatus, ServerRequestFlags, ServerResponseFlags,
+    Sspi, SspiEx, PACKAGE_ID_NONE,
+};
+use crate::{
+    AcceptSecurityContextResult, AcquireCredentialsHandleResult, AuthIdentityBuffers, InitializeSecurityContextResult,
+    Ntlm,
+};
+
+pub const PKG_NAME: &str = "Negotiate";
+
+// [RFC 4178 4.2.2](https://www.rfc-editor.org/rfc/rfc4178#section-4.2.2): negState values
+const REJECT: [u8; 3] = [0x0a, 0x01, 0x02];
+const REQUEST_MIC: [u8; 3] = [0x0a, 0x01, 0x03];
+
+lazy_static! {
+    pub static ref PACKAGE_INFO: PackageInfo = PackageInfo {
+        capabilities: PackageCapabilities::empty(),
+        rpc_id: PACKAGE_ID_NONE,
+        max_token_len: 0xbb80, // 48 000 bytes: default maximum token len in Windows
+        name: SecurityPackageType::Other(PKG_NAME.into()),
+        comment: String::from("Negotiate Security Package"),
+    };
+}
+
+/// Settings of the Negotiate security package
+#[derive(Debug, Clone, Default)]
+pub struct NegotiateConfig {
+    /// Kerberos is negotiated only when its config is specified. Otherwise NTLM is always used
+    pub kerberos_config: Option<KerberosConfig>,
+    /// Acceptor-side settings of the Kerberos security package
+    pub server_properties: ServerProperties,
+}
+
+impl NegotiateConfig {
+    pub fn new(kerberos_config: Option<KerberosConfig>) -> Self {
+        Self {
+            kerberos_config,
+            server_properties: ServerProperties::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+enum NegotiatedProtocol {
+    Kerberos(Kerberos),
+    Ntlm(Ntlm),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum NegotiateState {
+    Initial,
+    // the initiator proposed Kerberos and waits for the mechanism selected by the acceptor
+    MechSelection,
+    // the acceptor switched to NTLM: the NTLM NEGOTIATE message is sent by the initiator and awaited by the acceptor
+    NtlmNegotiate,
+    InProgress,
+    MechListMic,
+    Final,
+}
+
+/// Implements the SPNEGO-based Negotiate security package.
+///
+/// Kerberos is chosen when the Kerberos config is provided and the target name is a service principal name.
+/// Otherwise NTLM wrapped in SPNEGO is used. The initiator obtains the TGT before the first Kerberos token
+/// and falls back to NTLM if no KDC of the client realm replies.
+/// NTLM is proposed after Kerberos, so the initiator also switches to it when the acceptor selects NTLM.
+///
+/// The NTLM acceptor verifies the initiator's mechListMIC and generates its own one in the `complete_auth_token`
+/// call because the NTLM session key is known only after the identity of the client is set.
+/// The final SPNEGO token is written to the `Token` buffer passed to `complete_auth_token`.
+///
+/// # MSDN
+///
+/// * [[MS-SPNG]: Simple and Protected GSS-API Negotiation Mechanism (SPNEGO) Extension](https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-spng/f377a379-c24f-4a0f-a3eb-0d835389e28a)
+#[derive(Debug, Clone)]
+pub struct Negotiate {
+    config: NegotiateConfig,
+    protocol: Option<NegotiatedProtocol>,
+    state: NegotiateState,
+    auth_data: Option<Credentials>,
+    credential_use: CredentialUse,
+    // DER-encoded mechTypes protected by the mechListMIC
+    mech_types: Vec<u8>,
+    // the mechListMIC of the initiator is verified when the NTLM session key is established
+    peer_mech_list_mic: Option<Vec<u8>>,
+    // the mechListMIC is mandatory when the negotiated mechanism is not the preferred mechanism of the initiator
+    is_mech_list_mic_required: bool,
+    // the flags of the NTLM context are reported again with the final token
+    ntlm_flags: ClientResponseFlags,
+}
+
+impl Negotiate {
+    pub fn new(config: NegotiateConfig) -> Self {
+        Self {
+            config,
+            protocol: None,
+            state: NegotiateState::Initial,
+            auth_data: None,
+            credential_use: CredentialUse::Outbound,
+            mech_types: Vec::new(),
+            peer_mech_list_mic: None,
+            is_mech_list_mic_required: false,
+            ntlm_flags: ClientResponseFlags::empty(),
+        }
+    }
+
+    /// Returns the name of the negotiated security package, if any
+    pub fn negotiated_package(&self) -> Option<&'static str> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(_)) => Some(crate::sspi::kerberos::PKG_NAME),
+            Some(NegotiatedProtocol::Ntlm(_)) => Some(crate::sspi::ntlm::PKG_NAME),
+            None => None,
+        }
+    }
+
+    fn is_kerberos_available(&self, target_name: Option<&str>) -> bool {
+        // `service/host` or the `service@host` host-based service name
+        self.config.kerberos_config.is_some()
+            && target_name
+                .map(|target_name| target_name.contains('/') || target_name.contains('@'))
+                .unwrap_or(false)
+    }
+
+    fn new_kerberos(&self) -> Result<Kerberos> {
+        let kerberos_config = self.config.kerberos_config.clone().ok_or_else(|| {
+            Error::new(
+                ErrorKind::SecurityPackageNotFound,
+                "Kerberos config is not specified".into(),
+            )
+        })?;
+
+        let mut kerberos = match self.credential_use {
+            CredentialUse::Outbound => Kerberos::new_client_from_config(kerberos_config)?,
+            _ => Kerberos::new_server_from_config_with_properties(
+                kerberos_config,
+                self.config.server_properties.clone(),
+            )?,
+        };
+
+        let builder = kerberos
+            .acquire_credentials_handle()
+            .with_credential_use(self.credential_use);
+        match self.auth_data {
+            Some(ref auth_data) => builder.with_auth_data(auth_data).execute()?,
+            None => builder.execute()?,
+        };
+        kerberos.set_mech_types(mech_list());
+
+        Ok(kerberos)
+    }
+
+    fn new_ntlm(&self) -> Result<Ntlm> {
+        let identity = match self.auth_data {
+            Some(Credentials::AuthIdentity(ref identity)) => Some(identity.clone()),
+            Some(Credentials::Keytab(_)) | Some(Credentials::PkInit(_)) => {
+                return Err(Error::new(
+                    ErrorKind::NoCredentials,
+                    "NTLM requires the username and password of the client".into(),
+                ))
+            }
+            None => None,
+        };
+
+        let mut ntlm = Ntlm::new();
+
+        let builder = ntlm
+            .acquire_credentials_handle()
+            .with_credential_use(self.credential_use);
+        match identity {
+            Some(ref identity) => builder.with_auth_data(identity).execute()?,
+            None => builder.execute()?,
+        };
+
+        Ok(ntlm)
+    }
+
+    fn ntlm(&mut self) -> Result<&mut Ntlm> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => Ok(ntlm),
+            _ => Err(Error::new(
+                ErrorKind::OutOfSequence,
+                "NTLM has not been negotiated".into(),
+            )),
+        }
+    }
+
+    fn initialize_ntlm(
+        &mut self,
+        builder: FilledInitializeSecurityContext<'_, Self, <Self as SspiImpl>::CredentialsHandle>,
+    ) -> Result<InitializeSecurityContextResult> {
+        let mut credentials_handle = builder
+            .credentials_handle
+            .as_ref()
+            .and_then(|handle| handle.as_ref().cloned())
+            .and_then(CredentialsBuffers::auth_identity);
+        let context_requirements = builder.context_requirements;
+        let target_data_representation = builder.target_data_representation;
+        let target_name = builder.target_name;
+
+        let (status, output_token) = match self.state {
+            NegotiateState::Initial => {
+                let (result, negotiate_message) = initialize_ntlm(
+                    self.ntlm()?,
+                    &mut credentials_handle,
+                    context_requirements,
+                    target_data_representation,
+                    target_name,
+                    None,
+                )?;
+                self.ntlm_flags = result.flags;
+
+                let mech_types = MechTypeList::from(vec![mech_type(NTLM_SSP)]);
+                self.mech_types = picky_asn1_der::to_vec(&mech_types)?;
+                self.state = NegotiateState::InProgress;
+
+                (
+                    result.status,
+                    picky_asn1_der::to_vec(&generate_neg_token_init(mech_types, negotiate_message))?,
+                )
+            }
+            // the acceptor selected NTLM instead of the proposed Kerberos
+            NegotiateState::NtlmNegotiate => {
+                let (result, negotiate_message) = initialize_ntlm(
+                    self.ntlm()?,
+                    &mut credentials_handle,
+                    context_requirements,
+                    target_data_representation,
+                    target_name,
+                    None,
+                )?;
+                self.ntlm_flags = result.flags;
+                self.state = NegotiateState::InProgress;
+
+                (
+                    result.status,
+                    picky_asn1_der::to_vec(&generate_neg_token_resp(None, None, Some(negotiate_message), None))?,
+                )
+            }
+            NegotiateState::InProgress => {
+                let token = parse_neg_token_resp(input_token(builder.input)?)?;
+                if let Some(ref supported_mech) = token.supported_mech {
+                    if supported_mech.0 != oid(NTLM_SSP) {
+                        return Err(Error::new(
+                            ErrorKind::SecurityPackageNotFound,
+                            format!("The acceptor selected unsupported mechanism: {:?}", supported_mech.0),
+                        ));
+                    }
+                }
+                let challenge_message = token.mech_token.ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidToken,
+                        "Missing NTLM CHALLENGE message in the NegTokenResp".into(),
+                    )
+                })?;
+
+                let (result, authenticate_message) = initialize_ntlm(
+                    self.ntlm()?,
+                    &mut credentials_handle,
+                    context_requirements,
+                    target_data_representation,
+                    target_name,
+                    Some(challenge_message),
+                )?;
+                self.ntlm_flags = result.flags;
+
+                let mech_types = self.mech_types.clone();
+                let mech_list_mic = self.ntlm()?.mech_list_mic(&mech_types)?;
+                self.state = NegotiateState::MechListMic;
+
+                (
+                    result.status,
+                    picky_asn1_der::to_vec(&generate_neg_token_resp(
+                        None,
+                        None,
+                        Some(authenticate_message),
+                        Some(mech_list_mic.to_vec()),
+                    ))?,
+                )
+            }
+            // the final NegTokenResp of the acceptor is optional for the initiator
+            NegotiateState::MechListMic => {
+                let token = parse_neg_token_resp(input_token(builder.input)?)?;
+                if let Some(ref mech_list_mic) = token.mech_list_mic {
+                    let mech_types = self.mech_types.clone();
+                    self.ntlm()?.verify_mech_list_mic(&mech_types, mech_list_mic)?;
+                }
+                self.state = NegotiateState::Final;
+
+                (SecurityStatus::Ok, Vec::new())
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::OutOfSequence,
+                    format!("Got wrong Negotiate state: {:?}", self.state),
+                ))
+            }
+        };
+
+        let output = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
+        output.buffer.write_all(&output_token)?;
+
+        Ok(InitializeSecurityContextResult {
+            status,
+            flags: self.ntlm_flags,
+            expiry: None,
+        })
+    }
+
+    fn accept_ntlm(
+        &mut self,
+        builder: FilledAcceptSecurityContext<'_, Self, <Self as SspiImpl>::CredentialsHandle>,
+        token: SpnegoToken,
+    ) -> Result<AcceptSecurityContextResult> {
+        let mut credentials_handle = builder
+            .credentials_handle
+            .as_ref()
+            .and_then(|handle| handle.as_ref().cloned())
+            .and_then(CredentialsBuffers::auth_identity);
+        let context_requirements = builder.context_requirements;
+        let target_data_representation = builder.target_data_representation;
+
+        let (status, flags, output_token) = match self.state {
+            NegotiateState::Initial | NegotiateState::NtlmNegotiate => match token.mech_token {
+                Some(negotiate_message) => {
+                    let (result, challenge_message) = accept_ntlm(
+                        self.ntlm()?,
+                        &mut credentials_handle,
+                        context_requirements,
+                        target_data_representation,
+                        negotiate_message,
+                    )?;
+                    let supported_mech = if self.state == NegotiateState::Initial {
+                        Some(mech_type(NTLM_SSP))
+                    } else {
+                        None
+                    };
+                    self.state = NegotiateState::InProgress;
+
+                    (
+                        result.status,
+                        result.flags,
+                        generate_neg_token_resp(
+                            Some(ACCEPT_INCOMPLETE.to_vec()),
+                            supported_mech,
+                            Some(challenge_message),
+                            None,
+                        ),
+                    )
+                }
+                // the optimistic token of the initiator is not an NTLM token
+                None => {
+                    self.state = NegotiateState::NtlmNegotiate;
+
+                    (
+                        SecurityStatus::ContinueNeeded,
+                        ServerResponseFlags::empty(),
+                        generate_neg_token_resp(Some(REQUEST_MIC.to_vec()), Some(mech_type(NTLM_SSP)), None, None),
+                    )
+                }
+            },
+            NegotiateState::InProgress => {
+                let authenticate_message = token.mech_token.ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidToken,
+                        "Missing NTLM AUTHENTICATE message in the NegTokenResp".into(),
+                    )
+                })?;
+                if self.is_mech_list_mic_required && token.mech_list_mic.is_none() {
+                    return Err(Error::new(
+                        ErrorKind::DowngradeDetected,
+                        "The initiator did not send the mechListMIC".into(),
+                    ));
+                }
+
+                let (result, _) = accept_ntlm(
+                    self.ntlm()?,
+                    &mut credentials_handle,
+                    context_requirements,
+                    target_data_representation,
+                    authenticate_message,
+                )?;
+                self.peer_mech_list_mic = token.mech_list_mic;
+                self.state = NegotiateState::MechListMic;
+
+                return Ok(result);
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::OutOfSequence,
+                    format!("Got wrong Negotiate state: {:?}", self.state),
+                ))
+            }
+        };
+
+        let output = SecurityBuffer::find_buffer_mut(builder.output, SecurityBufferType::Token)?;
+        output.buffer.write_all(&picky_asn1_der::to_vec(&output_token)?)?;
+
+        Ok(AcceptSecurityContextResult {
+            status,
+            flags,
+            expiry: None,
+        })
+    }
+
+    // verifies the mechListMIC of the initiator and generates the final NegTokenResp
+    fn complete_ntlm_mech_list_mic(&mut self, token: &mut [SecurityBuffer]) -> Result<()> {
+        let mech_types = self.mech_types.clone();
+        let peer_mech_list_mic = self.peer_mech_list_mic.take();
+        let ntlm = self.ntlm()?;
+
+        let mech_list_mic = match peer_mech_list_mic {
+            Some(ref peer_mech_list_mic) => {
+                ntlm.verify_mech_list_mic(&mech_types, peer_mech_list_mic)?;
+
+                Some(ntlm.mech_list_mic(&mech_types)?.to_vec())
+            }
+            None => None,
+        };
+        self.state = NegotiateState::Final;
+
+        if let Ok(output) = SecurityBuffer::find_buffer_mut(token, SecurityBufferType::Token) {
+            output
+                .buffer
+                .write_all(&picky_asn1_der::to_vec(&generate_neg_token_resp(
+                    Some(ACCEPT_COMPLETE.to_vec()),
+                    None,
+                    None,
+                    mech_list_mic,
+                ))?)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SspiImpl for Negotiate {
+    type CredentialsHandle = Option<CredentialsBuffers>;
+    type AuthenticationData = Credentials;
+
+    fn acquire_credentials_handle_impl(
+        &mut self,
+        builder: FilledAcquireCredentialsHandle<'_, Self, Self::CredentialsHandle, Self::AuthenticationData>,
+    ) -> Result<AcquireCredentialsHandleResult<Self::CredentialsHandle>> {
+        if builder.credential_use == CredentialUse::Outbound && builder.auth_data.is_none() {
+            return Err(Error::new(
+                ErrorKind::NoCredentials,
+                String::from("The client must specify the auth data"),
+            ));
+        }
+
+        self.auth_data = builder.auth_data.cloned();
+        self.credential_use = builder.credential_use;
+
+        Ok(AcquireCredentialsHandleResult {
+            credentials_handle: self.auth_data.clone().map(CredentialsBuffers::from),
+            expiry: None,
+        })
+    }
+
+    fn initialize_security_context_impl(
+        &mut self,
+        mut builder: FilledInitializeSecurityContext<'_, Self, Self::CredentialsHandle>,
+    ) -> Result<InitializeSecurityContextResult> {
+        if self.protocol.is_none() {
+            if self.is_kerberos_available(builder.target_name) {
+                let mut kerberos = self.new_kerberos()?;
+
+                // NTLM is used only if the KDC does not reply
+                match initialize_kerberos(&mut kerberos, &mut builder) {
+                    Ok(result) => {
+                        self.protocol = Some(NegotiatedProtocol::Kerberos(kerberos));
+                        self.state = NegotiateState::MechSelection;
+
+                        return Ok(result);
+                    }
+                    Err(error) if error.error_type == ErrorKind::NoAuthenticatingAuthority => {}
+                    Err(error) => return Err(error),
+                }
+            }
+
+            self.protocol = Some(NegotiatedProtocol::Ntlm(self.new_ntlm()?));
+        }
+
+        if self.state == NegotiateState::MechSelection {
+            let is_ntlm_selected = input_token(builder.input.as_deref_mut())
+                .and_then(parse_neg_token_resp)
+                .map(|token| token.supported_mech == Some(mech_type(NTLM_SSP)))
+                .unwrap_or(false);
+
+            if is_ntlm_selected {
+                self.protocol = Some(NegotiatedProtocol::Ntlm(self.new_ntlm()?));
+                self.mech_types = picky_asn1_der::to_vec(&mech_list())?;
+                self.state = NegotiateState::NtlmNegotiate;
+            } else {
+                self.state = NegotiateState::InProgress;
+            }
+        }
+
+        // the Kerberos package generates SPNEGO tokens and handles the mechListMIC by itself
+        if let Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) = self.protocol {
+            return builder.transform(kerberos).execute();
+        }
+
+        self.initialize_ntlm(builder)
+    }
+
+    fn accept_security_context_impl(
+        &mut self,
+        builder: FilledAcceptSecurityContext<'_, Self, Self::CredentialsHandle>,
+    ) -> Result<AcceptSecurityContextResult> {
+        if let Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) = self.protocol {
+            return builder.transform(kerberos).execute();
+        }
+
+        let input = SecurityBuffer::find_buffer(
+            builder
+                .input
+                .as_deref()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidToken, "Input buffers must be specified".into()))?,
+            SecurityBufferType::Token,
+        )?;
+
+        if self.protocol.is_some() {
+            let token = parse_neg_token_resp(&input.buffer)?;
+
+            return self.accept_ntlm(builder, token);
+        }
+
+        let token = parse_neg_token_init(&input.buffer)?;
+        let mech_types = token
+            .mech_types
+            .clone()
+            .unwrap_or_else(|| MechTypeList::from(Vec::new()));
+        let preferred_mech = mech_types.0.first().map(|mech_type| mech_type.0.clone());
+
+        let is_kerberos_preferred = preferred_mech == Some(oid(MS_KRB5)) || preferred_mech == Some(oid(KRB5));
+        if is_kerberos_preferred && self.config.kerberos_config.is_some() {
+            let mut kerberos = self.new_kerberos()?;
+            let result = builder.transform(&mut kerberos).execute();
+            self.protocol = Some(NegotiatedProtocol::Kerberos(kerberos));
+
+            return result;
+        }
+
+        if !mech_types.0.iter().any(|mech_type| mech_type.0 == oid(NTLM_SSP)) {
+            return Err(Error::new(
+                ErrorKind::SecurityPackageNotFound,
+                format!("None of the proposed mechanisms is supported: {:?}", mech_types.0),
+            ));
+        }
+
+        self.protocol = Some(NegotiatedProtocol::Ntlm(self.new_ntlm()?));
+        self.mech_types = picky_asn1_der::to_vec(&mech_types)?;
+        self.is_mech_list_mic_required = preferred_mech != Some(oid(NTLM_SSP));
+
+        let token = if self.is_mech_list_mic_required {
+            SpnegoToken {
+                mech_token: None,
+                ..token
+            }
+        } else {
+            token
+        };
+
+        self.accept_ntlm(builder, token)
+    }
+}
+
+impl Sspi for Negotiate {
+    fn complete_auth_token(&mut self, token: &mut [SecurityBuffer]) -> Result<SecurityStatus> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.complete_auth_token(token),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => {
+                let status = ntlm.complete_auth_token(token)?;
+
+                if self.credential_use != CredentialUse::Outbound && self.state == NegotiateState::MechListMic {
+                    self.complete_ntlm_mech_list_mic(token)?;
+                }
+
+                Ok(status)
+            }
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn encrypt_message(
+        &mut self,
+        flags: EncryptionFlags,
+        message: &mut [SecurityBuffer],
+        sequence_number: u32,
+    ) -> Result<SecurityStatus> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => {
+                kerberos.encrypt_message(flags, message, sequence_number)
+            }
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.encrypt_message(flags, message, sequence_number),
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn decrypt_message(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> Result<DecryptionFlags> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.decrypt_message(message, sequence_number),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.decrypt_message(message, sequence_number),
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn make_signature(&mut self, flags: u32, message: &mut [SecurityBuffer], sequence_number: u32) -> Result<()> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => {
+                kerberos.make_signature(flags, message, sequence_number)
+            }
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.make_signature(flags, message, sequence_number),
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn verify_signature(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> Result<u32> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.verify_signature(message, sequence_number),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.verify_signature(message, sequence_number),
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn query_context_sizes(&mut self) -> Result<ContextSizes> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.query_context_sizes(),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.query_context_sizes(),
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn query_context_names(&mut self) -> Result<ContextNames> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.query_context_names(),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.query_context_names(),
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn query_context_package_info(&mut self) -> Result<PackageInfo> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.query_context_package_info(),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.query_context_package_info(),
+            None => Ok(PACKAGE_INFO.clone()),
+        }
+    }
+
+    fn query_context_cert_trust_status(&mut self) -> Result<CertTrustStatus> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.query_context_cert_trust_status(),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.query_context_cert_trust_status(),
+            None => Err(no_protocol_error()),
+        }
+    }
+
+    fn query_context_pac(&mut self) -> Result<Pac> {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.query_context_pac(),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => ntlm.query_context_pac(),
+            None => Err(no_protocol_error()),
+        }
+    }
+}
+
+impl SspiEx for Negotiate {
+    fn custom_set_auth_identity(&mut self, identity: Self::AuthenticationData) {
+        match self.protocol {
+            Some(NegotiatedProtocol::Kerberos(ref mut kerberos)) => kerberos.custom_set_auth_identity(identity.clone()),
+            Some(NegotiatedProtocol::Ntlm(ref mut ntlm)) => {
+                if let Credentials::AuthIdentity(ref identity) = identity {
+                    ntlm.custom_set_auth_identity(identity.clone());
+                }
+            }
+            None => {}
+        }
+
+        self.auth_data = Some(identity);
+    }
+}
+
+fn no_protocol_error() -> Error {
+    Error::new(
+        ErrorKind::OutOfSequence,
+        "The security package has not been negotiated yet".into(),
+    )
+}
+
+fn oid(oid: &str) -> ObjectIdentifier {
+    ObjectIdentifier::try_from(oid).unwrap()
+}
+
+fn mech_type(oid_value: &str) -> MechType {
+    MechType::from(oid(oid_value))
+}
+
+// the initiator prefers Kerberos and proposes NTLM after it
+fn mech_list() -> MechTypeList {
+    MechTypeList::from(vec![mech_type(MS_KRB5), mech_type(KRB5), mech_type(NTLM_SSP)])
+}
+
+fn input_token(input: Option<&mut [SecurityBuffer]>) -> Result<&[u8]> {
+    let input = input.ok_or_else(|| Error::new(ErrorKind::InvalidToken, "Input buffers must be specified".into()))?;
+
+    Ok(SecurityBuffer::find_buffer(input, SecurityBufferType::Token)?
+        .buffer
+        .as_slice())
+}
+
+// the builder is reborrowed to pass it to NTLM if the KDC does not reply
+fn initialize_kerberos(
+    kerberos: &mut Kerberos,
+    builder: &mut FilledInitializeSecurityContext<'_, Negotiate, Option<CredentialsBuffers>>,
+) -> Result<InitializeSecurityContextResult> {
+    let mut credentials_handle = builder.credentials_handle.as_deref().cloned().flatten();
+
+    // the AS exchange is the first one which needs the KDC
+    let credentials = credentials_handle
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::NoCredentials, "No credentials provided".into()))?;
+    kerberos.request_initial_tgt(credentials)?;
+
+    let mut kerberos_builder = kerberos
+        .initialize_security_context()
+        .with_credentials_handle(&mut credentials_handle)
+        .with_context_requirements(builder.context_requirements)
+        .with_target_data_representation(builder.target_data_representation)
+        .with_output(builder.output);
+    if let Some(target_name) = builder.target_name {
+        kerberos_builder = kerberos_builder.with_target_name(target_name);
+    }
+
+    kerberos_builder.execute()
+}
+
+fn initialize_ntlm(
+    ntlm: &mut Ntlm,
+    credentials_handle: &mut Option<AuthIdentityBuffers>,
+    context_requirements: ClientRequestFlags,
+    target_data_representation: DataRepresentation,
+    target_name: Option<&str>,
+    input_token: Option<Vec<u8>>,
+) -> Result<(InitializeSecurityContextResult, Vec<u8>)> {
+    let mut input = input_token
+        .map(|token| vec![SecurityBuffer::new(token, SecurityBufferType::Token)])
+        .unwrap_or_default();
+    let mut output = vec![SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+
+    let mut builder = ntlm
+        .initialize_security_context()
+        .with_credentials_handle(credentials_handle)
+        .with_context_requirements(context_requirements)
+        .with_target_data_representation(target_data_representation)
+        .with_input(&mut input)
+        .with_output(&mut output);
+    if let Some(target_name) = target_name {
+        builder = builder.with_target_name(target_name);
+    }
+    let result = builder.execute()?;
+
+    Ok((result, output.remove(0).buffer))
+}
+
+fn accept_ntlm(
+    ntlm: &mut Ntlm,
+    credentials_handle: &mut Option<AuthIdentityBuffers>,
+    context_requirements: ServerRequestFlags,
+    target_data_representation: DataRepresentation,
+    input_token: Vec<u8>,
+) -> Result<(AcceptSecurityContextResult, Vec<u8>)> {
+    let mut input = vec![SecurityBuffer::new(input_token, SecurityBufferType::Token)];
+    let mut output = vec![SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+
+    let result = ntlm
+        .accept_security_context()
+        .with_credentials_handle(credentials_handle)
+        .with_context_requirements(context_requirements)
+        .with_target_data_representation(target_data_representation)
+        .with_input(&mut input)
+        .with_output(&mut output)
+        .execute()?;
+
+    Ok((result, output.remove(0).buffer))
+}
+
+/// Fields of the SPNEGO NegTokenInit or NegTokenResp
+#[derive(Debug, Default)]
+struct SpnegoToken {
+    mech_types: Option<MechTypeList>,
+    supported_mech: Option<MechType>,
+    mech_token: Option<Vec<u8>>,
+    mech_list_mic: Option<Vec<u8>>,
+}
+
+fn parse_neg_token_init(data: &[u8]) -> Result<SpnegoToken> {
+    let mut reader = data;
+
+    let oid: ApplicationTag<Asn1RawDer, 0> = picky_asn1_der::from_reader(&mut reader)
+        .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+    let oid: ObjectIdentifierAsn1 = picky_asn1_der::from_bytes(&oid.0 .0)?;
+    if oid.0 != self::oid(SPNEGO) {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            format!("Expected SPNEGO token but got: {:?}", oid.0),
+        ));
+    }
+
+    // NegTokenInit is expected to be wrapped into the [0] tag but some initiators omit it
+    let neg_token_init: NegTokenInit = if reader.first() == Some(&0xa0) {
+        picky_asn1_der::from_reader::<ExplicitContextTag0<NegTokenInit>>(&mut reader).map(|token| token.0)
+    } else {
+        picky_asn1_der::from_reader(&mut reader)
+    }
+    .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+    let NegTokenInit {
+        mech_types,
+        mech_token,
+        mech_list_mic,
+        ..
+    } = neg_token_init;
+
+    Ok(SpnegoToken {
+        mech_types: mech_types.0.map(|mech_types| mech_types.0),
+        supported_mech: None,
+        mech_token: mech_token.0.map(|token| token.0 .0),
+        mech_list_mic: mech_list_mic.0.map(|mic| mic.0 .0),
+    })
+}
+
+/// NegTokenResp without the negState field. The picky-krb `NegTokenTarg` decoder takes the first present field
+/// as the negState, so the tokens without it are decoded using this structure
+#[derive(Debug, Deserialize)]
+struct StatelessNegTokenResp {
+    #[serde(default)]
+    supported_mech: Optional<Option<ExplicitContextTag1<MechType>>>,
+    #[serde(default)]
+    response_token: Optional<Option<ExplicitContextTag2<OctetStringAsn1>>>,
+    #[serde(default)]
+    mech_list_mic: Optional<Option<ExplicitContextTag3<OctetStringAsn1>>>,
+}
+
+fn parse_neg_token_resp(data: &[u8]) -> Result<SpnegoToken> {
+    let neg_token_resp: NegTokenTarg1 =
+        picky_asn1_der::from_bytes(data).map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+    let NegTokenTarg {
+        neg_result,
+        supported_mech,
+        response_token,
+        mech_list_mic,
+    } = neg_token_resp.0;
+
+    match neg_result.0 {
+        // negState is ENUMERATED
+        Some(neg_result) if neg_result.0 .0.first() == Some(&0x0a) => {
+            if neg_result.0 .0 == REJECT {
+                return Err(Error::new(
+                    ErrorKind::LogonDenied,
+                    "The peer rejected the authentication".into(),
+                ));
+            }
+
+            Ok(SpnegoToken {
+                mech_types: None,
+                supported_mech: supported_mech.0.map(|mech| mech.0),
+                mech_token: response_token.0.map(|token| token.0 .0),
+                mech_list_mic: mech_list_mic.0.map(|mic| mic.0 .0),
+            })
+        }
+        _ => {
+            let neg_token_resp: ExplicitContextTag1<StatelessNegTokenResp> = picky_asn1_der::from_bytes(data)
+                .map_err(|e| Error::new(ErrorKind::InvalidToken, format!("{:?}", e)))?;
+            let StatelessNegTokenResp {
+                supported_mech,
+                response_token,
+                mech_list_mic,
+            } = neg_token_resp.0;
+
+            Ok(SpnegoToken {
+                mech_types: None,
+                supported_mech: supported_mech.0.map(|mech| mech.0),
+                mech_token: response_token.0.map(|token| token.0 .0),
+                mech_list_mic: mech_list_mic.0.map(|mic| mic.0 .0),
+            })
+        }
+    }
+}
+
+fn generate_neg_token_init(mech_types: MechTypeList, mech_token: Vec<u8>) -> ApplicationTag0<GssApiNegInit> {
+    ApplicationTag0(GssApiNegInit {
+        oid: ObjectIdentifierAsn1::from(oid(SPNEGO)),
+        neg_token_init: ExplicitContextTag0::from(NegTokenInit {
+            mech_types: Optional::from(Some(ExplicitContextTag0::from(mech_types))),
+            req_flags: Optional::from(None),
+            mech_token: Optional::from(Some(ExplicitContextTag2::from(OctetStringAsn1::from(mech_token)))),
+            mech_list_mic: Optional::from(None),
+        }),
+    })
+}
+
+fn generate_neg_token_resp(
+    neg_result: Option<Vec<u8>>,
+    supported_mech: Option<MechType>,
+    response_token: Option<Vec<u8>>,
+    mech_list_mic: Option<Vec<u8>>,
+) -> NegTokenTarg1 {
+    NegTokenTarg1::from(NegTokenTarg {
+        neg_result: Optional::from(neg_result.map(|neg_result| ExplicitContextTag0::from(Asn1RawDer(neg_result)))),
+        supported_mech: Optional::from(supported_mech.map(ExplicitContextTag1::from)),
+        response_token: Optional::from(
+            response_token.map(|token| ExplicitContextTag2::from(OctetStringAsn1::from(token))),
+        ),
+        mech_list_mic: Optional::from(mech_list_mic.map(|mic| ExplicitContextTag3::from(OctetStringAsn1::from(mic)))),
+    })
+}
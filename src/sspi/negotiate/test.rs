@@ -0,0 +1,378 @@
+use url::Url;
+
+use std::sync::Arc;
+
+use super::*;
+use crate::sspi::kerberos::ccache::{CredentialsCache, MemoryCredentialsCache};
+use crate::sspi::kerberos::network_client::NetworkClient;
+use crate::sspi::kerberos::test::credentials_for;
+use crate::AuthIdentity;
+
+#[derive(Debug, Clone)]
+struct UnreachableNetworkClient;
+
+impl NetworkClient for UnreachableNetworkClient {
+    fn send(&self, _url: &Url, _data: &[u8]) -> Result<Vec<u8>> {
+        Err(Error::new(
+            ErrorKind::NoAuthenticatingAuthority,
+            "KDC is unreachable".into(),
+        ))
+    }
+
+    fn send_http(&self, _url: &Url, _data: &[u8], _domain: Option<String>) -> Result<Vec<u8>> {
+        Err(Error::new(
+            ErrorKind::NoAuthenticatingAuthority,
+            "KDC is unreachable".into(),
+        ))
+    }
+
+    fn box_clone(&self) -> Box<dyn NetworkClient> {
+        Box::new(self.clone())
+    }
+}
+
+// replies with the empty TCP message to any request
+#[derive(Debug, Clone)]
+struct ReachableNetworkClient;
+
+impl NetworkClient for ReachableNetworkClient {
+    fn send(&self, _url: &Url, _data: &[u8]) -> Result<Vec<u8>> {
+        Ok(vec![0x00; 4])
+    }
+
+    fn send_http(&self, _url: &Url, _data: &[u8], _domain: Option<String>) -> Result<Vec<u8>> {
+        Ok(vec![0x00; 4])
+    }
+
+    fn box_clone(&self) -> Box<dyn NetworkClient> {
+        Box::new(self.clone())
+    }
+}
+
+fn credentials() -> Credentials {
+    Credentials::AuthIdentity(AuthIdentity {
+        username: "user".into(),
+        password: "password".into(),
+        domain: Some("EXAMPLE.COM".into()),
+    })
+}
+
+fn kerberos_config(network_client: Box<dyn NetworkClient>) -> KerberosConfig {
+    KerberosConfig::new(Url::parse("tcp://kdc.example.com:88").unwrap(), network_client)
+}
+
+// the KDC is unreachable, so Kerberos is chosen only with the cached TGT of the client
+fn kerberos_config_with_tgt() -> KerberosConfig {
+    let credentials_cache = MemoryCredentialsCache::new();
+    credentials_cache
+        .store(credentials_for("krbtgt/EXAMPLE.COM", "EXAMPLE.COM", &[1; 32]))
+        .unwrap();
+
+    kerberos_config(Box::new(UnreachableNetworkClient)).with_credentials_cache(Arc::new(credentials_cache))
+}
+
+fn client(config: NegotiateConfig) -> (Negotiate, Option<CredentialsBuffers>) {
+    let mut negotiate = Negotiate::new(config);
+    let credentials_handle = negotiate
+        .acquire_credentials_handle()
+        .with_credential_use(CredentialUse::Outbound)
+        .with_auth_data(&credentials())
+        .execute()
+        .unwrap()
+        .credentials_handle;
+
+    (negotiate, credentials_handle)
+}
+
+fn server(config: NegotiateConfig) -> (Negotiate, Option<CredentialsBuffers>) {
+    let mut negotiate = Negotiate::new(config);
+    let credentials_handle = negotiate
+        .acquire_credentials_handle()
+        .with_credential_use(CredentialUse::Inbound)
+        .execute()
+        .unwrap()
+        .credentials_handle;
+
+    (negotiate, credentials_handle)
+}
+
+fn initialize(
+    client: &mut Negotiate,
+    credentials_handle: &mut Option<CredentialsBuffers>,
+    target_name: &str,
+    input: Option<Vec<u8>>,
+) -> Result<(SecurityStatus, Vec<u8>)> {
+    let mut input = input
+        .map(|token| vec![SecurityBuffer::new(token, SecurityBufferType::Token)])
+        .unwrap_or_default();
+    let mut output = vec![SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+
+    let result = client
+        .initialize_security_context()
+        .with_credentials_handle(credentials_handle)
+        .with_context_requirements(ClientRequestFlags::empty())
+        .with_target_data_representation(DataRepresentation::Native)
+        .with_target_name(target_name)
+        .with_input(&mut input)
+        .with_output(&mut output)
+        .execute()?;
+
+    Ok((result.status, output.remove(0).buffer))
+}
+
+fn accept(
+    server: &mut Negotiate,
+    credentials_handle: &mut Option<CredentialsBuffers>,
+    input: Vec<u8>,
+) -> Result<(SecurityStatus, Vec<u8>)> {
+    let mut input = vec![SecurityBuffer::new(input, SecurityBufferType::Token)];
+    let mut output = vec![SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+
+    let result = server
+        .accept_security_context()
+        .with_credentials_handle(credentials_handle)
+        .with_context_requirements(ServerRequestFlags::empty())
+        .with_target_data_representation(DataRepresentation::Native)
+        .with_input(&mut input)
+        .with_output(&mut output)
+        .execute()?;
+
+    Ok((result.status, output.remove(0).buffer))
+}
+
+fn complete(server: &mut Negotiate) -> Result<Vec<u8>> {
+    server.custom_set_auth_identity(credentials());
+
+    let mut token = [SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+    server.complete_auth_token(&mut token)?;
+    let [token] = token;
+
+    Ok(token.buffer)
+}
+
+#[test]
+fn client_falls_back_to_ntlm_without_kerberos_config() {
+    let (mut client, mut credentials_handle) = client(NegotiateConfig::default());
+
+    let (status, token) = initialize(&mut client, &mut credentials_handle, "TERMSRV/server.example.com", None).unwrap();
+
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    assert_eq!(client.negotiated_package(), Some(crate::sspi::ntlm::PKG_NAME));
+
+    let token = parse_neg_token_init(&token).unwrap();
+    assert_eq!(token.mech_types.unwrap().0, vec![mech_type(NTLM_SSP)]);
+    assert_eq!(&token.mech_token.unwrap()[..8], b"NTLMSSP\0");
+}
+
+#[test]
+fn client_falls_back_to_ntlm_when_target_is_not_spn() {
+    let (mut client, mut credentials_handle) = client(NegotiateConfig::new(Some(kerberos_config(Box::new(
+        ReachableNetworkClient,
+    )))));
+
+    initialize(&mut client, &mut credentials_handle, "server.example.com", None).unwrap();
+
+    assert_eq!(client.negotiated_package(), Some(crate::sspi::ntlm::PKG_NAME));
+}
+
+#[test]
+fn client_falls_back_to_ntlm_when_kdc_is_unreachable() {
+    let (mut client, mut credentials_handle) = client(NegotiateConfig::new(Some(kerberos_config(Box::new(
+        UnreachableNetworkClient,
+    )))));
+
+    initialize(&mut client, &mut credentials_handle, "TERMSRV/server.example.com", None).unwrap();
+
+    assert_eq!(client.negotiated_package(), Some(crate::sspi::ntlm::PKG_NAME));
+}
+
+#[test]
+fn client_chooses_kerberos_with_tgt() {
+    let (mut client, mut credentials_handle) = client(NegotiateConfig::new(Some(kerberos_config_with_tgt())));
+
+    let (status, token) = initialize(&mut client, &mut credentials_handle, "TERMSRV/server.example.com", None).unwrap();
+
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    assert_eq!(client.negotiated_package(), Some(crate::sspi::kerberos::PKG_NAME));
+    assert_eq!(
+        parse_neg_token_init(&token).unwrap().mech_types.unwrap().0,
+        vec![mech_type(MS_KRB5), mech_type(KRB5), mech_type(NTLM_SSP)]
+    );
+}
+
+#[test]
+fn client_fails_when_kdc_reply_is_invalid() {
+    let (mut client, mut credentials_handle) = client(NegotiateConfig::new(Some(kerberos_config(Box::new(
+        ReachableNetworkClient,
+    )))));
+
+    // only the unreachable KDC is a reason to fall back to NTLM
+    let error = initialize(&mut client, &mut credentials_handle, "TERMSRV/server.example.com", None).unwrap_err();
+
+    assert_eq!(error.error_type, ErrorKind::InvalidToken);
+    assert_eq!(client.negotiated_package(), None);
+}
+
+#[test]
+fn client_switches_to_ntlm_selected_by_server() {
+    let target_name = "TERMSRV/server.example.com";
+    let (mut client, mut client_credentials_handle) = client(NegotiateConfig::new(Some(kerberos_config_with_tgt())));
+    let (mut server, mut server_credentials_handle) = server(NegotiateConfig::default());
+
+    let (_, init) = initialize(&mut client, &mut client_credentials_handle, target_name, None).unwrap();
+    let (status, selection) = accept(&mut server, &mut server_credentials_handle, init).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+
+    let (status, negotiate) = initialize(
+        &mut client,
+        &mut client_credentials_handle,
+        target_name,
+        Some(selection),
+    )
+    .unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    assert_eq!(client.negotiated_package(), Some(crate::sspi::ntlm::PKG_NAME));
+
+    let (status, challenge) = accept(&mut server, &mut server_credentials_handle, negotiate).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+
+    let (status, authenticate) = initialize(
+        &mut client,
+        &mut client_credentials_handle,
+        target_name,
+        Some(challenge),
+    )
+    .unwrap();
+    assert_eq!(status, SecurityStatus::Ok);
+
+    let (status, _) = accept(&mut server, &mut server_credentials_handle, authenticate).unwrap();
+    assert_eq!(status, SecurityStatus::CompleteNeeded);
+
+    let final_token = complete(&mut server).unwrap();
+    let (status, _) = initialize(
+        &mut client,
+        &mut client_credentials_handle,
+        target_name,
+        Some(final_token),
+    )
+    .unwrap();
+    assert_eq!(status, SecurityStatus::Ok);
+}
+
+#[test]
+fn ntlm_authentication_verifies_mech_list_mic() {
+    let target_name = "TERMSRV/server.example.com";
+    let (mut client, mut client_credentials_handle) = client(NegotiateConfig::default());
+    let (mut server, mut server_credentials_handle) = server(NegotiateConfig::default());
+
+    let (_, negotiate) = initialize(&mut client, &mut client_credentials_handle, target_name, None).unwrap();
+    let (status, challenge) = accept(&mut server, &mut server_credentials_handle, negotiate).unwrap();
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+
+    let (status, authenticate) = initialize(
+        &mut client,
+        &mut client_credentials_handle,
+        target_name,
+        Some(challenge),
+    )
+    .unwrap();
+    assert_eq!(status, SecurityStatus::Ok);
+    assert!(parse_neg_token_resp(&authenticate).unwrap().mech_list_mic.is_some());
+
+    let (status, _) = accept(&mut server, &mut server_credentials_handle, authenticate).unwrap();
+    assert_eq!(status, SecurityStatus::CompleteNeeded);
+
+    let final_token = complete(&mut server).unwrap();
+    assert!(parse_neg_token_resp(&final_token).unwrap().mech_list_mic.is_some());
+
+    let (status, _) = initialize(
+        &mut client,
+        &mut client_credentials_handle,
+        target_name,
+        Some(final_token),
+    )
+    .unwrap();
+    assert_eq!(status, SecurityStatus::Ok);
+}
+
+#[test]
+fn ntlm_authentication_fails_on_altered_mech_list_mic() {
+    let target_name = "TERMSRV/server.example.com";
+    let (mut client, mut client_credentials_handle) = client(NegotiateConfig::default());
+    let (mut server, mut server_credentials_handle) = server(NegotiateConfig::default());
+
+    let (_, negotiate) = initialize(&mut client, &mut client_credentials_handle, target_name, None).unwrap();
+    let (_, challenge) = accept(&mut server, &mut server_credentials_handle, negotiate).unwrap();
+    let (_, authenticate) = initialize(
+        &mut client,
+        &mut client_credentials_handle,
+        target_name,
+        Some(challenge),
+    )
+    .unwrap();
+
+    let mut token = parse_neg_token_resp(&authenticate).unwrap();
+    token.mech_list_mic.as_mut().unwrap()[15] ^= 0xff;
+    let authenticate = picky_asn1_der::to_vec(&generate_neg_token_resp(
+        None,
+        None,
+        token.mech_token,
+        token.mech_list_mic,
+    ))
+    .unwrap();
+
+    accept(&mut server, &mut server_credentials_handle, authenticate).unwrap();
+
+    assert_eq!(complete(&mut server).unwrap_err().error_type, ErrorKind::MessageAltered);
+}
+
+#[test]
+fn server_requests_mic_when_ntlm_is_not_preferred_mechanism() {
+    let (mut server, mut credentials_handle) = server(NegotiateConfig::default());
+    let token = picky_asn1_der::to_vec(&generate_neg_token_init(
+        MechTypeList::from(vec![mech_type(MS_KRB5), mech_type(NTLM_SSP)]),
+        vec![0x60, 0x00],
+    ))
+    .unwrap();
+
+    let (status, token) = accept(&mut server, &mut credentials_handle, token).unwrap();
+
+    assert_eq!(status, SecurityStatus::ContinueNeeded);
+    let neg_token_resp: NegTokenTarg1 = picky_asn1_der::from_bytes(&token).unwrap();
+    assert_eq!(neg_token_resp.0.neg_result.0.unwrap().0 .0, REQUEST_MIC.to_vec());
+    assert_eq!(neg_token_resp.0.supported_mech.0.unwrap().0, mech_type(NTLM_SSP));
+    assert!(neg_token_resp.0.response_token.0.is_none());
+}
+
+#[test]
+fn server_fails_without_supported_mechanisms() {
+    let (mut server, mut credentials_handle) = server(NegotiateConfig::default());
+    let token = picky_asn1_der::to_vec(&generate_neg_token_init(
+        MechTypeList::from(vec![mech_type(KRB5)]),
+        vec![0x60, 0x00],
+    ))
+    .unwrap();
+
+    assert_eq!(
+        accept(&mut server, &mut credentials_handle, token)
+            .unwrap_err()
+            .error_type,
+        ErrorKind::SecurityPackageNotFound
+    );
+}
+
+#[test]
+fn parse_neg_token_resp_without_neg_state() {
+    let token = picky_asn1_der::to_vec(&generate_neg_token_resp(
+        None,
+        None,
+        Some(vec![0x42; 200]),
+        Some(vec![0x24; 16]),
+    ))
+    .unwrap();
+
+    let token = parse_neg_token_resp(&token).unwrap();
+
+    assert_eq!(token.mech_token, Some(vec![0x42; 200]));
+    assert_eq!(token.mech_list_mic, Some(vec![0x24; 16]));
+}
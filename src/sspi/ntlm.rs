@@ -12,6 +12,7 @@ use serde_derive::{Deserialize, Serialize};
 
 use crate::crypto::{compute_hmac_md5, Rc4, HASH_SIZE};
 use crate::sspi::internal::SspiImpl;
+use crate::sspi::kerberos::pac::Pac;
 use crate::sspi::{
     self, CertTrustStatus, ClientResponseFlags, ContextNames, ContextSizes, CredentialUse, DecryptionFlags,
     EncryptionFlags, FilledAcceptSecurityContext, FilledAcquireCredentialsHandle, FilledInitializeSecurityContext,
@@ -133,6 +134,43 @@ impl Ntlm {
     pub fn set_version(&mut self, version: [u8; NTLM_VERSION_SIZE]) {
         self.version = version;
     }
+
+    /// Computes the SPNEGO mechListMIC over the DER-encoded mechTypes.
+    /// The RC4 state is reset after the mechListMIC, so a copy of the sealing key is used
+    pub(crate) fn mech_list_mic(&self, mech_types: &[u8]) -> sspi::Result<[u8; SIGNATURE_SIZE]> {
+        let mut sealing_key = self.send_sealing_key.clone().ok_or_else(|| {
+            sspi::Error::new(
+                sspi::ErrorKind::OutOfSequence,
+                String::from("The mechListMIC cannot be computed before the session key is established"),
+            )
+        })?;
+
+        let digest = compute_digest(&self.send_signing_key, 0, mech_types)?;
+        let checksum = sealing_key.process(&digest[0..SIGNATURE_CHECKSUM_SIZE]);
+
+        Ok(compute_signature(&checksum, 0))
+    }
+
+    pub(crate) fn verify_mech_list_mic(&self, mech_types: &[u8], mech_list_mic: &[u8]) -> sspi::Result<()> {
+        let mut sealing_key = self.recv_sealing_key.clone().ok_or_else(|| {
+            sspi::Error::new(
+                sspi::ErrorKind::OutOfSequence,
+                String::from("The mechListMIC cannot be verified before the session key is established"),
+            )
+        })?;
+
+        let digest = compute_digest(&self.recv_signing_key, 0, mech_types)?;
+        let checksum = sealing_key.process(&digest[0..SIGNATURE_CHECKSUM_SIZE]);
+
+        if mech_list_mic != compute_signature(&checksum, 0).as_ref() {
+            return Err(sspi::Error::new(
+                sspi::ErrorKind::MessageAltered,
+                String::from("mechListMIC verification failed"),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for Ntlm {
@@ -313,6 +351,61 @@ impl Sspi for Ntlm {
         Ok(DecryptionFlags::empty())
     }
 
+    fn make_signature(
+        &mut self,
+        _flags: u32,
+        message: &mut [SecurityBuffer],
+        sequence_number: u32,
+    ) -> sspi::Result<()> {
+        SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?; // check if exists
+        let data = SecurityBuffer::find_buffer(message, SecurityBufferType::Data)?;
+
+        let digest = compute_digest(&self.send_signing_key, sequence_number, data.buffer.as_slice())?;
+        let checksum = self
+            .send_sealing_key
+            .as_mut()
+            .ok_or_else(|| {
+                sspi::Error::new(
+                    sspi::ErrorKind::OutOfSequence,
+                    String::from("The message cannot be signed before the session key is established"),
+                )
+            })?
+            .process(&digest[0..SIGNATURE_CHECKSUM_SIZE]);
+
+        let signature = SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?;
+        *signature.buffer.as_mut() = compute_signature(&checksum, sequence_number).to_vec();
+
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> sspi::Result<u32> {
+        SecurityBuffer::find_buffer_mut(message, SecurityBufferType::Token)?; // check if exists
+        let data = SecurityBuffer::find_buffer(message, SecurityBufferType::Data)?;
+
+        let digest = compute_digest(&self.recv_signing_key, sequence_number, data.buffer.as_slice())?;
+        let checksum = self
+            .recv_sealing_key
+            .as_mut()
+            .ok_or_else(|| {
+                sspi::Error::new(
+                    sspi::ErrorKind::OutOfSequence,
+                    String::from("The signature cannot be verified before the session key is established"),
+                )
+            })?
+            .process(&digest[0..SIGNATURE_CHECKSUM_SIZE]);
+        let expected_signature = compute_signature(&checksum, sequence_number);
+
+        let signature = SecurityBuffer::find_buffer(message, SecurityBufferType::Token)?;
+        if signature.buffer.as_slice() != expected_signature.as_ref() {
+            return Err(sspi::Error::new(
+                sspi::ErrorKind::MessageAltered,
+                String::from("Signature verification failed, something nasty is going on!"),
+            ));
+        }
+
+        Ok(0)
+    }
+
     fn query_context_sizes(&mut self) -> sspi::Result<ContextSizes> {
         Ok(ContextSizes {
             max_token: 2010,
@@ -347,6 +440,13 @@ impl Sspi for Ntlm {
             String::from("Certificate trust status is not supported"),
         ))
     }
+
+    fn query_context_pac(&mut self) -> sspi::Result<Pac> {
+        Err(sspi::Error::new(
+            sspi::ErrorKind::UnsupportedFunction,
+            String::from("PAC is not supported by NTLM"),
+        ))
+    }
 }
 
 impl SspiEx for Ntlm {
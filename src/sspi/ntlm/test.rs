@@ -209,6 +209,68 @@ fn decrypt_message_fails_on_incorrect_sealing_key() {
     assert!(context.decrypt_message(&mut buffers, TEST_SEQ_NUM).is_err());
 }
 
+#[test]
+fn make_signature_does_not_encrypt_data() {
+    let mut context = Ntlm::new();
+    context.send_signing_key = SIGNING_KEY;
+    context.send_sealing_key = Some(Rc4::new(&SEALING_KEY));
+
+    let mut buffers = vec![
+        SecurityBuffer::new(TEST_DATA.clone(), SecurityBufferType::Data),
+        SecurityBuffer::new(Vec::with_capacity(100), SecurityBufferType::Token),
+    ];
+
+    context.make_signature(0, &mut buffers, TEST_SEQ_NUM).unwrap();
+    let data = SecurityBuffer::find_buffer(&buffers, SecurityBufferType::Data).unwrap();
+    let signature = SecurityBuffer::find_buffer(&buffers, SecurityBufferType::Token).unwrap();
+
+    assert_eq!(TEST_DATA.as_slice(), data.buffer.as_slice());
+    assert_eq!(SIGNATURE_SIZE, signature.buffer.len());
+    assert_eq!(TEST_SEQ_NUM.to_le_bytes(), signature.buffer[12..SIGNATURE_SIZE]);
+}
+
+#[test]
+fn verify_signature_does_not_fail_on_correct_signature() {
+    let mut context = Ntlm::new();
+    context.send_signing_key = SIGNING_KEY;
+    context.send_sealing_key = Some(Rc4::new(&SEALING_KEY));
+    context.recv_signing_key = SIGNING_KEY;
+    context.recv_sealing_key = Some(Rc4::new(&SEALING_KEY));
+
+    let mut buffers = vec![
+        SecurityBuffer::new(TEST_DATA.clone(), SecurityBufferType::Data),
+        SecurityBuffer::new(Vec::with_capacity(100), SecurityBufferType::Token),
+    ];
+
+    context.make_signature(0, &mut buffers, TEST_SEQ_NUM).unwrap();
+    context.verify_signature(&mut buffers, TEST_SEQ_NUM).unwrap();
+}
+
+#[test]
+fn verify_signature_fails_on_altered_data() {
+    let mut context = Ntlm::new();
+    context.send_signing_key = SIGNING_KEY;
+    context.send_sealing_key = Some(Rc4::new(&SEALING_KEY));
+    context.recv_signing_key = SIGNING_KEY;
+    context.recv_sealing_key = Some(Rc4::new(&SEALING_KEY));
+
+    let mut buffers = vec![
+        SecurityBuffer::new(TEST_DATA.clone(), SecurityBufferType::Data),
+        SecurityBuffer::new(Vec::with_capacity(100), SecurityBufferType::Token),
+    ];
+
+    context.make_signature(0, &mut buffers, TEST_SEQ_NUM).unwrap();
+    buffers[0].buffer[0] ^= 0xff;
+
+    assert_eq!(
+        context
+            .verify_signature(&mut buffers, TEST_SEQ_NUM)
+            .unwrap_err()
+            .error_type,
+        ErrorKind::MessageAltered
+    );
+}
+
 #[test]
 fn initialize_security_context_wrong_state_negotiate() {
     let mut context = Ntlm::new();
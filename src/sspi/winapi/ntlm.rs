@@ -9,6 +9,7 @@ use crate::sspi::builders::{
     InitializeSecurityContextResult,
 };
 use crate::sspi::internal::SspiImpl;
+use crate::sspi::kerberos::pac::Pac;
 use crate::sspi::ntlm::AuthIdentity;
 use crate::sspi::{
     self, CertTrustStatus, ContextNames, ContextSizes, DecryptionFlags, EncryptionFlags, FilledAcceptSecurityContext,
@@ -112,6 +113,14 @@ impl Sspi for Ntlm {
         self.0.decrypt_message(message, sequence_number)
     }
 
+    fn make_signature(&mut self, flags: u32, message: &mut [SecurityBuffer], sequence_number: u32) -> sspi::Result<()> {
+        self.0.make_signature(flags, message, sequence_number)
+    }
+
+    fn verify_signature(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> sspi::Result<u32> {
+        self.0.verify_signature(message, sequence_number)
+    }
+
     fn query_context_sizes(&mut self) -> sspi::Result<ContextSizes> {
         self.0.query_context_sizes()
     }
@@ -126,4 +135,8 @@ impl Sspi for Ntlm {
     fn query_context_cert_trust_status(&mut self) -> sspi::Result<CertTrustStatus> {
         self.0.query_context_cert_trust_status()
     }
+
+    fn query_context_pac(&mut self) -> sspi::Result<Pac> {
+        self.0.query_context_pac()
+    }
 }
@@ -5,9 +5,9 @@ use num_traits::ToPrimitive;
 use winapi::ctypes::c_void;
 use winapi::shared::sspi::{
     AcceptSecurityContext, AcquireCredentialsHandleW, CompleteAuthToken, CredHandle, CtxtHandle, DecryptMessage,
-    DeleteSecurityContext, EncryptMessage, FreeContextBuffer, InitializeSecurityContextW, QueryContextAttributesW,
-    SecBuffer, SecPkgContext_NamesW, SecPkgContext_PackageInfoW, SecPkgContext_Sizes, TimeStamp, SECPKG_ATTR_NAMES,
-    SECPKG_ATTR_PACKAGE_INFO, SECPKG_ATTR_SIZES,
+    DeleteSecurityContext, EncryptMessage, FreeContextBuffer, InitializeSecurityContextW, MakeSignature,
+    QueryContextAttributesW, SecBuffer, SecPkgContext_NamesW, SecPkgContext_PackageInfoW, SecPkgContext_Sizes,
+    TimeStamp, VerifySignature, SECPKG_ATTR_NAMES, SECPKG_ATTR_PACKAGE_INFO, SECPKG_ATTR_SIZES,
 };
 use winapi::um::wincrypt::CERT_TRUST_STATUS;
 
@@ -19,6 +19,7 @@ use crate::sspi::builders::{
     AcceptSecurityContextResult, AcquireCredentialsHandleResult, InitializeSecurityContextResult,
 };
 use crate::sspi::internal::SspiImpl;
+use crate::sspi::kerberos::pac::Pac;
 use crate::sspi::{
     self, CertTrustErrorStatus, CertTrustInfoStatus, CertTrustStatus, ClientRequestFlags, ClientResponseFlags,
     ContextNames, ContextSizes, DecryptionFlags, EncryptionFlags, FilledAcceptSecurityContext,
@@ -331,6 +332,50 @@ impl Sspi for SecurityPackage {
         Ok(DecryptionFlags::from_bits_truncate(flags))
     }
 
+    fn make_signature(&mut self, flags: u32, message: &mut [SecurityBuffer], sequence_number: u32) -> sspi::Result<()> {
+        let context = self
+            .context
+            .as_mut()
+            .expect("MakeSignature cannot be fired without context");
+
+        let mut output_buffers = buffers_as_winapi(message);
+        let mut output_buffer_descriptor = construct_buffer_desc(&mut output_buffers);
+
+        unsafe {
+            convert_winapi_status(MakeSignature(
+                &mut context.0 as *mut _,
+                flags,
+                &mut output_buffer_descriptor as *mut _,
+                sequence_number,
+            ))?
+        };
+
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, message: &mut [SecurityBuffer], sequence_number: u32) -> sspi::Result<u32> {
+        let context = self
+            .context
+            .as_mut()
+            .expect("VerifySignature cannot be fired without context");
+
+        let mut output_buffers = buffers_as_winapi(message);
+        let mut output_buffer_descriptor = construct_buffer_desc(&mut output_buffers);
+
+        let mut flags = 0;
+
+        unsafe {
+            convert_winapi_status(VerifySignature(
+                &mut context.0 as *mut _,
+                &mut output_buffer_descriptor as *mut _,
+                sequence_number,
+                &mut flags as *mut _,
+            ))?
+        };
+
+        Ok(flags)
+    }
+
     fn query_context_sizes(&mut self) -> sspi::Result<ContextSizes> {
         let mut buffer = SecPkgContext_Sizes::default();
         self.query_context_attributes(SECPKG_ATTR_SIZES, &mut buffer)?;
@@ -377,6 +422,13 @@ impl Sspi for SecurityPackage {
             info_status: CertTrustInfoStatus::from_bits_truncate(buffer.dwInfoStatus),
         })
     }
+
+    fn query_context_pac(&mut self) -> sspi::Result<Pac> {
+        Err(sspi::Error::new(
+            sspi::ErrorKind::UnsupportedFunction,
+            String::from("PAC is not available through the Windows security package"),
+        ))
+    }
 }
 
 fn as_mut_ptr_or_null<T>(value: Option<&mut T>) -> *mut T {
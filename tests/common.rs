@@ -193,3 +193,19 @@ pub fn check_messages_encryption(client: &mut impl Sspi, server: &mut impl Sspi)
 
     Ok(())
 }
+
+pub fn check_messages_signature(client: &mut impl Sspi, server: &mut impl Sspi) -> sspi::Result<()> {
+    let sequence_number = 1;
+
+    let mut messages = [
+        SecurityBuffer::new(MESSAGE_TO_CLIENT.clone(), SecurityBufferType::Data),
+        SecurityBuffer::new(Vec::new(), SecurityBufferType::Token),
+    ];
+    server.make_signature(0, &mut messages, sequence_number)?;
+    assert_eq!(*MESSAGE_TO_CLIENT, messages[0].buffer);
+    assert!(!messages[1].buffer.is_empty());
+
+    client.verify_signature(&mut messages, sequence_number)?;
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+pub mod common;
+
+use common::{
+    check_messages_encryption, check_messages_signature, create_client_credentials_handle,
+    create_server_credentials_handle, process_authentication_without_complete, CredentialsProxyImpl, CREDENTIALS,
+};
+use sspi::internal::credssp::{
+    ClientMode, ClientState, CredSspClient, CredSspMode, CredSspServer, ServerState, TsRequest,
+};
+use sspi::{
+    ContextNames, Credentials, Negotiate, NegotiateConfig, SecurityBuffer, SecurityBufferType, SecurityStatus, Sspi,
+    SspiEx,
+};
+
+#[test]
+fn successful_ntlm_authentication_through_negotiate() {
+    let credentials = Credentials::from(CREDENTIALS.clone());
+
+    let mut client = Negotiate::new(NegotiateConfig::default());
+    let client_credentials_handle = create_client_credentials_handle(&mut client, Some(&credentials)).unwrap();
+
+    let mut server = Negotiate::new(NegotiateConfig::default());
+    let server_credentials_handle = create_server_credentials_handle(&mut server).unwrap();
+
+    let (client_status, server_status) = process_authentication_without_complete(
+        &mut client,
+        client_credentials_handle,
+        &mut server,
+        server_credentials_handle,
+    )
+    .unwrap();
+    assert_eq!(client_status, SecurityStatus::Ok);
+    assert_eq!(server_status, SecurityStatus::CompleteNeeded);
+
+    let ContextNames { username, .. } = server.query_context_names().unwrap();
+    assert_eq!(username, CREDENTIALS.username);
+    server.custom_set_auth_identity(credentials);
+
+    let mut final_token = vec![SecurityBuffer::new(Vec::new(), SecurityBufferType::Token)];
+    server.complete_auth_token(&mut final_token).unwrap();
+    assert!(!final_token[0].buffer.is_empty());
+
+    check_messages_encryption(&mut client, &mut server).unwrap();
+    check_messages_signature(&mut client, &mut server).unwrap();
+}
+
+#[test]
+fn successful_credssp_authentication_with_negotiate() {
+    let public_key = vec![0x30, 0x0a, 0x02, 0x03, 0x01, 0x00, 0x01, 0x02, 0x03, 0x01, 0x00, 0x01];
+
+    let mut client = CredSspClient::new(
+        public_key.clone(),
+        CREDENTIALS.clone(),
+        CredSspMode::WithCredentials,
+        ClientMode::Negotiate(NegotiateConfig::default()),
+        "TERMSRV/server.example.com".into(),
+    )
+    .unwrap();
+    let mut server = CredSspServer::new(
+        public_key,
+        CredentialsProxyImpl::new(&CREDENTIALS),
+        ClientMode::Negotiate(NegotiateConfig::default()),
+    )
+    .unwrap();
+
+    let mut ts_request = TsRequest::default();
+    loop {
+        ts_request = match client.process(ts_request).unwrap() {
+            ClientState::ReplyNeeded(ts_request) | ClientState::FinalMessage(ts_request) => ts_request,
+        };
+
+        match server.process(ts_request).unwrap() {
+            ServerState::ReplyNeeded(reply) => ts_request = reply,
+            ServerState::Finished(identity) => {
+                assert_eq!(identity, *CREDENTIALS);
+
+                break;
+            }
+        }
+    }
+}
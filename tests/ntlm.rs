@@ -1,9 +1,9 @@
 pub mod common;
 
 use common::{
-    check_messages_encryption, create_client_credentials_handle, create_server_credentials_handle,
-    process_authentication_without_complete, set_identity_and_try_complete_authentication, try_complete_authentication,
-    CredentialsProxyImpl, CREDENTIALS,
+    check_messages_encryption, check_messages_signature, create_client_credentials_handle,
+    create_server_credentials_handle, process_authentication_without_complete,
+    set_identity_and_try_complete_authentication, try_complete_authentication, CredentialsProxyImpl, CREDENTIALS,
 };
 use sspi::Ntlm;
 
@@ -28,4 +28,5 @@ fn successful_ntlm_authentication_with_client_auth_data() {
     set_identity_and_try_complete_authentication(&mut server, server_status, &mut credentials_proxy).unwrap();
 
     check_messages_encryption(&mut client, &mut server).unwrap();
+    check_messages_signature(&mut client, &mut server).unwrap();
 }